@@ -0,0 +1,279 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use fuser::MountOption;
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::filesystem::{Daniel, MAX_READ};
+
+/// the fsname and subtype `mount` and `findmnt` show unless they're configured
+pub const DEFAULT_FSNAME: &str = "daniel";
+
+/// one `[[mount]]` table of the config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountSpec {
+    pub name: String,
+    pub mountpoint: PathBuf,
+    /// the source `mount` shows for the mount
+    pub fsname: String,
+    /// shown after `fuse.` as the type of the mount
+    pub subtype: String,
+    pub read_only: bool,
+    /// the most bytes of file data the mount may hold
+    pub quota: Option<u64>,
+}
+
+impl MountSpec {
+    pub fn mount_options(&self) -> Vec<MountOption> {
+        mount_options(&self.fsname, &self.subtype, self.read_only)
+    }
+}
+
+/// the options a mount is made with, it's labeled with `fsname` and `subtype`. reads are capped
+/// at [`MAX_READ`]
+pub fn mount_options(fsname: &str, subtype: &str, read_only: bool) -> Vec<MountOption> {
+    let mut options = vec![
+        MountOption::FSName(fsname.into()),
+        MountOption::Subtype(subtype.into()),
+        MountOption::CUSTOM(format!("max_read={MAX_READ}")),
+    ];
+    if read_only {
+        options.push(MountOption::RO);
+    }
+
+    options
+}
+
+/// makes the directories of a comma separated `--skeleton` list like `a/b/c,x/y` in `fs`, along
+/// with the ones leading up to them
+pub fn build_skeleton(fs: &mut Daniel, list: &str) -> Result<(), String> {
+    for path in list.split(',').map(str::trim) {
+        if path.is_empty() {
+            return Err(format!("empty path in the skeleton {list:?}"));
+        }
+        fs.create_dir_path(Path::new(path))
+            .map_err(|err| format!("can't create {path:?}: {err}"))?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl ConfigError {
+    fn new(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            message: message.into(),
+        }
+    }
+
+    /// the error toml reports, on the line its span starts at in `src`
+    fn from_toml(src: &str, err: &toml::de::Error) -> Self {
+        let start = err.span().map_or(0, |span| span.start);
+        let line = src[..start.min(src.len())].matches('\n').count() + 1;
+        Self::new(line, err.message())
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    mount: Vec<Spanned<RawSpec>>,
+}
+
+/// a `[[mount]]` table as it's written, before the defaults are filled in
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSpec {
+    name: Option<String>,
+    mountpoint: PathBuf,
+    fsname: Option<String>,
+    subtype: Option<String>,
+    #[serde(default)]
+    read_only: bool,
+    quota: Option<u64>,
+    /// nothing can import a directory into a mount yet, so it's refused instead of ignored
+    import: Option<PathBuf>,
+}
+
+impl RawSpec {
+    fn finish(self, line: usize) -> Result<MountSpec, ConfigError> {
+        if self.import.is_some() {
+            return Err(ConfigError::new(line, "`import` isn't supported yet"));
+        }
+        let name = match self.name {
+            Some(name) => name,
+            None => self.mountpoint.display().to_string(),
+        };
+
+        Ok(MountSpec {
+            name,
+            mountpoint: self.mountpoint,
+            fsname: self.fsname.unwrap_or_else(|| DEFAULT_FSNAME.into()),
+            subtype: self.subtype.unwrap_or_else(|| DEFAULT_FSNAME.into()),
+            read_only: self.read_only,
+            quota: self.quota,
+        })
+    }
+}
+
+/// parses the config file, a list of `[[mount]]` tables
+pub fn parse_config(src: &str) -> Result<Vec<MountSpec>, ConfigError> {
+    let file: ConfigFile = toml::from_str(src).map_err(|err| ConfigError::from_toml(src, &err))?;
+
+    file.mount
+        .into_iter()
+        .map(|spec| {
+            let line = src[..spec.span().start].matches('\n').count() + 1;
+            spec.into_inner().finish(line)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use fuser::MountOption;
+
+    use super::{ConfigError, MountSpec, build_skeleton, mount_options, parse_config};
+    use crate::filesystem::{Daniel, Errno};
+
+    #[test]
+    fn mounts() {
+        let src = r#"
+# scratch space
+[[mount]]
+name = "scratch"
+mountpoint = "/tmp/daniel/scratch"
+fsname = "scratch"
+quota = 1_048_576 # a MiB
+
+[[mount]]
+mountpoint = "/tmp/daniel/seed"
+read_only = true
+"#;
+
+        assert_eq!(
+            parse_config(src),
+            Ok(vec![
+                MountSpec {
+                    name: "scratch".into(),
+                    mountpoint: "/tmp/daniel/scratch".into(),
+                    fsname: "scratch".into(),
+                    subtype: "daniel".into(),
+                    read_only: false,
+                    quota: Some(1 << 20),
+                },
+                MountSpec {
+                    name: "/tmp/daniel/seed".into(),
+                    mountpoint: "/tmp/daniel/seed".into(),
+                    fsname: "daniel".into(),
+                    subtype: "daniel".into(),
+                    read_only: true,
+                    quota: None,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_config(""), Ok(vec![]));
+        assert_eq!(
+            parse_config("[[mount]]\nname = \"a\"\n"),
+            Err(ConfigError::new(1, "missing field `mountpoint`"))
+        );
+        assert_eq!(
+            parse_config("[[mount]]\nmountpoint = \"/mnt\"\nread_only = \"yes\"\n"),
+            Err(ConfigError::new(
+                3,
+                "invalid type: string \"yes\", expected a boolean"
+            ))
+        );
+        assert_eq!(parse_config("name = \"a\"\n").unwrap_err().line, 1);
+        assert_eq!(
+            parse_config("[[mount]]\nmountpoint = \"/mnt\nquota = 1\n")
+                .unwrap_err()
+                .line,
+            2
+        );
+
+        // a directory to import is refused instead of quietly ignored
+        assert_eq!(
+            parse_config(
+                "[[mount]]\nmountpoint = \"/a\"\n\n[[mount]]\nmountpoint = \"/b\"\nimport = \"/srv\"\n"
+            ),
+            Err(ConfigError::new(4, "`import` isn't supported yet"))
+        );
+    }
+
+    #[test]
+    fn options() {
+        assert_eq!(
+            mount_options("daniel", "daniel", false),
+            [
+                MountOption::FSName("daniel".into()),
+                MountOption::Subtype("daniel".into()),
+                MountOption::CUSTOM("max_read=1048576".into()),
+            ]
+        );
+
+        let spec = &parse_config(
+            "[[mount]]\nmountpoint = \"/mnt\"\nsubtype = \"scratch\"\nread_only = true\n",
+        )
+        .unwrap()[0];
+        assert_eq!(
+            spec.mount_options(),
+            [
+                MountOption::FSName("daniel".into()),
+                MountOption::Subtype("scratch".into()),
+                MountOption::CUSTOM("max_read=1048576".into()),
+                MountOption::RO,
+            ]
+        );
+    }
+
+    #[test]
+    fn skeleton() {
+        let mut fs = Daniel::new();
+        build_skeleton(&mut fs, "a/b/c, x/y,/a/d").unwrap();
+        for path in ["/a", "/a/b", "/a/b/c", "/a/d", "/x", "/x/y"] {
+            let ino = fs.resolve_following_symlinks(path, 0).unwrap();
+            assert!(fs.directory(ino).is_ok(), "{path}");
+        }
+        let root = fs.list_dir(crate::filesystem::ROOT_INODE).unwrap();
+        assert_eq!(root.len(), 3);
+
+        assert_eq!(
+            build_skeleton(&mut fs, "a,,b"),
+            Err("empty path in the skeleton \"a,,b\"".into())
+        );
+        assert_eq!(
+            build_skeleton(&mut fs, "../escape"),
+            Err(format!("can't create \"../escape\": {}", Errno::EINVAL))
+        );
+        fs.create_path(Path::new("/file"), 0o644).unwrap();
+        assert_eq!(
+            build_skeleton(&mut fs, "file/below"),
+            Err(format!("can't create \"file/below\": {}", Errno::ENOTDIR))
+        );
+    }
+}
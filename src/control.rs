@@ -0,0 +1,309 @@
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream, ToSocketAddrs},
+    path::Path,
+    sync::{Arc, PoisonError, RwLock},
+    thread::{self, JoinHandle},
+};
+
+use fuser::FileType;
+use tracing::{info, warn};
+
+use crate::filesystem::{Daniel, Errno, Inode, ROOT_INODE};
+
+/// how many symlinks a path given to a command may go through
+const MAX_HOPS: usize = 40;
+
+/// the longest command line a connection may send, it's dropped after a longer one
+const MAX_LINE: u64 = 4096;
+
+/// listens on `addr`, a bare port is taken to be on the loopback address. anything that isn't
+/// loopback is refused since the commands aren't authenticated
+pub fn bind(addr: &str) -> std::io::Result<TcpListener> {
+    let addrs: Vec<_> = match addr.parse::<u16>() {
+        Ok(port) => vec![(Ipv4Addr::LOCALHOST, port).into()],
+        Err(_) => addr.to_socket_addrs()?.collect(),
+    };
+    if let Some(open) = addrs.iter().find(|addr| !addr.ip().is_loopback()) {
+        return Err(std::io::Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{open} isn't a loopback address"),
+        ));
+    }
+
+    TcpListener::bind(&*addrs)
+}
+
+/// accepts connections on `listener` until it fails, each is served on its own thread. a
+/// connection sends one command per line and gets the reply of [`execute`] back
+pub fn serve(listener: TcpListener, fs: Arc<RwLock<Daniel>>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!(%err, "failed to accept a control connection");
+                    continue;
+                }
+            };
+            let fs = Arc::clone(&fs);
+            thread::spawn(move || {
+                if let Err(err) = serve_connection(stream, &fs) {
+                    warn!(%err, "control connection failed");
+                }
+            });
+        }
+    })
+}
+
+fn serve_connection(stream: TcpStream, fs: &RwLock<Daniel>) -> std::io::Result<()> {
+    info!(peer = ?stream.peer_addr(), "control connection");
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    loop {
+        let line = match read_line(&mut reader) {
+            Ok(Some(line)) => line,
+            Ok(None) => return Ok(()),
+            Err(err) if err.kind() == ErrorKind::InvalidData => {
+                // the rest of the line can't be told apart from the next command
+                writer.write_all(format!("ERR {err}\n").as_bytes())?;
+                return Err(err);
+            }
+            Err(err) => return Err(err),
+        };
+        let reply = {
+            let mut fs = fs.write().unwrap_or_else(PoisonError::into_inner);
+            execute(&mut fs, &line)
+        };
+        writer.write_all(reply.as_bytes())?;
+    }
+}
+
+/// the next line without its newline, `None` at the end. reads at most [`MAX_LINE`] bytes of it,
+/// `InvalidData` for a longer line or one that isn't utf-8
+fn read_line(reader: &mut impl BufRead) -> std::io::Result<Option<String>> {
+    let mut line = Vec::new();
+    let read = reader.take(MAX_LINE + 1).read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    } else if read as u64 > MAX_LINE {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, "line too long"));
+    }
+
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err))
+}
+
+/// runs a single command, the reply is any output followed by a line of `OK` or `ERR <reason>`
+///
+/// - `STAT <path>` the attributes of the entry
+/// - `LS <path>` the children of a directory, one `<kind> <name>` per line
+/// - `RM <path>` removes the entry and everything below it
+/// - `HANDLES <path>` the handles open on the entry, one per line
+/// - `DUMP` every entry in the tree, one `<kind> <path> <size>` per line
+pub fn execute(fs: &mut Daniel, line: &str) -> String {
+    let line = line.trim();
+    let (command, arg) = match line.split_once(' ') {
+        Some((command, arg)) => (command, Some(arg.trim())),
+        None => (line, None),
+    };
+
+    let result = match (command.to_ascii_uppercase().as_str(), arg) {
+        ("STAT", Some(path)) => stat(fs, path),
+        ("LS", Some(path)) => ls(fs, path),
+        ("RM", Some(path)) => rm(fs, path),
+        ("HANDLES", Some(path)) => handles(fs, path),
+        ("DUMP", None) => Ok(dump(fs)),
+        ("STAT" | "LS" | "RM" | "HANDLES", None) => Err(format!("usage: {command} <path>")),
+        ("DUMP", Some(_)) => Err("usage: DUMP".into()),
+        _ => Err(format!("unknown command {command:?}")),
+    };
+
+    match result {
+        Ok(output) => output + "OK\n",
+        Err(reason) => format!("ERR {reason}\n"),
+    }
+}
+
+fn resolve(fs: &Daniel, path: &str) -> Result<Inode, String> {
+    if !path.starts_with('/') {
+        return Err(format!("{path:?} isn't absolute"));
+    }
+    fs.resolve_following_symlinks(path, MAX_HOPS)
+        .ok_or_else(|| format!("{:?}", Errno::ENOENT))
+}
+
+fn stat(fs: &mut Daniel, path: &str) -> Result<String, String> {
+    let ino = resolve(fs, path)?;
+    let attr = fs
+        .getattr(ino.into(), None)
+        .map_err(|err| format!("{err:?}"))?
+        .inner();
+
+    Ok(format!(
+        "ino {}\nkind {}\nsize {}\nperm {:o}\nuid {}\ngid {}\nnlink {}\n",
+        attr.ino,
+        kind(attr.kind),
+        attr.size,
+        attr.perm,
+        attr.uid,
+        attr.gid,
+        attr.nlink
+    ))
+}
+
+fn ls(fs: &mut Daniel, path: &str) -> Result<String, String> {
+    let ino = resolve(fs, path)?;
+    let children = fs.list_dir(ino).map_err(|err| format!("{err:?}"))?;
+
+    Ok(children
+        .into_iter()
+        .map(|(name, typ, _)| format!("{} {}\n", kind(typ), name.display()))
+        .collect())
+}
+
+fn rm(fs: &mut Daniel, path: &str) -> Result<String, String> {
+    let path = Path::new(path);
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err("the root can't be removed".into());
+    };
+    let parent = resolve(fs, &parent.to_string_lossy())?;
+    // the socket only takes local connections, so it acts as root
+    fs.remove_recursive(parent, name, 0)
+        .map_err(|err| format!("{err:?}"))?;
+    // the kernel may have the entry cached
+    fs.flush_notifications();
+
+    Ok(String::new())
+}
+
+fn handles(fs: &mut Daniel, path: &str) -> Result<String, String> {
+    let ino = resolve(fs, path)?;
+
+    Ok(fs
+        .handles_for(ino.into())
+        .into_iter()
+        .map(|handle| format!("{handle}\n"))
+        .collect())
+}
+
+fn dump(fs: &mut Daniel) -> String {
+    let mut out = String::new();
+    let mut pending = vec![(ROOT_INODE, Path::new("/").to_path_buf())];
+    while let Some((ino, path)) = pending.pop() {
+        let Ok(attr) = fs.getattr(ino.into(), None) else {
+            continue;
+        };
+        let attr = attr.inner();
+        out += &format!("{} {} {}\n", kind(attr.kind), path.display(), attr.size);
+
+        let Ok(children) = fs.list_dir(ino) else {
+            continue;
+        };
+        // popped in the order they're listed
+        for (name, _, child) in children.into_iter().rev() {
+            pending.push((child, path.join(name)));
+        }
+    }
+
+    out
+}
+
+/// the letter `ls -l` uses for `kind`
+fn kind(kind: FileType) -> char {
+    match kind {
+        FileType::NamedPipe => 'p',
+        FileType::CharDevice => 'c',
+        FileType::BlockDevice => 'b',
+        FileType::Directory => 'd',
+        FileType::RegularFile => '-',
+        FileType::Symlink => 'l',
+        FileType::Socket => 's',
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Cursor, path::Path};
+
+    use super::{MAX_LINE, bind, execute, read_line};
+    use crate::filesystem::{Daniel, STATUS_FILE};
+
+    #[test]
+    fn commands() {
+        let mut fs = Daniel::new();
+        let foo = fs.create_path(Path::new("/a/foo"), 0o640).unwrap();
+        fs.write(foo.into(), 0, b"hello").unwrap();
+        fs.create_path(Path::new("/a/b/bar"), 0o644).unwrap();
+
+        let stat = execute(&mut fs, "STAT /a/foo");
+        assert_eq!(
+            stat,
+            format!(
+                "ino {}\nkind -\nsize 5\nperm 640\nuid 0\ngid 0\nnlink 1\nOK\n",
+                u64::from(foo)
+            )
+        );
+
+        assert_eq!(execute(&mut fs, "ls /a"), "- foo\nd b\nOK\n");
+        assert_eq!(
+            execute(&mut fs, "DUMP"),
+            format!("d / 0\n- /{STATUS_FILE} 0\nd /a 0\n- /a/foo 5\nd /a/b 0\n- /a/b/bar 0\nOK\n")
+        );
+
+        assert_eq!(execute(&mut fs, "HANDLES /a/foo"), "OK\n");
+        let fh = fs.open(foo.into(), libc::O_RDWR, 0, 0).unwrap();
+        assert_eq!(
+            execute(&mut fs, "HANDLES /a/foo"),
+            format!("fh {fh:#x} rw flags 0o2\nOK\n")
+        );
+
+        assert_eq!(execute(&mut fs, "RM /a/b"), "OK\n");
+        assert_eq!(execute(&mut fs, "LS /a"), "- foo\nOK\n");
+        assert_eq!(execute(&mut fs, "STAT /a/b/bar"), "ERR ENOENT\n");
+        assert_eq!(execute(&mut fs, "RM /"), "ERR the root can't be removed\n");
+    }
+
+    #[test]
+    fn malformed() {
+        let mut fs = Daniel::new();
+        assert_eq!(execute(&mut fs, "LS"), "ERR usage: LS <path>\n");
+        assert_eq!(execute(&mut fs, "DUMP /"), "ERR usage: DUMP\n");
+        assert_eq!(execute(&mut fs, "LS a"), "ERR \"a\" isn't absolute\n");
+        assert_eq!(
+            execute(&mut fs, "MKDIR /a"),
+            "ERR unknown command \"MKDIR\"\n"
+        );
+        let status = format!("LS /{STATUS_FILE}");
+        assert_eq!(execute(&mut fs, &status), "ERR ENOTDIR\n");
+    }
+
+    #[test]
+    fn lines() {
+        let mut reader = Cursor::new(b"LS /\nDUMP".to_vec());
+        assert_eq!(read_line(&mut reader).unwrap().as_deref(), Some("LS /"));
+        assert_eq!(read_line(&mut reader).unwrap().as_deref(), Some("DUMP"));
+        assert_eq!(read_line(&mut reader).unwrap(), None);
+
+        let longest = format!("{}\n", "a".repeat(MAX_LINE as usize));
+        assert!(read_line(&mut Cursor::new(longest)).unwrap().is_some());
+        let long = "a".repeat(MAX_LINE as usize + 1);
+        let err = read_line(&mut Cursor::new(long)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn loopback_only() {
+        let listener = bind("0").unwrap();
+        assert!(listener.local_addr().unwrap().ip().is_loopback());
+        assert!(bind("127.0.0.1:0").is_ok());
+        assert_eq!(
+            bind("0.0.0.0:0").unwrap_err().kind(),
+            std::io::ErrorKind::PermissionDenied
+        );
+    }
+}
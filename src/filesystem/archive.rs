@@ -0,0 +1,27 @@
+use std::path::{Component, Path, PathBuf};
+
+/// what [`Daniel::from_tar`](super::Daniel::from_tar) does with an entry a tree can't hold, like
+/// a GNU volume label
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnsupportedEntry {
+    /// stop reading the archive with an [`std::io::ErrorKind::Unsupported`] error
+    #[default]
+    Error,
+    /// leave the entry out and carry on
+    Skip,
+}
+
+/// the path of an archive entry relative to the root of the tree, `None` if it goes up out of it.
+/// the archive's own root, `./`, is the empty path
+pub fn archive_path(path: &Path) -> Option<PathBuf> {
+    let mut clean = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => clean.push(name),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(clean)
+}
@@ -0,0 +1,45 @@
+/// a run of contiguous writes on one handle waiting to be applied to the file in one go
+#[derive(Debug, Clone)]
+pub struct WriteBuffer {
+    offset: u64,
+    data: Vec<u8>,
+}
+
+impl WriteBuffer {
+    pub fn new(offset: u64, capacity: usize) -> Self {
+        Self {
+            offset,
+            data: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// the offset a write has to start at to be appended to this buffer
+    pub fn end(&self) -> u64 {
+        self.offset + self.data.len() as u64
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn append(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+    }
+
+    /// drops everything past the first `len` bytes
+    pub fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+    }
+}
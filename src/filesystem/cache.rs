@@ -0,0 +1,172 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::{FileAttribute, Inode};
+
+/// a least recently used cache of attributes handed out by `getattr`, a capacity of 0 caches
+/// nothing
+#[derive(Debug, Default)]
+pub struct AttrCache {
+    capacity: usize,
+    /// the attribute and the tick it was last used at
+    entries: HashMap<Inode, (FileAttribute, u64)>,
+    /// the reverse of the ticks in `entries`, the first one is the least recently used
+    used: BTreeMap<u64, Inode>,
+    tick: u64,
+}
+
+impl AttrCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, ino: &Inode) -> bool {
+        self.entries.contains_key(ino)
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    pub fn get(&mut self, ino: &Inode) -> Option<&FileAttribute> {
+        let tick = self.next_tick();
+        let (attr, used) = self.entries.get_mut(ino)?;
+        self.used.remove(used);
+        self.used.insert(tick, *ino);
+        *used = tick;
+        Some(attr)
+    }
+
+    pub fn insert(&mut self, ino: Inode, attr: FileAttribute) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.invalidate(&ino);
+        if self.entries.len() == self.capacity
+            && let Some((_, oldest)) = self.used.pop_first()
+        {
+            self.entries.remove(&oldest);
+        }
+
+        let tick = self.next_tick();
+        self.entries.insert(ino, (attr, tick));
+        self.used.insert(tick, ino);
+    }
+
+    pub fn invalidate(&mut self, ino: &Inode) {
+        if let Some((_, used)) = self.entries.remove(ino) {
+            self.used.remove(&used);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.used.clear();
+    }
+}
+
+/// tracks how much memory file contents take up and which were used least recently, so contents
+/// can be dropped once they go over `budget`
+#[derive(Debug, Default)]
+pub struct ContentLru {
+    budget: Option<u64>,
+    /// the bytes held and the tick they were last used at
+    entries: HashMap<Inode, (u64, u64)>,
+    /// the reverse of the ticks in `entries`, the first one is the least recently used
+    used: BTreeMap<u64, Inode>,
+    tick: u64,
+    total: u64,
+}
+
+impl ContentLru {
+    pub fn new(budget: u64) -> Self {
+        Self {
+            budget: Some(budget),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.budget.is_some()
+    }
+
+    /// the bytes held by every tracked file
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn over_budget(&self) -> bool {
+        self.budget.is_some_and(|budget| self.total > budget)
+    }
+
+    /// marks `ino` as just used while holding `size` bytes
+    pub fn touch(&mut self, ino: Inode, size: u64) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        self.remove(&ino);
+        self.tick += 1;
+        self.entries.insert(ino, (size, self.tick));
+        self.used.insert(self.tick, ino);
+        self.total += size;
+    }
+
+    /// marks `ino` as just used without changing its size, if it's tracked
+    pub fn access(&mut self, ino: Inode) {
+        if let Some(&(size, _)) = self.entries.get(&ino) {
+            self.touch(ino, size);
+        }
+    }
+
+    pub fn remove(&mut self, ino: &Inode) {
+        if let Some((size, used)) = self.entries.remove(ino) {
+            self.used.remove(&used);
+            self.total -= size;
+        }
+    }
+
+    /// the least recently used file other than `keep`, which stops being tracked
+    pub fn pop_lru(&mut self, keep: Inode) -> Option<Inode> {
+        let ino = *self.used.values().find(|ino| **ino != keep)?;
+        self.remove(&ino);
+        Some(ino)
+    }
+
+    /// moves every tracked file to its new inode in `renumbered`, keeping the order they were used
+    /// in, files missing from it stop being tracked
+    pub fn remap(&mut self, renumbered: &HashMap<Inode, Inode>) {
+        let entries = std::mem::take(&mut self.entries);
+        self.clear();
+        for (ino, (size, used)) in entries {
+            let Some(&ino) = renumbered.get(&ino) else {
+                continue;
+            };
+            self.entries.insert(ino, (size, used));
+            self.used.insert(used, ino);
+            self.total += size;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.used.clear();
+        self.total = 0;
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+/// where `Daniel` gets the time for every timestamp it sets
+pub trait Clock: std::fmt::Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+impl Default for Box<dyn Clock> {
+    fn default() -> Self {
+        Box::new(SystemClock)
+    }
+}
+
+/// the real time
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// a clock that only moves when told to, clones share the same time
+#[derive(Debug, Clone)]
+pub struct ManualClock(Arc<Mutex<SystemTime>>);
+
+impl ManualClock {
+    pub fn new(time: SystemTime) -> Self {
+        Self(Arc::new(Mutex::new(time)))
+    }
+
+    pub fn set(&self, time: SystemTime) {
+        *self.0.lock().unwrap() = time;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// how long `Relatime` lets an atime go stale before a read refreshes it anyway
+pub const RELATIME_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// when a read moves the atime of a file forward, like the mount options of the same names
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AtimePolicy {
+    /// every read
+    Strict,
+    /// only if the atime isn't newer than the mtime or ctime, or is older than
+    /// [`RELATIME_MAX_AGE`]
+    #[default]
+    Relatime,
+    /// never
+    Noatime,
+}
+
+impl AtimePolicy {
+    /// whether a read at `now` updates the atime of a file with `attr`
+    pub fn updates(&self, attr: &fuser::FileAttr, now: SystemTime) -> bool {
+        match self {
+            AtimePolicy::Strict => true,
+            AtimePolicy::Relatime => {
+                attr.atime <= attr.mtime
+                    || attr.atime <= attr.ctime
+                    || now
+                        .duration_since(attr.atime)
+                        .is_ok_and(|age| age >= RELATIME_MAX_AGE)
+            }
+            AtimePolicy::Noatime => false,
+        }
+    }
+}
@@ -1,27 +1,196 @@
 use std::{
-    ffi::c_int,
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    ffi::{OsStr, OsString, c_int},
+    io::{Read, Write},
     num::NonZero,
     ops::ControlFlow,
-    path::Path,
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+    sync::{Mutex, MutexGuard, PoisonError},
     time::{self, Duration},
 };
 
 use fuser::FileType;
-static EPERM: i32 = 1;
-static ENOENT: i32 = 2;
-static ENOSYS: i32 = 38;
-use tracing::{debug, error, info, instrument, warn};
 
-use crate::{filesystem::EntryType, unchecked_inode};
+const O_CREAT: i32 = libc::O_CREAT;
+const O_EXCL: i32 = libc::O_EXCL;
+const O_TRUNC: i32 = libc::O_TRUNC;
+const O_ACCMODE: i32 = libc::O_ACCMODE;
+const O_WRONLY: i32 = libc::O_WRONLY;
+const O_RDWR: i32 = libc::O_RDWR;
+const O_DIRECTORY: i32 = libc::O_DIRECTORY;
 
-use super::{DirEntry, DirList, Directory, FileAttribute, Inode, InodeMapper, file_types::File};
+/// the `access` mask bits, which line up with each triad of the permission bits
+static R_OK: i32 = libc::R_OK;
+static W_OK: i32 = libc::W_OK;
+static X_OK: i32 = libc::X_OK;
+
+static XATTR_CREATE: i32 = 1;
+static XATTR_REPLACE: i32 = 2;
+
+/// the capability that leaves applying the umask of `create` and `mkdir` to the filesystem
+static FUSE_DONT_MASK: u64 = 1 << 6;
+
+/// set in `write_flags` when the write comes from the kernel's writeback cache
+static FUSE_WRITE_CACHE: u32 = 1;
+
+/// set in the poll `flags` when the kernel wants to be notified once the file changes
+static FUSE_POLL_SCHEDULE_NOTIFY: u32 = 1;
+static POLLIN: u32 = libc::POLLIN as u32;
+static POLLOUT: u32 = libc::POLLOUT as u32;
+
+/// the fallocate mode that only reserves space, leaving the size alone
+static FALLOC_FL_KEEP_SIZE: i32 = libc::FALLOC_FL_KEEP_SIZE;
+
+/// the rename flag asking for a whiteout to be left where the source was
+static RENAME_WHITEOUT: u32 = libc::RENAME_WHITEOUT;
+
+/// `FS_IMMUTABLE_FL` in the attribute flags, the file can't be changed, removed or renamed
+static FS_IMMUTABLE_FL: u32 = 0x10;
+/// `FS_APPEND_FL` in the attribute flags, like `FS_IMMUTABLE_FL` except that the file may still be
+/// written at its end
+static FS_APPEND_FL: u32 = 0x20;
+
+/// the ioctls `chattr` and `lsattr` get and set the attribute flags with
+static FS_IOC_GETFLAGS: u32 = 0x8008_6601;
+static FS_IOC_SETFLAGS: u32 = 0x4008_6602;
+use tracing::{
+    Span, debug, error,
+    field::{self, Empty},
+    info, instrument, warn,
+};
+
+use crate::unchecked_inode;
+
+use super::{
+    AtimePolicy, AttrCache, AttrChanges, AttrObserver, AttrPatch, Child, Clock, Compressed,
+    ContentLru, DirEntry, DirList, Directory, EntryType,
+    Errno::{self, *},
+    F_UNLCK, FileAttribute, FileHandle, ImportPlan, Inode, InodeMapper, Journal, JournalEntry,
+    JournalOp, Lock, LockTable, MIME_XATTR, Metrics, Notification, NotifierSlot, OPAQUE_XATTR, Op,
+    OpCounters, OpenHandle, SharedDaniel, SharedOpCounters, SpaceUsage, StatFs, Status,
+    UnsupportedEntry, WriteBuffer, archive_path, file_type_from_mode,
+    file_types::File,
+    is_opaque, persist, sniff,
+};
 
 pub const ROOT_INODE: Inode = Inode::new(NonZero::new(1).unwrap());
 
+/// a read only file in the root whose contents are live stats about the mount as json
+pub const STATUS_FILE: &str = ".daniel-status";
+/// kept out of the range the mapper hands out
+pub const STATUS_INODE: Inode = Inode::new(NonZero::new(u64::MAX).unwrap());
+
+/// a read only directory in the root with a file for each of [`OpCounters::NAMES`] holding the
+/// count as text, see [`Daniel::with_metrics_dir`]
+pub const METRICS_DIR: &str = ".daniel-metrics";
+/// right below [`STATUS_INODE`], the counter files take the inodes below it in order
+pub const METRICS_INODE: Inode = Inode::new(NonZero::new(u64::MAX - 1).unwrap());
+/// the readdir cookie of [`METRICS_DIR`] in the root, after every stored entry
+const METRICS_COOKIE: u64 = i64::MAX as u64;
+
+/// the most a single read will ever return, whatever size is asked for. mounts pass it to the
+/// kernel as the `max_read` option so it never asks for more
+pub const MAX_READ: u32 = 1 << 20;
+
+/// how long the kernel may cache entries and attributes unless told otherwise
+pub const DEFAULT_TTL: Duration = Duration::from_secs(1);
+
+/// the listing of a directory as it was when the first of its open handles was opened, shared by
+/// all of them since they share their `fh`
+#[derive(Debug, Clone, Default)]
+struct DirSnapshot {
+    handles: usize,
+    entries: Vec<(u64, Inode, EntryType, PathBuf)>,
+}
+
+/// a copy of the tree taken by [`Daniel::checkpoint`], file contents are shared copy-on-write so
+/// taking one is cheap and later writes to either side don't affect the other
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    mapper: InodeMapper,
+    list: DirList,
+}
+
 #[derive(Debug, Default)]
 pub struct Daniel {
     mapper: InodeMapper,
     list: DirList,
+    locks: LockTable,
+    journal: Journal,
+    notifier: NotifierSlot,
+    /// invalidations waiting to be sent once the current request has been replied to
+    pending: Vec<Notification>,
+    /// the kernel leaves applying the umask to us, see [`Daniel::negotiate_dont_mask`]
+    dont_mask: bool,
+    /// how many bytes of sequential writes a handle may buffer, 0 disables buffering
+    write_buffer_capacity: usize,
+    /// the sequential writes each handle has buffered, by inode and `fh`
+    write_buffers: BTreeMap<(Inode, u64), WriteBuffer>,
+    attr_cache: AttrCache,
+    /// emit readdir entries sorted by name instead of in `entries` order
+    sort_dirents: bool,
+    ops: SharedOpCounters,
+    /// how long each op took, by `clock`
+    metrics: Metrics,
+    /// how many entries a single directory may hold, the status file doesn't count
+    max_entries_per_dir: Option<usize>,
+    /// bumped for every new entry so handles to a removed or reused inode go stale, entries
+    /// without one are generation 0
+    generations: BTreeMap<Inode, u32>,
+    next_generation: u32,
+    /// bumped for every handle handed out, so no two opens share an `fh`
+    next_handle: u64,
+    /// new files keep their contents compressed
+    compress: bool,
+    /// how many references to each inode the kernel holds, handed out by replies to lookup,
+    /// create, mkdir and the like and given back by forget. behind a mutex so lookups served
+    /// through a shared reference can count theirs
+    lookups: Mutex<BTreeMap<Inode, u64>>,
+    /// entries that were removed while the kernel still referenced them, they're freed once
+    /// they're forgotten
+    orphans: BTreeSet<Inode>,
+    /// every handle from [`Daniel::open`] and `opendir` by its `fh`, a removed entry lives on
+    /// until the handles to it are released
+    open_files: BTreeMap<u64, FileHandle>,
+    /// the capacity `statfs` reports, unlimited when unset
+    max_bytes: Option<u64>,
+    /// what's in use against `max_bytes`, buffered writes aside
+    usage: SpaceUsage,
+    max_inodes: Option<u64>,
+    on_attr_change: AttrObserver,
+    clock: Box<dyn Clock>,
+    /// file contents are dropped, least recently used first, once they take up more than the
+    /// budget
+    contents: ContentLru,
+    /// kernel poll handles waiting for a file to change, each is woken once
+    poll_handles: BTreeMap<Inode, BTreeSet<u64>>,
+    /// page readdir over the listing a directory had when it was opened
+    readdir_snapshots: bool,
+    /// the listings of the open directories, see [`Daniel::with_readdir_snapshots`]
+    dir_snapshots: BTreeMap<Inode, DirSnapshot>,
+    /// where fsync saves the tree to, see [`Daniel::save`]
+    snapshot_path: Option<PathBuf>,
+    /// mounted read only, every change is refused with `EROFS`
+    read_only: bool,
+    /// when reads update the atime
+    atime_policy: AtimePolicy,
+    /// leave overlayfs whiteouts out of readdir
+    hide_whiteouts: bool,
+    /// reading a directory returns the names in it, one per line, instead of `EISDIR`
+    dir_read_as_listing: bool,
+    /// how often a mount runs [`Daniel::sweep`] in the background
+    gc_interval: Option<Duration>,
+    /// serve [`METRICS_DIR`]
+    metrics_dir: bool,
+    /// how long replies may be cached for without an override, [`DEFAULT_TTL`] when unset
+    ttl: Option<Duration>,
+    /// refuse new names that aren't UTF-8
+    require_utf8_names: bool,
+    /// files moved in here are written out to `export_path` instead, see [`Daniel::set_export_dir`]
+    export_dir: Option<Inode>,
+    export_path: Option<PathBuf>,
 }
 
 impl Daniel {
@@ -29,918 +198,8747 @@ impl Daniel {
         Self::default()
     }
 
-    pub fn push(&mut self, item: DirEntry) {
-        let (parent, name, ino) = match &item {
-            DirEntry::Directory(dir) => {
-                let name = dir.name();
-                let parent = dir.parent();
-                (parent, name, dir.attr().inner().ino)
-            }
-            DirEntry::File(file) => {
-                let name = file.name();
-                let parent = file.parent();
-                (parent, name, file.attr().inner().ino)
-            }
-        };
-
-        let ino = unchecked_inode!(ino);
-        self.mapper.insert(parent, name, ino);
+    /// keeps the last `capacity` mutations in a journal readable through [`Daniel::journal`]
+    pub fn with_journal(mut self, capacity: usize) -> Self {
+        self.journal = Journal::new(capacity);
+        self
+    }
 
-        self.list
+    /// sets the owner and permissions of the root directory, eg. to the mounting user so
+    /// `allow_other` isn't needed
+    pub fn with_root(mut self, uid: u32, gid: u32, perms: u16) -> Self {
+        let attr = self
+            .list
             .map_mut()
-            .get_mut(&parent)
-            .expect("failed to get dir")
-            .directory_mut()
-            .insert(
-                ino,
-                item.kind()
-                    .try_into()
-                    .expect("failed to convert file type into EntryType"),
-            );
+            .get_mut(&ROOT_INODE)
+            .expect("root always exists")
+            .attr_mut()
+            .inner_mut();
+        attr.uid = uid;
+        attr.gid = gid;
+        attr.perm = perms;
 
-        self.list.map_mut().insert(ino, item);
+        self
     }
 
-    pub fn create(
-        &mut self,
-        parent: Inode,
-        path: impl AsRef<Path>,
-        _mode: u16,
-        perms: u16,
-    ) -> FileAttribute {
-        let inode = self.mapper.next_inode();
-        self.push(DirEntry::File(File::new(
-            path.as_ref().to_path_buf(),
-            parent,
-            inode,
-            perms,
-        )));
+    pub fn journal(&self) -> &[JournalEntry] {
+        self.journal.entries()
+    }
 
-        self.list
-            .map()
-            .get(&inode)
-            .expect("failed to get entry that was just pushed")
-            .file()
-            .attr()
+    /// coalesces sequential writes on the same handle into a buffer of up to `capacity` bytes
+    pub fn with_write_buffer(mut self, capacity: usize) -> Self {
+        self.write_buffer_capacity = capacity;
+        self
     }
 
-    fn mkdir(
-        &mut self,
-        parent: u64,
-        name: &std::ffi::OsStr,
-        mode: u32,
-        umask: u32,
-    ) -> FileAttribute {
-        let inode = self.mapper.next_inode();
-        self.push(DirEntry::Directory(Directory::new(
-            unchecked_inode!(parent),
-            name.into(),
-            inode,
-            0o755,
-        )));
+    /// keeps the attributes of the last `capacity` inodes passed to `getattr` until they change
+    pub fn with_attr_cache(mut self, capacity: usize) -> Self {
+        self.attr_cache = AttrCache::new(capacity);
+        self
+    }
 
-        self.list
-            .map()
-            .get(&inode)
-            .expect("failed to get entry that was just pushed")
-            .directory()
-            .attr()
+    /// lists children by name instead of the order they were added in. the readdir cookies are
+    /// then positions in that order, which only stay put over the snapshot an open directory
+    /// handle gets, like with [`Daniel::with_readdir_snapshots`]
+    pub fn with_sort_dirents(mut self, sort_dirents: bool) -> Self {
+        self.sort_dirents = sort_dirents;
+        self
     }
 
-    pub fn readdir(&self, ino: u64, _fh: u64, offset: u64) -> ControlFlow<(), &DirEntry> {
-        let Some(entry) = self.list.map().get(&unchecked_inode!(ino)) else {
-            return ControlFlow::Break(());
-        };
+    /// stores the contents of files created from now on compressed, see [`Compressed`]
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
 
-        match entry {
-            DirEntry::Directory(_directory) => {
-                let idx = offset.max(1);
-                let Some(entry) = self.list.map().get(&unchecked_inode!(idx)) else {
-                    return ControlFlow::Break(());
-                };
+    /// refuses every change to the tree with `EROFS`, whatever the permissions of the entries
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
 
-                ControlFlow::Continue(entry)
-            }
-            DirEntry::File(_file) => ControlFlow::Break(()),
-        }
+    pub fn with_atime_policy(mut self, policy: AtimePolicy) -> Self {
+        self.atime_policy = policy;
+        self
     }
 
-    pub fn lookup(&mut self, parent: u64, name: &std::ffi::OsStr) -> Result<FileAttribute, ()> {
-        for (ino, _) in self
-            .list
-            .map()
-            .get(&unchecked_inode!(parent))
-            .expect("failed to get dir")
-            .directory()
-            .entries()
-            .iter()
-        {
-            let (path, attr) = match self.list.map().get(&ino).expect("invalid entry in dir") {
-                DirEntry::Directory(directory) => (directory.name(), directory.attr()),
-                DirEntry::File(file) => (file.name(), file.attr()),
-            };
+    /// lists directories without their whiteouts, they can still be looked up by name
+    pub fn with_hide_whiteouts(mut self, hide: bool) -> Self {
+        self.hide_whiteouts = hide;
+        self
+    }
 
-            if path == name {
-                return Ok(attr);
+    /// [`Daniel::read`] on a directory returns the names in it, one per line, for callers that
+    /// can read but not list. the kernel answers `read(2)` on a directory itself, so a mount never
+    /// gets here
+    pub fn with_dir_read_as_listing(mut self, enabled: bool) -> Self {
+        self.dir_read_as_listing = enabled;
+        self
+    }
+
+    /// once mounted, runs [`Daniel::sweep`] on a thread of its own every `interval`. the mount
+    /// goes through a [`SharedDaniel`] so the sweeps take turns with requests
+    pub fn with_gc_interval(mut self, interval: Duration) -> Self {
+        self.gc_interval = Some(interval);
+        self
+    }
+
+    pub fn gc_interval(&self) -> Option<Duration> {
+        self.gc_interval
+    }
+
+    /// readdir pages over the listing a directory had when it was opened, so entries added or
+    /// removed while it's being read neither show up nor go missing. the listing is dropped once
+    /// every handle to the directory is released
+    pub fn with_readdir_snapshots(mut self, enabled: bool) -> Self {
+        self.readdir_snapshots = enabled;
+        self
+    }
+
+    /// how long the kernel may cache entries and attributes without an override instead of
+    /// [`DEFAULT_TTL`], see [`Daniel::set_ttl`]
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// refuses to create, link or rename anything to a name that isn't valid UTF-8 with `EINVAL`,
+    /// by default a name may be any bytes
+    pub fn with_require_utf8_names(mut self, require: bool) -> Self {
+        self.require_utf8_names = require;
+        self
+    }
+
+    /// serves [`METRICS_DIR`] in the root for textfile collectors to scrape. it and its files are
+    /// made up on every request instead of being stored, so they don't count against any limit and
+    /// shadow a stored entry of the same name
+    pub fn with_metrics_dir(mut self, enabled: bool) -> Self {
+        self.metrics_dir = enabled;
+        self
+    }
+
+    /// new entries get inodes from `base` on, the ones between the root and it are left for
+    /// whatever else reserves them, see [`InodeMapper::with_base`]
+    pub fn with_inode_base(mut self, base: Inode) -> Self {
+        self.mapper = std::mem::take(&mut self.mapper).with_base(base);
+        self
+    }
+
+    pub fn with_max_entries_per_dir(mut self, max: usize) -> Self {
+        self.max_entries_per_dir = Some(max);
+        self
+    }
+
+    /// takes every timestamp from `clock` instead of the system time, the root and the status file
+    /// are restamped with its current time
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        for ino in [ROOT_INODE, STATUS_INODE] {
+            if let Some(entry) = self.list.map_mut().get_mut(&ino) {
+                stamp(entry, self.clock.now());
             }
         }
 
-        Err(())
+        self
     }
 
-    pub fn access(&mut self, ino: u64, mask: i32) -> Result<(), ()> {
-        match self.list.map().get(&unchecked_inode!(ino)) {
-            Some(_) => Ok(()),
-            None => Err(()),
-        }
+    /// keeps file contents under `budget` bytes, writes past it drop the contents of the least
+    /// recently used other files, see [`File::evict`]
+    pub fn with_content_budget(mut self, budget: u64) -> Self {
+        self.contents = ContentLru::new(budget);
+        self
     }
 
-    pub fn getattr(&mut self, ino: u64, fh: Option<u64>) -> &FileAttribute {
-        self.list
-            .map()
-            .get(&unchecked_inode!(ino))
-            .expect("failed to find ino in backing fs")
-            .attr()
+    /// makes fsync save the whole tree to `path`
+    pub fn with_snapshot_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.snapshot_path = Some(path.into());
+        self
     }
 
-    pub fn unlink(&mut self, parent: u64, name: &std::ffi::OsStr) -> Result<(), ()> {
-        let ino = self
-            .mapper
-            .get_map(unchecked_inode!(parent), name)
-            .expect("failed to find inode");
-        self.list.map_mut().remove(ino);
-        self.mapper.remove(unchecked_inode!(parent), name);
+    /// a regular file renamed into the directory `dir` is written to the host directory `path`
+    /// under its new name and removed from the tree, `ENOTDIR` if `dir` isn't a directory
+    pub fn set_export_dir(&mut self, dir: Inode, path: impl Into<PathBuf>) -> Result<(), Errno> {
+        self.directory(dir)?;
+        self.export_dir = Some(dir);
+        self.export_path = Some(path.into());
 
         Ok(())
     }
-}
 
-impl std::fmt::Display for Daniel {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.mapper)?;
-        write!(f, "{}", self.list)?;
-
-        Ok(())
+    /// calls `callback` after every chmod or chown with the inode and its new attributes
+    pub fn with_on_attr_change(
+        mut self,
+        callback: impl FnMut(Inode, &fuser::FileAttr) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_attr_change = AttrObserver::new(callback);
+        self
     }
-}
 
-impl fuser::Filesystem for Daniel {
-    #[instrument(skip(self, _req, reply))]
-    fn create(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        parent: u64,
-        name: &std::ffi::OsStr,
-        mode: u32,
-        umask: u32,
-        flags: i32,
-        reply: fuser::ReplyCreate,
-    ) {
-        reply.created(
-            &Duration::from_secs(1),
-            &self
-                .create(unchecked_inode!(parent), name, mode as u16, flags as u16)
-                .inner(),
-            0,
-            0,
-            flags as u32,
-        );
+    /// the byte quota `statfs` reports the free space against, writes that would go over it fail
+    /// with `ENOSPC`. see [`SpaceUsage`] for what counts
+    pub fn with_max_bytes(mut self, max: u64) -> Self {
+        self.max_bytes = Some(max);
+        self
     }
 
-    #[instrument(skip(self, _req, reply))]
-    fn mkdir(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        parent: u64,
-        name: &std::ffi::OsStr,
-        mode: u32,
-        umask: u32,
-        reply: fuser::ReplyEntry,
-    ) {
-        reply.entry(
-            &Duration::from_secs(1),
-            &self.mkdir(parent, name, mode, umask).inner(),
-            0,
-        );
+    /// the inode limit `statfs` reports the free inodes against
+    pub fn with_max_inodes(mut self, max: u64) -> Self {
+        self.max_inodes = Some(max);
+        self
     }
 
-    #[instrument(skip(self, _req, reply))]
-    fn readdir(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        fh: u64,
-        offset: i64,
-        mut reply: fuser::ReplyDirectory,
-    ) {
-        info!(?offset);
-        if offset > 50 {
-            panic!()
-        }
+    /// the latency histograms of each op
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
 
-        if offset == 0 {
-            _ = reply.add(1, 1, FileType::Directory, ".");
-        } else if offset == 1 {
-            _ = reply.add(1, 2, FileType::Directory, "..");
+    /// the counter of [`OpCounters::NAMES`] `ino` is the file of in [`METRICS_DIR`]
+    fn metric_file(&self, ino: Inode) -> Option<&'static str> {
+        if !self.metrics_dir {
+            return None;
         }
+        let below = u64::from(METRICS_INODE).checked_sub(ino.into())?;
+        let index = usize::try_from(below.checked_sub(1)?).ok()?;
+        OpCounters::NAMES.get(index).copied()
+    }
 
-        let dir = self
-            .list
-            .map()
-            .get(&unchecked_inode!(ino))
-            .expect("failed to get dir in readdir");
-        for (i, (ino, kind)) in dir
-            .directory()
-            .entries()
-            .iter()
-            .enumerate()
-            .skip(offset as usize)
-        {
-            let Some(entry) = self.list.map().get(ino) else {
-                reply.error(ENOENT);
-                return;
-            };
-
-            let name = match entry {
-                DirEntry::Directory(directory) => directory.name(),
-                DirEntry::File(file) => file.name(),
-            };
+    fn metric_inode(index: usize) -> Inode {
+        unchecked_inode!(u64::from(METRICS_INODE) - 1 - index as u64)
+    }
 
-            info!(?offset, ?name);
-            let kind = kind.clone();
-            if reply.add(ino.into(), (i + 1) as i64, kind.into(), name) {
-                error!("early return");
-                break;
-            }
-        }
+    /// whether `ino` is [`METRICS_DIR`] or one of its files
+    fn is_virtual(&self, ino: Inode) -> bool {
+        (self.metrics_dir && ino == METRICS_INODE) || self.metric_file(ino).is_some()
+    }
 
-        reply.ok();
+    /// what the file of the counter `name` reads as
+    fn metric_contents(&self, name: &str) -> Vec<u8> {
+        let count = self.ops.load().get(name).unwrap_or_default();
+        format!("{count}\n").into_bytes()
     }
 
-    #[instrument(skip(self, _req, reply))]
-    fn lookup(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        parent: u64,
-        name: &std::ffi::OsStr,
-        reply: fuser::ReplyEntry,
-    ) {
-        let Ok(attr) = self.lookup(parent, name) else {
-            reply.error(ENOENT);
-            return;
+    /// the attributes of [`METRICS_DIR`] or one of its files, they're dated to when the root was
+    /// created
+    fn virtual_attr(&self, ino: Inode) -> Option<FileAttribute> {
+        let (kind, perm, size, nlink) = match self.metric_file(ino) {
+            Some(name) => (
+                FileType::RegularFile,
+                0o444,
+                self.metric_contents(name).len(),
+                1,
+            ),
+            None if self.is_virtual(ino) => (FileType::Directory, 0o555, 0, 2),
+            None => return None,
         };
-        reply.entry(&Duration::from_secs(1), &attr.inner(), 0);
+
+        let created = self.list.map()[&ROOT_INODE].attr().inner().crtime;
+        let mut attr = FileAttribute::new(ino.into(), kind, perm);
+        attr.set_size(size as u64);
+        let inner = attr.inner_mut();
+        inner.nlink = nlink;
+        (inner.atime, inner.mtime, inner.ctime, inner.crtime) =
+            (created, created, created, created);
+        Some(attr)
     }
 
-    #[instrument(skip(self, _req, reply))]
-    fn access(&mut self, _req: &fuser::Request<'_>, ino: u64, mask: i32, reply: fuser::ReplyEmpty) {
-        let res = self.access(ino, mask);
-        match res {
-            Ok(()) => reply.ok(),
-            Err(()) => reply.error(ENOENT),
+    /// [`Daniel::lookup`] of [`METRICS_DIR`] or one of its files, `None` for any other name
+    fn lookup_virtual(
+        &self,
+        parent: Inode,
+        name: &std::ffi::OsStr,
+    ) -> Option<Result<FileAttribute, Errno>> {
+        if !self.metrics_dir {
+            return None;
+        }
+        if parent == ROOT_INODE && name == METRICS_DIR {
+            return self.virtual_attr(METRICS_INODE).map(Ok);
+        }
+        if parent != METRICS_INODE {
+            return None;
         }
+
+        let index = OpCounters::NAMES
+            .iter()
+            .position(|counter| name == *counter);
+        Some(
+            index
+                .and_then(|index| self.virtual_attr(Self::metric_inode(index)))
+                .ok_or(ENOENT),
+        )
     }
 
-    #[instrument(skip(self, _req, reply))]
-    fn getattr(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        fh: Option<u64>,
-        reply: fuser::ReplyAttr,
-    ) {
-        reply.attr(&Duration::from_secs(1), &self.getattr(ino, fh).inner());
+    /// runs `f` and records how long it took by the injected clock under `op`
+    fn timed<T>(&mut self, op: Op, f: impl FnOnce(&mut Self) -> T) -> T {
+        let start = self.clock.now();
+        let out = f(self);
+        self.record_latency(op, start);
+        out
     }
 
-    fn unlink(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        parent: u64,
-        name: &std::ffi::OsStr,
-        reply: fuser::ReplyEmpty,
-    ) {
-        let res = self.unlink(parent, name);
-        match res {
-            Ok(()) => reply.ok(),
-            Err(()) => reply.error(ENOENT),
-        }
+    fn record_latency(&self, op: Op, start: time::SystemTime) {
+        let took = self.clock.now().duration_since(start).unwrap_or_default();
+        self.metrics.record(op, took);
     }
 
-    fn init(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        _config: &mut fuser::KernelConfig,
-    ) -> Result<(), c_int> {
-        Ok(())
+    /// the stats served by the status file, which itself isn't counted
+    pub fn status(&self) -> Status {
+        let inodes = self
+            .list
+            .map()
+            .keys()
+            .filter(|ino| **ino != STATUS_INODE)
+            .count();
+
+        // the root is created along with the filesystem
+        let mounted = self.list.map()[&ROOT_INODE].attr().inner().crtime;
+        let uptime_secs = self
+            .clock
+            .now()
+            .duration_since(mounted)
+            .unwrap_or_default()
+            .as_secs();
+
+        Status {
+            inodes,
+            used_bytes: self.used_bytes(),
+            uptime_secs,
+            ops: self.ops.load(),
+        }
     }
 
-    fn destroy(&mut self) {}
+    /// the space in use, see [`SpaceUsage`], and what the buffered writes take once applied
+    fn used_bytes(&self) -> u64 {
+        let buffered: u64 = self
+            .write_buffers
+            .iter()
+            .map(|((ino, _), buf)| self.growth(*ino, buf.offset(), buf.end()))
+            .sum();
 
-    fn forget(&mut self, _req: &fuser::Request<'_>, _ino: u64, _nlookup: u64) {}
+        self.usage.total() + buffered
+    }
 
-    fn setattr(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        _mode: Option<u32>,
-        _uid: Option<u32>,
-        _gid: Option<u32>,
-        size: Option<u64>,
-        atime: Option<fuser::TimeOrNow>,
-        mtime: Option<fuser::TimeOrNow>,
-        ctime: Option<std::time::SystemTime>,
-        fh: Option<u64>,
-        _crtime: Option<std::time::SystemTime>,
-        _chgtime: Option<std::time::SystemTime>,
-        _bkuptime: Option<std::time::SystemTime>,
-        flags: Option<u32>,
-        reply: fuser::ReplyAttr,
-    ) {
-        let Some(entry) = self.list.map_mut().get_mut(&unchecked_inode!(ino)) else {
-            reply.error(ENOENT);
-            return;
-        };
-        let attr = entry.attr_mut().inner_mut();
-        if let Some(size) = size {
-            attr.size = size
+    /// at most how many more bytes are in use once `offset..end` of `ino` is written, an inode that
+    /// isn't in use yet counts as empty. growing into what fallocate reserved takes nothing more
+    fn growth(&self, ino: Inode, offset: u64, end: u64) -> u64 {
+        let Ok(file) = self.file(ino) else {
+            return end;
         };
-
-        if let Some(time) = atime {
-            attr.atime = match time {
-                fuser::TimeOrNow::SpecificTime(system_time) => system_time,
-                fuser::TimeOrNow::Now => time::SystemTime::now(),
-            }
+        let storage = file.storage();
+        let growth = match self.usage.is_shared(storage) {
+            // the write copies the shared contents
+            true => end.max(storage.len()),
+            false => file.growth(offset, end),
         };
+        let reserved = self
+            .usage
+            .reserved_past(ino, storage.len())
+            .min(end.saturating_sub(storage.len()));
 
-        if let Some(time) = mtime {
-            attr.mtime = match time {
-                fuser::TimeOrNow::SpecificTime(system_time) => system_time,
-                fuser::TimeOrNow::Now => time::SystemTime::now(),
-            }
-        };
+        growth.saturating_sub(reserved)
+    }
 
-        if let Some(time) = ctime {
-            attr.ctime = time
+    /// `ENOSPC` if writing `offset..end` of `ino` could go over the byte quota. it runs under the
+    /// same `&mut self` as the growth it's checking for, so nothing else can take the space in
+    /// between
+    fn reserve(&self, ino: Inode, offset: u64, end: u64) -> Result<(), Errno> {
+        let Some(max) = self.max_bytes else {
+            return Ok(());
         };
+        let growth = self.growth(ino, offset, end);
+        if growth > 0 && self.used_bytes().saturating_add(growth) > max {
+            return Err(ENOSPC);
+        }
 
-        reply.attr(&Duration::from_secs(1), attr);
+        Ok(())
     }
 
-    fn readlink(&mut self, _req: &fuser::Request<'_>, ino: u64, reply: fuser::ReplyData) {
-        debug!("[Not Implemented] readlink(ino: {:#x?})", ino);
-        reply.error(ENOSYS);
+    /// `ENOSPC` if what's in use, buffered writes included, is over the byte quota
+    fn check_space(&self) -> Result<(), Errno> {
+        match self.max_bytes.is_some_and(|max| self.used_bytes() > max) {
+            true => Err(ENOSPC),
+            false => Ok(()),
+        }
     }
 
-    fn mknod(
+    /// runs `f` on the file at `ino` with its contents taken out of the space usage, then counts
+    /// them again
+    fn update_contents<R>(
         &mut self,
-        _req: &fuser::Request<'_>,
-        parent: u64,
-        name: &std::ffi::OsStr,
-        mode: u32,
-        umask: u32,
-        rdev: u32,
-        reply: fuser::ReplyEntry,
-    ) {
-        debug!(
-            "[Not Implemented] mknod(parent: {:#x?}, name: {:?}, mode: {}, \
-            umask: {:#x?}, rdev: {})",
-            parent, name, mode, umask, rdev
-        );
-        reply.error(ENOSYS);
+        ino: Inode,
+        f: impl FnOnce(&mut File) -> R,
+    ) -> Result<R, Errno> {
+        let file = self
+            .list
+            .map_mut()
+            .get_mut(&ino)
+            .ok_or(ENOENT)?
+            .as_file_mut()
+            .ok_or(EISDIR)?;
+        self.usage.uncount(ino, file.storage());
+        let out = f(file);
+        self.usage.count(ino, file.storage());
+
+        Ok(out)
     }
 
-    fn rmdir(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        parent: u64,
-        name: &std::ffi::OsStr,
-        reply: fuser::ReplyEmpty,
-    ) {
-        debug!(
-            "[Not Implemented] rmdir(parent: {:#x?}, name: {:?})",
-            parent, name,
-        );
-        reply.error(ENOSYS);
+    /// counts every file from scratch, for when the whole tree was swapped out
+    fn recount(&mut self) {
+        self.usage = SpaceUsage::default();
+        for (ino, entry) in self.list.map() {
+            if let Some(file) = entry.as_file()
+                && *ino != STATUS_INODE
+            {
+                self.usage.count(*ino, file.storage());
+            }
+        }
     }
 
-    fn symlink(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        parent: u64,
-        link_name: &std::ffi::OsStr,
-        target: &Path,
-        reply: fuser::ReplyEntry,
-    ) {
-        debug!(
-            "[Not Implemented] symlink(parent: {:#x?}, link_name: {:?}, target: {:?})",
-            parent, link_name, target,
-        );
-        reply.error(EPERM);
+    /// `ENOMEM` if the memory for writing `ino` at `offset` up to `end` can't be had, checked
+    /// before anything is changed
+    fn allocate(&mut self, ino: Inode, offset: u64, end: u64) -> Result<(), Errno> {
+        // reserving memory for shared contents copies them
+        self.update_contents(ino, |file| file.try_reserve(offset, end))?
+            .map_err(|err| {
+                warn!(%err, ino = u64::from(ino), end, "out of memory for the contents");
+                ENOMEM
+            })
     }
 
-    fn rename(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        parent: u64,
-        name: &std::ffi::OsStr,
-        newparent: u64,
-        newname: &std::ffi::OsStr,
-        flags: u32,
-        reply: fuser::ReplyEmpty,
-    ) {
-        debug!(
-            "[Not Implemented] rename(parent: {:#x?}, name: {:?}, newparent: {:#x?}, \
-            newname: {:?}, flags: {})",
-            parent, name, newparent, newname, flags,
-        );
-        reply.error(ENOSYS);
+    pub fn statfs(&mut self, _ino: u64) -> StatFs {
+        self.flush_writes();
+        StatFs::new(&self.status(), self.max_bytes, self.max_inodes)
     }
 
-    fn link(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        newparent: u64,
-        newname: &std::ffi::OsStr,
-        reply: fuser::ReplyEntry,
-    ) {
-        debug!(
-            "[Not Implemented] link(ino: {:#x?}, newparent: {:#x?}, newname: {:?})",
-            ino, newparent, newname
+    /// what importing the host directory `root` would take and whether it fits the limits, nothing
+    /// is created
+    pub fn import_dry_run(&self, root: &Path) -> std::io::Result<ImportPlan> {
+        let mut plan = ImportPlan::scan(root, self.max_entries_per_dir)?;
+        let status = self.status();
+        plan.check_capacity(
+            self.max_bytes
+                .map(|max| max.saturating_sub(status.used_bytes)),
+            self.max_inodes
+                .map(|max| max.saturating_sub(status.inodes as u64)),
         );
-        reply.error(EPERM);
+
+        Ok(plan)
     }
 
-    fn open(&mut self, _req: &fuser::Request<'_>, _ino: u64, _flags: i32, reply: fuser::ReplyOpen) {
-        reply.opened(0, 0);
+    /// writes the tree to `path` in the format of [`persist::encode`]. the new file is synced
+    /// before it replaces the old one and the directory is synced after, so a crash leaves either
+    /// the old or the new tree. orphans and the status file aren't saved
+    pub fn save(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        self.flush_writes();
+
+        let mut entries = Vec::new();
+        let mut links = Vec::new();
+        let mut queue = VecDeque::from([ROOT_INODE]);
+        while let Some(ino) = queue.pop_front() {
+            let entry = &self.list.map()[&ino];
+            entries.push(entry);
+            if let Some(dir) = entry.as_directory() {
+                // a hard linked file is saved under the name its entry has, the others are links
+                let mut children = Vec::new();
+                for (name, child) in dir.entries() {
+                    if child.ino == STATUS_INODE {
+                        continue;
+                    }
+                    let entry = &self.list.map()[&child.ino];
+                    match entry.parent() == ino && entry.name() == name.as_path() {
+                        true => children.push(child.ino),
+                        false => links.push(persist::Link {
+                            ino: child.ino,
+                            parent: ino,
+                            name: name.clone(),
+                        }),
+                    }
+                }
+                children.sort();
+                queue.extend(children);
+            }
+        }
+        links.sort_by(|a, b| (a.parent, &a.name).cmp(&(b.parent, &b.name)));
+
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_os_string();
+        tmp.push(".tmp");
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(&persist::encode(entries.into_iter(), &links))?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        std::fs::File::open(dir)?.sync_all()
     }
 
-    fn read(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        fh: u64,
-        offset: i64,
-        size: u32,
-        flags: i32,
-        lock_owner: Option<u64>,
-        reply: fuser::ReplyData,
-    ) {
-        warn!(
-            "[Not Implemented] read(ino: {:#x?}, fh: {}, offset: {}, size: {}, \
-            flags: {:#x?}, lock_owner: {:?})",
-            ino, fh, offset, size, flags, lock_owner
-        );
-        reply.error(ENOSYS);
+    /// a tree saved by [`Daniel::save`], entries are given new inodes in the order they were saved
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let saved = persist::decode(&std::fs::read(path)?)?;
+        let mut entries = saved.entries.into_iter();
+        let mut fs = Self::new();
+
+        let Some(saved_root @ DirEntry::Directory(_)) = entries.next() else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "snapshot doesn't start with the root directory",
+            ));
+        };
+        let root = fs
+            .list
+            .map_mut()
+            .get_mut(&ROOT_INODE)
+            .expect("root always exists");
+        let mut attr = saved_root.attr().inner();
+        attr.ino = ROOT_INODE.into();
+        *root.attr_mut().inner_mut() = attr;
+        *root.xattrs_mut() = saved_root.xattrs().clone();
+        if let (Some(root), Some(saved_root)) = (root.as_directory_mut(), saved_root.as_directory())
+        {
+            root.set_default_child_mode(saved_root.default_child_mode());
+        }
+
+        let mut renumbered = HashMap::from([(saved_root.attr().inner().ino, ROOT_INODE)]);
+        for mut entry in entries {
+            let Some(&parent) = renumbered.get(&u64::from(entry.parent())) else {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "entry saved before its parent",
+                ));
+            };
+            let ino = fs.mapper.next_inode();
+            renumbered.insert(entry.attr().inner().ino, ino);
+            entry.attr_mut().inner_mut().ino = ino.into();
+            let name = entry.name().to_path_buf();
+            entry.rename(parent, name);
+            fs.push(entry).map_err(std::io::Error::other)?;
+        }
+
+        // the other names of hard linked files, entries are loaded with one
+        for link in saved.links {
+            let (Some(&ino), Some(&parent)) = (
+                renumbered.get(&u64::from(link.ino)),
+                renumbered.get(&u64::from(link.parent)),
+            ) else {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "link to an entry that wasn't saved",
+                ));
+            };
+            if fs.directory(ino).is_ok() || fs.mapper.get_map(parent, &link.name).is_some() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "link to a directory or over an existing name",
+                ));
+            }
+            let kind = fs.list.map()[&ino].entry_type();
+            fs.directory_mut(parent)
+                .map_err(std::io::Error::other)?
+                .push(ino, kind, &link.name);
+            fs.mapper.link(parent, &link.name, ino);
+            if let Some(entry) = fs.list.map_mut().get_mut(&ino) {
+                entry.attr_mut().inner_mut().nlink += 1;
+            }
+        }
+
+        Ok(fs)
     }
 
-    fn write(
+    /// a tree read from the tar archive in `reader`, entries keep their modes and mtimes. what
+    /// happens to entries a tree can't hold is up to `unsupported`
+    pub fn from_tar(reader: impl Read, unsupported: UnsupportedEntry) -> std::io::Result<Self> {
+        let mut fs = Self::new();
+        let mut dirs = Vec::new();
+        for entry in tar::Archive::new(reader).entries()? {
+            let mut entry = entry?;
+            let path = archive_path(&entry.path()?).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "entry outside of the archive",
+                )
+            })?;
+            let header = entry.header();
+            let kind = header.entry_type();
+            let patch = AttrPatch {
+                mode: Some(header.mode()?),
+                mtime: Some(time::UNIX_EPOCH + Duration::from_secs(header.mtime()?)),
+                ..Default::default()
+            };
+            // only devices fill in the device numbers, other entries may leave them blank
+            let rdev = match kind {
+                tar::EntryType::Char | tar::EntryType::Block => {
+                    match (header.device_major()?, header.device_minor()?) {
+                        (Some(major), Some(minor)) => libc::makedev(major, minor) as u32,
+                        _ => 0,
+                    }
+                }
+                _ => 0,
+            };
+            let target = entry.link_name()?.map(Cow::into_owned);
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+
+            let extracted = fs
+                .extract(&path, kind, rdev, target.as_deref(), &data)
+                .map_err(std::io::Error::other)?;
+            match extracted {
+                Some(ino) if kind == tar::EntryType::Directory => dirs.push((ino, patch)),
+                Some(ino) => {
+                    fs.setattr(ino.into(), patch, 0, 0)
+                        .map_err(std::io::Error::other)?;
+                }
+                None if unsupported == UnsupportedEntry::Skip => {
+                    warn!(path = %path.display(), ?kind, "skipping unsupported archive entry");
+                }
+                None => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::Unsupported,
+                        format!("{} is an unsupported {kind:?} entry", path.display()),
+                    ));
+                }
+            }
+        }
+        // adding entries to a directory changes its mtime, so directories are done last
+        for (ino, patch) in dirs {
+            fs.setattr(ino.into(), patch, 0, 0)
+                .map_err(std::io::Error::other)?;
+        }
+
+        Ok(fs)
+    }
+
+    /// creates the archive entry at `path`, replacing whatever is there unless both are
+    /// directories. `None` if it's of a kind a tree can't hold
+    fn extract(
         &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        fh: u64,
-        offset: i64,
+        path: &Path,
+        kind: tar::EntryType,
+        rdev: u32,
+        target: Option<&Path>,
         data: &[u8],
-        write_flags: u32,
-        flags: i32,
-        lock_owner: Option<u64>,
-        reply: fuser::ReplyWrite,
-    ) {
-        debug!(
-            "[Not Implemented] write(ino: {:#x?}, fh: {}, offset: {}, data.len(): {}, \
-            write_flags: {:#x?}, flags: {:#x?}, lock_owner: {:?})",
-            ino,
-            fh,
-            offset,
-            data.len(),
-            write_flags,
-            flags,
-            lock_owner
+    ) -> Result<Option<Inode>, Errno> {
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            // the archive's own root
+            return Ok(Some(ROOT_INODE));
+        };
+        if kind == tar::EntryType::Directory {
+            return self.extract_dirs(path).map(Some);
+        }
+        let node = match kind {
+            tar::EntryType::Char => Some(libc::S_IFCHR),
+            tar::EntryType::Block => Some(libc::S_IFBLK),
+            tar::EntryType::Fifo => Some(libc::S_IFIFO),
+            _ => None,
+        };
+        let regular = matches!(
+            kind,
+            tar::EntryType::Regular | tar::EntryType::Continuous | tar::EntryType::GNUSparse
         );
-        reply.error(ENOSYS);
+        let link = matches!(kind, tar::EntryType::Symlink | tar::EntryType::Link);
+        if !regular && !link && node.is_none() {
+            return Ok(None);
+        }
+
+        let parent = self.extract_dirs(parent)?;
+        if self.mapper.get_map(parent, name).is_some() {
+            self.remove_recursive(parent, name, 0)?;
+        }
+        let attr = match kind {
+            tar::EntryType::Symlink => {
+                self.symlink(parent.into(), name, target.ok_or(EINVAL)?, 0, 0)?
+            }
+            tar::EntryType::Link => {
+                // hard link targets are paths in the archive, not relative to the link
+                let target = archive_path(target.ok_or(EINVAL)?).ok_or(EINVAL)?;
+                let ino = *self
+                    .mapper
+                    .get_path(Path::new("/").join(target))
+                    .ok_or(ENOENT)?;
+                self.link(ino.into(), parent.into(), name)?
+            }
+            _ if regular => {
+                let attr = self.create(parent, name, 0, 0o600)?;
+                self.write(attr.inner().ino, 0, data)?;
+                attr
+            }
+            _ => self.mknod(parent.into(), name, node.unwrap_or_default(), rdev, 0, 0)?,
+        };
+
+        Ok(Some(unchecked_inode!(attr.inner().ino)))
     }
 
-    fn flush(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        fh: u64,
-        lock_owner: u64,
-        reply: fuser::ReplyEmpty,
-    ) {
-        debug!(
-            "[Not Implemented] flush(ino: {:#x?}, fh: {}, lock_owner: {:?})",
-            ino, fh, lock_owner
-        );
-        reply.error(ENOSYS);
+    /// the directory at `path` below the root, made along with any parents it's missing
+    fn extract_dirs(&mut self, path: &Path) -> Result<Inode, Errno> {
+        let mut dir = ROOT_INODE;
+        for name in path {
+            dir = match self.mapper.get_map(dir, name).copied() {
+                Some(ino) if self.directory(ino).is_ok() => ino,
+                existing => {
+                    if existing.is_some() {
+                        self.remove_recursive(dir, name, 0)?;
+                    }
+                    unchecked_inode!(self.mkdir(dir.into(), name, 0o755, 0, 0, 0)?.inner().ino)
+                }
+            };
+        }
+
+        Ok(dir)
     }
 
-    fn release(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        _ino: u64,
-        _fh: u64,
-        _flags: i32,
-        _lock_owner: Option<u64>,
-        _flush: bool,
-        reply: fuser::ReplyEmpty,
-    ) {
-        reply.ok();
+    /// writes the tree to `writer` as a tar archive [`Daniel::from_tar`] reads back, parents come
+    /// before their children. a hard linked file is stored under its first name and a link record
+    /// for each other one, sockets are left out since tar has no record for them. buffered writes
+    /// aren't part of the archive until they're flushed
+    pub fn to_tar(&self, writer: impl Write) -> std::io::Result<()> {
+        let mut builder = tar::Builder::new(writer);
+        let mut links = Vec::new();
+        let mut queue = VecDeque::from([(ROOT_INODE, PathBuf::new())]);
+        while let Some((dir, path)) = queue.pop_front() {
+            let mut children: Vec<(&Path, Inode)> = self
+                .directory(dir)
+                .map_err(std::io::Error::other)?
+                .entries()
+                .iter()
+                .filter(|(_, child)| child.ino != STATUS_INODE)
+                .map(|(name, child)| (name.as_path(), child.ino))
+                .collect();
+            children.sort();
+
+            for (name, ino) in children {
+                let path = path.join(name);
+                let entry = &self.list.map()[&ino];
+                // the other names go last so the name they link to is already there
+                if entry.parent() != dir || entry.name() != name {
+                    links.push((path, entry));
+                    continue;
+                }
+                if entry.as_directory().is_some() {
+                    queue.push_back((ino, path.clone()));
+                }
+                self.append_tar(&mut builder, &path, entry)?;
+            }
+        }
+
+        for (path, entry) in links {
+            let mut header = tar_header(&entry.attr().inner());
+            header.set_entry_type(tar::EntryType::Link);
+            let target = self.path_of(unchecked_inode!(entry.attr().inner().ino));
+            let target = target.ok_or_else(|| std::io::Error::other(ENOENT))?;
+            builder.append_link(
+                &mut header,
+                path,
+                target.strip_prefix("/").unwrap_or(&target),
+            )?;
+        }
+
+        builder.finish()
     }
 
-    fn fsync(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        fh: u64,
-        datasync: bool,
-        reply: fuser::ReplyEmpty,
-    ) {
-        debug!(
-            "[Not Implemented] fsync(ino: {:#x?}, fh: {}, datasync: {})",
-            ino, fh, datasync
-        );
-        reply.error(ENOSYS);
+    /// adds `entry` to `builder` as `path`
+    fn append_tar(
+        &self,
+        builder: &mut tar::Builder<impl Write>,
+        path: &Path,
+        entry: &DirEntry,
+    ) -> std::io::Result<()> {
+        let attr = entry.attr().inner();
+        let mut header = tar_header(&attr);
+        let kind = match attr.kind {
+            FileType::Directory => tar::EntryType::Directory,
+            FileType::RegularFile => tar::EntryType::Regular,
+            FileType::Symlink => tar::EntryType::Symlink,
+            FileType::CharDevice => tar::EntryType::Char,
+            FileType::BlockDevice => tar::EntryType::Block,
+            FileType::NamedPipe => tar::EntryType::Fifo,
+            FileType::Socket => {
+                warn!(path = %path.display(), "leaving a socket out of the archive");
+                return Ok(());
+            }
+        };
+        header.set_entry_type(kind);
+        if matches!(kind, tar::EntryType::Char | tar::EntryType::Block) {
+            header.set_device_major(libc::major(attr.rdev.into()))?;
+            header.set_device_minor(libc::minor(attr.rdev.into()))?;
+        }
+
+        match entry.as_file() {
+            Some(file) if kind == tar::EntryType::Symlink => {
+                let target = file.data();
+                builder.append_link(&mut header, path, Path::new(OsStr::from_bytes(&target)))
+            }
+            Some(file) if kind == tar::EntryType::Regular => {
+                let data = file.data();
+                header.set_size(data.len() as u64);
+                builder.append_data(&mut header, path, &*data)
+            }
+            _ => builder.append_data(&mut header, path, std::io::empty()),
+        }
     }
 
-    fn opendir(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        _ino: u64,
-        _flags: i32,
-        reply: fuser::ReplyOpen,
-    ) {
-        reply.opened(0, 0);
+    /// saves the tree to the snapshot path if there is one
+    pub fn persist(&mut self) -> std::io::Result<()> {
+        match self.snapshot_path.clone() {
+            Some(path) => self.save(path),
+            None => Ok(()),
+        }
     }
 
-    fn readdirplus(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        fh: u64,
-        offset: i64,
-        reply: fuser::ReplyDirectoryPlus,
-    ) {
-        debug!(
-            "[Not Implemented] readdirplus(ino: {:#x?}, fh: {}, offset: {})",
-            ino, fh, offset
-        );
-        reply.error(ENOSYS);
+    /// saves the tree to the snapshot path on unmount with [`Daniel::save`], a failure can only be
+    /// logged by then
+    pub fn destroy(&mut self) {
+        let Some(path) = self.snapshot_path.clone() else {
+            return;
+        };
+        match self.save(&path) {
+            Ok(()) => info!(path = %path.display(), "saved the tree on unmount"),
+            Err(err) => error!(%err, path = %path.display(), "failed to save the tree on unmount"),
+        }
     }
 
-    fn releasedir(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        _ino: u64,
-        _fh: u64,
-        _flags: i32,
-        reply: fuser::ReplyEmpty,
-    ) {
-        reply.ok();
+    /// the whole tree is saved for fsync and fsyncdir of any inode, `EIO` if that fails
+    pub fn fsync(&mut self) -> Result<(), Errno> {
+        self.persist().map_err(|err| {
+            error!(%err, "failed to save the tree");
+            EIO
+        })
     }
 
-    fn fsyncdir(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        fh: u64,
-        datasync: bool,
-        reply: fuser::ReplyEmpty,
-    ) {
-        debug!(
-            "[Not Implemented] fsyncdir(ino: {:#x?}, fh: {}, datasync: {})",
-            ino, fh, datasync
-        );
-        reply.error(ENOSYS);
+    /// buffered writes aren't part of the checkpoint until they're flushed
+    /// orphans aren't part of the tree, so they're left out
+    pub fn checkpoint(&self) -> Snapshot {
+        let mut list = self.list.clone();
+        for ino in &self.orphans {
+            list.map_mut().remove(ino);
+        }
+
+        Snapshot {
+            mapper: self.mapper.clone(),
+            list,
+        }
     }
 
-    fn statfs(&mut self, _req: &fuser::Request<'_>, _ino: u64, reply: fuser::ReplyStatfs) {
-        reply.statfs(0, 0, 0, 0, 0, 512, 255, 0);
+    /// swaps the tree in `snapshot` back in, dropping all locks since they belong to handles
+    /// opened against the replaced tree
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        let old = std::mem::replace(&mut self.list, snapshot.list);
+        self.mapper = snapshot.mapper;
+        self.locks = LockTable::new();
+        self.write_buffers.clear();
+        self.dir_snapshots.clear();
+        self.attr_cache.clear();
+        self.orphans.clear();
+        self.contents.clear();
+        self.recount();
+        let list = &self.list;
+        self.generations
+            .retain(|ino, _| list.map().contains_key(ino));
+        self.open_files
+            .retain(|_, handle| list.map().contains_key(&handle.inode));
+
+        let mut inodes: Vec<Inode> = old
+            .map()
+            .keys()
+            .chain(self.list.map().keys())
+            .copied()
+            .collect();
+        inodes.sort();
+        inodes.dedup();
+        for ino in inodes {
+            self.queue_notification(Notification::Inode(ino.into()));
+        }
     }
 
-    fn setxattr(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        name: &std::ffi::OsStr,
-        _value: &[u8],
-        flags: i32,
-        position: u32,
-        reply: fuser::ReplyEmpty,
-    ) {
-        debug!(
-            "[Not Implemented] setxattr(ino: {:#x?}, name: {:?}, flags: {:#x?}, position: {})",
-            ino, name, flags, position
-        );
-        reply.error(ENOSYS);
+    pub fn notifier_slot(&self) -> NotifierSlot {
+        self.notifier.clone()
     }
 
-    fn getxattr(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        name: &std::ffi::OsStr,
-        size: u32,
-        reply: fuser::ReplyXattr,
-    ) {
-        debug!(
-            "[Not Implemented] getxattr(ino: {:#x?}, name: {:?}, size: {})",
-            ino, name, size
-        );
-        reply.error(ENOSYS);
+    /// mounts the filesystem on a background thread, dropping the returned session unmounts it
+    pub fn spawn(
+        self,
+        mountpoint: impl AsRef<Path>,
+        options: &[fuser::MountOption],
+    ) -> std::io::Result<fuser::BackgroundSession> {
+        if self.gc_interval.is_some() {
+            return SharedDaniel::new(self).spawn(mountpoint, options);
+        }
+        let notifier = self.notifier_slot();
+        let session = fuser::spawn_mount2(self, mountpoint, options)?;
+        notifier.set(session.notifier());
+        Ok(session)
     }
 
-    fn listxattr(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        size: u32,
-        reply: fuser::ReplyXattr,
-    ) {
-        debug!(
-            "[Not Implemented] listxattr(ino: {:#x?}, size: {})",
-            ino, size
-        );
-        reply.error(ENOSYS);
+    /// the inode the next new entry gets
+    pub fn next_inode(&self) -> Inode {
+        self.mapper.next_inode()
     }
 
-    fn removexattr(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        name: &std::ffi::OsStr,
-        reply: fuser::ReplyEmpty,
-    ) {
-        debug!(
-            "[Not Implemented] removexattr(ino: {:#x?}, name: {:?})",
-            ino, name
+    /// makes `ino` the inode of the next new entry, meant for tests that want to know which inodes
+    /// they'll get. `EINVAL` if `ino` isn't above every inode in use, removed entries the kernel
+    /// still references included, or if it's below the inode base
+    pub fn set_next_inode(&mut self, ino: Inode) -> Result<(), Errno> {
+        let max = self
+            .list
+            .map()
+            .keys()
+            .filter(|used| **used != STATUS_INODE)
+            .max()
+            .copied()
+            .unwrap_or(ROOT_INODE);
+        if ino <= max {
+            return Err(EINVAL);
+        }
+
+        self.mapper.set_next_inode(ino)
+    }
+
+    /// the regular file (or other non directory) at `ino`
+    pub fn file(&self, ino: Inode) -> Result<&File, Errno> {
+        self.list
+            .map()
+            .get(&ino)
+            .ok_or(ENOENT)?
+            .as_file()
+            .ok_or(EISDIR)
+    }
+
+    pub fn file_mut(&mut self, ino: Inode) -> Result<&mut File, Errno> {
+        self.list
+            .map_mut()
+            .get_mut(&ino)
+            .ok_or(ENOENT)?
+            .as_file_mut()
+            .ok_or(EISDIR)
+    }
+
+    pub fn directory(&self, ino: Inode) -> Result<&Directory, Errno> {
+        self.list
+            .map()
+            .get(&ino)
+            .ok_or(ENOENT)?
+            .as_directory()
+            .ok_or(ENOTDIR)
+    }
+
+    pub fn directory_mut(&mut self, ino: Inode) -> Result<&mut Directory, Errno> {
+        self.list
+            .map_mut()
+            .get_mut(&ino)
+            .ok_or(ENOENT)?
+            .as_directory_mut()
+            .ok_or(ENOTDIR)
+    }
+
+    fn queue_notification(&mut self, notification: Notification) {
+        if self.notifier.is_set() {
+            self.pending.push(notification);
+        }
+    }
+
+    /// sends the queued invalidations, this must only be called after replying since the kernel may
+    /// still hold locks the invalidation needs while a request is in flight
+    pub fn flush_notifications(&mut self) {
+        let Some(notifier) = self.notifier.get() else {
+            return;
+        };
+
+        for notification in self.pending.drain(..) {
+            let res = match &notification {
+                Notification::Inode(ino) => notifier.inval_inode(*ino),
+                Notification::Entry(parent, name) => notifier.inval_entry(*parent, name),
+                Notification::Poll(kh) => notifier.poll(*kh),
+            };
+
+            if let Err(err) = res {
+                warn!(?notification, %err, "failed to notify kernel");
+            }
+        }
+    }
+
+    /// `ENOSPC` if `parent` can't take another entry
+    fn check_dir_capacity(&self, parent: Inode) -> Result<(), Errno> {
+        let Some(max) = self.max_entries_per_dir else {
+            return Ok(());
+        };
+
+        let dir = self.directory(parent)?;
+        let held = dir.entries().len() - dir.contains(STATUS_INODE) as usize;
+        if held >= max {
+            return Err(ENOSPC);
+        }
+
+        Ok(())
+    }
+
+    /// `EINVAL` if `name` isn't UTF-8 and [`Daniel::with_require_utf8_names`] is set
+    fn check_name(&self, name: &OsStr) -> Result<(), Errno> {
+        match self.require_utf8_names && name.to_str().is_none() {
+            true => Err(EINVAL),
+            false => Ok(()),
+        }
+    }
+
+    pub fn push(&mut self, item: DirEntry) -> Result<(), Errno> {
+        let (parent, name, ino) = match &item {
+            DirEntry::Directory(dir) => {
+                let name = dir.name();
+                let parent = dir.parent();
+                (parent, name, dir.attr().inner().ino)
+            }
+            DirEntry::File(file) => {
+                let name = file.name();
+                let parent = file.parent();
+                (parent, name, file.attr().inner().ino)
+            }
+        };
+
+        let ino = unchecked_inode!(ino);
+        self.check_dir_capacity(parent)?;
+        let dir = self.directory_mut(parent)?;
+
+        dir.insert(ino, item.entry_type(), name);
+        self.mapper.insert(parent, name, ino);
+
+        let is_dir = item.as_directory().is_some();
+        if let Some(file) = item.as_file() {
+            self.usage.count(ino, file.storage());
+        }
+        self.list.map_mut().insert(ino, item);
+        self.next_generation = self.next_generation.wrapping_add(1);
+        self.generations.insert(ino, self.next_generation);
+        if is_dir {
+            self.count_subdir(parent, true);
+        }
+
+        Ok(())
+    }
+
+    /// a subdirectory's `..` links to its parent, so `dir` gains a link for every one added to it
+    /// and loses one for every one taken out
+    fn count_subdir(&mut self, dir: Inode, added: bool) {
+        if let Some(entry) = self.list.map_mut().get_mut(&dir) {
+            let attr = entry.attr_mut().inner_mut();
+            attr.nlink = match added {
+                true => attr.nlink + 1,
+                false => attr.nlink.saturating_sub(1),
+            };
+        }
+        self.attr_cache.invalidate(&dir);
+    }
+
+    /// pushes a newly created entry owned by `uid` and `gid`, with every time set to now.
+    /// `EEXIST` if its name is taken
+    fn push_new(&mut self, mut item: DirEntry, uid: u32, gid: u32) -> Result<(), Errno> {
+        self.check_name(item.name().as_os_str())?;
+        if self.mapper.get_map(item.parent(), item.name()).is_some() {
+            return Err(EEXIST);
+        }
+        stamp(&mut item, self.clock.now());
+        let attr = item.attr_mut().inner_mut();
+        attr.uid = uid;
+        attr.gid = gid;
+        let parent = item.parent();
+        self.push(item)?;
+        self.touch_dir(parent);
+
+        Ok(())
+    }
+
+    /// sets the mtime and ctime of the directory `ino` to now, after one of its children was added
+    /// or removed
+    fn touch_dir(&mut self, ino: Inode) {
+        let now = self.clock.now();
+        if let Ok(dir) = self.directory_mut(ino) {
+            dir.touch(now);
+        }
+        self.attr_cache.invalidate(&ino);
+    }
+
+    pub fn generation(&self, ino: Inode) -> u32 {
+        self.generations.get(&ino).copied().unwrap_or_default()
+    }
+
+    /// overrides how long the kernel may cache `ino` and everything below it that doesn't have an
+    /// override of its own, `None` goes back to what its parent has
+    pub fn set_ttl(&mut self, ino: u64, ttl: Option<Duration>) -> Result<(), Errno> {
+        let entry = self
+            .list
+            .map_mut()
+            .get_mut(&unchecked_inode!(ino))
+            .ok_or(ENOENT)?;
+        entry.set_ttl(ttl);
+
+        Ok(())
+    }
+
+    /// how long replies about `ino` may be cached for, the override nearest to it on the way up to
+    /// the root or the default. the status file and [`METRICS_DIR`] change all the time, so
+    /// they're never cached
+    pub fn ttl(&self, ino: u64) -> Duration {
+        let mut ino = unchecked_inode!(ino);
+        if ino == STATUS_INODE || self.is_virtual(ino) {
+            return Duration::ZERO;
+        }
+        while let Some(entry) = self.list.map().get(&ino) {
+            if let Some(ttl) = entry.ttl() {
+                return ttl;
+            }
+            if ino == ROOT_INODE {
+                break;
+            }
+            ino = entry.parent();
+        }
+
+        self.ttl.unwrap_or(DEFAULT_TTL)
+    }
+
+    /// hands out a new `fh` for `ino` opened with `flags`, it stays open until it's released
+    pub fn open_handle(&mut self, ino: u64, flags: i32) -> Result<u64, Errno> {
+        let ino = unchecked_inode!(ino);
+        if !self.list.map().contains_key(&ino) && !self.is_virtual(ino) {
+            return Err(ENOENT);
+        }
+
+        self.next_handle += 1;
+        let handle = FileHandle::new(ino, self.generation(ino), flags);
+        self.open_files.insert(self.next_handle, handle);
+
+        Ok(self.next_handle)
+    }
+
+    /// whether any handle is open on `ino`
+    fn is_open(&self, ino: Inode) -> bool {
+        self.open_files.values().any(|handle| handle.inode == ino)
+    }
+
+    /// opens `ino` with open(2) `flags` on behalf of `uid` and returns its `fh`, `EACCES` if the
+    /// access mode or `O_TRUNC` isn't permitted. the entry outlives being unlinked or renamed
+    /// over until the handle is given to [`Daniel::release`]. with `O_DIRECTORY` it's opened
+    /// like [`Daniel::opendir`] does, `ENOTDIR` if it isn't a directory
+    pub fn open(&mut self, ino: u64, flags: i32, uid: u32, gid: u32) -> Result<u64, Errno> {
+        if flags & O_DIRECTORY != 0 {
+            self.check_access(ino, R_OK, uid, gid)?;
+            self.opendir(ino)?;
+            return self.open_handle(ino, flags);
+        }
+
+        let mut mask = match flags & O_ACCMODE {
+            mode if mode == O_WRONLY => W_OK,
+            mode if mode == O_RDWR => R_OK | W_OK,
+            _ => R_OK,
+        };
+        if flags & O_TRUNC != 0 {
+            mask |= W_OK;
+        }
+        self.check_access(ino, mask, uid, gid)?;
+
+        self.open_handle(ino, flags)
+    }
+
+    /// closes a handle from [`Daniel::open`], flushing its buffered writes. a removed entry is
+    /// freed with its last handle unless the kernel still references it
+    pub fn release(&mut self, ino: u64, fh: u64) -> Result<(), Errno> {
+        self.check_handle(ino, fh)?;
+        let flushed = self.flush_handle(unchecked_inode!(ino), fh);
+
+        // the error was reported, there's nobody left to retry it
+        self.write_buffers.remove(&(unchecked_inode!(ino), fh));
+        self.close_handle(fh);
+
+        flushed
+    }
+
+    /// forgets `fh`, freeing what it was opened on if that was removed and is referenced no more
+    fn close_handle(&mut self, fh: u64) {
+        if let Some(handle) = self.open_files.remove(&fh) {
+            // directories opened with open(2) are released without a releasedir
+            if handle.flags & O_DIRECTORY != 0 {
+                self.releasedir(handle.inode.into());
+            }
+            self.free_orphan(handle.inode);
+        }
+    }
+
+    /// every handle open on `ino` with the flags it was opened with, oldest first
+    pub fn handles_for(&self, ino: u64) -> Vec<OpenHandle> {
+        let ino = unchecked_inode!(ino);
+        self.open_files
+            .iter()
+            .filter(|(_, handle)| handle.inode == ino)
+            .map(|(fh, handle)| OpenHandle {
+                fh: *fh,
+                flags: handle.flags,
+            })
+            .collect()
+    }
+
+    /// `ESTALE` unless `fh` is open on `ino` as it is now
+    pub fn check_handle(&self, ino: u64, fh: u64) -> Result<(), Errno> {
+        let ino = unchecked_inode!(ino);
+        let current = self.list.map().contains_key(&ino) || self.is_virtual(ino);
+        let opened = self.open_files.get(&fh);
+        if !current || !opened.is_some_and(|handle| handle.matches(ino, self.generation(ino))) {
+            return Err(ESTALE);
+        }
+
+        Ok(())
+    }
+
+    /// limits the permissions of everything created in `ino` from now on to `mode`
+    pub fn set_default_child_mode(&mut self, ino: Inode, mode: Option<u16>) -> Result<(), Errno> {
+        self.directory_mut(ino)?.set_default_child_mode(mode);
+        Ok(())
+    }
+
+    pub fn create(
+        &mut self,
+        parent: Inode,
+        path: impl AsRef<Path>,
+        mode: u16,
+        perms: u16,
+    ) -> Result<FileAttribute, Errno> {
+        self.timed(Op::Create, |fs| {
+            fs.create_file(parent, path, mode, perms, 0, 0)
+        })
+    }
+
+    /// creates the file `path` in `parent` owned by `uid` and `gid`
+    fn create_file(
+        &mut self,
+        parent: Inode,
+        path: impl AsRef<Path>,
+        _mode: u16,
+        perms: u16,
+        uid: u32,
+        gid: u32,
+    ) -> Result<FileAttribute, Errno> {
+        self.check_mount_writable()?;
+        let perms = self.directory(parent)?.child_perms(perms);
+        let inode = self.mapper.next_inode();
+        let name = path.as_ref().to_path_buf();
+        let file = match self.compress {
+            true => File::with_storage(name, parent, inode, perms, Box::new(Compressed::default())),
+            false => File::new(name, parent, inode, perms),
+        };
+        self.push_new(DirEntry::File(file), uid, gid)?;
+        self.journal
+            .record(self.clock.now(), JournalOp::Create, inode, path.as_ref());
+
+        Ok(*self
+            .list
+            .map()
+            .get(&inode)
+            .expect("failed to get entry that was just pushed")
+            .attr())
+    }
+
+    /// creates the file at `path` relative to the root along with any missing directories leading
+    /// up to it, existing directories are reused and an existing file is returned as is
+    pub fn create_path(&mut self, path: &Path, perms: u16) -> Result<Inode, Errno> {
+        let names = path_names(path)?;
+        let Some((file, dirs)) = names.split_last() else {
+            return Err(EINVAL);
+        };
+
+        let parent = self.create_dirs(dirs)?;
+        if let Some(&ino) = self.mapper.get_map(parent, file) {
+            return match self.file(ino) {
+                Ok(_) => Ok(ino),
+                Err(_) => Err(EEXIST),
+            };
+        }
+
+        let ino = self.create(parent, file, 0, perms)?.inner().ino;
+        Ok(unchecked_inode!(ino))
+    }
+
+    /// creates the directory at `path` relative to the root along with any missing ones leading up
+    /// to it like `mkdir -p`, `ENOTDIR` if a file is in the way
+    pub fn create_dir_path(&mut self, path: &Path) -> Result<Inode, Errno> {
+        let names = path_names(path)?;
+        if names.is_empty() {
+            return Err(EINVAL);
+        }
+
+        self.create_dirs(&names)
+    }
+
+    /// walks `names` down from the root, making the directories that are missing
+    fn create_dirs(&mut self, names: &[&OsStr]) -> Result<Inode, Errno> {
+        let mut parent = ROOT_INODE;
+        for name in names {
+            parent = match self.mapper.get_map(parent, name) {
+                Some(&ino) => {
+                    self.directory(ino)?;
+                    ino
+                }
+                None => {
+                    let ino = self.mkdir(parent.into(), name, 0o755, 0, 0, 0)?.inner().ino;
+                    unchecked_inode!(ino)
+                }
+            };
+        }
+
+        Ok(parent)
+    }
+
+    /// `create` as called with open(2) `flags` by `uid` in group `gid`, who owns the file if it's
+    /// created. an existing file is opened unless `O_EXCL` is set
+    pub fn create_or_open(
+        &mut self,
+        parent: Inode,
+        name: &std::ffi::OsStr,
+        perms: u16,
+        flags: i32,
+        uid: u32,
+        gid: u32,
+    ) -> Result<FileAttribute, Errno> {
+        let Some(&ino) = self.mapper.get_map(parent, name) else {
+            if flags & O_CREAT == 0 {
+                return Err(ENOENT);
+            }
+            return self.timed(Op::Create, |fs| {
+                fs.create_file(parent, name, 0, perms, uid, gid)
+            });
+        };
+
+        if flags & (O_CREAT | O_EXCL) == O_CREAT | O_EXCL {
+            return Err(EEXIST);
+        }
+
+        Ok(self.file(ino)?.attr())
+    }
+
+    /// makes the directory `name` in `parent` owned by `uid` and `gid`
+    fn mkdir(
+        &mut self,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        mode: u32,
+        umask: u32,
+        uid: u32,
+        gid: u32,
+    ) -> Result<FileAttribute, Errno> {
+        self.check_mount_writable()?;
+        let parent = unchecked_inode!(parent);
+        let perms = self
+            .directory(parent)?
+            .child_perms(self.masked(mode, umask));
+        let inode = self.mapper.next_inode();
+        self.push_new(
+            DirEntry::Directory(Directory::new(parent, name.into(), inode, perms)),
+            uid,
+            gid,
+        )?;
+        self.journal
+            .record(self.clock.now(), JournalOp::Mkdir, inode, name);
+
+        Ok(*self
+            .list
+            .map()
+            .get(&inode)
+            .expect("failed to get entry that was just pushed")
+            .attr())
+    }
+
+    /// makes the node `name` in `parent` owned by `uid` and `gid`
+    pub fn mknod(
+        &mut self,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        mode: u32,
+        rdev: u32,
+        uid: u32,
+        gid: u32,
+    ) -> Result<FileAttribute, Errno> {
+        self.check_mount_writable()?;
+        let perms = (mode & 0o7777) as u16;
+        let kind = match file_type_from_mode(mode) {
+            Some(FileType::RegularFile) | None => FileType::RegularFile,
+            Some(
+                kind @ (FileType::CharDevice
+                | FileType::BlockDevice
+                | FileType::NamedPipe
+                | FileType::Socket),
+            ) => kind,
+            Some(FileType::Directory | FileType::Symlink) => return Err(EINVAL),
+        };
+
+        let inode = self.mapper.next_inode();
+        let parent = unchecked_inode!(parent);
+        self.push_new(
+            DirEntry::File(File::node(name.into(), parent, inode, perms, kind, rdev)),
+            uid,
+            gid,
+        )?;
+        self.journal
+            .record(self.clock.now(), JournalOp::Create, inode, name);
+
+        Ok(*self
+            .list
+            .map()
+            .get(&inode)
+            .expect("failed to get entry that was just pushed")
+            .attr())
+    }
+
+    /// an overlayfs whiteout named `name`, a 0/0 character device
+    pub fn whiteout(
+        &mut self,
+        parent: u64,
+        name: &std::ffi::OsStr,
+    ) -> Result<FileAttribute, Errno> {
+        self.mknod(parent, name, libc::S_IFCHR, 0, 0, 0)
+    }
+
+    /// makes the symlink `link_name` in `parent` to `target` owned by `uid` and `gid`
+    pub fn symlink(
+        &mut self,
+        parent: u64,
+        link_name: &std::ffi::OsStr,
+        target: &Path,
+        uid: u32,
+        gid: u32,
+    ) -> Result<FileAttribute, Errno> {
+        self.check_mount_writable()?;
+        let inode = self.mapper.next_inode();
+        self.push_new(
+            DirEntry::File(File::symlink(
+                link_name.into(),
+                unchecked_inode!(parent),
+                inode,
+                target,
+            )),
+            uid,
+            gid,
+        )?;
+        self.journal
+            .record(self.clock.now(), JournalOp::Create, inode, link_name);
+
+        Ok(*self
+            .list
+            .map()
+            .get(&inode)
+            .expect("failed to get entry that was just pushed")
+            .attr())
+    }
+
+    pub fn readlink(&mut self, ino: u64) -> Result<Cow<'_, [u8]>, Errno> {
+        let file = self
+            .list
+            .map()
+            .get(&unchecked_inode!(ino))
+            .ok_or(ENOENT)?
+            .as_file()
+            .ok_or(EINVAL)?;
+
+        match file.kind() {
+            FileType::Symlink => Ok(file.data()),
+            _ => Err(EINVAL),
+        }
+    }
+
+    pub fn opendir(&mut self, ino: u64) -> Result<(), Errno> {
+        let ino = unchecked_inode!(ino);
+        if self.metrics_dir && ino == METRICS_INODE {
+            return Ok(());
+        }
+        self.directory(ino)?;
+        if !self.readdir_snapshots && !self.sort_dirents {
+            return Ok(());
+        }
+
+        if !self.dir_snapshots.contains_key(&ino) {
+            let entries = self
+                .dir_entries_after(ino, 0)?
+                .into_iter()
+                .map(|(cookie, child, kind, name)| (cookie, child, kind, name.to_path_buf()))
+                .collect();
+            self.dir_snapshots.insert(
+                ino,
+                DirSnapshot {
+                    handles: 0,
+                    entries,
+                },
+            );
+        }
+        if let Some(snapshot) = self.dir_snapshots.get_mut(&ino) {
+            snapshot.handles += 1;
+        }
+
+        Ok(())
+    }
+
+    /// closes a handle from [`Daniel::opendir`], the directory's snapshot goes with its last one
+    pub fn releasedir(&mut self, ino: u64) {
+        let ino = unchecked_inode!(ino);
+        if let Some(snapshot) = self.dir_snapshots.get_mut(&ino) {
+            snapshot.handles -= 1;
+            if snapshot.handles == 0 {
+                self.dir_snapshots.remove(&ino);
+            }
+        }
+    }
+
+    /// the entries readdir returns after `cookie`, from the directory's snapshot while it has one
+    /// and from [`Daniel::dir_entries_after`] otherwise
+    pub fn readdir_after(
+        &self,
+        ino: Inode,
+        cookie: u64,
+    ) -> Result<Vec<(u64, Inode, EntryType, PathBuf)>, Errno> {
+        let Some(snapshot) = self.dir_snapshots.get(&ino) else {
+            return Ok(self
+                .dir_entries_after(ino, cookie)?
+                .into_iter()
+                .map(|(cookie, child, kind, name)| (cookie, child, kind, name.to_path_buf()))
+                .collect());
+        };
+
+        let entries = &snapshot.entries;
+        Ok(match entries.iter().position(|entry| entry.0 == cookie) {
+            Some(i) => entries[i + 1..].to_vec(),
+            None => entries
+                .iter()
+                .filter(|entry| entry.0 > cookie)
+                .cloned()
+                .collect(),
+        })
+    }
+
+    /// walks an absolute `path` from the root, following symlinks in every component including the
+    /// last, relative targets are resolved against the directory holding the link and absolute ones
+    /// against the root. returns `None` if a component is missing or more than `max_hops` links
+    /// had to be followed, eg. for a loop
+    pub fn resolve_following_symlinks(
+        &self,
+        path: impl AsRef<Path>,
+        max_hops: usize,
+    ) -> Option<Inode> {
+        let mut pending: VecDeque<OsString> = path
+            .as_ref()
+            .components()
+            .map(|component| component.as_os_str().to_os_string())
+            .collect();
+        let mut current = ROOT_INODE;
+        let mut hops = 0;
+
+        while let Some(component) = pending.pop_front() {
+            match Path::new(&component).components().next()? {
+                Component::RootDir => current = ROOT_INODE,
+                Component::CurDir => {}
+                Component::ParentDir => current = self.directory(current).ok()?.parent(),
+                Component::Prefix(_) => return None,
+                Component::Normal(name) => {
+                    self.directory(current).ok()?;
+                    let child = *self.mapper.get_map(current, name)?;
+                    let file = match self.list.map().get(&child)? {
+                        DirEntry::File(file) if file.kind() == FileType::Symlink => file,
+                        _ => {
+                            current = child;
+                            continue;
+                        }
+                    };
+
+                    hops += 1;
+                    if hops > max_hops {
+                        return None;
+                    }
+
+                    // an absolute target starts with the root component which resets `current`,
+                    // a relative one is resolved from the directory holding the link
+                    let target = file.data();
+                    let target = Path::new(OsStr::from_bytes(&target));
+                    for component in target.components().rev() {
+                        pending.push_front(component.as_os_str().to_os_string());
+                    }
+                }
+            }
+        }
+
+        Some(current)
+    }
+
+    /// the absolute path of `ino` like `/a/b/c`, put together by walking up its parents, the
+    /// inverse of [`Daniel::resolve_following_symlinks`]. `None` for unknown and removed inodes
+    pub fn path_of(&self, ino: Inode) -> Option<PathBuf> {
+        let mut names = Vec::new();
+        let mut current = ino;
+        while current != ROOT_INODE {
+            // more parents than entries means they go around in a cycle
+            if names.len() > self.list.map().len() {
+                error!(
+                    ino = u64::from(ino),
+                    "the parents of the entry form a cycle"
+                );
+                return None;
+            }
+            let entry = self.list.map().get(&current)?;
+            let parent = entry.parent();
+            // removed entries still point at the directory they were in
+            if !self.directory(parent).ok()?.contains(current) {
+                return None;
+            }
+            names.push(entry.name());
+            current = parent;
+        }
+
+        Some(
+            names
+                .iter()
+                .rev()
+                .fold(PathBuf::from("/"), |path, name| path.join(name)),
+        )
+    }
+
+    /// whether `ino` is `ancestor` or somewhere below it
+    fn is_within(&self, ino: Inode, ancestor: Inode) -> bool {
+        let mut current = ino;
+        // more parents than entries means they go around in a cycle, which no move may join
+        for _ in 0..=self.list.map().len() {
+            if current == ancestor {
+                return true;
+            }
+            match self.list.map().get(&current) {
+                Some(entry) if current != ROOT_INODE => current = entry.parent(),
+                _ => return false,
+            }
+        }
+
+        true
+    }
+
+    /// records the path and kind of `ino` in the `path` and `kind` fields of the current span, so
+    /// logs of an op say what it was on instead of only giving inodes
+    fn record_entry(&self, ino: Inode) {
+        let span = Span::current();
+        if span.is_disabled() {
+            return;
+        }
+
+        if let Some(path) = self.path_of(ino) {
+            span.record("path", field::display(path.display()));
+        }
+        if let Some(entry) = self.list.map().get(&ino) {
+            span.record("kind", field::display(entry.entry_type().name()));
+        }
+    }
+
+    /// `.`, `..` and then the children of `ino` in the order readdir emits them, the position in
+    /// the list is what readdir offsets refer to
+    fn dir_entries(&self, ino: Inode) -> Result<Vec<(Inode, EntryType, &Path)>, Errno> {
+        let dir = self.directory(ino)?;
+        let mut children = Vec::new();
+        for (name, child) in dir.entries() {
+            if self.hide_whiteouts && child.kind == EntryType::Whiteout {
+                continue;
+            }
+            children.push((child.ino, child.kind.clone(), name.as_path()));
+        }
+
+        match self.sort_dirents {
+            true => children.sort_by_key(|(_, _, name)| *name),
+            false => children.sort_by_key(|(_, _, name)| dir.seq(name)),
+        }
+
+        // root is its own parent
+        let mut entries = vec![
+            (ino, EntryType::Directory, Path::new(".")),
+            (dir.parent(), EntryType::Directory, Path::new("..")),
+        ];
+        entries.append(&mut children);
+
+        Ok(entries)
+    }
+
+    /// the entries of [`Daniel::dir_entries`] that come after the one with the readdir cookie
+    /// `cookie`, each with its own cookie. `.` and `..` are 1 and 2 and the children follow the
+    /// order they were added in, so an entry keeps its cookie while others are added or removed.
+    /// sorted by name the cookies are positions instead, so they still grow in the order the
+    /// entries are listed. if the entry with `cookie` is gone, every entry with a larger cookie is
+    /// returned
+    fn dir_entries_after(
+        &self,
+        ino: Inode,
+        cookie: u64,
+    ) -> Result<Vec<(u64, Inode, EntryType, &Path)>, Errno> {
+        if self.metrics_dir && ino == METRICS_INODE {
+            return Ok(Self::metric_entries_after(cookie));
+        }
+        let dir = self.directory(ino)?;
+        let entries: Vec<_> = self
+            .dir_entries(ino)?
+            .into_iter()
+            .enumerate()
+            .map(|(i, (child, kind, name))| {
+                let cookie = match i < 2 || self.sort_dirents {
+                    true => i as u64 + 1,
+                    false => dir.seq(name).expect("every child has a seq") + 2,
+                };
+                (cookie, child, kind, name)
+            })
+            .collect();
+
+        let mut after = match entries.iter().position(|entry| entry.0 == cookie) {
+            Some(i) => entries[i + 1..].to_vec(),
+            None => entries
+                .into_iter()
+                .filter(|entry| entry.0 > cookie)
+                .collect(),
+        };
+        if self.metrics_dir && ino == ROOT_INODE && cookie < METRICS_COOKIE {
+            let name = Path::new(METRICS_DIR);
+            after.push((METRICS_COOKIE, METRICS_INODE, EntryType::Directory, name));
+        }
+
+        Ok(after)
+    }
+
+    /// [`Daniel::dir_entries_after`] for [`METRICS_DIR`], the counter files follow `.` and `..`
+    /// in the order of [`OpCounters::NAMES`]
+    fn metric_entries_after(cookie: u64) -> Vec<(u64, Inode, EntryType, &'static Path)> {
+        let files = OpCounters::NAMES
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (Self::metric_inode(i), EntryType::File, Path::new(*name)));
+        [
+            (METRICS_INODE, EntryType::Directory, Path::new(".")),
+            (ROOT_INODE, EntryType::Directory, Path::new("..")),
+        ]
+        .into_iter()
+        .chain(files)
+        .zip(1..)
+        .map(|((ino, kind, name), entry_cookie)| (entry_cookie, ino, kind, name))
+        .filter(|(entry_cookie, ..)| *entry_cookie > cookie)
+        .collect()
+    }
+
+    /// every child of `ino` in readdir order, without `.` and `..`
+    pub fn list_dir(&self, ino: Inode) -> Result<Vec<(PathBuf, FileType, Inode)>, Errno> {
+        Ok(self
+            .dir_entries(ino)?
+            .into_iter()
+            .skip(2)
+            .map(|(ino, kind, name)| (name.to_path_buf(), kind.into(), ino))
+            .collect())
+    }
+
+    /// the child at position `offset` in readdir order, `Break` once they've all been returned or
+    /// if `ino` isn't a directory
+    pub fn readdir(&self, ino: u64, _fh: u64, offset: u64) -> ControlFlow<(), &DirEntry> {
+        let Ok(entries) = self.dir_entries(unchecked_inode!(ino)) else {
+            return ControlFlow::Break(());
+        };
+
+        // skips `.` and `..`
+        let child = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| entries.get(offset.checked_add(2)?))
+            .and_then(|(ino, _, _)| self.list.map().get(ino));
+        match child {
+            Some(entry) => ControlFlow::Continue(entry),
+            None => ControlFlow::Break(()),
+        }
+    }
+
+    pub fn lookup(&mut self, parent: u64, name: &std::ffi::OsStr) -> Result<FileAttribute, Errno> {
+        self.timed(Op::Lookup, |fs| {
+            fs.flush_writes();
+            fs.lookup_flushed(parent, name)
+        })
+    }
+
+    /// [`Daniel::lookup`] through a shared reference so it can run alongside other readers, `None`
+    /// while writes are buffered since only [`Daniel::lookup`] can flush them
+    pub fn try_lookup(
+        &self,
+        parent: u64,
+        name: &std::ffi::OsStr,
+    ) -> Option<Result<FileAttribute, Errno>> {
+        let start = self.clock.now();
+        let attr = self
+            .write_buffers
+            .is_empty()
+            .then(|| self.lookup_flushed(parent, name))?;
+        self.record_latency(Op::Lookup, start);
+        Some(attr)
+    }
+
+    fn lookup_flushed(&self, parent: u64, name: &std::ffi::OsStr) -> Result<FileAttribute, Errno> {
+        self.ops.lookup();
+        let parent = unchecked_inode!(parent);
+        if let Some(attr) = self.lookup_virtual(parent, name) {
+            return attr;
+        }
+        self.directory(parent)?;
+
+        let ino = *self.mapper.get_map(parent, name).ok_or(ENOENT)?;
+        // the name still maps to an inode that's gone
+        let entry = self.list.map().get(&ino).ok_or(ESTALE)?;
+        Ok(*entry.attr())
+    }
+
+    /// `ENOENT` if `ino` is gone and `EACCES` if `uid` in group `gid` may not do everything in
+    /// `mask`, see [`check_permission`]
+    pub fn access(&mut self, ino: u64, mask: i32, uid: u32, gid: u32) -> Result<(), Errno> {
+        self.check_access(ino, mask, uid, gid)
+    }
+
+    fn check_access(&self, ino: u64, mask: i32, uid: u32, gid: u32) -> Result<(), Errno> {
+        let ino = unchecked_inode!(ino);
+        let attr = match self.virtual_attr(ino) {
+            Some(attr) => attr,
+            None => *self.list.map().get(&ino).ok_or(ENOENT)?.attr(),
+        };
+        match check_permission(&attr.inner(), uid, gid, mask) {
+            true => Ok(()),
+            false => Err(EACCES),
+        }
+    }
+
+    /// `ENOENT` for an inode that's gone, the kernel may still ask about one it references
+    pub fn getattr(&mut self, ino: u64, _fh: Option<u64>) -> Result<FileAttribute, Errno> {
+        let start = self.clock.now();
+        self.flush_writes();
+        let ino = unchecked_inode!(ino);
+        if let Some(attr) = self.virtual_attr(ino) {
+            self.record_latency(Op::Getattr, start);
+            return Ok(attr);
+        }
+        if self.attr_cache.capacity() == 0 || !self.attr_cache.contains(&ino) {
+            let Some(entry) = self.list.map().get(&ino) else {
+                self.record_latency(Op::Getattr, start);
+                return Err(ENOENT);
+            };
+            let attr = *entry.attr();
+            self.attr_cache.insert(ino, attr);
+            if self.attr_cache.capacity() == 0 {
+                self.record_latency(Op::Getattr, start);
+                return Ok(attr);
+            }
+        }
+
+        self.record_latency(Op::Getattr, start);
+        Ok(*self.attr_cache.get(&ino).expect("attr was just cached"))
+    }
+
+    /// [`Daniel::getattr`] through a shared reference, `None` while writes are buffered. it goes
+    /// straight to the entry since the attribute cache needs `&mut self` to track use
+    pub fn try_getattr(&self, ino: u64) -> Option<Result<FileAttribute, Errno>> {
+        let start = self.clock.now();
+        let attr = || {
+            let ino = unchecked_inode!(ino);
+            if let Some(attr) = self.virtual_attr(ino) {
+                return Ok(attr);
+            }
+            let entry = self.list.map().get(&ino).ok_or(ENOENT)?;
+            Ok(*entry.attr())
+        };
+        let attr = self.write_buffers.is_empty().then(attr)?;
+        self.record_latency(Op::Getattr, start);
+        Some(attr)
+    }
+
+    /// in a sticky directory only root and the owners of the directory or the entry may remove or
+    /// rename the entry
+    fn check_sticky(&self, parent: Inode, ino: Inode, uid: u32) -> Result<(), Errno> {
+        let dir = self.directory(parent)?.attr().inner();
+        if dir.perm & 0o1000 == 0 || uid == 0 || uid == dir.uid {
+            return Ok(());
+        }
+
+        match self.list.map().get(&ino) {
+            Some(entry) if entry.attr().inner().uid == uid => Ok(()),
+            Some(_) => Err(EACCES),
+            None => Err(ENOENT),
+        }
+    }
+
+    /// drops the name `name` of `ino` from its parent without any permission checks. once its last
+    /// name is gone it's freed right away unless the kernel still references it
+    fn remove_entry(&mut self, parent: Inode, name: &std::ffi::OsStr, ino: Inode) {
+        self.attr_cache.invalidate(&ino);
+        if let Ok(dir) = self.directory_mut(parent) {
+            dir.remove(name);
+        }
+        if self.directory(ino).is_ok() {
+            self.count_subdir(parent, false);
+        }
+        self.touch_dir(parent);
+        self.queue_notification(Notification::Entry(parent.into(), name.into()));
+        self.queue_notification(Notification::Inode(ino.into()));
+
+        // the other names of a hard linked file keep it around
+        let links = self.file(ino).map_or(0, |file| file.attr().inner().nlink);
+        let renamed = match links > 1 {
+            true => self.mapper.unlink(parent, name),
+            false => {
+                self.mapper.remove(parent, name);
+                None
+            }
+        };
+        let now = self.clock.now();
+        if let Some(entry) = self.list.map_mut().get_mut(&ino)
+            && entry.as_file().is_some()
+        {
+            if let Some((parent, name)) = renamed {
+                entry.rename(parent, name);
+            }
+            let attr = entry.attr_mut().inner_mut();
+            attr.nlink = links.saturating_sub(1);
+            attr.ctime = now;
+        }
+        if links > 1 {
+            return;
+        }
+
+        match self.looked_up(ino) || self.is_open(ino) {
+            true => _ = self.orphans.insert(ino),
+            false => self.free(ino),
+        }
+    }
+
+    /// frees `ino` if it was removed and nothing references it anymore
+    fn free_orphan(&mut self, ino: Inode) {
+        let referenced = self.looked_up(ino) || self.is_open(ino);
+        if self.orphans.contains(&ino) && !referenced {
+            self.free(ino);
+        }
+    }
+
+    /// frees the removed entries nothing references any more, eg. ones the kernel never sent a
+    /// forget for, and gives capacity the indexes and contents don't use back to the allocator.
+    /// returns how many entries were freed
+    pub fn sweep(&mut self) -> usize {
+        let unreferenced: Vec<Inode> = self
+            .orphans
+            .iter()
+            .filter(|ino| !self.looked_up(**ino) && !self.is_open(**ino))
+            .copied()
+            .collect();
+        for ino in &unreferenced {
+            self.free(*ino);
+        }
+
+        self.lookup_counts().retain(|_, count| *count > 0);
+        self.poll_handles.retain(|_, handles| !handles.is_empty());
+        for entry in self.list.map_mut().values_mut() {
+            if let Some(file) = entry.as_file_mut() {
+                file.shrink();
+            }
+        }
+        debug!(freed = unreferenced.len(), "swept");
+
+        unreferenced.len()
+    }
+
+    /// drops `ino` from every index, handles to it go stale
+    fn free(&mut self, ino: Inode) {
+        self.orphans.remove(&ino);
+        self.open_files.retain(|_, handle| handle.inode != ino);
+        self.poll_handles.remove(&ino);
+        self.dir_snapshots.remove(&ino);
+        self.contents.remove(&ino);
+        self.generations.remove(&ino);
+        self.write_buffers
+            .retain(|(buffered, _), _| *buffered != ino);
+        self.attr_cache.invalidate(&ino);
+        let removed = self.list.map_mut().remove(&ino);
+        if let Some(file) = removed.as_ref().and_then(DirEntry::as_file) {
+            self.usage.unreserve(ino, file.storage().len());
+            self.usage.uncount(ino, file.storage());
+        }
+        self.locks.remove(&ino);
+    }
+
+    /// a panic while counting doesn't lose the counts
+    fn lookup_counts(&self) -> MutexGuard<'_, BTreeMap<Inode, u64>> {
+        self.lookups.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// whether the kernel holds any reference to `ino`
+    fn looked_up(&self, ino: Inode) -> bool {
+        self.lookup_counts().contains_key(&ino)
+    }
+
+    /// counts a reply handing `ino` to the kernel
+    pub(crate) fn remember(&self, ino: u64) {
+        *self
+            .lookup_counts()
+            .entry(unchecked_inode!(ino))
+            .or_default() += 1;
+    }
+
+    /// the kernel dropped `nlookup` references to `ino`, once it holds none a removed entry is
+    /// freed
+    pub fn forget(&mut self, ino: u64, nlookup: u64) {
+        self.batch_forget([(ino, nlookup)]);
+    }
+
+    /// [`Daniel::forget`] for each `(ino, nlookup)`, the counts are locked once for all of them
+    /// and the removed entries nothing references any more are freed afterwards
+    pub fn batch_forget(&mut self, nodes: impl IntoIterator<Item = (u64, u64)>) {
+        let mut forgotten = Vec::new();
+        let mut lookups = self.lookup_counts();
+        for (ino, nlookup) in nodes {
+            let ino = unchecked_inode!(ino);
+            let Some(count) = lookups.get_mut(&ino) else {
+                continue;
+            };
+
+            *count = count.saturating_sub(nlookup);
+            if *count == 0 {
+                lookups.remove(&ino);
+                forgotten.push(ino);
+            }
+        }
+        drop(lookups);
+
+        for ino in forgotten {
+            self.free_orphan(ino);
+        }
+    }
+
+    pub fn unlink(&mut self, parent: u64, name: &std::ffi::OsStr, uid: u32) -> Result<(), Errno> {
+        self.timed(Op::Unlink, |fs| fs.unlink_entry(parent, name, uid))
+    }
+
+    fn unlink_entry(&mut self, parent: u64, name: &std::ffi::OsStr, uid: u32) -> Result<(), Errno> {
+        self.check_mount_writable()?;
+        let parent = unchecked_inode!(parent);
+        let ino = *self.mapper.get_map(parent, name).ok_or(ENOENT)?;
+        // the status file is made up and always listed in the root
+        if ino == STATUS_INODE {
+            return Err(EPERM);
+        }
+        self.file(ino)?;
+        self.check_mutable(ino)?;
+        self.check_sticky(parent, ino, uid)?;
+
+        self.remove_entry(parent, name, ino);
+        self.journal
+            .record(self.clock.now(), JournalOp::Unlink, ino, name);
+
+        Ok(())
+    }
+
+    /// removes the empty directory `name` from `parent` on behalf of `uid`
+    pub fn rmdir(&mut self, parent: u64, name: &std::ffi::OsStr, uid: u32) -> Result<(), Errno> {
+        self.check_mount_writable()?;
+        let parent = unchecked_inode!(parent);
+        let ino = *self.mapper.get_map(parent, name).ok_or(ENOENT)?;
+        if !self.directory(ino)?.entries().is_empty() {
+            return Err(ENOTEMPTY);
+        }
+        self.check_mutable(ino)?;
+        self.check_sticky(parent, ino, uid)?;
+
+        self.remove_entry(parent, name, ino);
+        self.journal
+            .record(self.clock.now(), JournalOp::Rmdir, ino, name);
+
+        Ok(())
+    }
+
+    /// gives the file `ino` the extra name `newname` in `newparent`, which may already list it
+    /// under another name. directories can't be linked
+    pub fn link(
+        &mut self,
+        ino: u64,
+        newparent: u64,
+        newname: &std::ffi::OsStr,
+    ) -> Result<FileAttribute, Errno> {
+        self.check_mount_writable()?;
+        self.check_name(newname)?;
+        let ino = unchecked_inode!(ino);
+        let newparent = unchecked_inode!(newparent);
+        // directories can't be linked, that could make a cycle, and neither can the made up entries
+        if self.is_virtual(ino) {
+            return Err(EPERM);
+        }
+        let kind = match self.file(ino) {
+            Ok(_) if ino == STATUS_INODE => return Err(EPERM),
+            Ok(_) => self.list.map()[&ino].entry_type(),
+            Err(EISDIR) => return Err(EPERM),
+            Err(err) => return Err(err),
+        };
+        self.check_mutable(ino)?;
+        self.directory(newparent)?;
+        if self.mapper.get_map(newparent, newname).is_some() {
+            return Err(EEXIST);
+        }
+        self.check_dir_capacity(newparent)?;
+
+        self.directory_mut(newparent)?.push(ino, kind, newname);
+        self.mapper.link(newparent, newname, ino);
+        self.touch_dir(newparent);
+        let now = self.clock.now();
+        let entry = self.list.map_mut().get_mut(&ino).ok_or(ENOENT)?;
+        let attr = entry.attr_mut().inner_mut();
+        // snapshots from before links were counted have files without any
+        attr.nlink = attr.nlink.max(1) + 1;
+        attr.ctime = now;
+        let attr = *entry.attr();
+
+        self.attr_cache.invalidate(&ino);
+        self.journal
+            .record(self.clock.now(), JournalOp::Link, ino, newname);
+        self.queue_notification(Notification::Inode(ino.into()));
+
+        Ok(attr)
+    }
+
+    /// removes `name` and everything below it like `rm -rf` on behalf of `uid`, children are
+    /// removed before their directories. every entry goes through the checks of
+    /// [`Daniel::unlink`] and [`Daniel::rmdir`] before anything is removed, so either all of it
+    /// goes or none of it does
+    pub fn remove_recursive(
+        &mut self,
+        parent: Inode,
+        name: &std::ffi::OsStr,
+        uid: u32,
+    ) -> Result<(), Errno> {
+        self.check_mount_writable()?;
+        self.check_removable(parent, name, uid, &mut BTreeSet::new())?;
+        self.remove_tree(parent, name, &mut BTreeSet::new())
+    }
+
+    /// whether `uid` may remove `name` and everything below it. gives up with `ELOOP` on reaching
+    /// a directory in `visited` again instead of going around a cycle forever
+    fn check_removable(
+        &self,
+        parent: Inode,
+        name: &std::ffi::OsStr,
+        uid: u32,
+        visited: &mut BTreeSet<Inode>,
+    ) -> Result<(), Errno> {
+        let ino = *self.mapper.get_map(parent, name).ok_or(ENOENT)?;
+        match ino {
+            ROOT_INODE => return Err(EINVAL),
+            STATUS_INODE => return Err(EPERM),
+            _ => {}
+        }
+        self.check_mutable(ino)?;
+        self.check_sticky(parent, ino, uid)?;
+
+        let Ok(dir) = self.directory(ino) else {
+            return Ok(());
+        };
+        if !visited.insert(ino) {
+            error!(ino = u64::from(ino), "the directory is below itself");
+            return Err(ELOOP);
+        }
+        for child in dir.entries().keys() {
+            self.check_removable(ino, child.as_os_str(), uid, visited)?;
+        }
+
+        Ok(())
+    }
+
+    /// [`Daniel::remove_recursive`] without the checks, still `ELOOP` on a cycle
+    fn remove_tree(
+        &mut self,
+        parent: Inode,
+        name: &std::ffi::OsStr,
+        visited: &mut BTreeSet<Inode>,
+    ) -> Result<(), Errno> {
+        let ino = *self.mapper.get_map(parent, name).ok_or(ENOENT)?;
+        if ino == ROOT_INODE {
+            return Err(EINVAL);
+        }
+
+        let Ok(dir) = self.directory(ino) else {
+            self.remove_entry(parent, name, ino);
+            self.journal
+                .record(self.clock.now(), JournalOp::Unlink, ino, name);
+            return Ok(());
+        };
+
+        let children: Vec<PathBuf> = dir.entries().keys().cloned().collect();
+        if !visited.insert(ino) {
+            error!(ino = u64::from(ino), "the directory is below itself");
+            return Err(ELOOP);
+        }
+        for child in children {
+            self.remove_tree(ino, child.as_os_str(), visited)?;
+        }
+
+        self.remove_entry(parent, name, ino);
+        self.journal
+            .record(self.clock.now(), JournalOp::Rmdir, ino, name);
+
+        Ok(())
+    }
+
+    /// renumbers every inode into a dense range from the inode base, breadth first with children in
+    /// name order, every open handle goes stale and entries that were only kept alive by the
+    /// kernel are dropped. `EBUSY` while mounted, the kernel would keep resolving cached names to
+    /// the old inodes
+    pub fn compact(&mut self) -> Result<(), Errno> {
+        if self.notifier.is_set() {
+            return Err(EBUSY);
+        }
+
+        self.flush_writes();
+        for ino in self.orphans.clone() {
+            self.free(ino);
+        }
+
+        let mut renumbered =
+            HashMap::from([(ROOT_INODE, ROOT_INODE), (STATUS_INODE, STATUS_INODE)]);
+        // the order the mapper is rebuilt in, parents before their children
+        let mut order = Vec::new();
+        let mut queue = VecDeque::from([ROOT_INODE]);
+        let base = self.mapper.base();
+        let mut next = u64::from(base) - 1;
+        while let Some(dir) = queue.pop_front() {
+            let mut children: Vec<(&Path, Inode, bool)> = self
+                .directory(dir)?
+                .entries()
+                .iter()
+                .filter(|(_, child)| child.ino != STATUS_INODE)
+                // a hard linked file is numbered under the name its entry has
+                .filter(|(name, child)| {
+                    let entry = &self.list.map()[&child.ino];
+                    entry.parent() == dir && entry.name() == name.as_path()
+                })
+                .map(|(name, child)| {
+                    let is_dir = child.kind == EntryType::Directory;
+                    (name.as_path(), child.ino, is_dir)
+                })
+                .collect();
+            children.sort();
+
+            for (_, ino, is_dir) in children {
+                next += 1;
+                renumbered.insert(ino, unchecked_inode!(next));
+                order.push(ino);
+                if is_dir {
+                    queue.push_back(ino);
+                }
+            }
+        }
+
+        let old = std::mem::take(self.list.map_mut());
+        for (ino, mut entry) in old {
+            let Some(&new) = renumbered.get(&ino) else {
+                warn!(ino = u64::from(ino), "dropping unreachable entry");
+                continue;
+            };
+            entry.attr_mut().inner_mut().ino = new.into();
+            let name = entry.name().to_path_buf();
+            entry.rename(renumbered[&entry.parent()], name);
+            if let Some(dir) = entry.as_directory_mut() {
+                let mut children: Vec<(PathBuf, Child)> = dir
+                    .entries()
+                    .iter()
+                    .map(|(name, child)| (name.clone(), child.clone()))
+                    .collect();
+                // keeps the readdir order
+                children.sort_by_key(|(_, child)| child.seq);
+                for (name, _) in &children {
+                    dir.remove(name);
+                }
+                for (name, child) in children {
+                    dir.insert(renumbered[&child.ino], child.kind, name);
+                }
+            }
+            self.list.map_mut().insert(new, entry);
+        }
+
+        let mut mapper = InodeMapper::new().with_base(base);
+        for ino in &order {
+            let entry = &self.list.map()[&renumbered[ino]];
+            mapper.insert(entry.parent(), entry.name(), renumbered[ino]);
+        }
+        // the other names of hard linked files, once every directory has its path
+        for (ino, entry) in self.list.map() {
+            let Some(dir) = entry.as_directory() else {
+                continue;
+            };
+            for (name, Child { ino: child, .. }) in dir.entries() {
+                let linked = &self.list.map()[child];
+                if linked.parent() != *ino || linked.name() != name.as_path() {
+                    mapper.link(*ino, name, *child);
+                }
+            }
+        }
+        self.mapper = mapper;
+
+        let mut inodes: Vec<Inode> = self.list.map().keys().copied().collect();
+        inodes.sort();
+        self.generations.clear();
+        for ino in inodes {
+            self.next_generation = self.next_generation.wrapping_add(1);
+            self.generations.insert(ino, self.next_generation);
+        }
+
+        self.locks = LockTable::new();
+        self.attr_cache.clear();
+        self.lookup_counts().clear();
+        self.open_files.clear();
+        self.poll_handles.clear();
+        self.dir_snapshots.clear();
+        self.contents.remap(&renumbered);
+        self.usage.remap(&renumbered);
+        self.export_dir = self
+            .export_dir
+            .and_then(|dir| renumbered.get(&dir).copied());
+
+        Ok(())
+    }
+
+    /// replaces `name` in `parent` with the whole tree of `new_root`, whatever was there is removed
+    /// like `remove_recursive` and the grafted entries get fresh inodes from this tree. the new
+    /// tree is grafted before the old one is touched, so a failure leaves the old one in place
+    pub fn replace_subtree(
+        &mut self,
+        parent: Inode,
+        name: &std::ffi::OsStr,
+        new_root: Daniel,
+    ) -> Result<(), Errno> {
+        self.directory(parent)?;
+        if let Some(max) = self.max_entries_per_dir {
+            let too_big = new_root.list.map().values().any(|entry| {
+                entry.as_directory().is_some_and(|dir| {
+                    dir.entries()
+                        .values()
+                        .filter(|child| child.ino != STATUS_INODE)
+                        .count()
+                        > max
+                })
+            });
+            if too_big {
+                return Err(ENOSPC);
+            }
+        }
+
+        let old = self.mapper.get_map(parent, name).copied();
+        match old {
+            Some(STATUS_INODE) => return Err(EACCES),
+            Some(_) => self.check_removable(parent, name, 0, &mut BTreeSet::new())?,
+            None => self.check_dir_capacity(parent)?,
+        }
+
+        // grafted under a name nobody uses, it only takes the place of `name` once it's complete
+        let spare = (0..)
+            .map(|i| OsString::from(format!(".daniel-replace-{i}")))
+            .find(|spare| self.mapper.get_map(parent, spare).is_none())
+            .expect("some name is free");
+        // the capacity was checked above, the spare name only stands in for `name`
+        let max = self.max_entries_per_dir.take();
+        let grafted = self.graft(&new_root, ROOT_INODE, parent, &spare);
+        self.max_entries_per_dir = max;
+        let new = match grafted {
+            Ok(new) => new,
+            Err(err) => {
+                if self.mapper.get_map(parent, &spare).is_some() {
+                    self.remove_tree(parent, &spare, &mut BTreeSet::new())?;
+                }
+                return Err(err);
+            }
+        };
+
+        if old.is_some() {
+            self.remove_tree(parent, name, &mut BTreeSet::new())?;
+        }
+        let dir = self.directory_mut(parent)?;
+        let kind = dir.remove(&spare).ok_or(ENOENT)?.kind;
+        dir.insert(new, kind, name);
+        self.mapper.rename(parent, &spare, parent, name);
+        if let Some(entry) = self.list.map_mut().get_mut(&new) {
+            entry.rename(parent, name.into());
+        }
+        self.journal
+            .record(self.clock.now(), JournalOp::Rename, new, name);
+        self.touch_dir(parent);
+        Ok(())
+    }
+
+    /// copies `ino` of `source` and everything below it into `parent` as `name`
+    fn graft(
+        &mut self,
+        source: &Daniel,
+        ino: Inode,
+        parent: Inode,
+        name: &std::ffi::OsStr,
+    ) -> Result<Inode, Errno> {
+        let mut entry = source.list.map().get(&ino).ok_or(ENOENT)?.clone();
+        let inode = self.mapper.next_inode();
+        if let Some(dir) = entry.as_directory_mut() {
+            let names: Vec<PathBuf> = dir.entries().keys().cloned().collect();
+            for name in names {
+                dir.remove(name);
+            }
+        }
+        if entry.kind() == FileType::Directory {
+            // the grafted subdirectories count themselves again
+            entry.attr_mut().inner_mut().nlink = 2;
+        }
+        entry.attr_mut().inner_mut().ino = inode.into();
+        entry.rename(parent, name.into());
+        let op = match entry.kind() {
+            FileType::Directory => JournalOp::Mkdir,
+            _ => JournalOp::Create,
+        };
+        self.push(entry)?;
+        self.journal.record(self.clock.now(), op, inode, name);
+
+        let Ok(dir) = source.directory(ino) else {
+            return Ok(inode);
+        };
+        for (child_name, child) in dir.entries() {
+            if child.ino != STATUS_INODE {
+                self.graft(source, child.ino, inode, child_name.as_os_str())?;
+            }
+        }
+
+        Ok(inode)
+    }
+
+    pub fn rename(
+        &mut self,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        newparent: u64,
+        newname: &std::ffi::OsStr,
+        uid: u32,
+    ) -> Result<(), Errno> {
+        self.timed(Op::Rename, |fs| {
+            fs.rename_entry(parent, name, newparent, newname, uid)
+        })
+    }
+
+    fn rename_entry(
+        &mut self,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        newparent: u64,
+        newname: &std::ffi::OsStr,
+        uid: u32,
+    ) -> Result<(), Errno> {
+        self.check_mount_writable()?;
+        self.check_name(newname)?;
+        let parent = unchecked_inode!(parent);
+        let newparent = unchecked_inode!(newparent);
+        self.directory(newparent)?;
+
+        let ino = *self.mapper.get_map(parent, name).ok_or(ENOENT)?;
+        if ino == STATUS_INODE || self.mapper.get_map(newparent, newname) == Some(&STATUS_INODE) {
+            return Err(EPERM);
+        }
+        self.check_mutable(ino)?;
+        self.check_sticky(parent, ino, uid)?;
+        // a directory can't be moved into itself or anywhere below it
+        if self.directory(ino).is_ok() && self.is_within(newparent, ino) {
+            return Err(EINVAL);
+        }
+        let target = self.mapper.get_map(newparent, newname).copied();
+        if let Some(target) = target {
+            if target == ino {
+                return Ok(());
+            }
+            self.check_mutable(target)?;
+            self.check_sticky(newparent, target, uid)?;
+
+            let moving_dir = self.directory(ino).is_ok();
+            match self.directory(target) {
+                Ok(_) if !moving_dir => return Err(EISDIR),
+                Ok(dir) if !dir.entries().is_empty() => return Err(ENOTEMPTY),
+                Err(_) if moving_dir => return Err(ENOTDIR),
+                _ => {}
+            }
+        }
+        if newparent != parent
+            && self.export_dir == Some(newparent)
+            && self
+                .file(ino)
+                .is_ok_and(|file| file.kind() == FileType::RegularFile)
+        {
+            self.export(parent, name, ino, newname)?;
+            // the name it was renamed over goes away like with any other rename
+            if let Some(target) = target {
+                self.remove_entry(newparent, newname, target);
+            }
+            return Ok(());
+        }
+        if let Some(target) = target {
+            self.remove_entry(newparent, newname, target);
+        } else if newparent != parent {
+            self.check_dir_capacity(newparent)?;
+        }
+
+        let kind = self.directory_mut(parent)?.remove(name).ok_or(ENOENT)?.kind;
+        let is_dir = kind == EntryType::Directory;
+        self.directory_mut(newparent)?.insert(ino, kind, newname);
+        if is_dir && newparent != parent {
+            self.count_subdir(parent, false);
+            self.count_subdir(newparent, true);
+        }
+        self.touch_dir(parent);
+        self.touch_dir(newparent);
+        self.mapper.rename(parent, name, newparent, newname);
+        let entry = self.list.map_mut().get_mut(&ino).ok_or(ENOENT)?;
+        entry.rename(newparent, newname.into());
+        // only the name changed, it's still the same inode, so ctime moves and crtime stays
+        entry.attr_mut().inner_mut().ctime = self.clock.now();
+        self.attr_cache.invalidate(&ino);
+
+        self.journal
+            .record(self.clock.now(), JournalOp::Rename, ino, newname);
+        self.queue_notification(Notification::Entry(parent.into(), name.into()));
+        self.queue_notification(Notification::Entry(newparent.into(), newname.into()));
+
+        Ok(())
+    }
+
+    /// writes the file `ino` to `newname` in the host directory of [`Daniel::set_export_dir`] and
+    /// drops its name `name` in `parent`, as if it was renamed away. `EEXIST` if the host already
+    /// has a `newname`, it's never overwritten, and `EIO` if the write fails. either way the file is
+    /// left where it was
+    fn export(
+        &mut self,
+        parent: Inode,
+        name: &std::ffi::OsStr,
+        ino: Inode,
+        newname: &std::ffi::OsStr,
+    ) -> Result<(), Errno> {
+        let (Some(dir), Some(host)) = (self.export_dir, self.export_path.clone()) else {
+            return Err(EIO);
+        };
+        self.flush_writes();
+        let path = host.join(newname);
+        let opened = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path);
+        let mut out = match opened {
+            Ok(out) => out,
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => return Err(EEXIST),
+            Err(err) => {
+                error!(%err, path = %path.display(), "failed to export");
+                return Err(EIO);
+            }
+        };
+        if let Err(err) = out.write_all(&self.file(ino)?.data()) {
+            error!(%err, path = %path.display(), "failed to export");
+            // don't leave half of it behind
+            let _ = std::fs::remove_file(&path);
+            return Err(EIO);
+        }
+        info!(path = %path.display(), "exported");
+
+        self.remove_entry(parent, name, ino);
+        self.journal
+            .record(self.clock.now(), JournalOp::Unlink, ino, name);
+        // the kernel thinks the file now has `newname`
+        self.queue_notification(Notification::Entry(dir.into(), newname.into()));
+
+        Ok(())
+    }
+
+    /// changes the permission bits on behalf of `uid` in group `gid`, `EPERM` unless it's the
+    /// owner or root. setgid is dropped when a caller other than root isn't in the file's group
+    pub fn chmod(
+        &mut self,
+        ino: u64,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+    ) -> Result<FileAttribute, Errno> {
+        self.check_mutable(unchecked_inode!(ino))?;
+        let attr = self
+            .list
+            .map()
+            .get(&unchecked_inode!(ino))
+            .ok_or(ENOENT)?
+            .attr()
+            .inner();
+        let perm = permitted_mode(&attr, mode, uid, gid)?;
+        self.change_attr(ino, |attr| attr.perm = perm)
+    }
+
+    /// replaces the attribute flags, only root may set or clear `FS_IMMUTABLE_FL` or
+    /// `FS_APPEND_FL`. this is the one change an immutable file still allows
+    pub fn set_flags(&mut self, ino: u64, flags: u32, uid: u32) -> Result<FileAttribute, Errno> {
+        let current = self
+            .list
+            .map()
+            .get(&unchecked_inode!(ino))
+            .ok_or(ENOENT)?
+            .attr()
+            .inner()
+            .flags;
+        if (current ^ flags) & (FS_IMMUTABLE_FL | FS_APPEND_FL) != 0 && uid != 0 {
+            return Err(EPERM);
+        }
+
+        self.change_attr(ino, |attr| attr.flags = flags)
+    }
+
+    /// `FS_IOC_GETFLAGS` and `FS_IOC_SETFLAGS` on behalf of `uid`, the flags travel as a native
+    /// endian 4 byte int. only `FS_IMMUTABLE_FL` and `FS_APPEND_FL` can be set, other commands
+    /// get `ENOTTY`
+    pub fn ioctl(
+        &mut self,
+        ino: u64,
+        cmd: u32,
+        in_data: &[u8],
+        uid: u32,
+    ) -> Result<Vec<u8>, Errno> {
+        match cmd {
+            cmd if cmd == FS_IOC_GETFLAGS => {
+                let entry = self.list.map().get(&unchecked_inode!(ino)).ok_or(ENOENT)?;
+                Ok(entry.attr().inner().flags.to_ne_bytes().to_vec())
+            }
+            cmd if cmd == FS_IOC_SETFLAGS => {
+                let flags = in_data.first_chunk().ok_or(EINVAL)?;
+                let flags = u32::from_ne_bytes(*flags);
+                if flags & !(FS_IMMUTABLE_FL | FS_APPEND_FL) != 0 {
+                    return Err(EOPNOTSUPP);
+                }
+                self.set_flags(ino, flags, uid)?;
+                Ok(Vec::new())
+            }
+            _ => Err(ENOTTY),
+        }
+    }
+
+    /// changes the owner and group on behalf of `uid`, `None` leaves that one as it is. only root
+    /// may give a file away, `EPERM` for anyone else
+    pub fn chown(
+        &mut self,
+        ino: u64,
+        owner: Option<u32>,
+        group: Option<u32>,
+        uid: u32,
+    ) -> Result<FileAttribute, Errno> {
+        self.check_mutable(unchecked_inode!(ino))?;
+        let attr = self
+            .list
+            .map()
+            .get(&unchecked_inode!(ino))
+            .ok_or(ENOENT)?
+            .attr()
+            .inner();
+        check_owner_change(&attr, owner, group, uid)?;
+        self.change_attr(ino, |attr| {
+            attr.uid = owner.unwrap_or(attr.uid);
+            attr.gid = group.unwrap_or(attr.gid);
+        })
+    }
+
+    /// applies every attribute of `patch` on behalf of `uid` and returns which ones changed. the
+    /// size goes through [`Daniel::truncate`] so the data follows it, and ctime is bumped when
+    /// anything changed unless the patch sets it
+    pub fn setattr(
+        &mut self,
+        ino: u64,
+        patch: AttrPatch,
+        uid: u32,
+        gid: u32,
+    ) -> Result<(FileAttribute, AttrChanges), Errno> {
+        self.timed(Op::Setattr, |fs| fs.apply_attrs(ino, patch, uid, gid))
+    }
+
+    fn apply_attrs(
+        &mut self,
+        ino: u64,
+        patch: AttrPatch,
+        uid: u32,
+        gid: u32,
+    ) -> Result<(FileAttribute, AttrChanges), Errno> {
+        self.check_mount_writable()?;
+        self.flush_writes();
+        let before = self
+            .list
+            .map()
+            .get(&unchecked_inode!(ino))
+            .ok_or(ENOENT)?
+            .attr()
+            .inner();
+
+        // the flags go first so clearing the immutable flag lets the rest through
+        let mut changes = AttrChanges::default();
+        if let Some(flags) = patch.flags {
+            self.set_flags(ino, flags, uid)?;
+            changes.flags = before.flags != flags;
+        }
+        let patch = AttrPatch {
+            flags: None,
+            ..patch
+        };
+        if !patch.is_empty() {
+            self.check_mutable(unchecked_inode!(ino))?;
+        }
+        check_owner_change(&before, patch.uid, patch.gid, uid)?;
+        let patch = AttrPatch {
+            mode: patch
+                .mode
+                .map(|mode| permitted_mode(&before, mode, uid, gid).map(u32::from))
+                .transpose()?,
+            ..patch
+        };
+
+        if patch.size.is_some() && !check_permission(&before, uid, gid, W_OK) {
+            return Err(EACCES);
+        }
+        // truncating to the same size would still touch mtime
+        if let Some(size) = patch.size.filter(|&size| size != before.size) {
+            self.truncate(ino, size)?;
+            changes.size = true;
+        }
+
+        let now = self.clock.now();
+        let ino = unchecked_inode!(ino);
+        let entry = self.list.map_mut().get_mut(&ino).ok_or(ENOENT)?;
+        let applied = entry.attr_mut().apply(AttrPatch {
+            size: None,
+            ..patch
+        });
+        let changes = AttrChanges {
+            size: changes.size,
+            flags: changes.flags,
+            ..applied
+        };
+        if changes.any() && patch.ctime.is_none() {
+            entry.attr_mut().inner_mut().ctime = now;
+        }
+        let attr = *entry.attr();
+
+        self.attr_cache.invalidate(&ino);
+        self.journal.record(
+            self.clock.now(),
+            JournalOp::Setattr,
+            ino,
+            self.list.map()[&ino].name(),
+        );
+        self.on_attr_change.notify(ino, &attr.inner());
+
+        Ok((attr, changes))
+    }
+
+    /// applies `change` to the attributes of `ino` and tells the observer about the result
+    fn change_attr(
+        &mut self,
+        ino: u64,
+        change: impl FnOnce(&mut fuser::FileAttr),
+    ) -> Result<FileAttribute, Errno> {
+        self.check_mount_writable()?;
+        let ino = unchecked_inode!(ino);
+        let entry = self.list.map_mut().get_mut(&ino).ok_or(ENOENT)?;
+        let attr = entry.attr_mut().inner_mut();
+        change(attr);
+        attr.ctime = self.clock.now();
+        let attr = *entry.attr();
+
+        self.attr_cache.invalidate(&ino);
+        self.journal.record(
+            self.clock.now(),
+            JournalOp::Setattr,
+            ino,
+            self.list.map()[&ino].name(),
+        );
+        self.on_attr_change.notify(ino, &attr.inner());
+
+        Ok(attr)
+    }
+
+    /// returns the first lock conflicting with `lock`, or `lock` itself as `F_UNLCK` if the range
+    /// is free
+    pub fn getlk(&mut self, ino: u64, lock: Lock) -> Result<Lock, Errno> {
+        let ino = unchecked_inode!(ino);
+        if !self.list.map().contains_key(&ino) {
+            return Err(ENOENT);
+        }
+
+        match self.locks.conflict(&ino, &lock) {
+            Some(held) => Ok(*held),
+            None => Ok(Lock {
+                typ: F_UNLCK,
+                ..lock
+            }),
+        }
+    }
+
+    /// blocking requests aren't supported yet since the handlers can't wait on another owner
+    pub fn setlk(&mut self, ino: u64, lock: Lock, sleep: bool) -> Result<(), Errno> {
+        let ino = unchecked_inode!(ino);
+        if !self.list.map().contains_key(&ino) {
+            return Err(ENOENT);
+        }
+
+        if sleep {
+            return Err(ENOSYS);
+        }
+
+        self.locks.set(ino, lock).map_err(|_| EAGAIN)
+    }
+
+    pub fn setxattr(
+        &mut self,
+        ino: u64,
+        name: &std::ffi::OsStr,
+        value: &[u8],
+        flags: i32,
+    ) -> Result<(), Errno> {
+        self.check_mount_writable()?;
+        let ino = unchecked_inode!(ino);
+        let entry = self.list.map_mut().get_mut(&ino).ok_or(ENOENT)?;
+        if name == OPAQUE_XATTR && entry.as_directory().is_none() {
+            return Err(ENOTDIR);
+        }
+        let xattrs = entry.xattrs_mut();
+        match xattrs.contains_key(name) {
+            true if flags & XATTR_CREATE != 0 => return Err(EEXIST),
+            false if flags & XATTR_REPLACE != 0 => return Err(ENODATA),
+            _ => {}
+        }
+
+        xattrs.insert(name.to_os_string(), value.to_vec());
+        entry.attr_mut().inner_mut().ctime = self.clock.now();
+        self.attr_cache.invalidate(&ino);
+
+        Ok(())
+    }
+
+    /// whether the directory `ino` hides the lower layers of an overlay, see [`OPAQUE_XATTR`]
+    pub fn is_opaque(&self, ino: Inode) -> Result<bool, Errno> {
+        let entry = self.list.map().get(&ino).ok_or(ENOENT)?;
+        if entry.as_directory().is_none() {
+            return Err(ENOTDIR);
+        }
+
+        Ok(entry
+            .xattrs()
+            .get(OsStr::new(OPAQUE_XATTR))
+            .is_some_and(|value| is_opaque(value)))
+    }
+
+    /// set xattrs first, then [`MIME_XATTR`] which is sniffed from the file's contents on demand
+    pub fn getxattr(&mut self, ino: u64, name: &std::ffi::OsStr) -> Result<Vec<u8>, Errno> {
+        self.flush_writes();
+        let entry = self.list.map().get(&unchecked_inode!(ino)).ok_or(ENOENT)?;
+        if let Some(value) = entry.xattrs().get(name) {
+            return Ok(value.clone());
+        }
+
+        if name == MIME_XATTR
+            && let Some(mime) = entry.as_file().and_then(|file| sniff(&file.data()))
+        {
+            return Ok(mime.as_bytes().to_vec());
+        }
+
+        Err(ENODATA)
+    }
+
+    /// the names of the set xattrs, each followed by a nul
+    pub fn listxattr(&mut self, ino: u64) -> Result<Vec<u8>, Errno> {
+        let entry = self.list.map().get(&unchecked_inode!(ino)).ok_or(ENOENT)?;
+        let mut names = Vec::new();
+        for name in entry.xattrs().keys() {
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+        }
+
+        Ok(names)
+    }
+
+    pub fn removexattr(&mut self, ino: u64, name: &std::ffi::OsStr) -> Result<(), Errno> {
+        self.check_mount_writable()?;
+        let ino = unchecked_inode!(ino);
+        let entry = self.list.map_mut().get_mut(&ino).ok_or(ENOENT)?;
+        entry.xattrs_mut().remove(name).ok_or(ENODATA)?;
+        entry.attr_mut().inner_mut().ctime = self.clock.now();
+        self.attr_cache.invalidate(&ino);
+
+        Ok(())
+    }
+
+    /// records whether the kernel agreed to leave the umask of `create` and `mkdir` to us, it's
+    /// already applied to the mode otherwise
+    pub fn negotiate_dont_mask(&mut self, granted: bool) {
+        self.dont_mask = granted;
+    }
+
+    /// the permission bits of `mode`, with `umask` taken off when the kernel hasn't done that
+    fn masked(&self, mode: u32, umask: u32) -> u16 {
+        match self.dont_mask {
+            true => (mode & !umask & 0o7777) as u16,
+            false => (mode & 0o7777) as u16,
+        }
+    }
+
+    /// reads at most `size` bytes, clamped to [`MAX_READ`] and never past the end of the file
+    pub fn read(&mut self, ino: u64, offset: i64, size: u32) -> Result<Cow<'_, [u8]>, Errno> {
+        // the data is borrowed from the file, so only getting to it is timed
+        let start = self.clock.now();
+        let range = self.prepare_read(ino, offset, size);
+        self.record_latency(Op::Read, start);
+        let (offset, size) = range?;
+        let ino = unchecked_inode!(ino);
+        let synthetic = if ino == STATUS_INODE {
+            Some(self.status().to_json().into_bytes())
+        } else if let Some(name) = self.metric_file(ino) {
+            Some(self.metric_contents(name))
+        } else if self.reads_as_listing(ino) {
+            Some(self.listing(ino)?)
+        } else {
+            None
+        };
+        if let Some(contents) = synthetic {
+            let start = (offset as usize).min(contents.len());
+            let end = start.saturating_add(size as usize).min(contents.len());
+            return Ok(Cow::Owned(contents[start..end].to_vec()));
+        }
+        let file = self.file(ino)?;
+
+        Ok(file.read(offset, size))
+    }
+
+    /// everything [`Daniel::read`] does before reading, returns the offset and clamped size
+    fn prepare_read(&mut self, ino: u64, offset: i64, size: u32) -> Result<(u64, u32), Errno> {
+        let offset = u64::try_from(offset).map_err(|_| EINVAL)?;
+        let size = size.min(MAX_READ);
+        self.ops.read();
+        self.flush_writes();
+        let ino = unchecked_inode!(ino);
+        if ino == STATUS_INODE || self.metric_file(ino).is_some() {
+            return Ok((offset, size));
+        }
+        if self.is_virtual(ino) {
+            return Err(EISDIR);
+        }
+        if !self.reads_as_listing(ino) {
+            self.contents.access(ino);
+            self.file(ino)?;
+        }
+        self.update_atime(ino);
+
+        Ok((offset, size))
+    }
+
+    fn reads_as_listing(&self, ino: Inode) -> bool {
+        self.dir_read_as_listing && self.directory(ino).is_ok()
+    }
+
+    /// the names in the directory `ino` in readdir order, each followed by a newline
+    fn listing(&self, ino: Inode) -> Result<Vec<u8>, Errno> {
+        let mut listing = Vec::new();
+        for (name, _, _) in self.list_dir(ino)? {
+            listing.extend_from_slice(name.as_os_str().as_encoded_bytes());
+            listing.push(b'\n');
+        }
+
+        Ok(listing)
+    }
+
+    /// moves the atime of `ino` to now if the atime policy asks for it, a read only mount is left
+    /// alone
+    fn update_atime(&mut self, ino: Inode) {
+        if self.read_only {
+            return;
+        }
+
+        let now = self.clock.now();
+        let Some(entry) = self.list.map_mut().get_mut(&ino) else {
+            return;
+        };
+        let attr = entry.attr_mut().inner_mut();
+        if self.atime_policy.updates(attr, now) {
+            attr.atime = now;
+            self.attr_cache.invalidate(&ino);
+        }
+    }
+
+    pub fn write(&mut self, ino: u64, offset: i64, data: &[u8]) -> Result<u32, Errno> {
+        self.timed(Op::Write, |fs| {
+            let offset = u64::try_from(offset).map_err(|_| EINVAL)?;
+            fs.ops.write();
+            fs.flush_writes();
+            fs.write_at(unchecked_inode!(ino), offset, data)
+        })
+    }
+
+    /// `EROFS` on a read only mount, before anything about the file itself is looked at
+    fn check_mount_writable(&self) -> Result<(), Errno> {
+        match self.read_only {
+            true => Err(EROFS),
+            false => Ok(()),
+        }
+    }
+
+    /// `EPERM` for an immutable or append only entry
+    fn check_mutable(&self, ino: Inode) -> Result<(), Errno> {
+        self.check_unflagged(ino, FS_IMMUTABLE_FL | FS_APPEND_FL)
+    }
+
+    /// `EPERM` if any of `flags` is set on `ino`
+    fn check_unflagged(&self, ino: Inode, flags: u32) -> Result<(), Errno> {
+        let entry = self.list.map().get(&ino).ok_or(ENOENT)?;
+        match entry.attr().inner().flags & flags {
+            0 => Ok(()),
+            _ => Err(EPERM),
+        }
+    }
+
+    /// `EROFS` on a read only mount or for files on read only storage, `EPERM` for immutable or
+    /// append only files and `EACCES` for the status file. whether the caller may write it is
+    /// checked with [`check_permission`] where the caller is known, by open and setattr
+    fn check_writable(&self, ino: Inode) -> Result<(), Errno> {
+        self.check_writable_unless(ino, FS_IMMUTABLE_FL | FS_APPEND_FL)
+    }
+
+    /// [`Daniel::check_writable`] for a write at `offset`, which an append only file allows at its
+    /// end
+    fn check_writable_at(&self, ino: Inode, offset: u64) -> Result<(), Errno> {
+        let appending = self.file(ino).is_ok_and(|file| {
+            let attr = file.attr().inner();
+            attr.flags & FS_APPEND_FL != 0 && attr.size == offset
+        });
+        match appending {
+            true => self.check_writable_unless(ino, FS_IMMUTABLE_FL),
+            false => self.check_writable(ino),
+        }
+    }
+
+    fn check_writable_unless(&self, ino: Inode, flags: u32) -> Result<(), Errno> {
+        self.check_mount_writable()?;
+        if self.is_virtual(ino) {
+            return Err(EACCES);
+        }
+        self.check_unflagged(ino, flags)?;
+        if ino == STATUS_INODE {
+            return Err(EACCES);
+        }
+        let file = self.file(ino)?;
+        if file.storage().read_only() {
+            return Err(EROFS);
+        }
+
+        Ok(())
+    }
+
+    /// which of `events` are ready on `ino`, it's readable once it holds data, buffered or not, and
+    /// writable unless it's read only. `kh` is woken on the next change to the file
+    pub fn poll(&mut self, ino: u64, kh: Option<u64>, events: u32) -> Result<u32, Errno> {
+        let ino = unchecked_inode!(ino);
+        let file = self.file(ino)?;
+        let buffered = self
+            .write_buffers
+            .range((ino, 0)..=(ino, u64::MAX))
+            .any(|(_, buf)| !buf.is_empty());
+
+        let mut ready = 0;
+        if ino == STATUS_INODE || !file.storage().is_empty() || buffered {
+            ready |= POLLIN;
+        }
+        if self.check_writable(ino).is_ok() {
+            ready |= POLLOUT;
+        }
+
+        if let Some(kh) = kh {
+            self.poll_handles.entry(ino).or_default().insert(kh);
+        }
+
+        Ok(ready & events)
+    }
+
+    /// queues a wakeup for everyone polling `ino`
+    fn wake_pollers(&mut self, ino: Inode) {
+        for kh in self.poll_handles.remove(&ino).unwrap_or_default() {
+            self.queue_notification(Notification::Poll(kh));
+        }
+    }
+
+    fn write_at(&mut self, ino: Inode, offset: u64, data: &[u8]) -> Result<u32, Errno> {
+        self.check_writable_at(ino, offset)?;
+        let end = offset.saturating_add(data.len() as u64);
+        self.reserve(ino, offset, end)?;
+        self.allocate(ino, offset, end)?;
+        let now = self.clock.now();
+        let written = self.update_contents(ino, |file| file.write(offset, data) as u32)?;
+        let file = self.file_mut(ino)?;
+
+        file.touch(now);
+        let name = file.name().to_path_buf();
+        self.journal
+            .record(self.clock.now(), JournalOp::Write, ino, name);
+        self.attr_cache.invalidate(&ino);
+        self.charge(ino);
+        self.wake_pollers(ino);
+
+        Ok(written)
+    }
+
+    /// updates what `ino` holds against the content budget and evicts the least recently used
+    /// other files until everything fits again
+    fn charge(&mut self, ino: Inode) {
+        if !self.contents.is_enabled() {
+            return;
+        }
+        let Ok(file) = self.file(ino) else {
+            return;
+        };
+
+        self.contents.touch(ino, file.storage().stored_len());
+        while self.contents.over_budget()
+            && let Some(victim) = self.contents.pop_lru(ino)
+        {
+            let _ = self.update_contents(victim, File::evict);
+            debug!(ino = u64::from(victim), "evicted contents");
+            self.attr_cache.invalidate(&victim);
+            self.queue_notification(Notification::Inode(victim.into()));
+        }
+    }
+
+    /// like [`Daniel::write`] but appends to the handle's buffer when `offset` continues the
+    /// previous write, the buffer is applied once full, on a non sequential write, or when
+    /// anything else could observe the file
+    pub fn buffered_write(
+        &mut self,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+    ) -> Result<u32, Errno> {
+        let capacity = self.write_buffer_capacity;
+        // appends to an append only file are checked against its size as they're made
+        let append_only = self
+            .check_unflagged(unchecked_inode!(ino), FS_APPEND_FL)
+            .is_err();
+        if capacity == 0 || data.len() >= capacity || append_only {
+            return self.write(ino, offset, data);
+        }
+
+        let offset = u64::try_from(offset).map_err(|_| EINVAL)?;
+        let ino = unchecked_inode!(ino);
+        self.check_writable(ino)?;
+        self.ops.write();
+
+        let key = (ino, fh);
+        if self
+            .write_buffers
+            .get(&key)
+            .is_none_or(|buf| buf.end() != offset)
+        {
+            self.flush_writes();
+        }
+
+        let buf = self
+            .write_buffers
+            .entry(key)
+            .or_insert_with(|| WriteBuffer::new(offset, capacity));
+        let kept = buf.len();
+        buf.append(data);
+        let applied = match buf.len() >= capacity {
+            true => self.flush_handle(ino, fh),
+            // the buffered bytes hold their space under a byte quota until they're applied
+            false => self.check_space(),
+        };
+        if let Err(err) = applied {
+            // this write failed, what was buffered before it is still waiting
+            match kept {
+                0 => _ = self.write_buffers.remove(&key),
+                _ => self.write_buffers.get_mut(&key).unwrap().truncate(kept),
+            }
+            return Err(err);
+        }
+        self.wake_pollers(ino);
+
+        Ok(data.len() as u32)
+    }
+
+    /// a write as received from the kernel, writeback cache writes flush dirty pages in whatever
+    /// order the kernel picks, possibly past the current end of the file, so they're applied
+    /// directly instead of going through the sequential write buffer
+    pub fn write_with_flags(
+        &mut self,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        write_flags: u32,
+    ) -> Result<u32, Errno> {
+        if write_flags & FUSE_WRITE_CACHE == 0 {
+            return self.buffered_write(ino, fh, offset, data);
+        }
+
+        debug!(ino, offset, len = data.len(), "writeback cache write");
+        self.write(ino, offset, data)
+    }
+
+    /// applies the buffered writes of `fh`. they're kept when that fails, so the error is
+    /// reported again on the next flush, fsync or release of the handle
+    pub fn flush_handle(&mut self, ino: Inode, fh: u64) -> Result<(), Errno> {
+        let Some(buf) = self.write_buffers.remove(&(ino, fh)) else {
+            return Ok(());
+        };
+        match self.write_at(ino, buf.offset(), buf.data()) {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                self.write_buffers.insert((ino, fh), buf);
+                Err(err)
+            }
+        }
+    }
+
+    /// applies every pending buffered write. one that fails is left for its own handle to report
+    /// instead of failing whichever request needed the file up to date
+    pub fn flush_writes(&mut self) {
+        let buffered: Vec<_> = self.write_buffers.keys().copied().collect();
+        for (ino, fh) in buffered {
+            if let Err(err) = self.flush_handle(ino, fh) {
+                debug!(
+                    ino = u64::from(ino),
+                    fh,
+                    ?err,
+                    "keeping a buffered write that failed"
+                );
+            }
+        }
+    }
+
+    pub fn truncate(&mut self, ino: u64, size: u64) -> Result<(), Errno> {
+        self.check_writable(unchecked_inode!(ino))?;
+        self.flush_writes();
+        self.reserve(unchecked_inode!(ino), size, size)?;
+        self.allocate(unchecked_inode!(ino), size, size)?;
+        let now = self.clock.now();
+        let before = self.file(unchecked_inode!(ino))?.storage().len();
+        self.update_contents(unchecked_inode!(ino), |file| file.truncate(size))?;
+        // shrinking frees what fallocate reserved past the end too
+        if size < before {
+            self.usage.unreserve(unchecked_inode!(ino), size);
+        }
+        self.file_mut(unchecked_inode!(ino))?.touch(now);
+        self.attr_cache.invalidate(&unchecked_inode!(ino));
+        self.charge(unchecked_inode!(ino));
+        self.wake_pollers(unchecked_inode!(ino));
+        self.queue_notification(Notification::Inode(ino));
+
+        Ok(())
+    }
+
+    /// truncates the file and writes `data` in its place under the one lock, so nothing sees it
+    /// empty or half written
+    pub fn replace_contents(&mut self, ino: u64, data: &[u8]) -> Result<(), Errno> {
+        let ino = unchecked_inode!(ino);
+        self.check_writable(ino)?;
+        self.flush_writes();
+        self.reserve(ino, 0, data.len() as u64)?;
+        self.allocate(ino, 0, data.len() as u64)?;
+        let now = self.clock.now();
+        self.update_contents(ino, |file| {
+            file.truncate(0);
+            file.write(0, data);
+        })?;
+        let file = self.file_mut(ino)?;
+
+        file.touch(now);
+        let name = file.name().to_path_buf();
+        self.journal
+            .record(self.clock.now(), JournalOp::Write, ino, name);
+        self.attr_cache.invalidate(&ino);
+        self.charge(ino);
+        self.wake_pollers(ino);
+        self.queue_notification(Notification::Inode(ino.into()));
+
+        Ok(())
+    }
+
+    /// grows the file to cover `offset..offset + length` without storing anything for the new
+    /// range, or only reserves the space past its end with `FALLOC_FL_KEEP_SIZE`. punching holes
+    /// isn't supported
+    pub fn fallocate(
+        &mut self,
+        ino: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+    ) -> Result<(), Errno> {
+        let (Ok(offset), Ok(length)) = (u64::try_from(offset), u64::try_from(length)) else {
+            return Err(EINVAL);
+        };
+        if length == 0 {
+            return Err(EINVAL);
+        }
+        self.check_writable(unchecked_inode!(ino))?;
+        let end = offset.checked_add(length).ok_or(EINVAL)?;
+
+        match mode {
+            0 => {
+                if end > self.file(unchecked_inode!(ino))?.storage().len() {
+                    self.truncate(ino, end)?;
+                }
+                Ok(())
+            }
+            // nothing is stored, but the space is held until a truncate shrinks the file
+            _ if mode == FALLOC_FL_KEEP_SIZE => {
+                let ino = unchecked_inode!(ino);
+                self.reserve(ino, offset, end)?;
+                let len = self.file(ino)?.storage().len();
+                self.usage.reserve(ino, len, end);
+                Ok(())
+            }
+            _ => Err(EOPNOTSUPP),
+        }
+    }
+
+    /// copies `len` bytes between files, sharing the source's data outright when the whole file is
+    /// copied over a destination that's no larger than it
+    pub fn copy_file_range(
+        &mut self,
+        ino_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        offset_out: i64,
+        len: u64,
+    ) -> Result<u32, Errno> {
+        let offset_in = u64::try_from(offset_in).map_err(|_| EINVAL)?;
+        let offset_out = u64::try_from(offset_out).map_err(|_| EINVAL)?;
+        self.flush_writes();
+        let src_file = self.file(unchecked_inode!(ino_in))?;
+        // contents already kept in an `Arc` are shared without taking any more space
+        let copies = src_file.storage().shared().is_none();
+        let src = src_file.shared_data();
+        let ino_out = unchecked_inode!(ino_out);
+        self.check_writable(ino_out)?;
+        let now = self.clock.now();
+
+        let len = len.min(u32::MAX as u64) as usize;
+        let start = (offset_in as usize).min(src.len());
+        let end = start.saturating_add(len).min(src.len());
+        let share = offset_in == 0
+            && offset_out == 0
+            && len >= src.len()
+            && self.file(ino_out)?.storage().len() <= src.len() as u64;
+        match share {
+            true if copies => self.reserve(ino_out, 0, src.len() as u64)?,
+            true => {}
+            false => {
+                let end = offset_out.saturating_add((end - start) as u64);
+                self.reserve(ino_out, offset_out, end)?;
+                self.allocate(ino_out, offset_out, end)?;
+            }
+        }
+
+        let written = self.update_contents(ino_out, |dst| match share {
+            true => {
+                let written = src.len() as u32;
+                dst.share(src);
+                written
+            }
+            false => dst.write(offset_out, &src[start..end]) as u32,
+        })?;
+        let dst = self.file_mut(ino_out)?;
+        dst.touch(now);
+        let name = dst.name().to_path_buf();
+        self.journal
+            .record(self.clock.now(), JournalOp::Write, ino_out, name);
+        self.attr_cache.invalidate(&ino_out);
+        self.charge(ino_out);
+        self.wake_pollers(ino_out);
+
+        Ok(written)
+    }
+
+    /// a new file `name` in `parent` with the contents of `src`, like `cp --reflink`. the two share
+    /// their data copy-on-write until either is written, everything else about the copy is new
+    pub fn reflink(&mut self, src: Inode, parent: Inode, name: &OsStr) -> Result<Inode, Errno> {
+        self.flush_writes();
+        let file = self.file(src)?;
+        if file.attr().inner().kind != FileType::RegularFile {
+            return Err(EINVAL);
+        }
+        // contents already kept in an `Arc` are shared without taking any more space, anything
+        // else is copied. the copy doesn't exist yet, so there's nothing to reserve against
+        let copied = match file.storage().shared() {
+            Some(_) => 0,
+            None => file.storage().len(),
+        };
+        if copied > 0
+            && self
+                .max_bytes
+                .is_some_and(|max| self.used_bytes().saturating_add(copied) > max)
+        {
+            return Err(ENOSPC);
+        }
+        let data = file.shared_data();
+        let perms = file.attr().inner().perm;
+
+        let ino = unchecked_inode!(self.create(parent, name, 0, perms)?.inner().ino);
+        self.update_contents(ino, |file| file.share(data))?;
+        self.charge(ino);
+
+        Ok(ino)
+    }
+
+    pub fn content_hash(&self, ino: u64) -> Option<u64> {
+        self.list
+            .map()
+            .get(&unchecked_inode!(ino))?
+            .as_file()
+            .map(File::content_hash)
+    }
+}
+
+impl std::fmt::Display for Daniel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.mapper)?;
+        write!(f, "{}", self.list)?;
+
+        Ok(())
+    }
+}
+
+/// a tar header with the mode, owner and mtime of `attr` and no contents
+fn tar_header(attr: &fuser::FileAttr) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(attr.perm.into());
+    header.set_uid(attr.uid.into());
+    header.set_gid(attr.gid.into());
+    let mtime = attr.mtime.duration_since(time::UNIX_EPOCH);
+    header.set_mtime(mtime.unwrap_or_default().as_secs());
+    header.set_size(0);
+    header
+}
+
+/// whether `uid` in group `gid` may do everything in `mask` to `attr`, going by the owner, group
+/// or other bits, whichever class the caller falls in first. root may do anything except execute
+/// a file without any execute bit
+pub fn check_permission(attr: &fuser::FileAttr, uid: u32, gid: u32, mask: i32) -> bool {
+    let mask = (mask & (R_OK | W_OK | X_OK)) as u16;
+    if uid == 0 {
+        let executable = attr.kind == FileType::Directory || attr.perm & 0o111 != 0;
+        return mask & X_OK as u16 == 0 || executable;
+    }
+
+    let granted = if uid == attr.uid {
+        attr.perm >> 6
+    } else if gid == attr.gid {
+        attr.perm >> 3
+    } else {
+        attr.perm
+    };
+    granted & mask == mask
+}
+
+/// the permission bits `mode` leaves `attr` with when `uid` in group `gid` changes them, `EPERM`
+/// unless it's the owner or root. setgid is dropped when the caller isn't in the file's group
+fn permitted_mode(attr: &fuser::FileAttr, mode: u32, uid: u32, gid: u32) -> Result<u16, Errno> {
+    if uid != 0 && uid != attr.uid {
+        return Err(EPERM);
+    }
+
+    let mut perm = (mode & 0o7777) as u16;
+    if uid != 0 && gid != attr.gid {
+        perm &= !(libc::S_ISGID as u16);
+    }
+    Ok(perm)
+}
+
+/// `EPERM` unless `uid` is root or the owner and group stay as they are
+fn check_owner_change(
+    attr: &fuser::FileAttr,
+    owner: Option<u32>,
+    group: Option<u32>,
+    uid: u32,
+) -> Result<(), Errno> {
+    let changes = owner.is_some_and(|owner| owner != attr.uid)
+        || group.is_some_and(|group| group != attr.gid);
+    match changes && uid != 0 {
+        true => Err(EPERM),
+        false => Ok(()),
+    }
+}
+
+/// the names along a path relative to the root, `EINVAL` if it climbs out with `..`
+fn path_names(path: &Path) -> Result<Vec<&OsStr>, Errno> {
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::RootDir | Component::CurDir => {}
+            Component::Normal(name) => names.push(name),
+            Component::ParentDir | Component::Prefix(_) => return Err(EINVAL),
+        }
+    }
+
+    Ok(names)
+}
+
+/// sets every time of `entry` to `now`
+fn stamp(entry: &mut DirEntry, now: time::SystemTime) {
+    let attr = entry.attr_mut().inner_mut();
+    attr.atime = now;
+    attr.mtime = now;
+    attr.ctime = now;
+    attr.crtime = now;
+}
+
+/// a `size` of 0 asks how big the value is, anything smaller than the value is an error
+fn reply_xattr(reply: fuser::ReplyXattr, size: u32, value: &[u8]) {
+    if size == 0 {
+        reply.size(value.len() as u32);
+    } else if value.len() > size as usize {
+        reply.error(ERANGE.as_i32());
+    } else {
+        reply.data(value);
+    }
+}
+
+impl fuser::Filesystem for Daniel {
+    #[instrument(skip(self, req, reply))]
+    fn create(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        mode: u32,
+        umask: u32,
+        flags: i32,
+        reply: fuser::ReplyCreate,
+    ) {
+        let perms = self.masked(mode, umask);
+        let parent = unchecked_inode!(parent);
+        let existed = self.mapper.get_map(parent, name).is_some();
+        let created = self.create_or_open(parent, name, perms, flags, req.uid(), req.gid());
+        let attr = match created {
+            Ok(attr) => attr.inner(),
+            Err(err) => {
+                reply.error(err.as_i32());
+                return;
+            }
+        };
+
+        let generation = self.generation(unchecked_inode!(attr.ino));
+        let opened = match existed {
+            true => self.open(attr.ino, flags, req.uid(), req.gid()),
+            false => self.open_handle(attr.ino, flags),
+        };
+        match opened {
+            Ok(fh) => {
+                self.remember(attr.ino);
+                reply.created(
+                    &self.ttl(attr.ino),
+                    &attr,
+                    generation as u64,
+                    fh,
+                    flags as u32,
+                )
+            }
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    #[instrument(skip(self, req, reply))]
+    fn mkdir(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        mode: u32,
+        umask: u32,
+        reply: fuser::ReplyEntry,
+    ) {
+        match self.mkdir(parent, name, mode, umask, req.uid(), req.gid()) {
+            Ok(attr) => {
+                let generation = self.generation(unchecked_inode!(attr.inner().ino));
+                self.remember(attr.inner().ino);
+                reply.entry(
+                    &self.ttl(attr.inner().ino),
+                    &attr.inner(),
+                    generation as u64,
+                )
+            }
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    #[instrument(skip(self, _req, reply))]
+    fn readdir(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: fuser::ReplyDirectory,
+    ) {
+        if let Err(err) = self.check_handle(ino, fh) {
+            reply.error(err.as_i32());
+            return;
+        }
+
+        let entries = match self.readdir_after(unchecked_inode!(ino), offset as u64) {
+            Ok(entries) => entries,
+            Err(err) => {
+                reply.error(err.as_i32());
+                return;
+            }
+        };
+
+        for (cookie, ino, kind, name) in entries {
+            if reply.add(ino.into(), cookie as i64, kind.into(), &name) {
+                break;
+            }
+        }
+
+        reply.ok();
+    }
+
+    #[instrument(skip(self, _req, reply), fields(path = Empty, kind = Empty))]
+    fn lookup(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        reply: fuser::ReplyEntry,
+    ) {
+        let attr = match self.lookup(parent, name) {
+            Ok(attr) => attr,
+            Err(err) => {
+                reply.error(err.as_i32());
+                return;
+            }
+        };
+        self.record_entry(unchecked_inode!(attr.inner().ino));
+        let generation = self.generation(unchecked_inode!(attr.inner().ino));
+        self.remember(attr.inner().ino);
+        reply.entry(
+            &self.ttl(attr.inner().ino),
+            &attr.inner(),
+            generation as u64,
+        );
+    }
+
+    #[instrument(skip(self, req, reply))]
+    fn access(&mut self, req: &fuser::Request<'_>, ino: u64, mask: i32, reply: fuser::ReplyEmpty) {
+        let res = self.access(ino, mask, req.uid(), req.gid());
+        match res {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    #[instrument(skip(self, _req, reply), fields(path = Empty, kind = Empty))]
+    fn getattr(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        fh: Option<u64>,
+        reply: fuser::ReplyAttr,
+    ) {
+        self.record_entry(unchecked_inode!(ino));
+        match self.getattr(ino, fh) {
+            Ok(attr) => reply.attr(&self.ttl(ino), &attr.inner()),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    #[instrument(skip(self, req, reply), fields(path = Empty, kind = Empty))]
+    fn unlink(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        reply: fuser::ReplyEmpty,
+    ) {
+        // recorded while the entry still has its path
+        if let Some(&ino) = self.mapper.get_map(unchecked_inode!(parent), name) {
+            self.record_entry(ino);
+        }
+        let res = self.unlink(parent, name, req.uid());
+        match res {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err.as_i32()),
+        }
+        self.flush_notifications();
+    }
+
+    fn init(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        config: &mut fuser::KernelConfig,
+    ) -> Result<(), c_int> {
+        let dont_mask = config.add_capabilities(FUSE_DONT_MASK).is_ok();
+        self.negotiate_dont_mask(dont_mask);
+        info!(dont_mask, "negotiated umask handling");
+
+        Ok(())
+    }
+
+    fn destroy(&mut self) {
+        self.destroy();
+    }
+
+    fn forget(&mut self, _req: &fuser::Request<'_>, ino: u64, nlookup: u64) {
+        self.forget(ino, nlookup);
+    }
+
+    #[instrument(skip(self, _req, nodes), fields(nodes = nodes.len()))]
+    fn batch_forget(&mut self, _req: &fuser::Request<'_>, nodes: &[fuser::fuse_forget_one]) {
+        self.batch_forget(nodes.iter().map(|node| (node.nodeid, node.nlookup)));
+    }
+
+    fn setattr(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<fuser::TimeOrNow>,
+        mtime: Option<fuser::TimeOrNow>,
+        ctime: Option<std::time::SystemTime>,
+        _fh: Option<u64>,
+        crtime: Option<std::time::SystemTime>,
+        _chgtime: Option<std::time::SystemTime>,
+        _bkuptime: Option<std::time::SystemTime>,
+        flags: Option<u32>,
+        reply: fuser::ReplyAttr,
+    ) {
+        let now = self.clock.now();
+        let resolve = |time: fuser::TimeOrNow| match time {
+            fuser::TimeOrNow::SpecificTime(system_time) => system_time,
+            fuser::TimeOrNow::Now => now,
+        };
+        let patch = AttrPatch {
+            mode,
+            uid,
+            gid,
+            size,
+            atime: atime.map(resolve),
+            mtime: mtime.map(resolve),
+            ctime,
+            crtime,
+            flags,
+        };
+
+        match self.setattr(ino, patch, req.uid(), req.gid()) {
+            Ok((attr, _)) => reply.attr(&self.ttl(ino), &attr.inner()),
+            Err(err) => reply.error(err.as_i32()),
+        }
+        self.flush_notifications();
+    }
+
+    fn readlink(&mut self, _req: &fuser::Request<'_>, ino: u64, reply: fuser::ReplyData) {
+        match self.readlink(ino) {
+            Ok(target) => reply.data(&target),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    fn mknod(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        mode: u32,
+        _umask: u32,
+        rdev: u32,
+        reply: fuser::ReplyEntry,
+    ) {
+        match self.mknod(parent, name, mode, rdev, req.uid(), req.gid()) {
+            Ok(attr) => {
+                let generation = self.generation(unchecked_inode!(attr.inner().ino));
+                self.remember(attr.inner().ino);
+                reply.entry(
+                    &self.ttl(attr.inner().ino),
+                    &attr.inner(),
+                    generation as u64,
+                )
+            }
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    #[instrument(skip(self, req, reply), fields(path = Empty, kind = Empty))]
+    fn rmdir(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        reply: fuser::ReplyEmpty,
+    ) {
+        if let Some(&ino) = self.mapper.get_map(unchecked_inode!(parent), name) {
+            self.record_entry(ino);
+        }
+        match self.rmdir(parent, name, req.uid()) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err.as_i32()),
+        }
+        self.flush_notifications();
+    }
+
+    fn symlink(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        link_name: &std::ffi::OsStr,
+        target: &Path,
+        reply: fuser::ReplyEntry,
+    ) {
+        match self.symlink(parent, link_name, target, req.uid(), req.gid()) {
+            Ok(attr) => {
+                let generation = self.generation(unchecked_inode!(attr.inner().ino));
+                self.remember(attr.inner().ino);
+                reply.entry(
+                    &self.ttl(attr.inner().ino),
+                    &attr.inner(),
+                    generation as u64,
+                )
+            }
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    fn rename(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        newparent: u64,
+        newname: &std::ffi::OsStr,
+        flags: u32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        if flags & !RENAME_WHITEOUT != 0 {
+            debug!(
+                "[Not Implemented] rename(parent: {:#x?}, name: {:?}, newparent: {:#x?}, \
+                newname: {:?}, flags: {})",
+                parent, name, newparent, newname, flags,
+            );
+            reply.error(EINVAL.as_i32());
+            return;
+        }
+
+        let res = self
+            .rename(parent, name, newparent, newname, req.uid())
+            .and_then(|()| match flags & RENAME_WHITEOUT {
+                0 => Ok(()),
+                _ => self.whiteout(parent, name).map(|_| ()),
+            });
+        match res {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err.as_i32()),
+        }
+        self.flush_notifications();
+    }
+
+    fn link(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        newparent: u64,
+        newname: &std::ffi::OsStr,
+        reply: fuser::ReplyEntry,
+    ) {
+        match self.link(ino, newparent, newname) {
+            Ok(attr) => {
+                let generation = self.generation(unchecked_inode!(ino));
+                self.remember(ino);
+                reply.entry(&self.ttl(ino), &attr.inner(), generation as u64)
+            }
+            Err(err) => reply.error(err.as_i32()),
+        }
+        self.flush_notifications();
+    }
+
+    fn open(&mut self, req: &fuser::Request<'_>, ino: u64, flags: i32, reply: fuser::ReplyOpen) {
+        let fh = match self.open(ino, flags, req.uid(), req.gid()) {
+            Ok(fh) => fh,
+            Err(err) => {
+                reply.error(err.as_i32());
+                return;
+            }
+        };
+
+        // the status file reports a size of 0, direct io makes the kernel read it anyway
+        if unchecked_inode!(ino) == STATUS_INODE {
+            reply.opened(fh, fuser::consts::FOPEN_DIRECT_IO);
+        } else {
+            reply.opened(fh, 0);
+        }
+    }
+
+    #[instrument(skip(self, _req, reply), fields(path = Empty, kind = Empty))]
+    fn read(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: fuser::ReplyData,
+    ) {
+        self.record_entry(unchecked_inode!(ino));
+        if let Err(err) = self.check_handle(ino, fh) {
+            reply.error(err.as_i32());
+            return;
+        }
+
+        match self.read(ino, offset, size) {
+            Ok(data) => reply.data(&data),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    #[instrument(
+        skip(self, _req, data, reply),
+        fields(len = data.len(), path = Empty, kind = Empty)
+    )]
+    fn write(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: fuser::ReplyWrite,
+    ) {
+        self.record_entry(unchecked_inode!(ino));
+        if let Err(err) = self.check_handle(ino, fh) {
+            reply.error(err.as_i32());
+            return;
+        }
+
+        match self.write_with_flags(ino, fh, offset, data, write_flags) {
+            Ok(written) => reply.written(written),
+            Err(err) => reply.error(err.as_i32()),
+        }
+        self.flush_notifications();
+    }
+
+    fn poll(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        ph: fuser::PollHandle,
+        events: u32,
+        flags: u32,
+        reply: fuser::ReplyPoll,
+    ) {
+        if let Err(err) = self.check_handle(ino, fh) {
+            reply.error(err.as_i32());
+            return;
+        }
+
+        let kh = (flags & FUSE_POLL_SCHEDULE_NOTIFY != 0).then(|| ph.into());
+        match self.poll(ino, kh, events) {
+            Ok(revents) => reply.poll(revents),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    fn flush(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        _lock_owner: u64,
+        reply: fuser::ReplyEmpty,
+    ) {
+        match self.flush_handle(unchecked_inode!(ino), fh) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        _flags: i32,
+        lock_owner: Option<u64>,
+        _flush: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        if let Some(owner) = lock_owner {
+            self.locks.release(&unchecked_inode!(ino), owner);
+        }
+        match self.release(ino, fh) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    fn fsync(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        debug!(ino, fh, datasync, "fsync");
+        let synced = self
+            .flush_handle(unchecked_inode!(ino), fh)
+            .and_then(|()| self.fsync());
+        match synced {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    fn opendir(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        _flags: i32,
+        reply: fuser::ReplyOpen,
+    ) {
+        match self
+            .opendir(ino)
+            .and_then(|()| self.open_handle(ino, O_DIRECTORY))
+        {
+            Ok(fh) => reply.opened(fh, 0),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    fn readdirplus(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        reply: fuser::ReplyDirectoryPlus,
+    ) {
+        debug!(
+            "[Not Implemented] readdirplus(ino: {:#x?}, fh: {}, offset: {})",
+            ino, fh, offset
+        );
+        reply.error(ENOSYS.as_i32());
+    }
+
+    fn releasedir(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        _flags: i32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        debug!(ino, fh, "releasedir");
+        self.close_handle(fh);
+        reply.ok();
+    }
+
+    fn fsyncdir(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        debug!(ino, fh, datasync, "fsyncdir");
+        match self.fsync() {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    fn statfs(&mut self, _req: &fuser::Request<'_>, ino: u64, reply: fuser::ReplyStatfs) {
+        let stats = self.statfs(ino);
+        reply.statfs(
+            stats.blocks,
+            stats.bfree,
+            stats.bavail,
+            stats.files,
+            stats.ffree,
+            stats.bsize,
+            stats.namelen,
+            stats.frsize,
+        );
+    }
+
+    fn setxattr(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        name: &std::ffi::OsStr,
+        value: &[u8],
+        flags: i32,
+        _position: u32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        match self.setxattr(ino, name, value, flags) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    fn getxattr(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        name: &std::ffi::OsStr,
+        size: u32,
+        reply: fuser::ReplyXattr,
+    ) {
+        match self.getxattr(ino, name) {
+            Ok(value) => reply_xattr(reply, size, &value),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    fn listxattr(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        size: u32,
+        reply: fuser::ReplyXattr,
+    ) {
+        match self.listxattr(ino) {
+            Ok(names) => reply_xattr(reply, size, &names),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    fn removexattr(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        name: &std::ffi::OsStr,
+        reply: fuser::ReplyEmpty,
+    ) {
+        match self.removexattr(ino, name) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    fn getlk(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        _fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+        reply: fuser::ReplyLock,
+    ) {
+        match self.getlk(ino, Lock::new(lock_owner, start, end, typ, pid)) {
+            Ok(lock) => reply.locked(lock.start, lock.end, lock.typ, lock.pid),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    fn setlk(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        _fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+        sleep: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        match self.setlk(ino, Lock::new(lock_owner, start, end, typ, pid), sleep) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    fn bmap(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+        reply: fuser::ReplyBmap,
+    ) {
+        debug!(
+            "[Not Implemented] bmap(ino: {:#x?}, blocksize: {}, idx: {})",
+            ino, blocksize, idx,
+        );
+        reply.error(ENOSYS.as_i32());
+    }
+
+    #[instrument(skip(self, req, in_data, reply))]
+    fn ioctl(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        _fh: u64,
+        _flags: u32,
+        cmd: u32,
+        in_data: &[u8],
+        _out_size: u32,
+        reply: fuser::ReplyIoctl,
+    ) {
+        match self.ioctl(ino, cmd, in_data, req.uid()) {
+            Ok(out) => reply.ioctl(0, &out),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+
+    fn fallocate(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        if let Err(err) = self.check_handle(ino, fh) {
+            reply.error(err.as_i32());
+            return;
+        }
+
+        match self.fallocate(ino, offset, length, mode) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err.as_i32()),
+        }
+        self.flush_notifications();
+    }
+
+    fn lseek(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        whence: i32,
+        reply: fuser::ReplyLseek,
+    ) {
+        debug!(
+            "[Not Implemented] lseek(ino: {:#x?}, fh: {}, offset: {}, whence: {})",
+            ino, fh, offset, whence
+        );
+        reply.error(ENOSYS.as_i32());
+    }
+
+    fn copy_file_range(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino_in: u64,
+        _fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        _fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        _flags: u32,
+        reply: fuser::ReplyWrite,
+    ) {
+        match self.copy_file_range(ino_in, offset_in, ino_out, offset_out, len) {
+            Ok(written) => reply.written(written),
+            Err(err) => reply.error(err.as_i32()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::{
+        collections::HashMap,
+        ffi::OsString,
+        ops::ControlFlow,
+        os::unix::ffi::OsStringExt,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    };
+
+    use fuser::FileType;
+
+    use tracing::{info, instrument, level_filters::LevelFilter};
+    use tracing_subscriber::{fmt::format::FmtSpan, util::SubscriberInitExt};
+
+    use crate::{
+        filesystem::{
+            AtimePolicy, AttrChanges, AttrPatch, COMPRESS_CHUNK, DirEntry, Directory, EntryType,
+            F_RDLCK, F_UNLCK, F_WRLCK, File, FileHandle, ImportProblem, Inode, JournalOp,
+            LATENCY_BUCKETS, Lock, MIME_XATTR, ManualClock, Memory, Notification, Notify,
+            OPAQUE_XATTR, Op, OpenHandle, RELATIME_MAX_AGE, Storage, UnsupportedEntry,
+        },
+        unchecked_inode,
+    };
+
+    use super::{
+        Daniel, FALLOC_FL_KEEP_SIZE, METRICS_DIR, METRICS_INODE, ROOT_INODE, STATUS_FILE,
+        STATUS_INODE, check_permission,
+    };
+
+    pub(crate) fn init() {
+        let _ = tracing_subscriber::FmtSubscriber::builder()
+            .with_ansi(true)
+            .with_max_level(LevelFilter::INFO)
+            .with_span_events(FmtSpan::ACTIVE)
+            .finish()
+            .try_init();
+    }
+
+    /// a new empty directory for `test` under the system temp dir, made with mkdtemp so tests
+    /// running at the same time never share one
+    pub(crate) fn temp_dir(test: &str) -> PathBuf {
+        let template = std::env::temp_dir().join(format!("daniel-{test}-XXXXXX"));
+        let mut template = template.into_os_string().into_vec();
+        template.push(0);
+        // SAFETY: `template` is a writable nul terminated path ending in XXXXXX, mkdtemp only
+        // replaces those in place
+        let dir = unsafe { libc::mkdtemp(template.as_mut_ptr().cast()) };
+        assert!(!dir.is_null(), "{}", std::io::Error::last_os_error());
+        template.pop();
+
+        PathBuf::from(OsString::from_vec(template))
+    }
+
+    #[test]
+    #[instrument]
+    pub fn default() {
+        init();
+        let fs = Daniel::default();
+
+        // the root and the status file
+        assert_eq!(fs.mapper.map().len(), 2);
+        assert_eq!(fs.list.map().len(), 2);
+
+        info!(%fs);
+    }
+
+    #[test]
+    #[instrument]
+    pub fn empty_dir() {
+        init();
+
+        let mut fs = Daniel::default();
+        fs.push(DirEntry::Directory(Directory::new(
+            ROOT_INODE,
+            "foo".into(),
+            unchecked_inode!(2),
+            0o755,
+        )))
+        .unwrap();
+
+        assert_eq!(fs.mapper.map().len(), 3);
+        assert_eq!(fs.list.map().len(), 3);
+
+        info!(%fs);
+    }
+
+    #[test]
+    #[instrument]
+    pub fn filled_dir() {
+        init();
+
+        let _s = tracing::info_span!("filled_dir");
+        let _s = _s.entered();
+
+        let mut fs = Daniel::default();
+
+        fs.push(DirEntry::Directory(Directory::new(
+            ROOT_INODE,
+            "foo".into(),
+            unchecked_inode!(2),
+            0o755,
+        )))
+        .unwrap();
+
+        let map = &fs.mapper;
+        let list = &fs.list;
+
+        info!(%map);
+        info!(%list);
+
+        let entry = fs
+            .list
+            .map_mut()
+            .get_mut(&unchecked_inode!(2))
+            .unwrap()
+            .as_directory_mut()
+            .unwrap();
+
+        entry.insert(unchecked_inode!(3), EntryType::File, "bar");
+
+        fs.push(DirEntry::File(File::new(
+            "bar".into(),
+            unchecked_inode!(2),
+            unchecked_inode!(3),
+            0o655,
+        )))
+        .unwrap();
+
+        assert_eq!(fs.mapper.map().len(), 4);
+        assert_eq!(fs.list.map().len(), 4);
+
+        info!(%fs);
+    }
+
+    #[test]
+    #[instrument]
+    fn readdir() {
+        init();
+
+        let _s = tracing::info_span!("readdir");
+        let _s = _s.entered();
+
+        let mut fs = Daniel::new().with_sort_dirents(true);
+
+        fs.push(DirEntry::File(File::new(
+            "foo".into(),
+            ROOT_INODE,
+            unchecked_inode!(2),
+            0o655,
+        )))
+        .unwrap();
+
+        // the status file sorts first
+        let entry = fs.readdir(ROOT_INODE.into(), 0, 1);
+        match entry {
+            ControlFlow::Continue(entry) => match entry {
+                DirEntry::File(file) => assert_eq!(file.name(), Path::new("foo")),
+                DirEntry::Directory(_directory) => panic!("expected foo found a directory"),
+            },
+            ControlFlow::Break(_) => panic!(),
+        }
+        assert!(matches!(
+            fs.readdir(ROOT_INODE.into(), 0, 2),
+            ControlFlow::Break(())
+        ));
+        assert!(matches!(fs.readdir(2, 0, 0), ControlFlow::Break(())));
+    }
+
+    #[test]
+    #[instrument]
+    fn write_lock() {
+        init();
+
+        let mut fs = Daniel::new();
+        let ino: u64 = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+
+        assert_eq!(
+            fs.setlk(ino, Lock::new(1, 0, 99, F_WRLCK, 10), false),
+            Ok(())
+        );
+        assert_eq!(fs.locks.locks(&unchecked_inode!(ino)).len(), 1);
+
+        // the owner can always upgrade or replace its own lock
+        assert_eq!(
+            fs.setlk(ino, Lock::new(1, 0, 99, F_WRLCK, 10), false),
+            Ok(())
+        );
+        assert_eq!(fs.locks.locks(&unchecked_inode!(ino)).len(), 1);
+
+        assert_eq!(
+            fs.setlk(ino, Lock::new(2, 50, 150, F_RDLCK, 20), false),
+            Err(super::EAGAIN)
+        );
+        assert_eq!(
+            fs.setlk(ino, Lock::new(2, 100, 150, F_WRLCK, 20), false),
+            Ok(())
+        );
+    }
+
+    #[test]
+    #[instrument]
+    fn conflicting_getlk() {
+        init();
+
+        let mut fs = Daniel::new();
+        let ino: u64 = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+
+        fs.setlk(ino, Lock::new(1, 10, 19, F_WRLCK, 10), false)
+            .unwrap();
+
+        let held = fs.getlk(ino, Lock::new(2, 0, 15, F_RDLCK, 20)).unwrap();
+        assert_eq!(held, Lock::new(1, 10, 19, F_WRLCK, 10));
+
+        let free = fs.getlk(ino, Lock::new(2, 20, 30, F_WRLCK, 20)).unwrap();
+        assert_eq!(free.typ, F_UNLCK);
+        assert_eq!((free.start, free.end), (20, 30));
+    }
+
+    #[test]
+    #[instrument]
+    fn release_lock() {
+        init();
+
+        let mut fs = Daniel::new();
+        let ino: u64 = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+
+        fs.setlk(ino, Lock::new(1, 0, 99, F_WRLCK, 10), false)
+            .unwrap();
+
+        // unlocking the middle splits the held range in two
+        fs.setlk(ino, Lock::new(1, 40, 59, F_UNLCK, 10), false)
+            .unwrap();
+        assert_eq!(fs.locks.locks(&unchecked_inode!(ino)).len(), 2);
+        let free = fs.getlk(ino, Lock::new(2, 40, 59, F_WRLCK, 20)).unwrap();
+        assert_eq!(free.typ, F_UNLCK);
+
+        fs.setlk(ino, Lock::new(1, 0, 99, F_UNLCK, 10), false)
+            .unwrap();
+        assert!(fs.locks.locks(&unchecked_inode!(ino)).is_empty());
+        assert_eq!(
+            fs.setlk(ino, Lock::new(2, 0, 99, F_WRLCK, 20), false),
+            Ok(())
+        );
+    }
+
+    #[test]
+    #[instrument]
+    fn blocking_lock() {
+        init();
+
+        let mut fs = Daniel::new();
+        let ino: u64 = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+
+        assert_eq!(
+            fs.setlk(ino, Lock::new(1, 0, 99, F_WRLCK, 10), true),
+            Err(super::ENOSYS)
+        );
+    }
+
+    #[test]
+    #[instrument]
+    fn as_file_on_dir() {
+        init();
+
+        let mut fs = Daniel::new();
+        fs.push(DirEntry::Directory(Directory::new(
+            ROOT_INODE,
+            "foo".into(),
+            unchecked_inode!(2),
+            0o755,
+        )))
+        .unwrap();
+
+        let entry = fs.list.map_mut().get_mut(&unchecked_inode!(2)).unwrap();
+        assert!(entry.as_file().is_none());
+        assert!(entry.as_file_mut().is_none());
+        assert!(entry.as_directory().is_some());
+
+        // pushing into something that isn't a directory is an error instead of a panic
+        let res = fs.push(DirEntry::File(File::new(
+            "bar".into(),
+            unchecked_inode!(3),
+            unchecked_inode!(4),
+            0o644,
+        )));
+        assert_eq!(res, Err(super::ENOENT));
+    }
+
+    #[test]
+    #[instrument]
+    fn content_hash() {
+        init();
+
+        let mut fs = Daniel::new();
+        let ino: u64 = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+
+        fs.write(ino, 0, b"hello world").unwrap();
+        let hash = fs.content_hash(ino).unwrap();
+        // FNV-1a, fixed so it can be compared between runs
+        assert_eq!(hash, 0x779a_65e7_023c_d2e7);
+
+        fs.write(ino, 0, b"hello world").unwrap();
+        assert_eq!(fs.content_hash(ino), Some(hash));
+
+        fs.write(ino, 6, b"daniel").unwrap();
+        assert_ne!(fs.content_hash(ino), Some(hash));
+        assert_eq!(&*fs.read(ino, 0, 64).unwrap(), b"hello daniel");
+
+        fs.truncate(ino, 5).unwrap();
+        assert_eq!(&*fs.read(ino, 0, 64).unwrap(), b"hello");
+        assert_ne!(fs.content_hash(ino), Some(hash));
+
+        assert_eq!(fs.content_hash(ROOT_INODE.into()), None);
+    }
+
+    #[test]
+    #[instrument]
+    fn copy_on_write() {
+        init();
+
+        let mut fs = Daniel::new();
+        let foo: u64 = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        let bar: u64 = fs.create(ROOT_INODE, "bar", 0, 0o644).unwrap().inner().ino;
+
+        fs.write(foo, 0, b"shared contents").unwrap();
+        assert_eq!(fs.copy_file_range(foo, 0, bar, 0, u64::MAX), Ok(15));
+
+        let file = |fs: &Daniel, ino: u64| {
+            fs.list
+                .map()
+                .get(&unchecked_inode!(ino))
+                .unwrap()
+                .as_file()
+                .unwrap()
+                .shared_data()
+        };
+        assert!(Arc::ptr_eq(&file(&fs, foo), &file(&fs, bar)));
+        // one for each file plus the temporary
+        assert_eq!(Arc::strong_count(&file(&fs, foo)), 3);
+        assert_eq!(fs.content_hash(foo), fs.content_hash(bar));
+
+        fs.write(bar, 0, b"SHARED").unwrap();
+        assert!(!Arc::ptr_eq(&file(&fs, foo), &file(&fs, bar)));
+        assert_eq!(Arc::strong_count(&file(&fs, foo)), 2);
+        assert_eq!(&*fs.read(foo, 0, 64).unwrap(), b"shared contents");
+        assert_eq!(&*fs.read(bar, 0, 64).unwrap(), b"SHARED contents");
+    }
+
+    #[test]
+    #[instrument]
+    fn mknod_rdev() {
+        init();
+
+        let mut fs = Daniel::new();
+        // major 1, minor 3, ie /dev/null
+        let rdev = (1 << 8) | 3;
+        let attr = fs
+            .mknod(ROOT_INODE.into(), "null".as_ref(), 0o020666, rdev, 0, 0)
+            .unwrap();
+        let ino = attr.inner().ino;
+
+        let attr = fs.getattr(ino, None).unwrap().inner();
+        assert_eq!(attr.kind, FileType::CharDevice);
+        assert_eq!(attr.rdev, rdev);
+        assert_eq!(attr.perm, 0o666);
+    }
+
+    #[test]
+    #[instrument]
+    fn reflink() {
+        init();
+
+        let mut fs = Daniel::new();
+        let foo = fs.create_path(Path::new("/foo"), 0o640).unwrap();
+        fs.write(foo.into(), 0, b"original contents").unwrap();
+        let bar = fs.create_path(Path::new("/dir/bar"), 0o644).unwrap();
+        let dir = fs.file(bar).unwrap().parent();
+
+        let copy = fs.reflink(foo, dir, "copy".as_ref()).unwrap();
+        assert_ne!(copy, foo);
+        assert_eq!(fs.resolve_following_symlinks("/dir/copy", 0), Some(copy));
+        assert_eq!(fs.getattr(copy.into(), None).unwrap().inner().perm, 0o640);
+        assert_eq!(&*fs.read(copy.into(), 0, 64).unwrap(), b"original contents");
+        assert!(Arc::ptr_eq(
+            &fs.file(foo).unwrap().shared_data(),
+            &fs.file(copy).unwrap().shared_data()
+        ));
+
+        fs.write(copy.into(), 0, b"modified").unwrap();
+        assert_eq!(&*fs.read(copy.into(), 0, 64).unwrap(), b"modified contents");
+        assert_eq!(&*fs.read(foo.into(), 0, 64).unwrap(), b"original contents");
+
+        assert_eq!(fs.reflink(foo, dir, "copy".as_ref()), Err(super::EEXIST));
+        assert_eq!(
+            fs.reflink(dir, ROOT_INODE, "dir".as_ref()),
+            Err(super::EISDIR)
+        );
+    }
+
+    #[test]
+    #[instrument]
+    fn from_tar() {
+        init();
+
+        let archive = |extra: Option<tar::EntryType>| {
+            let mut builder = tar::Builder::new(Vec::new());
+            let mut append =
+                |kind: tar::EntryType, path: &str, mode: u32, target: Option<&str>, data: &[u8]| {
+                    let mut header = tar::Header::new_gnu();
+                    header.set_entry_type(kind);
+                    header.set_mode(mode);
+                    header.set_mtime(1_000_000);
+                    header.set_size(data.len() as u64);
+                    if let Some(target) = target {
+                        header.set_link_name(target).unwrap();
+                    }
+                    builder.append_data(&mut header, path, data).unwrap();
+                };
+            append(tar::EntryType::Directory, "a", 0o750, None, b"");
+            append(tar::EntryType::Regular, "a/foo", 0o640, None, b"hello");
+            append(tar::EntryType::Symlink, "a/link", 0o777, Some("foo"), b"");
+            // the parents of an entry don't have to be in the archive
+            append(tar::EntryType::Regular, "b/c/deep", 0o600, None, b"nested");
+            append(tar::EntryType::Link, "b/hard", 0o640, Some("a/foo"), b"");
+            append(tar::EntryType::Fifo, "fifo", 0o644, None, b"");
+            if let Some(kind) = extra {
+                append(kind, "label", 0o644, None, b"");
+            }
+            builder.into_inner().unwrap()
+        };
+
+        let mut fs = Daniel::from_tar(&archive(None)[..], UnsupportedEntry::Error).unwrap();
+        let mut get = |path: &str| {
+            let ino = *fs.mapper.get_path(path).unwrap();
+            fs.getattr(ino.into(), None).unwrap().inner()
+        };
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+
+        let a = get("/a");
+        assert_eq!(
+            (a.kind, a.perm, a.mtime),
+            (FileType::Directory, 0o750, mtime)
+        );
+        let foo = get("/a/foo");
+        assert_eq!(
+            (foo.kind, foo.perm, foo.mtime),
+            (FileType::RegularFile, 0o640, mtime)
+        );
+        assert_eq!(foo.nlink, 2);
+        assert_eq!(get("/b/hard").ino, foo.ino);
+        assert_eq!(get("/b").perm, 0o755);
+        assert_eq!(get("/fifo").kind, FileType::NamedPipe);
+        let link = get("/a/link");
+        assert_eq!(link.kind, FileType::Symlink);
+        let deep = get("/b/c/deep").ino;
+
+        assert_eq!(&*fs.read(foo.ino, 0, 64).unwrap(), b"hello");
+        assert_eq!(&*fs.read(deep, 0, 64).unwrap(), b"nested");
+        assert_eq!(&*fs.readlink(link.ino).unwrap(), b"foo");
+
+        // a GNU volume label has no place in a tree
+        let label = Some(tar::EntryType::new(b'V'));
+        let err = Daniel::from_tar(&archive(label)[..], UnsupportedEntry::Error).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+        let fs = Daniel::from_tar(&archive(label)[..], UnsupportedEntry::Skip).unwrap();
+        assert!(fs.mapper.get_path("/label").is_none());
+        assert!(fs.mapper.get_path("/fifo").is_some());
+    }
+
+    #[test]
+    #[instrument]
+    fn tar_round_trip() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root = u64::from(ROOT_INODE);
+        let foo = fs.create_path(Path::new("/a/foo"), 0o640).unwrap();
+        fs.write(foo.into(), 0, b"hello").unwrap();
+        let big = fs.create_path(Path::new("/a/b/big"), 0o600).unwrap();
+        fs.write(big.into(), 0, &[7; 10_000]).unwrap();
+        let a = fs.file(foo).unwrap().parent();
+        fs.symlink(a.into(), "link".as_ref(), Path::new("b/big"), 0, 0)
+            .unwrap();
+        let c = fs
+            .mkdir(root, "c".as_ref(), 0o700, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        fs.link(foo.into(), c, "hard".as_ref()).unwrap();
+        fs.mknod(
+            c,
+            "null".as_ref(),
+            libc::S_IFCHR | 0o666,
+            (1 << 8) | 3,
+            0,
+            0,
+        )
+        .unwrap();
+        fs.mknod(root, "fifo".as_ref(), libc::S_IFIFO | 0o644, 0, 0, 0)
+            .unwrap();
+        let inodes: Vec<u64> = fs
+            .list
+            .map()
+            .keys()
+            .filter(|ino| **ino != STATUS_INODE)
+            .map(|ino| (*ino).into())
+            .collect();
+        for (i, ino) in inodes.into_iter().enumerate() {
+            let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1000 * i as u64);
+            let patch = AttrPatch {
+                mtime: Some(mtime),
+                ..Default::default()
+            };
+            fs.setattr(ino, patch, 0, 0).unwrap();
+        }
+
+        // everything about each entry but its inode
+        let tree = |fs: &Daniel| {
+            let mut entries = Vec::new();
+            let mut pending = vec![(ROOT_INODE, PathBuf::from("/"))];
+            while let Some((dir, path)) = pending.pop() {
+                for (name, _, ino) in fs.list_dir(dir).unwrap() {
+                    if ino == STATUS_INODE {
+                        continue;
+                    }
+                    let path = path.join(name);
+                    let attr = fs.list.map()[&ino].attr().inner();
+                    let data = fs.file(ino).map(|file| file.data().into_owned());
+                    entries.push((
+                        path.clone(),
+                        attr.kind,
+                        attr.perm,
+                        attr.mtime,
+                        attr.nlink,
+                        attr.rdev,
+                        data.ok(),
+                    ));
+                    if attr.kind == FileType::Directory {
+                        pending.push((ino, path));
+                    }
+                }
+            }
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            entries
+        };
+
+        let mut archive = Vec::new();
+        fs.to_tar(&mut archive).unwrap();
+        let imported = Daniel::from_tar(&archive[..], UnsupportedEntry::Error).unwrap();
+        assert_eq!(tree(&imported), tree(&fs));
+        assert_eq!(tree(&fs).len(), 9);
+    }
+
+    #[test]
+    #[instrument]
+    fn entry_kinds() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root = u64::from(ROOT_INODE);
+        fs.create(ROOT_INODE, "file", 0, 0o644).unwrap();
+        fs.mkdir(root, "dir".as_ref(), 0o755, 0, 0, 0).unwrap();
+        fs.symlink(root, "link".as_ref(), Path::new("file"), 0, 0)
+            .unwrap();
+        let nodes = [
+            ("chr", libc::S_IFCHR, FileType::CharDevice),
+            ("blk", libc::S_IFBLK, FileType::BlockDevice),
+            ("fifo", libc::S_IFIFO, FileType::NamedPipe),
+            ("sock", libc::S_IFSOCK, FileType::Socket),
+        ];
+        for (name, mode, kind) in nodes {
+            let attr = fs
+                .mknod(root, name.as_ref(), mode | 0o644, 0, 0, 0)
+                .unwrap();
+            assert_eq!(attr.inner().kind, kind);
+        }
+
+        // what readdir replies with
+        let listed: Vec<(PathBuf, FileType)> = fs
+            .dir_entries_after(ROOT_INODE, 0)
+            .unwrap()
+            .into_iter()
+            .skip(2)
+            .map(|(_, _, kind, name)| (name.to_path_buf(), kind.into()))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                (STATUS_FILE.into(), FileType::RegularFile),
+                ("file".into(), FileType::RegularFile),
+                ("dir".into(), FileType::Directory),
+                ("link".into(), FileType::Symlink),
+                ("chr".into(), FileType::CharDevice),
+                ("blk".into(), FileType::BlockDevice),
+                ("fifo".into(), FileType::NamedPipe),
+                ("sock".into(), FileType::Socket),
+            ]
+        );
+    }
+
+    #[test]
+    #[instrument]
+    fn symlink_size() {
+        init();
+
+        let mut fs = Daniel::new();
+        let target = Path::new("some/where/else");
+        let ino = fs
+            .symlink(ROOT_INODE.into(), "link".as_ref(), target, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+
+        let attr = fs.getattr(ino, None).unwrap().inner();
+        assert_eq!(attr.kind, FileType::Symlink);
+        assert_eq!(attr.size, target.as_os_str().len() as u64);
+        assert_eq!(&*fs.readlink(ino).unwrap(), b"some/where/else");
+    }
+
+    #[test]
+    #[instrument]
+    fn journal() {
+        init();
+
+        let start = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let clock = ManualClock::new(start);
+        let mut fs = Daniel::new().with_journal(3).with_clock(clock.clone());
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        clock.set(start + std::time::Duration::from_secs(1));
+        fs.write(foo, 0, b"data").unwrap();
+        let bar = fs
+            .mkdir(ROOT_INODE.into(), "bar".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+
+        let ops = |fs: &Daniel| {
+            fs.journal()
+                .iter()
+                .map(|entry| (entry.op, u64::from(entry.inode), entry.name.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ops(&fs),
+            [
+                (JournalOp::Create, foo, "foo".into()),
+                (JournalOp::Write, foo, "foo".into()),
+                (JournalOp::Mkdir, bar, "bar".into()),
+            ]
+        );
+
+        // the oldest entry is dropped once the journal is full
+        fs.unlink(ROOT_INODE.into(), "foo".as_ref(), 0).unwrap();
+        assert_eq!(
+            ops(&fs),
+            [
+                (JournalOp::Write, foo, "foo".into()),
+                (JournalOp::Mkdir, bar, "bar".into()),
+                (JournalOp::Unlink, foo, "foo".into()),
+            ]
+        );
+        // the timestamps come from the filesystem's clock
+        assert_eq!(
+            fs.journal()
+                .iter()
+                .map(|entry| entry.timestamp)
+                .collect::<Vec<_>>(),
+            [start + std::time::Duration::from_secs(1); 3]
+        );
+
+        assert!(Daniel::new().journal().is_empty());
+    }
+
+    #[derive(Default)]
+    struct MockNotifier {
+        sent: Mutex<Vec<Notification>>,
+    }
+
+    impl Notify for Arc<MockNotifier> {
+        fn inval_inode(&self, ino: u64) -> std::io::Result<()> {
+            self.sent.lock().unwrap().push(Notification::Inode(ino));
+            Ok(())
+        }
+
+        fn inval_entry(&self, parent: u64, name: &std::ffi::OsStr) -> std::io::Result<()> {
+            self.sent
+                .lock()
+                .unwrap()
+                .push(Notification::Entry(parent, name.into()));
+            Ok(())
+        }
+
+        fn poll(&self, kh: u64) -> std::io::Result<()> {
+            self.sent.lock().unwrap().push(Notification::Poll(kh));
+            Ok(())
+        }
+    }
+
+    #[test]
+    #[instrument]
+    fn unlink_notifies() {
+        init();
+
+        let mut fs = Daniel::new();
+        let ino = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+
+        // nothing is queued without a notifier
+        fs.unlink(ROOT_INODE.into(), "foo".as_ref(), 0).unwrap();
+        assert!(fs.pending.is_empty());
+
+        let notifier = Arc::new(MockNotifier::default());
+        fs.notifier_slot().set(Arc::clone(&notifier));
+
+        let ino2 = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        assert_ne!(ino, ino2);
+        fs.unlink(ROOT_INODE.into(), "foo".as_ref(), 0).unwrap();
+        // nothing is sent until the handler has replied
+        assert!(notifier.sent.lock().unwrap().is_empty());
+
+        fs.flush_notifications();
+        assert_eq!(
+            *notifier.sent.lock().unwrap(),
+            [
+                Notification::Entry(ROOT_INODE.into(), "foo".into()),
+                Notification::Inode(ino2),
+            ]
+        );
+        assert!(fs.pending.is_empty());
+    }
+
+    #[test]
+    #[instrument]
+    fn root_owner() {
+        init();
+
+        let mut fs = Daniel::new().with_root(1000, 100, 0o700);
+        let attr = fs.getattr(ROOT_INODE.into(), None).unwrap().inner();
+        assert_eq!((attr.uid, attr.gid, attr.perm), (1000, 100, 0o700));
+
+        let attr = Daniel::new()
+            .getattr(ROOT_INODE.into(), None)
+            .unwrap()
+            .inner();
+        assert_eq!((attr.uid, attr.gid, attr.perm), (0, 0, 0o755));
+    }
+
+    #[test]
+    #[instrument]
+    fn caller_owns_new_entries() {
+        init();
+
+        let mut fs = Daniel::new().with_journal(16);
+        let root = u64::from(ROOT_INODE);
+        let flags = super::O_CREAT | super::O_WRONLY;
+        let created = [
+            fs.create_or_open(ROOT_INODE, "file".as_ref(), 0o644, flags, 1000, 100),
+            fs.mkdir(root, "dir".as_ref(), 0o755, 0, 1000, 100),
+            fs.mknod(root, "fifo".as_ref(), libc::S_IFIFO | 0o644, 0, 1000, 100),
+            fs.symlink(root, "link".as_ref(), "file".as_ref(), 1000, 100),
+        ];
+        for attr in created {
+            let attr = attr.unwrap().inner();
+            assert_eq!((attr.uid, attr.gid), (1000, 100));
+        }
+
+        // they're created owned, not handed over afterwards
+        assert!(
+            fs.journal()
+                .iter()
+                .all(|entry| entry.op != JournalOp::Setattr)
+        );
+    }
+
+    #[test]
+    #[instrument]
+    fn wrong_inode_type() {
+        init();
+
+        let mut fs = Daniel::new();
+        let file = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        let root: u64 = ROOT_INODE.into();
+
+        assert_eq!(fs.read(root, 0, 10), Err(super::EISDIR));
+        assert_eq!(fs.write(root, 0, b"data"), Err(super::EISDIR));
+        assert_eq!(fs.truncate(root, 0), Err(super::EISDIR));
+
+        assert_eq!(fs.opendir(file), Err(super::ENOTDIR));
+        assert_eq!(
+            fs.directory(unchecked_inode!(file)).map(|_| ()),
+            Err(super::ENOTDIR)
+        );
+        assert_eq!(
+            fs.lookup(file, "bar".as_ref()).map(|_| ()),
+            Err(super::ENOTDIR)
+        );
+        assert!(matches!(fs.readdir(file, 0, 0), ControlFlow::Break(())));
+
+        assert_eq!(fs.opendir(root), Ok(()));
+
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY;
+        assert_eq!(fs.open(file, flags, 0, 0), Err(super::ENOTDIR));
+        assert_eq!(fs.handles_for(file), []);
+        let fh = fs.open(root, flags, 0, 0).unwrap();
+        assert_eq!(fs.check_handle(root, fh), Ok(()));
+        assert_eq!(fs.handles_for(root).len(), 1);
+
+        // listing a directory needs read permission like any other open
+        let private = fs
+            .mkdir(root, "private".as_ref(), 0o700, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        assert_eq!(fs.open(private, flags, 1000, 1000), Err(super::EACCES));
+        assert!(fs.open(private, flags, 0, 0).is_ok());
+    }
+
+    #[test]
+    #[instrument]
+    fn sticky_dir() {
+        init();
+
+        let mut fs = Daniel::new();
+        let tmp = fs
+            .mkdir(ROOT_INODE.into(), "tmp".as_ref(), 0o1777, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        let set_owner = |fs: &mut Daniel, ino: u64, uid: u32, perm: u16| {
+            let attr = fs
+                .list
+                .map_mut()
+                .get_mut(&unchecked_inode!(ino))
+                .unwrap()
+                .attr_mut()
+                .inner_mut();
+            attr.uid = uid;
+            attr.perm = perm;
+        };
+        set_owner(&mut fs, tmp, 0, 0o1777);
+
+        for name in ["foo", "bar"] {
+            let ino = fs
+                .create(unchecked_inode!(tmp), name, 0, 0o644)
+                .unwrap()
+                .inner()
+                .ino;
+            set_owner(&mut fs, ino, 1000, 0o644);
+        }
+
+        assert_eq!(fs.unlink(tmp, "foo".as_ref(), 1001), Err(super::EACCES));
+        assert_eq!(
+            fs.rename(tmp, "foo".as_ref(), tmp, "baz".as_ref(), 1001),
+            Err(super::EACCES)
+        );
+        assert!(fs.lookup(tmp, "foo".as_ref()).is_ok());
+
+        assert_eq!(fs.unlink(tmp, "foo".as_ref(), 1000), Ok(()));
+        assert_eq!(
+            fs.lookup(tmp, "foo".as_ref()).map(|_| ()),
+            Err(super::ENOENT)
+        );
+        assert!(fs.directory(unchecked_inode!(tmp)).unwrap().entries().len() == 1);
+
+        // the owner of the directory may remove anything in it, as may root
+        assert_eq!(
+            fs.rename(tmp, "bar".as_ref(), tmp, "baz".as_ref(), 0),
+            Ok(())
+        );
+        assert!(fs.lookup(tmp, "baz".as_ref()).is_ok());
+
+        // without the sticky bit anyone may remove the file
+        set_owner(&mut fs, tmp, 0, 0o777);
+        assert_eq!(fs.unlink(tmp, "baz".as_ref(), 1001), Ok(()));
+    }
+
+    #[test]
+    #[instrument]
+    fn rename() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root: u64 = ROOT_INODE.into();
+        let dir = fs
+            .mkdir(root, "dir".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        let bar = fs.create(ROOT_INODE, "bar", 0, 0o644).unwrap().inner().ino;
+        fs.write(foo, 0, b"foo").unwrap();
+
+        fs.rename(root, "foo".as_ref(), dir, "moved".as_ref(), 0)
+            .unwrap();
+        assert_eq!(fs.lookup(dir, "moved".as_ref()).unwrap().inner().ino, foo);
+        assert!(fs.lookup(root, "foo".as_ref()).is_err());
+        assert_eq!(&*fs.read(foo, 0, 10).unwrap(), b"foo");
+
+        // renaming over an existing file replaces it
+        fs.rename(dir, "moved".as_ref(), root, "bar".as_ref(), 0)
+            .unwrap();
+        assert_eq!(fs.lookup(root, "bar".as_ref()).unwrap().inner().ino, foo);
+        assert!(fs.file(unchecked_inode!(bar)).is_err());
+
+        assert_eq!(
+            fs.rename(root, "bar".as_ref(), root, "dir".as_ref(), 0),
+            Err(super::EISDIR)
+        );
+        assert_eq!(
+            fs.rename(root, "missing".as_ref(), root, "dir".as_ref(), 0),
+            Err(super::ENOENT)
+        );
+    }
+
+    #[test]
+    #[instrument]
+    fn checkpoint_restore() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root: u64 = ROOT_INODE.into();
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        fs.write(foo, 0, b"before").unwrap();
+
+        let snapshot = fs.checkpoint();
+        let dump = fs.to_string();
+
+        fs.write(foo, 0, b"AFTER!").unwrap();
+        let bar = fs.create(ROOT_INODE, "bar", 0, 0o644).unwrap().inner().ino;
+        fs.mkdir(root, "dir".as_ref(), 0o755, 0, 0, 0).unwrap();
+        fs.setlk(bar, Lock::new(1, 0, 10, F_WRLCK, 1), false)
+            .unwrap();
+        fs.unlink(root, "foo".as_ref(), 0).unwrap();
+
+        fs.restore(snapshot);
+
+        assert_eq!(&*fs.read(foo, 0, 64).unwrap(), b"before");
+        assert_eq!(fs.lookup(root, "foo".as_ref()).unwrap().inner().ino, foo);
+        assert!(fs.lookup(root, "bar".as_ref()).is_err());
+        assert!(fs.lookup(root, "dir".as_ref()).is_err());
+        assert_eq!(fs.list.map().len(), 3);
+        assert!(fs.locks.locks(&unchecked_inode!(bar)).is_empty());
+        assert_eq!(fs.directory(ROOT_INODE).unwrap().entries().len(), 2);
+        assert_eq!(fs.mapper.map().len(), 3);
+        // the directory entries are a HashMap so compare the mapper output which is ordered
+        assert!(dump.starts_with(&fs.mapper.to_string()));
+    }
+
+    #[test]
+    #[instrument]
+    fn same_name_paths() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root: u64 = ROOT_INODE.into();
+        let a = fs
+            .mkdir(root, "a".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        let b = fs
+            .mkdir(root, "b".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        let a_foo = fs
+            .create(unchecked_inode!(a), "foo", 0, 0o644)
+            .unwrap()
+            .inner()
+            .ino;
+        let b_foo = fs
+            .create(unchecked_inode!(b), "foo", 0, 0o644)
+            .unwrap()
+            .inner()
+            .ino;
+
+        assert_eq!(fs.mapper.get_path("/a/foo"), Some(&unchecked_inode!(a_foo)));
+        assert_eq!(fs.mapper.get_path("/b/foo"), Some(&unchecked_inode!(b_foo)));
+        assert_eq!(fs.mapper.get_path("/a"), Some(&unchecked_inode!(a)));
+        assert_eq!(fs.mapper.get_path("foo"), None);
+
+        fs.unlink(a, "foo".as_ref(), 0).unwrap();
+        assert_eq!(fs.mapper.get_path("/a/foo"), None);
+        assert_eq!(fs.mapper.get_path("/b/foo"), Some(&unchecked_inode!(b_foo)));
+
+        fs.rename(b, "foo".as_ref(), a, "bar".as_ref(), 0).unwrap();
+        assert_eq!(fs.mapper.get_path("/b/foo"), None);
+        assert_eq!(fs.mapper.get_path("/a/bar"), Some(&unchecked_inode!(b_foo)));
+    }
+
+    #[test]
+    #[instrument]
+    fn remove_recursive() {
+        init();
+
+        let mut fs = Daniel::new().with_journal(64);
+        let root: u64 = ROOT_INODE.into();
+        fs.create(ROOT_INODE, "keep", 0, 0o644).unwrap();
+        let (entries, mappings) = (fs.list.map().len(), fs.mapper.map().len());
+
+        let a = fs
+            .mkdir(root, "a".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        let b = fs
+            .mkdir(a, "b".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        let c = fs
+            .mkdir(b, "c".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        fs.mkdir(a, "empty".as_ref(), 0o755, 0, 0, 0).unwrap();
+        for (dir, name) in [(a, "foo"), (b, "bar"), (c, "baz"), (c, "qux")] {
+            let ino = fs
+                .create(unchecked_inode!(dir), name, 0, 0o644)
+                .unwrap()
+                .inner()
+                .ino;
+            fs.write(ino, 0, name.as_bytes()).unwrap();
+        }
+
+        fs.remove_recursive(ROOT_INODE, "a".as_ref(), 0).unwrap();
+
+        assert_eq!(fs.list.map().len(), entries);
+        assert_eq!(fs.mapper.map().len(), mappings);
+        assert!(fs.lookup(root, "a".as_ref()).is_err());
+        assert!(fs.lookup(root, "keep".as_ref()).is_ok());
+        assert_eq!(fs.mapper.get_path("/a/b/c/baz"), None);
+        assert_eq!(fs.directory(ROOT_INODE).unwrap().entries().len(), 2);
+
+        // the directory itself is removed last
+        let last = fs.journal().last().unwrap();
+        assert_eq!((last.op, u64::from(last.inode)), (JournalOp::Rmdir, a));
+
+        assert_eq!(
+            fs.remove_recursive(ROOT_INODE, "a".as_ref(), 0),
+            Err(super::ENOENT)
+        );
+        assert_eq!(
+            fs.remove_recursive(ROOT_INODE, "/".as_ref(), 0),
+            Err(super::EINVAL)
+        );
+        assert_eq!(
+            fs.remove_recursive(ROOT_INODE, STATUS_FILE.as_ref(), 0),
+            Err(super::EPERM)
+        );
+
+        // every entry gets the checks unlink and rmdir make
+        let tmp = fs
+            .mkdir(root, "tmp".as_ref(), 0o1777, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        let theirs = fs
+            .mkdir(tmp, "theirs".as_ref(), 0o777, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        let locked = fs
+            .create(unchecked_inode!(theirs), "locked", 0, 0o644)
+            .unwrap()
+            .inner()
+            .ino;
+        fs.set_flags(locked, super::FS_IMMUTABLE_FL, 0).unwrap();
+        assert_eq!(
+            fs.remove_recursive(unchecked_inode!(tmp), "theirs".as_ref(), 1000),
+            Err(super::EACCES)
+        );
+        assert_eq!(
+            fs.remove_recursive(unchecked_inode!(tmp), "theirs".as_ref(), 0),
+            Err(super::EPERM)
+        );
+        assert!(fs.lookup(theirs, "locked".as_ref()).is_ok());
+        fs.set_flags(locked, 0, 0).unwrap();
+
+        let mut fs = fs.with_read_only(true);
+        assert_eq!(
+            fs.remove_recursive(unchecked_inode!(tmp), "theirs".as_ref(), 0),
+            Err(super::EROFS)
+        );
+        let mut fs = fs.with_read_only(false);
+        fs.remove_recursive(unchecked_inode!(tmp), "theirs".as_ref(), 0)
+            .unwrap();
+    }
+
+    #[test]
+    #[instrument]
+    fn follow_symlinks() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root: u64 = ROOT_INODE.into();
+        let a = fs
+            .mkdir(root, "a".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        let b = fs
+            .mkdir(a, "b".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        let file = fs
+            .create(unchecked_inode!(b), "file", 0, 0o644)
+            .unwrap()
+            .inner()
+            .ino;
+
+        // relative to the directory the link lives in
+        fs.symlink(a, "rel".as_ref(), Path::new("b/file"), 0, 0)
+            .unwrap();
+        fs.symlink(b, "up".as_ref(), Path::new("../b/./file"), 0, 0)
+            .unwrap();
+        // absolute targets start over at the root
+        fs.symlink(root, "abs".as_ref(), Path::new("/a/b"), 0, 0)
+            .unwrap();
+        fs.symlink(root, "loop".as_ref(), Path::new("loop"), 0, 0)
+            .unwrap();
+        fs.symlink(root, "ping".as_ref(), Path::new("pong"), 0, 0)
+            .unwrap();
+        fs.symlink(root, "pong".as_ref(), Path::new("/ping"), 0, 0)
+            .unwrap();
+
+        let resolve = |path: &str| fs.resolve_following_symlinks(path, 8).map(u64::from);
+        assert_eq!(resolve("/a/b/file"), Some(file));
+        assert_eq!(resolve("/a/rel"), Some(file));
+        assert_eq!(resolve("/a/b/up"), Some(file));
+        assert_eq!(resolve("/abs"), Some(b));
+        assert_eq!(resolve("/abs/file"), Some(file));
+        assert_eq!(resolve("/abs/../rel"), Some(file));
+        assert_eq!(resolve("/abs/missing"), None);
+        assert_eq!(resolve("/a/b/file/nested"), None);
+
+        assert_eq!(resolve("/loop"), None);
+        assert_eq!(resolve("/ping"), None);
+        assert_eq!(fs.resolve_following_symlinks("/abs/file", 0), None);
+        assert_eq!(
+            fs.resolve_following_symlinks("/abs/file", 1).map(u64::from),
+            Some(file)
+        );
+    }
+
+    #[test]
+    #[instrument]
+    fn clamp_read() {
+        init();
+
+        let mut fs = Daniel::new();
+        let ino = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        fs.write(ino, 0, b"0123456789").unwrap();
+
+        assert_eq!(&*fs.read(ino, 0, u32::MAX).unwrap(), b"0123456789");
+        assert_eq!(&*fs.read(ino, 4, u32::MAX).unwrap(), b"456789");
+        assert!(fs.read(ino, 1 << 40, u32::MAX).unwrap().is_empty());
+        assert_eq!(fs.read(ino, -1, 10), Err(super::EINVAL));
+
+        // the whole requested size is served, up to the cap
+        let big = vec![7; super::MAX_READ as usize + 1];
+        fs.write(ino, 0, &big).unwrap();
+        assert_eq!(fs.read(ino, 0, 1 << 17).unwrap().len(), 1 << 17);
+        assert_eq!(
+            fs.read(ino, 0, u32::MAX).unwrap().len(),
+            super::MAX_READ as usize
+        );
+    }
+
+    #[test]
+    #[instrument]
+    fn failed_flush() {
+        init();
+
+        let mut fs = Daniel::new().with_write_buffer(4096);
+        let ino = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        let fh = fs.open(ino, super::O_RDWR, 0, 0).unwrap();
+        fs.buffered_write(ino, fh, 0, b"kept").unwrap();
+        fs.set_flags(ino, super::FS_IMMUTABLE_FL, 0).unwrap();
+
+        // requests that only need the file up to date don't see the error
+        assert_eq!(fs.getattr(ino, None).unwrap().inner().size, 0);
+        assert!(fs.read(ino, 0, 16).unwrap().is_empty());
+        fs.statfs(ino);
+        assert_eq!(fs.write_buffers.len(), 1);
+
+        // the handle that wrote gets it, and the data waits for the next try
+        let ino = unchecked_inode!(ino);
+        assert_eq!(fs.flush_handle(ino, fh), Err(super::EPERM));
+        assert_eq!(fs.flush_handle(ino, fh), Err(super::EPERM));
+        fs.set_flags(ino.into(), 0, 0).unwrap();
+        assert_eq!(fs.flush_handle(ino, fh), Ok(()));
+        assert_eq!(&*fs.read(ino.into(), 0, 16).unwrap(), b"kept");
+
+        // release reports it too, and the buffer goes with the handle
+        fs.buffered_write(ino.into(), fh, 4, b"lost").unwrap();
+        fs.set_flags(ino.into(), super::FS_IMMUTABLE_FL, 0).unwrap();
+        assert_eq!(fs.release(ino.into(), fh), Err(super::EPERM));
+        assert!(fs.write_buffers.is_empty());
+        assert_eq!(&*fs.read(ino.into(), 0, 16).unwrap(), b"kept");
+    }
+
+    #[test]
+    #[instrument]
+    fn failed_flush_per_handle() {
+        init();
+
+        let mut fs = Daniel::new().with_write_buffer(4096);
+        let ino = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        fs.write(ino, 0, b"old!").unwrap();
+        let failing = fs.open(ino, super::O_WRONLY, 0, 0).unwrap();
+        let working = fs.open(ino, super::O_WRONLY, 0, 0).unwrap();
+
+        // once the file is append only the overwrite can't be applied, the append still can
+        fs.buffered_write(ino, failing, 0, b"new!").unwrap();
+        fs.set_flags(ino, super::FS_APPEND_FL, 0).unwrap();
+        fs.buffered_write(ino, working, 4, b"more").unwrap();
+
+        // only the handle that wrote it sees the error, and its buffer outlives the other handle
+        let inode = unchecked_inode!(ino);
+        assert_eq!(fs.flush_handle(inode, working), Ok(()));
+        assert_eq!(fs.release(ino, working), Ok(()));
+        assert_eq!(fs.write_buffers.len(), 1);
+        assert_eq!(fs.flush_handle(inode, failing), Err(super::EPERM));
+        assert_eq!(fs.release(ino, failing), Err(super::EPERM));
+        assert!(fs.write_buffers.is_empty());
+        assert_eq!(&*fs.read(ino, 0, 16).unwrap(), b"old!more");
+    }
+
+    #[test]
+    #[instrument]
+    fn coalesced_writes() {
+        init();
+
+        let mut fs = Daniel::new().with_write_buffer(4096).with_journal(1024);
+        let ino = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+
+        let expected: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        for (i, byte) in expected.iter().enumerate() {
+            assert_eq!(fs.buffered_write(ino, 0, i as i64, &[*byte]), Ok(1));
+        }
+
+        // only full buffers have been applied so far, the reads below flush the rest
+        let writes = fs.journal().len() - 1;
+        assert_eq!(writes, 10_000 / 4096);
+
+        assert_eq!(fs.getattr(ino, None).unwrap().inner().size, 10_000);
+        assert_eq!(&*fs.read(ino, 0, u32::MAX).unwrap(), expected);
+
+        // a write somewhere else flushes the buffer before starting a new one
+        fs.buffered_write(ino, 0, 0, b"a").unwrap();
+        fs.buffered_write(ino, 0, 1, b"b").unwrap();
+        fs.buffered_write(ino, 0, 5000, b"z").unwrap();
+        assert_eq!(fs.write_buffers.len(), 1);
+        fs.flush_handle(unchecked_inode!(ino), 0).unwrap();
+        assert!(fs.write_buffers.is_empty());
+        assert_eq!(&*fs.read(ino, 0, 2).unwrap(), b"ab");
+        assert_eq!(&*fs.read(ino, 5000, 1).unwrap(), b"z");
+    }
+
+    #[test]
+    #[instrument]
+    fn attr_cache() {
+        init();
+
+        let mut fs = Daniel::new().with_attr_cache(2);
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        let bar = fs.create(ROOT_INODE, "bar", 0, 0o644).unwrap().inner().ino;
+        let baz = fs.create(ROOT_INODE, "baz", 0, 0o644).unwrap().inner().ino;
+
+        assert_eq!(fs.getattr(foo, None).unwrap().inner().size, 0);
+        assert_eq!(fs.getattr(bar, None).unwrap().inner().size, 0);
+        assert!(fs.attr_cache.contains(&unchecked_inode!(foo)));
+
+        fs.write(foo, 0, b"hello").unwrap();
+        assert!(!fs.attr_cache.contains(&unchecked_inode!(foo)));
+        assert_eq!(fs.getattr(foo, None).unwrap().inner().size, 5);
+
+        fs.truncate(foo, 2).unwrap();
+        assert_eq!(fs.getattr(foo, None).unwrap().inner().size, 2);
+
+        // bar is the least recently used so it's the one evicted
+        fs.getattr(baz, None).unwrap();
+        assert_eq!(fs.attr_cache.len(), 2);
+        assert!(!fs.attr_cache.contains(&unchecked_inode!(bar)));
+
+        fs.unlink(ROOT_INODE.into(), "foo".as_ref(), 0).unwrap();
+        assert!(!fs.attr_cache.contains(&unchecked_inode!(foo)));
+    }
+
+    #[test]
+    #[instrument]
+    fn sorted_dirents() {
+        init();
+
+        let mut fs = Daniel::new().with_sort_dirents(true);
+        for name in ["pear", "apple", "zucchini", "fig", "banana"] {
+            fs.create(ROOT_INODE, name, 0, 0o644).unwrap();
+        }
+        fs.mkdir(ROOT_INODE.into(), "cherry".as_ref(), 0, 0, 0, 0)
+            .unwrap();
+
+        let names: Vec<_> = fs
+            .dir_entries(ROOT_INODE)
+            .unwrap()
+            .into_iter()
+            .skip(2)
+            .map(|(_, _, name)| name.to_path_buf())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                STATUS_FILE,
+                "apple",
+                "banana",
+                "cherry",
+                "fig",
+                "pear",
+                "zucchini"
+            ]
+            .map(PathBuf::from)
+        );
+
+        // a paged call resumes at the same position of the same ordering
+        let again = fs.dir_entries(ROOT_INODE).unwrap();
+        assert_eq!(again[6].2, Path::new("fig"));
+
+        // the cookies grow in the order the entries are listed
+        let cookies: Vec<_> = fs
+            .dir_entries_after(ROOT_INODE, 0)
+            .unwrap()
+            .into_iter()
+            .map(|(cookie, ..)| cookie)
+            .collect();
+        assert!(cookies.is_sorted());
+        assert_eq!(cookies.len(), 9);
+
+        // and the listing an open handle pages over doesn't change under it
+        fs.opendir(ROOT_INODE.into()).unwrap();
+        let first: Vec<_> = fs.readdir_after(ROOT_INODE, 0).unwrap();
+        let fig = first
+            .iter()
+            .find(|entry| entry.3 == Path::new("fig"))
+            .unwrap()
+            .0;
+        fs.create(ROOT_INODE, "aardvark", 0, 0o644).unwrap();
+        fs.unlink(ROOT_INODE.into(), "banana".as_ref(), 0).unwrap();
+        let rest: Vec<_> = fs
+            .readdir_after(ROOT_INODE, fig)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.3)
+            .collect();
+        assert_eq!(rest, ["pear", "zucchini"].map(PathBuf::from));
+        fs.releasedir(ROOT_INODE.into());
+        assert!(fs.dir_snapshots.is_empty());
+    }
+
+    #[test]
+    #[instrument]
+    fn readdir_cookies() {
+        init();
+
+        let mut fs = Daniel::new();
+        let names: Vec<String> = (0..10).map(|i| format!("file{i}")).collect();
+        for name in &names {
+            fs.create(ROOT_INODE, name, 0, 0o644).unwrap();
+        }
+
+        let mut seen = Vec::new();
+        let mut cookie = 0;
+        for page in 0.. {
+            let entries = fs.dir_entries_after(ROOT_INODE, cookie).unwrap();
+            if entries.is_empty() {
+                break;
+            }
+            for (next, _, _, name) in entries.into_iter().take(4) {
+                seen.push(name.to_path_buf());
+                cookie = next;
+            }
+
+            // shifts every later entry if offsets were positions
+            if page == 0 {
+                fs.create(ROOT_INODE, "late", 0, 0o644).unwrap();
+                fs.unlink(ROOT_INODE.into(), "file0".as_ref(), 0).unwrap();
+            }
+        }
+
+        let mut expected: Vec<PathBuf> = [".", "..", STATUS_FILE]
+            .into_iter()
+            .chain(names.iter().map(String::as_str))
+            .chain(["late"])
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(seen, expected);
+
+        // an entry that was removed still lets the listing resume after it
+        let file1 = fs.dir_entries_after(ROOT_INODE, 0).unwrap()[3].0;
+        fs.unlink(ROOT_INODE.into(), "file1".as_ref(), 0).unwrap();
+        let rest = fs.dir_entries_after(ROOT_INODE, file1).unwrap();
+        expected.drain(..5);
+        let rest: Vec<PathBuf> = rest
+            .into_iter()
+            .map(|(_, _, _, name)| name.into())
+            .collect();
+        assert_eq!(rest, expected);
+    }
+
+    #[test]
+    #[instrument]
+    fn create_path() {
+        init();
+
+        let mut fs = Daniel::new();
+        let file = fs.create_path(Path::new("/a/b/c/file"), 0o644).unwrap();
+
+        let a = *fs.mapper.get_path("/a").unwrap();
+        let b = *fs.mapper.get_path("/a/b").unwrap();
+        let c = *fs.mapper.get_path("/a/b/c").unwrap();
+        for dir in [a, b, c] {
+            assert_eq!(fs.list.map()[&dir].kind(), FileType::Directory);
+        }
+        assert_eq!(fs.list.map()[&file].kind(), FileType::RegularFile);
+        assert_eq!(fs.list.map()[&file].parent(), c);
+        assert_eq!(fs.list.map().len(), 6);
+        assert_eq!(*fs.mapper.get_path("/a/b/c/file").unwrap(), file);
+
+        // existing directories are reused and the existing file is handed back
+        assert_eq!(fs.create_path(Path::new("a/b/c/file"), 0o644), Ok(file));
+        let other = fs.create_path(Path::new("/a/b/other"), 0o644).unwrap();
+        assert_eq!(fs.list.map()[&other].parent(), b);
+
+        assert_eq!(fs.create_path(Path::new("/a/b"), 0o644), Err(super::EEXIST));
+        assert_eq!(
+            fs.create_path(Path::new("/a/b/c/file/x"), 0o644),
+            Err(super::ENOTDIR)
+        );
+    }
+
+    #[test]
+    #[instrument]
+    fn dot_entries() {
+        init();
+
+        let mut fs = Daniel::new();
+        let sub = fs.create_path(Path::new("/a/b/file"), 0o644).unwrap();
+        let a = *fs.mapper.get_path("/a").unwrap();
+        let b = fs.list.map()[&sub].parent();
+
+        let entries = fs.dir_entries(b).unwrap();
+        assert_eq!(entries[0].0, b);
+        assert_eq!(entries[0].2, Path::new("."));
+        assert_eq!(entries[1].0, a);
+        assert_eq!(entries[1].2, Path::new(".."));
+        assert_eq!(entries[2].0, sub);
+
+        let root = fs.dir_entries(ROOT_INODE).unwrap();
+        assert_eq!(root[0].0, ROOT_INODE);
+        assert_eq!(root[1].0, ROOT_INODE);
+    }
+
+    #[test]
+    #[instrument]
+    fn default_child_mode() {
+        init();
+
+        let mut fs = Daniel::new();
+        fs.negotiate_dont_mask(true);
+        let root = ROOT_INODE.into();
+        let shared = fs
+            .mkdir(root, "shared".as_ref(), 0o777, 0o022, 0, 0)
+            .unwrap();
+        assert_eq!(shared.inner().perm, 0o755);
+
+        let shared = unchecked_inode!(shared.inner().ino);
+        fs.set_default_child_mode(shared, Some(0o750)).unwrap();
+
+        let file = fs.create(shared, "file", 0, 0o666).unwrap();
+        assert_eq!(file.inner().perm, 0o640);
+        let dir = fs
+            .mkdir(shared.into(), "dir".as_ref(), 0o777, 0o002, 0, 0)
+            .unwrap();
+        assert_eq!(dir.inner().perm, 0o750);
+
+        // children of children aren't affected
+        let nested = fs
+            .create(unchecked_inode!(dir.inner().ino), "nested", 0, 0o666)
+            .unwrap();
+        assert_eq!(nested.inner().perm, 0o666);
+
+        let file = fs.lookup(shared.into(), "file".as_ref()).unwrap();
+        assert_eq!(file.inner().perm, 0o640);
+    }
+
+    #[test]
+    #[instrument]
+    fn status_file() {
+        init();
+
+        // pulls a number out of the flat json the status file is made of
+        fn field(json: &str, name: &str) -> u64 {
+            let key = format!("\"{name}\":");
+            let start = json.find(&key).unwrap() + key.len();
+            json[start..]
+                .split([',', '}'])
+                .next()
+                .unwrap()
+                .parse()
+                .unwrap()
+        }
+
+        let mut fs = Daniel::new();
+        let status = fs.lookup(ROOT_INODE.into(), STATUS_FILE.as_ref()).unwrap();
+        assert_eq!(status.inner().ino, STATUS_INODE.into());
+        assert_eq!(status.inner().perm, 0o444);
+
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        fs.write(foo, 0, b"hello").unwrap();
+
+        let json =
+            String::from_utf8(fs.read(STATUS_INODE.into(), 0, u32::MAX).unwrap().to_vec()).unwrap();
+        assert!(json.starts_with('{') && json.trim_end().ends_with('}'));
+        assert_eq!(field(&json, "inodes"), 2);
+        assert_eq!(field(&json, "used_bytes"), 5);
+        assert_eq!(field(&json, "writes"), 1);
+        assert_eq!(field(&json, "lookups"), 1);
+        assert_eq!(field(&json, "reads"), 1);
+
+        // regenerated on every read
+        let json =
+            String::from_utf8(fs.read(STATUS_INODE.into(), 0, u32::MAX).unwrap().to_vec()).unwrap();
+        assert_eq!(field(&json, "reads"), 2);
+
+        assert_eq!(fs.write(STATUS_INODE.into(), 0, b"x"), Err(super::EACCES));
+        assert_eq!(fs.truncate(STATUS_INODE.into(), 0), Err(super::EACCES));
+
+        // it can't be removed, moved away or replaced
+        let root = ROOT_INODE.into();
+        let status = STATUS_FILE.as_ref();
+        assert_eq!(fs.unlink(root, status, 0), Err(super::EPERM));
+        assert_eq!(
+            fs.rename(root, status, root, "moved".as_ref(), 0),
+            Err(super::EPERM)
+        );
+        assert_eq!(
+            fs.rename(root, "foo".as_ref(), root, status, 0),
+            Err(super::EPERM)
+        );
+        assert_eq!(
+            fs.lookup(root, status).unwrap().inner().ino,
+            STATUS_INODE.into()
+        );
+        assert!(fs.lookup(root, "foo".as_ref()).is_ok());
+    }
+
+    #[test]
+    #[instrument]
+    fn mime_xattr() {
+        init();
+
+        let mut fs = Daniel::new();
+        let png = fs
+            .create(ROOT_INODE, "image", 0, 0o644)
+            .unwrap()
+            .inner()
+            .ino;
+        let mime: &std::ffi::OsStr = MIME_XATTR.as_ref();
+
+        assert_eq!(fs.getxattr(png, mime), Err(super::ENODATA));
+        fs.write(png, 0, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+        assert_eq!(fs.getxattr(png, mime).unwrap(), b"image/png");
+        // computed on demand, not stored
+        assert!(fs.listxattr(png).unwrap().is_empty());
+
+        fs.truncate(png, 0).unwrap();
+        fs.write(png, 0, b"plain old text").unwrap();
+        assert_eq!(fs.getxattr(png, mime).unwrap(), b"text/plain");
+
+        assert_eq!(fs.getxattr(ROOT_INODE.into(), mime), Err(super::ENODATA));
+
+        // a real xattr wins over the sniffed one
+        fs.setxattr(png, mime, b"image/x-custom", 0).unwrap();
+        assert_eq!(fs.getxattr(png, mime).unwrap(), b"image/x-custom");
+        assert_eq!(fs.listxattr(png).unwrap(), b"user.daniel.mime\0");
+        fs.removexattr(png, mime).unwrap();
+        assert_eq!(fs.getxattr(png, mime).unwrap(), b"text/plain");
+    }
+
+    #[test]
+    #[instrument]
+    fn xattr_flags() {
+        init();
+
+        let mut fs = Daniel::new();
+        let ino = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        let name: &std::ffi::OsStr = "user.tag".as_ref();
+
+        assert_eq!(fs.setxattr(ino, name, b"a", 2), Err(super::ENODATA));
+        fs.setxattr(ino, name, b"a", 1).unwrap();
+        assert_eq!(fs.setxattr(ino, name, b"b", 1), Err(super::EEXIST));
+        fs.setxattr(ino, name, b"b", 2).unwrap();
+        assert_eq!(fs.getxattr(ino, name).unwrap(), b"b");
+        assert_eq!(fs.removexattr(ino, name), Ok(()));
+        assert_eq!(fs.removexattr(ino, name), Err(super::ENODATA));
+    }
+
+    #[test]
+    #[instrument]
+    fn create_exclusive() {
+        init();
+
+        let mut fs = Daniel::new();
+        let name: &std::ffi::OsStr = "foo".as_ref();
+        let (creat, excl) = (super::O_CREAT, super::O_EXCL);
+
+        let foo = fs
+            .create_or_open(ROOT_INODE, name, 0o644, creat | excl, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        assert_eq!(
+            fs.create_or_open(ROOT_INODE, name, 0o644, creat | excl, 0, 0)
+                .err(),
+            Some(super::EEXIST)
+        );
+        // no second inode was allocated
+        assert_eq!(fs.list.map().len(), 3);
+        assert_eq!(fs.lookup(ROOT_INODE.into(), name).unwrap().inner().ino, foo);
+    }
+
+    #[test]
+    #[instrument]
+    fn create_reopens() {
+        init();
+
+        let mut fs = Daniel::new();
+        let name: &std::ffi::OsStr = "foo".as_ref();
+        let creat = super::O_CREAT;
+
+        let foo = fs
+            .create_or_open(ROOT_INODE, name, 0o644, creat, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        fs.write(foo, 0, b"kept").unwrap();
+
+        let again = fs
+            .create_or_open(ROOT_INODE, name, 0o600, creat, 0, 0)
+            .unwrap()
+            .inner();
+        assert_eq!(again.ino, foo);
+        assert_eq!(again.perm, 0o644);
+        assert_eq!(&*fs.read(foo, 0, 16).unwrap(), b"kept");
+
+        assert_eq!(
+            fs.create_or_open(ROOT_INODE, "missing".as_ref(), 0o644, 0, 0, 0)
+                .err(),
+            Some(super::ENOENT)
+        );
+        fs.mkdir(ROOT_INODE.into(), "dir".as_ref(), 0o755, 0, 0, 0)
+            .unwrap();
+        assert_eq!(
+            fs.create_or_open(ROOT_INODE, "dir".as_ref(), 0o644, creat, 0, 0)
+                .err(),
+            Some(super::EISDIR)
+        );
+    }
+
+    #[test]
+    #[instrument]
+    fn max_entries_per_dir() {
+        init();
+
+        let mut fs = Daniel::new().with_max_entries_per_dir(3);
+        let root = ROOT_INODE.into();
+        let dir = fs
+            .mkdir(root, "dir".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        fs.create(ROOT_INODE, "a", 0, 0o644).unwrap();
+        fs.create(ROOT_INODE, "b", 0, 0o644).unwrap();
+
+        // the root is full, the status file isn't counted
+        assert_eq!(
+            fs.create(ROOT_INODE, "c", 0, 0o644).err(),
+            Some(super::ENOSPC)
+        );
+        assert_eq!(
+            fs.mkdir(root, "d".as_ref(), 0o755, 0, 0, 0).err(),
+            Some(super::ENOSPC)
+        );
+        assert_eq!(
+            fs.symlink(root, "e".as_ref(), Path::new("a"), 0, 0).err(),
+            Some(super::ENOSPC)
+        );
+
+        let dir = unchecked_inode!(dir);
+        for name in ["x", "y", "z"] {
+            fs.create(dir, name, 0, 0o644).unwrap();
+        }
+        assert_eq!(fs.create(dir, "w", 0, 0o644).err(), Some(super::ENOSPC));
+
+        // moving into a full directory fails, renaming within it or replacing doesn't
+        assert_eq!(
+            fs.rename(root, "a".as_ref(), dir.into(), "w".as_ref(), 0),
+            Err(super::ENOSPC)
+        );
+        fs.rename(dir.into(), "x".as_ref(), dir.into(), "w".as_ref(), 0)
+            .unwrap();
+        fs.rename(root, "a".as_ref(), dir.into(), "y".as_ref(), 0)
+            .unwrap();
+        fs.create(ROOT_INODE, "c", 0, 0o644).unwrap();
+    }
+
+    #[test]
+    #[instrument]
+    fn handle_round_trip() {
+        init();
+
+        for (ino, generation) in [(1, 0), (2, 1), (0xdead_beef, u32::MAX), (u64::MAX, 7)] {
+            let handle = FileHandle::new(unchecked_inode!(ino), generation, 0);
+            assert!(handle.matches(unchecked_inode!(ino), generation));
+            assert!(!handle.matches(unchecked_inode!(ino), generation.wrapping_add(1)));
+            // inodes 2^32 apart are told apart too
+            assert!(!handle.matches(unchecked_inode!(ino ^ 1 << 32), generation));
+        }
+    }
+
+    #[test]
+    #[instrument]
+    fn stale_handle() {
+        init();
+
+        let mut fs = Daniel::new();
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        let fh = fs.open_handle(foo, 0).unwrap();
+        assert_eq!(fs.check_handle(foo, fh), Ok(()));
+        assert_eq!(fs.check_handle(foo, 0), Err(super::ESTALE));
+
+        let root = fs.open_handle(ROOT_INODE.into(), 0).unwrap();
+        assert_eq!(fs.check_handle(ROOT_INODE.into(), root), Ok(()));
+        assert_eq!(fs.check_handle(ROOT_INODE.into(), fh), Err(super::ESTALE));
+
+        // every open gets its own handle, releasing one leaves the other
+        let other = fs.open_handle(foo, 0).unwrap();
+        assert_ne!(other, fh);
+        fs.release(foo, other).unwrap();
+        assert_eq!(fs.check_handle(foo, other), Err(super::ESTALE));
+        assert_eq!(fs.check_handle(foo, fh), Ok(()));
+
+        // an unlinked file stays open until its last handle is released
+        fs.unlink(ROOT_INODE.into(), "foo".as_ref(), 0).unwrap();
+        assert_eq!(fs.check_handle(foo, fh), Ok(()));
+        fs.release(foo, fh).unwrap();
+        assert_eq!(fs.check_handle(foo, fh), Err(super::ESTALE));
+
+        // pushing a new entry at the same inode gives it a new generation
+        fs.push(DirEntry::File(File::new(
+            "bar".into(),
+            ROOT_INODE,
+            unchecked_inode!(foo),
+            0o644,
+        )))
+        .unwrap();
+        assert_eq!(fs.check_handle(foo, fh), Err(super::ESTALE));
+        let fh = fs.open_handle(foo, 0).unwrap();
+        assert_eq!(fs.check_handle(foo, fh), Ok(()));
+    }
+
+    #[test]
+    #[instrument]
+    fn list_dir() {
+        init();
+
+        let mut fs = Daniel::new();
+        let dir = fs
+            .mkdir(ROOT_INODE.into(), "dir".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        let dir = unchecked_inode!(dir);
+        let file = fs.create(dir, "file", 0, 0o644).unwrap().inner().ino;
+        let sub = fs
+            .mkdir(dir.into(), "sub".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        let link = fs
+            .symlink(dir.into(), "link".as_ref(), Path::new("file"), 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+
+        let mut children = fs.list_dir(dir).unwrap();
+        children.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+        assert_eq!(
+            children,
+            vec![
+                ("file".into(), FileType::RegularFile, unchecked_inode!(file)),
+                ("link".into(), FileType::Symlink, unchecked_inode!(link)),
+                ("sub".into(), FileType::Directory, unchecked_inode!(sub)),
+            ]
+        );
+
+        assert!(fs.list_dir(unchecked_inode!(sub)).unwrap().is_empty());
+        assert_eq!(fs.list_dir(unchecked_inode!(file)), Err(super::ENOTDIR));
+        assert_eq!(fs.list_dir(unchecked_inode!(1000)), Err(super::ENOENT));
+    }
+
+    #[test]
+    #[instrument]
+    fn rename_keeps_crtime() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root = ROOT_INODE.into();
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        let dir = fs
+            .mkdir(root, "dir".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+
+        let attr = fs
+            .list
+            .map_mut()
+            .get_mut(&unchecked_inode!(foo))
+            .unwrap()
+            .attr_mut()
+            .inner_mut();
+        attr.ctime = std::time::UNIX_EPOCH;
+        let crtime = attr.crtime;
+
+        fs.rename(root, "foo".as_ref(), dir, "bar".as_ref(), 0)
+            .unwrap();
+
+        let attr = fs.getattr(foo, None).unwrap().inner();
+        assert_eq!(attr.crtime, crtime);
+        assert!(attr.ctime > std::time::UNIX_EPOCH);
+    }
+
+    /// in memory storage that records every call made to it
+    #[derive(Debug, Clone, Default)]
+    struct Recording {
+        inner: Memory,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Storage for Recording {
+        fn read(&self, offset: u64, len: usize) -> std::borrow::Cow<'_, [u8]> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("read {offset} {len}"));
+            self.inner.read(offset, len)
+        }
+
+        fn write(&mut self, offset: u64, data: &[u8]) -> usize {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("write {offset} {}", data.len()));
+            self.inner.write(offset, data)
+        }
+
+        fn truncate(&mut self, len: u64) {
+            self.calls.lock().unwrap().push(format!("truncate {len}"));
+            self.inner.truncate(len)
+        }
+
+        fn len(&self) -> u64 {
+            self.inner.len()
+        }
+
+        fn box_clone(&self) -> Box<dyn Storage> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    #[instrument]
+    fn storage_delegation() {
+        init();
+
+        let storage = Recording::default();
+        let calls = storage.calls.clone();
+        let mut fs = Daniel::new();
+        let ino = fs.mapper.next_inode();
+        fs.push(DirEntry::File(File::with_storage(
+            "foo".into(),
+            ROOT_INODE,
+            ino,
+            0o644,
+            Box::new(storage),
+        )))
+        .unwrap();
+        let ino = u64::from(ino);
+
+        assert_eq!(fs.write(ino, 2, b"data"), Ok(4));
+        assert_eq!(&*fs.read(ino, 1, 3).unwrap(), b"\0da");
+        fs.truncate(ino, 3).unwrap();
+        assert_eq!(fs.getattr(ino, None).unwrap().inner().size, 3);
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            ["write 2 4", "read 1 3", "truncate 3"]
+        );
+    }
+
+    #[test]
+    #[instrument]
+    fn compression() {
+        init();
+
+        let mut fs = Daniel::new().with_compression(true);
+        let big = fs.create(ROOT_INODE, "big", 0, 0o644).unwrap().inner().ino;
+        let tiny = fs.create(ROOT_INODE, "tiny", 0, 0o644).unwrap().inner().ino;
+
+        let mut expected = vec![0; 1 << 20];
+        expected[1000..1010].copy_from_slice(b"not zeroes");
+        fs.write(big, 0, &expected).unwrap();
+        fs.write(big, 1 << 19, b"middle").unwrap();
+        expected[1 << 19..(1 << 19) + 6].copy_from_slice(b"middle");
+        fs.write(tiny, 0, b"tiny").unwrap();
+
+        assert_eq!(*fs.read(big, 0, u32::MAX).unwrap(), *expected);
+        assert_eq!(
+            &*fs.read(big, 995, 20).unwrap(),
+            b"\0\0\0\0\0not zeroes\0\0\0\0\0"
+        );
+        assert_eq!(&*fs.read(tiny, 0, 64).unwrap(), b"tiny");
+
+        let stored = |fs: &Daniel, ino: u64| {
+            fs.list.map()[&unchecked_inode!(ino)]
+                .as_file()
+                .unwrap()
+                .storage()
+                .stored_len()
+        };
+        assert!(stored(&fs, big) < 20_000);
+        assert_eq!(stored(&fs, tiny), 4);
+
+        let attr = fs.getattr(big, None).unwrap().inner();
+        assert_eq!(attr.size, 1 << 20);
+        assert!(attr.blocks < (1 << 20) / 512 / 10);
+
+        // writes and reads that straddle a chunk boundary
+        let boundary = COMPRESS_CHUNK * 3 - 4;
+        fs.write(big, boundary as i64, b"across a chunk").unwrap();
+        expected[boundary..boundary + 14].copy_from_slice(b"across a chunk");
+        assert_eq!(
+            &*fs.read(big, boundary as i64 - 2, 18).unwrap(),
+            b"\0\0across a chunk\0\0"
+        );
+        assert_eq!(*fs.read(big, 0, u32::MAX).unwrap(), *expected);
+        assert!(stored(&fs, big) < 20_000);
+
+        fs.truncate(big, 2000).unwrap();
+        assert_eq!(*fs.read(big, 0, u32::MAX).unwrap(), expected[..2000]);
+
+        // growing again past a few chunks fills with zeros
+        let grown = COMPRESS_CHUNK * 2 + 10;
+        fs.truncate(big, grown as u64).unwrap();
+        let mut regrown = expected[..2000].to_vec();
+        regrown.resize(grown, 0);
+        assert_eq!(*fs.read(big, 0, u32::MAX).unwrap(), *regrown);
+        fs.write(big, grown as i64, b"end").unwrap();
+        assert_eq!(&*fs.read(big, grown as i64 - 1, 64).unwrap(), b"\0end");
+    }
+
+    #[test]
+    #[instrument]
+    fn readdir_paging() {
+        init();
+
+        let mut fs = Daniel::new().with_sort_dirents(true);
+        let dir = fs
+            .mkdir(ROOT_INODE.into(), "dir".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        let names = ["a", "b", "c", "d", "e"];
+        for name in names.iter().rev() {
+            fs.create(unchecked_inode!(dir), name, 0, 0o644).unwrap();
+        }
+
+        let mut seen = Vec::new();
+        let mut offset = 0;
+        while let ControlFlow::Continue(entry) = fs.readdir(dir, 0, offset) {
+            seen.push(entry.name().to_path_buf());
+            offset += 1;
+        }
+
+        assert_eq!(seen, names.map(PathBuf::from));
+        assert!(matches!(
+            fs.readdir(dir, 0, u64::MAX),
+            ControlFlow::Break(())
+        ));
+    }
+
+    #[test]
+    #[instrument]
+    fn forget_frees_orphans() {
+        init();
+
+        let mut fs = Daniel::new();
+        let ino = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        let inode = unchecked_inode!(ino);
+        fs.write(ino, 0, b"still here").unwrap();
+        let fh = fs.open_handle(ino, 0).unwrap();
+        // as if the kernel had been handed it twice, by create and a later lookup
+        fs.remember(ino);
+        fs.remember(ino);
+
+        // the name goes away at once but the contents stay readable through the open handle
+        fs.unlink(ROOT_INODE.into(), "foo".as_ref(), 0).unwrap();
+        assert_eq!(
+            fs.lookup(ROOT_INODE.into(), "foo".as_ref()).err(),
+            Some(super::ENOENT)
+        );
+        assert!(!fs.directory(ROOT_INODE).unwrap().contains(inode));
+        assert!(fs.checkpoint().list.map().get(&inode).is_none());
+        assert_eq!(fs.check_handle(ino, fh), Ok(()));
+        assert_eq!(&*fs.read(ino, 0, 64).unwrap(), b"still here");
+
+        fs.forget(ino, 1);
+        fs.release(ino, fh).unwrap();
+        assert!(fs.list.map().contains_key(&inode));
+
+        fs.forget(ino, 1);
+        assert!(!fs.list.map().contains_key(&inode));
+        assert!(fs.orphans.is_empty() && fs.lookup_counts().is_empty());
+
+        // forgetting something that's still linked only drops the count
+        let bar = fs.create(ROOT_INODE, "bar", 0, 0o644).unwrap().inner().ino;
+        fs.remember(bar);
+        fs.forget(bar, 1);
+        assert!(fs.list.map().contains_key(&unchecked_inode!(bar)));
+        fs.unlink(ROOT_INODE.into(), "bar".as_ref(), 0).unwrap();
+        assert!(!fs.list.map().contains_key(&unchecked_inode!(bar)));
+    }
+
+    #[test]
+    #[instrument]
+    fn statfs() {
+        init();
+
+        let mut fs = Daniel::new().with_max_bytes(1 << 20).with_max_inodes(10);
+        let ino = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        fs.write(ino, 0, &[7; 5000]).unwrap();
+
+        let stats = fs.statfs(ROOT_INODE.into());
+        assert_eq!(stats.bsize, 512);
+        assert_eq!(stats.blocks, 2048);
+        // df's used is blocks - bfree
+        assert_eq!(stats.bfree, ((1 << 20) - 5000) / 512);
+        assert_eq!(stats.bavail, stats.bfree);
+        // the root and foo
+        assert_eq!((stats.files, stats.ffree), (10, 8));
+
+        let unlimited = Daniel::new().statfs(ROOT_INODE.into());
+        assert!(unlimited.blocks > 0 && unlimited.bfree == unlimited.blocks);
+        assert!(unlimited.ffree > 0);
+    }
+
+    #[test]
+    #[instrument]
+    fn byte_quota() {
+        init();
+
+        let mut fs = Daniel::new().with_max_bytes(100).with_write_buffer(64);
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        let bar = fs.create(ROOT_INODE, "bar", 0, 0o644).unwrap().inner().ino;
+        assert_eq!(fs.write(foo, 0, &[1; 60]), Ok(60));
+        assert_eq!(fs.buffered_write(bar, 0, 0, &[2; 40]), Ok(40));
+        assert_eq!(fs.status().used_bytes, 100);
+
+        // full, the failed writes leave everything as it was
+        assert_eq!(fs.write(bar, 40, b"x"), Err(super::ENOSPC));
+        assert_eq!(fs.buffered_write(bar, 0, 40, b"x"), Err(super::ENOSPC));
+        assert_eq!(fs.truncate(foo, 61), Err(super::ENOSPC));
+        assert_eq!(
+            fs.fallocate(foo, 60, 1, FALLOC_FL_KEEP_SIZE),
+            Err(super::ENOSPC)
+        );
+        assert_eq!(fs.copy_file_range(foo, 0, bar, 40, 1), Err(super::ENOSPC));
+        assert_eq!(fs.getattr(bar, None).unwrap().inner().size, 40);
+        assert_eq!(fs.status().used_bytes, 100);
+
+        // a reflink and a hard link share what's there, until the reflink is written
+        let copy = fs
+            .reflink(unchecked_inode!(foo), ROOT_INODE, "copy".as_ref())
+            .unwrap();
+        fs.link(foo, ROOT_INODE.into(), "linked".as_ref()).unwrap();
+        assert_eq!(fs.status().used_bytes, 100);
+        assert_eq!(fs.write(copy.into(), 0, b"x"), Err(super::ENOSPC));
+        fs.unlink(ROOT_INODE.into(), "copy".as_ref(), 0).unwrap();
+        assert_eq!(fs.status().used_bytes, 100);
+
+        // overwriting takes no more space, and shrinking a file frees some
+        assert_eq!(fs.write(foo, 0, &[3; 60]), Ok(60));
+        fs.truncate(foo, 50).unwrap();
+        assert_eq!(fs.status().used_bytes, 90);
+        assert_eq!(fs.write(bar, 40, &[4; 10]), Ok(10));
+        assert_eq!(fs.write(bar, 50, b"x"), Err(super::ENOSPC));
+
+        // space fallocate reserved past the end is held until the file grows into it or shrinks
+        fs.truncate(foo, 20).unwrap();
+        fs.fallocate(foo, 20, 20, FALLOC_FL_KEEP_SIZE).unwrap();
+        assert_eq!(fs.status().used_bytes, 90);
+        assert_eq!(fs.getattr(foo, None).unwrap().inner().size, 20);
+        assert_eq!(fs.write(bar, 50, &[5; 11]), Err(super::ENOSPC));
+        assert_eq!(fs.write(foo, 20, &[6; 20]), Ok(20));
+        assert_eq!(fs.status().used_bytes, 90);
+        fs.fallocate(foo, 40, 10, FALLOC_FL_KEEP_SIZE).unwrap();
+        fs.truncate(foo, 10).unwrap();
+        assert_eq!(fs.status().used_bytes, 60);
+
+        // buffered bytes hold their space before they're applied
+        assert_eq!(fs.buffered_write(bar, 1, 50, &[7; 30]), Ok(30));
+        assert_eq!(fs.write_buffers.len(), 1);
+        assert_eq!(fs.status().used_bytes, 90);
+        assert_eq!(fs.buffered_write(bar, 1, 80, &[7; 11]), Err(super::ENOSPC));
+        assert_eq!(fs.write(foo, 10, &[8; 11]), Err(super::ENOSPC));
+        assert_eq!(fs.buffered_write(bar, 1, 80, &[7; 10]), Ok(10));
+        fs.flush_writes();
+        assert_eq!(fs.getattr(bar, None).unwrap().inner().size, 90);
+        assert_eq!(fs.status().used_bytes, 100);
+
+        // holes aren't counted
+        let mut fs = Daniel::new().with_max_bytes(100);
+        let sparse = fs
+            .create(ROOT_INODE, "sparse", 0, 0o644)
+            .unwrap()
+            .inner()
+            .ino;
+        fs.write(sparse, 1 << 30, b"end").unwrap();
+        assert_eq!(fs.status().used_bytes, 3);
+        fs.write(sparse, 0, &[1; 90]).unwrap();
+        assert_eq!(fs.write(sparse, 1 << 20, &[2; 8]), Err(super::ENOSPC));
+        assert_eq!(fs.write(sparse, 10, &[3; 80]), Ok(80));
+        assert_eq!(fs.status().used_bytes, 93);
+
+        // a reflink of contents that aren't shared copies them, the copy has to fit
+        let mut fs = Daniel::new().with_max_bytes(100).with_compression(true);
+        let packed = fs
+            .create(ROOT_INODE, "packed", 0, 0o644)
+            .unwrap()
+            .inner()
+            .ino;
+        fs.write(packed, 0, &[1; 90]).unwrap();
+        assert_eq!(
+            fs.reflink(unchecked_inode!(packed), ROOT_INODE, "copy".as_ref()),
+            Err(super::ENOSPC)
+        );
+        assert_eq!(
+            fs.lookup(ROOT_INODE.into(), "copy".as_ref()).err(),
+            Some(super::ENOENT)
+        );
+        fs.truncate(packed, 50).unwrap();
+        fs.reflink(unchecked_inode!(packed), ROOT_INODE, "copy".as_ref())
+            .unwrap();
+        assert_eq!(fs.status().used_bytes, 100);
+    }
+
+    #[test]
+    #[instrument]
+    fn replace_subtree() {
+        init();
+
+        let mut fixture = Daniel::new();
+        let a = fixture.create_path(Path::new("sub/a"), 0o600).unwrap();
+        fixture.write(a.into(), 0, b"grafted").unwrap();
+        fixture.create_path(Path::new("b"), 0o644).unwrap();
+
+        let mut fs = Daniel::new();
+        let old = fs.create_path(Path::new("dir/x/old"), 0o644).unwrap();
+        let dir = *fs.mapper.get_map(ROOT_INODE, "dir").unwrap();
+        let before = fs.list.map().len();
+
+        fs.replace_subtree(dir, "x".as_ref(), fixture).unwrap();
+        assert!(!fs.list.map().contains_key(&old));
+        // x and old are gone, x, sub, sub/a and b are new
+        assert_eq!(fs.list.map().len(), before - 2 + 4);
+
+        let x = *fs.mapper.get_map(dir, "x").unwrap();
+        let names: Vec<PathBuf> = fs
+            .list_dir(x)
+            .unwrap()
+            .into_iter()
+            .map(|(name, _, _)| name)
+            .collect();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&"sub".into()) && names.contains(&"b".into()));
+
+        let sub = *fs.mapper.get_map(x, "sub").unwrap();
+        let a = *fs.mapper.get_map(sub, "a").unwrap();
+        assert_eq!(fs.directory(sub).unwrap().parent(), x);
+        assert_eq!(fs.file(a).unwrap().attr().inner().perm, 0o600);
+        assert_eq!(&*fs.read(a.into(), 0, 64).unwrap(), b"grafted");
+
+        // the status file of the fixture isn't grafted and the host's can't be replaced
+        assert!(fs.mapper.get_map(x, STATUS_FILE).is_none());
+        assert_eq!(
+            fs.replace_subtree(ROOT_INODE, STATUS_FILE.as_ref(), Daniel::new()),
+            Err(super::EACCES)
+        );
+
+        // an old subtree that can't be removed stays as it was, and nothing new is left behind
+        let locked = *fs.mapper.get_map(x, "b").unwrap();
+        fs.set_flags(locked.into(), super::FS_IMMUTABLE_FL, 0)
+            .unwrap();
+        let before = fs.list.map().len();
+        let mut fixture = Daniel::new();
+        fixture.create_path(Path::new("c"), 0o644).unwrap();
+        assert_eq!(
+            fs.replace_subtree(dir, "x".as_ref(), fixture),
+            Err(super::EPERM)
+        );
+        assert_eq!(fs.list.map().len(), before);
+        assert_eq!(fs.list_dir(x).unwrap().len(), 2);
+        assert_eq!(fs.list_dir(dir).unwrap().len(), 1);
+
+        // a full directory can still have an entry replaced
+        let mut fs = fs.with_max_entries_per_dir(1);
+        fs.set_flags(locked.into(), 0, 0).unwrap();
+        fs.replace_subtree(dir, "x".as_ref(), Daniel::new())
+            .unwrap();
+        let names: Vec<_> = fs.list_dir(dir).unwrap().into_iter().map(|e| e.0).collect();
+        assert_eq!(names, [PathBuf::from("x")]);
+    }
+
+    #[test]
+    #[instrument]
+    fn attr_observer() {
+        init();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut fs = Daniel::new().with_on_attr_change({
+            let seen = Arc::clone(&seen);
+            move |ino, attr| seen.lock().unwrap().push((ino, attr.perm, attr.uid))
+        });
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        let dir = fs
+            .mkdir(ROOT_INODE.into(), "dir".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+
+        fs.chmod(foo, 0o600, 0, 0).unwrap();
+        fs.chmod(dir, 0o1777, 0, 0).unwrap();
+        assert_eq!(fs.getattr(foo, None).unwrap().inner().perm, 0o600);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                (unchecked_inode!(foo), 0o600, 0),
+                (unchecked_inode!(dir), 0o1777, 0)
+            ]
+        );
+
+        fs.chown(foo, Some(1000), None, 0).unwrap();
+        assert_eq!(seen.lock().unwrap().len(), 3);
+        assert_eq!(fs.getattr(foo, None).unwrap().inner().uid, 1000);
+
+        // nothing is reported for a failed change
+        assert_eq!(fs.chmod(12345, 0o600, 0, 0).err(), Some(super::ENOENT));
+        assert_eq!(seen.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    #[instrument]
+    fn attr_permissions() {
+        init();
+
+        let mut fs = Daniel::new();
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        fs.chown(foo, Some(1000), Some(100), 0).unwrap();
+        let perm = |fs: &mut Daniel| fs.getattr(foo, None).unwrap().inner().perm;
+
+        // only root gives a file away, the owner may still "change" it to what it is
+        assert_eq!(
+            fs.chown(foo, Some(1001), None, 1000).err(),
+            Some(super::EPERM)
+        );
+        assert_eq!(
+            fs.chown(foo, None, Some(101), 1000).err(),
+            Some(super::EPERM)
+        );
+        assert!(fs.chown(foo, Some(1000), Some(100), 1000).is_ok());
+
+        // only the owner or root changes the mode
+        assert_eq!(fs.chmod(foo, 0o777, 1001, 100).err(), Some(super::EPERM));
+        assert_eq!(perm(&mut fs), 0o644);
+        fs.chmod(foo, 0o600, 1000, 100).unwrap();
+        assert_eq!(perm(&mut fs), 0o600);
+
+        // setgid is dropped unless the caller is in the file's group or root
+        fs.chmod(foo, 0o2755, 1000, 101).unwrap();
+        assert_eq!(perm(&mut fs), 0o755);
+        fs.chmod(foo, 0o2755, 1000, 100).unwrap();
+        assert_eq!(perm(&mut fs), 0o2755);
+        fs.chmod(foo, 0o2700, 0, 0).unwrap();
+        assert_eq!(perm(&mut fs), 0o2700);
+
+        // setattr goes by the same rules
+        let chown = AttrPatch {
+            uid: Some(1001),
+            ..Default::default()
+        };
+        assert_eq!(fs.setattr(foo, chown, 1000, 100).err(), Some(super::EPERM));
+        let chmod = AttrPatch {
+            mode: Some(0o2644),
+            ..Default::default()
+        };
+        assert_eq!(fs.setattr(foo, chmod, 1001, 100).err(), Some(super::EPERM));
+        let (attr, _) = fs.setattr(foo, chmod, 1000, 101).unwrap();
+        assert_eq!(attr.inner().perm, 0o644);
+        let (attr, _) = fs.setattr(foo, chown, 0, 0).unwrap();
+        assert_eq!(attr.inner().uid, 1001);
+    }
+
+    #[test]
+    #[instrument]
+    fn writeback_writes() {
+        init();
+
+        let mut fs = Daniel::new().with_write_buffer(1 << 16);
+        let ino = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        let fh = fs.open_handle(ino, 0).unwrap();
+        let page = |byte: u8| [byte; 4096];
+
+        // dirty pages flushed back to front, leaving a hole that's never written
+        fs.write_with_flags(ino, fh, 3 * 4096, &page(b'd'), super::FUSE_WRITE_CACHE)
+            .unwrap();
+        fs.write_with_flags(ino, fh, 4096, &page(b'b'), super::FUSE_WRITE_CACHE)
+            .unwrap();
+        fs.write_with_flags(ino, fh, 0, &page(b'a'), super::FUSE_WRITE_CACHE)
+            .unwrap();
+        // and a rewrite of an earlier page with newer data
+        fs.write_with_flags(ino, fh, 4096, &page(b'B'), super::FUSE_WRITE_CACHE)
+            .unwrap();
+        assert!(fs.write_buffers.is_empty());
+
+        let data = fs.read(ino, 0, 4 * 4096).unwrap().into_owned();
+        assert_eq!(data.len(), 4 * 4096);
+        assert_eq!(&data[..4096], page(b'a'));
+        assert_eq!(&data[4096..2 * 4096], page(b'B'));
+        assert_eq!(&data[2 * 4096..3 * 4096], [0; 4096]);
+        assert_eq!(&data[3 * 4096..], page(b'd'));
+        assert_eq!(fs.getattr(ino, None).unwrap().inner().size, 4 * 4096);
+
+        // without the flag sequential writes are still buffered
+        fs.write_with_flags(ino, fh, 4 * 4096, b"tail", 0).unwrap();
+        assert_eq!(fs.write_buffers.len(), 1);
+    }
+
+    #[test]
+    #[instrument]
+    fn import_dry_run() {
+        init();
+
+        let root = temp_dir("import");
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(root.join("a"), [0; 1000]).unwrap();
+        std::fs::write(root.join("sub/b"), [0; 1500]).unwrap();
+        std::fs::write(root.join("c"), b"").unwrap();
+
+        let fs = Daniel::new().with_max_bytes(2048).with_max_inodes(10);
+        let plan = fs.import_dry_run(&root).unwrap();
+        assert_eq!(plan.bytes, 2500);
+        assert_eq!(plan.entries, 4);
+        assert!(!plan.fits());
+        assert_eq!(
+            plan.problems,
+            [ImportProblem::OverQuota {
+                needed: 2500,
+                available: 2048
+            }]
+        );
+        // nothing was created
+        assert_eq!(fs.list.map().len(), 2);
+
+        // the root already takes up an inode
+        let plan = Daniel::new()
+            .with_max_inodes(4)
+            .import_dry_run(&root)
+            .unwrap();
+        assert_eq!(
+            plan.problems,
+            [ImportProblem::TooManyInodes {
+                needed: 4,
+                available: 3
+            }]
+        );
+
+        // the host won't hold a name too long for us, but it will hold a directory too big
+        let plan = Daniel::new()
+            .with_max_entries_per_dir(2)
+            .import_dry_run(&root)
+            .unwrap();
+        assert_eq!(plan.problems, [ImportProblem::TooManyEntries(root.clone())]);
+
+        assert!(Daniel::new().import_dry_run(&root).unwrap().fits());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    #[instrument]
+    fn mmap_file() {
+        init();
+
+        let dir = temp_dir("mmap");
+        let host = dir.join("blob");
+        let contents: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+        std::fs::write(&host, &contents).unwrap();
+
+        let mut fs = Daniel::new();
+        let inode = fs.mapper.next_inode();
+        let file = File::from_mmap("blob".into(), ROOT_INODE, inode, &host).unwrap();
+        fs.push(DirEntry::File(file)).unwrap();
+        let ino = inode.into();
+
+        let attr = fs
+            .lookup(ROOT_INODE.into(), "blob".as_ref())
+            .unwrap()
+            .inner();
+        assert_eq!((attr.size, attr.perm), (10_000, 0o444));
+        let read = fs.read(ino, 4000, 100).unwrap();
+        assert!(matches!(read, std::borrow::Cow::Borrowed(_)));
+        assert_eq!(&*read, &contents[4000..4100]);
+        // reads stop at the end of the mapping
+        assert_eq!(fs.read(ino, 9990, 100).unwrap().len(), 10);
+
+        assert_eq!(fs.write(ino, 0, b"x"), Err(super::EROFS));
+        assert_eq!(fs.truncate(ino, 0), Err(super::EROFS));
+        let copy = fs.create(ROOT_INODE, "copy", 0, 0o644).unwrap().inner().ino;
+        assert_eq!(fs.copy_file_range(copy, 0, ino, 0, 10), Err(super::EROFS));
+        assert_eq!(fs.copy_file_range(ino, 0, copy, 0, 10_000), Ok(10_000));
+        assert_eq!(&*fs.read(copy, 0, u32::MAX).unwrap(), &contents[..]);
+
+        // the host file is locked while it's mapped
+        let other = std::fs::File::open(&host).unwrap();
+        assert!(other.try_lock().is_err());
+        assert_eq!(
+            File::from_mmap("again".into(), ROOT_INODE, inode, &host)
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+
+        // the mapping outlives the host file's name
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(&*fs.read(ino, 0, 4).unwrap(), &contents[..4]);
+    }
+
+    #[test]
+    #[instrument]
+    fn manual_clock() {
+        init();
+
+        let start = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let clock = ManualClock::new(start);
+        let mut fs = Daniel::new().with_clock(clock.clone());
+        assert_eq!(
+            fs.getattr(ROOT_INODE.into(), None).unwrap().inner().crtime,
+            start
+        );
+
+        clock.advance(std::time::Duration::from_secs(10));
+        let created = start + std::time::Duration::from_secs(10);
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner();
+        assert_eq!(
+            (foo.atime, foo.mtime, foo.ctime, foo.crtime),
+            (created, created, created, created)
+        );
+        assert_eq!(fs.status().uptime_secs, 10);
+
+        clock.advance(std::time::Duration::from_secs(5));
+        let written = created + std::time::Duration::from_secs(5);
+        fs.write(foo.ino, 0, b"data").unwrap();
+        let attr = fs.getattr(foo.ino, None).unwrap().inner();
+        assert_eq!(
+            (attr.mtime, attr.ctime, attr.crtime),
+            (written, written, created)
+        );
+
+        clock.advance(std::time::Duration::from_secs(5));
+        fs.chmod(foo.ino, 0o600, 0, 0).unwrap();
+        let attr = fs.getattr(foo.ino, None).unwrap().inner();
+        assert_eq!(attr.ctime, written + std::time::Duration::from_secs(5));
+        assert_eq!(attr.mtime, written);
+    }
+
+    #[test]
+    #[instrument]
+    fn rename_moves_descendant_paths() {
+        init();
+
+        let mut fs = Daniel::new();
+        let c = fs.create_path(Path::new("a/b/c"), 0o644).unwrap();
+        // a sibling sharing the prefix of the name stays put
+        let other = fs.create_path(Path::new("a.b/x"), 0o644).unwrap();
+        let a = *fs.mapper.get_path("/a").unwrap();
+        let b = *fs.mapper.get_path("/a/b").unwrap();
+        let d = fs.create_path(Path::new("d/placeholder"), 0o644).unwrap();
+        let d = fs.file(d).unwrap().parent();
+
+        fs.rename(ROOT_INODE.into(), "a".as_ref(), d.into(), "e".as_ref(), 0)
+            .unwrap();
+
+        assert_eq!(fs.mapper.get_path("/d/e"), Some(&a));
+        assert_eq!(fs.mapper.get_path("/d/e/b"), Some(&b));
+        assert_eq!(fs.mapper.get_path("/d/e/b/c"), Some(&c));
+        assert_eq!(fs.mapper.path_of(c), Some(Path::new("/d/e/b/c")));
+        assert_eq!(fs.mapper.get_path("/a/b/c"), None);
+        assert_eq!(fs.mapper.get_path("/a.b/x"), Some(&other));
+
+        // the grandchild still resolves by name through its parents
+        let e = fs.lookup(d.into(), "e".as_ref()).unwrap().inner().ino;
+        let b_attr = fs.lookup(e, "b".as_ref()).unwrap().inner();
+        assert_eq!(b_attr.ino, u64::from(b));
+        assert_eq!(
+            fs.lookup(b_attr.ino, "c".as_ref()).unwrap().inner().ino,
+            u64::from(c)
+        );
+        assert_eq!(fs.create_path(Path::new("d/e/b/c"), 0o644), Ok(c));
+    }
+
+    #[test]
+    #[instrument]
+    fn rename_into_descendant() {
+        init();
+
+        let mut fs = Daniel::new();
+        let c = fs.create_path(Path::new("a/b/c"), 0o644).unwrap();
+        let a = *fs.mapper.get_path("/a").unwrap();
+        let b = *fs.mapper.get_path("/a/b").unwrap();
+        let root = u64::from(ROOT_INODE);
+
+        assert_eq!(
+            fs.rename(root, "a".as_ref(), b.into(), "a".as_ref(), 0),
+            Err(super::EINVAL)
+        );
+        assert_eq!(
+            fs.rename(root, "a".as_ref(), a.into(), "a".as_ref(), 0),
+            Err(super::EINVAL)
+        );
+        assert_eq!(fs.path_of(c), Some(PathBuf::from("/a/b/c")));
+        // a file may go anywhere and a directory may move up
+        fs.rename(b.into(), "c".as_ref(), a.into(), "c".as_ref(), 0)
+            .unwrap();
+        fs.rename(a.into(), "b".as_ref(), root, "b".as_ref(), 0)
+            .unwrap();
+        assert_eq!(fs.path_of(b), Some(PathBuf::from("/b")));
+
+        // a cycle made behind rename's back is noticed instead of followed forever
+        fs.list.map_mut().get_mut(&a).unwrap().rename(b, "a".into());
+        fs.list.map_mut().get_mut(&b).unwrap().rename(a, "b".into());
+        fs.directory_mut(b)
+            .unwrap()
+            .insert(a, EntryType::Directory, "a");
+        fs.directory_mut(a)
+            .unwrap()
+            .insert(b, EntryType::Directory, "b");
+        assert_eq!(fs.path_of(a), None);
+        fs.mapper.link(b, Path::new("a"), a);
+        fs.mapper.link(a, Path::new("b"), b);
+        assert_eq!(fs.remove_recursive(a, "b".as_ref(), 0), Err(super::ELOOP));
+    }
+
+    #[test]
+    #[instrument]
+    fn content_budget() {
+        init();
+
+        let mut fs = Daniel::new().with_content_budget(10_000);
+        let mut files = Vec::new();
+        for name in ["a", "b", "c"] {
+            let ino = fs.create(ROOT_INODE, name, 0, 0o644).unwrap().inner().ino;
+            fs.write(ino, 0, &[1; 4000]).unwrap();
+            files.push(ino);
+        }
+        let [a, b, c] = files[..] else { unreachable!() };
+
+        // c pushed the total over, a was used least recently
+        let evicted = fs.file(unchecked_inode!(a)).unwrap();
+        assert!(evicted.is_evicted());
+        assert!(fs.read(a, 0, 4000).unwrap().is_empty());
+        let attr = fs.getattr(a, None).unwrap().inner();
+        assert_eq!((attr.size, attr.perm), (0, 0o644));
+        assert!(fs.lookup(ROOT_INODE.into(), "a".as_ref()).is_ok());
+        assert_eq!(fs.contents.total(), 8000);
+
+        // reading b makes c the least recently used
+        assert_eq!(fs.read(b, 0, 1).unwrap().len(), 1);
+        fs.write(a, 0, &[2; 4000]).unwrap();
+        assert!(!fs.file(unchecked_inode!(a)).unwrap().is_evicted());
+        assert!(fs.file(unchecked_inode!(c)).unwrap().is_evicted());
+        assert_eq!(fs.read(b, 0, 4000).unwrap().len(), 4000);
+
+        // evicting contents shared with another file lets go of them instead of copying them
+        let data = Arc::new(vec![3; 4000]);
+        let storage = Box::new(Memory::new(Arc::clone(&data)));
+        let mut file =
+            File::with_storage("d".into(), ROOT_INODE, unchecked_inode!(9), 0o644, storage);
+        file.evict();
+        assert_eq!(file.storage().shared().unwrap().capacity(), 0);
+        assert_eq!((Arc::strong_count(&data), data.len()), (1, 4000));
+
+        // without a budget nothing is tracked
+        let mut fs = Daniel::new();
+        let ino = fs.create(ROOT_INODE, "a", 0, 0o644).unwrap().inner().ino;
+        fs.write(ino, 0, &[1; 4000]).unwrap();
+        assert_eq!(fs.contents.total(), 0);
+    }
+
+    #[test]
+    #[instrument]
+    fn poll() {
+        init();
+
+        let mut fs = Daniel::new().with_write_buffer(4096);
+        let notifier = Arc::new(MockNotifier::default());
+        fs.notifier_slot().set(Arc::clone(&notifier));
+        let both = super::POLLIN | super::POLLOUT;
+
+        let ino = fs
+            .create(ROOT_INODE, "events", 0, 0o644)
+            .unwrap()
+            .inner()
+            .ino;
+        let fh = fs.open_handle(ino, 0).unwrap();
+        assert_eq!(fs.poll(ino, Some(7), both), Ok(super::POLLOUT));
+        // polling again with the same handle doesn't wake it twice
+        assert_eq!(fs.poll(ino, Some(7), both), Ok(super::POLLOUT));
+        assert_eq!(fs.poll(ino, None, super::POLLIN), Ok(0));
+
+        // data still sitting in the write buffer counts, and the waiting handle is woken once
+        fs.buffered_write(ino, fh, 0, b"event").unwrap();
+        assert_eq!(fs.write_buffers.len(), 1);
+        assert_eq!(fs.poll(ino, None, both), Ok(both));
+        fs.flush_notifications();
+        assert_eq!(*notifier.sent.lock().unwrap(), [Notification::Poll(7)]);
+        fs.write(ino, 5, b"!").unwrap();
+        fs.flush_notifications();
+        assert_eq!(notifier.sent.lock().unwrap().len(), 1);
+
+        assert_eq!(fs.poll(STATUS_INODE.into(), None, both), Ok(super::POLLIN));
+        assert_eq!(fs.poll(ROOT_INODE.into(), None, both), Err(super::EISDIR));
+    }
+
+    #[test]
+    #[instrument]
+    fn compact() {
+        init();
+
+        let mut fs = Daniel::new();
+        for i in 0..20 {
+            fs.create_path(Path::new(&format!("dir{}/file{i}", i % 3)), 0o644)
+                .unwrap();
+        }
+        for i in (0..20).step_by(2) {
+            let dir = *fs.mapper.get_path(format!("/dir{}", i % 3)).unwrap();
+            fs.unlink(dir.into(), format!("file{i}").as_ref(), 0)
+                .unwrap();
+        }
+        let kept = fs.create_path(Path::new("dir1/kept"), 0o644).unwrap();
+        fs.write(kept.into(), 0, b"contents").unwrap();
+        let fh = fs.open_handle(kept.into(), 0).unwrap();
+        let count = fs.list.map().len();
+        assert!(u64::from(fs.mapper.next_inode()) > count as u64);
+
+        fs.compact().unwrap();
+
+        // the root, then the three directories, then their children
+        let mut inodes: Vec<u64> = fs
+            .list
+            .map()
+            .keys()
+            .filter(|ino| **ino != STATUS_INODE)
+            .map(|ino| u64::from(*ino))
+            .collect();
+        inodes.sort();
+        assert_eq!(inodes, (1..count as u64).collect::<Vec<_>>());
+        assert_eq!(u64::from(fs.mapper.next_inode()), count as u64);
+        assert_eq!(fs.mapper.get_path("/dir0"), Some(&unchecked_inode!(2)));
+
+        for (ino, entry) in fs.list.map() {
+            assert_eq!(entry.attr().inner().ino, u64::from(*ino));
+            if *ino == ROOT_INODE {
+                continue;
+            }
+            let parent = fs.directory(entry.parent()).unwrap();
+            assert!(parent.contains(*ino));
+            assert_eq!(fs.mapper.get_map(entry.parent(), entry.name()), Some(ino));
+            let path = fs.mapper.path_of(*ino).unwrap();
+            assert_eq!(fs.mapper.get_path(path), Some(ino));
+        }
+
+        let kept = *fs.mapper.get_path("/dir1/kept").unwrap();
+        assert_eq!(&*fs.read(kept.into(), 0, 64).unwrap(), b"contents");
+        assert_eq!(fs.check_handle(kept.into(), fh), Err(super::ESTALE));
+        let fh = fs.open_handle(kept.into(), 0).unwrap();
+        assert_eq!(fs.check_handle(kept.into(), fh), Ok(()));
+
+        // the kernel still caches names for the old inodes while mounted
+        let notifier = Arc::new(MockNotifier::default());
+        fs.notifier_slot().set(Arc::clone(&notifier));
+        let next = fs.mapper.next_inode();
+        assert_eq!(fs.compact(), Err(super::EBUSY));
+        assert_eq!(fs.mapper.next_inode(), next);
+        assert_eq!(fs.mapper.get_path("/dir1/kept"), Some(&kept));
+        assert!(notifier.sent.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    #[instrument]
+    fn lookup() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root = ROOT_INODE.into();
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+
+        assert_eq!(fs.lookup(root, "foo".as_ref()).unwrap().inner().ino, foo);
+        assert_eq!(fs.lookup(root, "bar".as_ref()).err(), Some(super::ENOENT));
+        assert_eq!(fs.lookup(foo, "bar".as_ref()).err(), Some(super::ENOTDIR));
+        assert_eq!(fs.lookup(12345, "bar".as_ref()).err(), Some(super::ENOENT));
+
+        fs.mapper
+            .insert(ROOT_INODE, "dangling", unchecked_inode!(12345));
+        assert_eq!(
+            fs.lookup(root, "dangling".as_ref()).err(),
+            Some(super::ESTALE)
+        );
+    }
+
+    #[test]
+    #[instrument]
+    fn lookup_large_dir() {
+        init();
+
+        let mut fs = Daniel::new();
+        let names: Vec<String> = (0..20_000).map(|i| format!("file{i}")).collect();
+        let mut inodes = Vec::with_capacity(names.len());
+        for name in &names {
+            inodes.push(fs.create(ROOT_INODE, name, 0, 0o644).unwrap().inner().ino);
+        }
+
+        let start = std::time::Instant::now();
+        for (name, ino) in names.iter().zip(inodes) {
+            let attr = fs.lookup(ROOT_INODE.into(), name.as_ref()).unwrap();
+            assert_eq!(attr.inner().ino, ino);
+        }
+        info!(elapsed = ?start.elapsed(), lookups = names.len(), "looked up every entry");
+    }
+
+    #[test]
+    #[instrument]
+    fn fsync_saves_snapshot() {
+        init();
+
+        let snapshots = temp_dir("fsync");
+        let path = snapshots.join("snapshot");
+        // without a snapshot path fsync has nothing to do
+        assert_eq!(Daniel::new().fsync(), Ok(()));
+
+        let mut fs = Daniel::new().with_snapshot_path(&path);
+        let foo = fs.create_path(Path::new("dir/foo"), 0o644).unwrap().into();
+        fs.write(foo, 0, b"hello").unwrap();
+        fs.fsync().unwrap();
+
+        let saved = super::persist::decode(&std::fs::read(&path).unwrap()).unwrap();
+        let file = saved
+            .entries
+            .iter()
+            .find(|entry| entry.name() == Path::new("foo"))
+            .and_then(|entry| entry.as_file())
+            .unwrap();
+        assert_eq!(&*file.data(), b"hello");
+
+        fs.write(foo, 5, b" world").unwrap();
+        // the other names of a hard linked file are saved too
+        fs.link(foo, ROOT_INODE.into(), "bar".as_ref()).unwrap();
+        let dir = fs.lookup(ROOT_INODE.into(), "dir".as_ref()).unwrap();
+        fs.link(foo, dir.inner().ino, "baz".as_ref()).unwrap();
+        fs.fsync().unwrap();
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        assert!(!Path::new(&tmp).exists());
+
+        let mut loaded = Daniel::load(&path).unwrap();
+        let dir = loaded.lookup(ROOT_INODE.into(), "dir".as_ref()).unwrap();
+        let foo = loaded.lookup(dir.inner().ino, "foo".as_ref()).unwrap();
+        assert_eq!(
+            &*loaded.read(foo.inner().ino, 0, u32::MAX).unwrap(),
+            b"hello world"
+        );
+        assert_eq!(foo.inner().nlink, 3);
+        let bar = loaded.lookup(ROOT_INODE.into(), "bar".as_ref()).unwrap();
+        let baz = loaded.lookup(dir.inner().ino, "baz".as_ref()).unwrap();
+        assert_eq!(bar.inner().ino, foo.inner().ino);
+        assert_eq!(baz.inner().ino, foo.inner().ino);
+        loaded.write(bar.inner().ino, 0, b"J").unwrap();
+        assert_eq!(
+            &*loaded.read(baz.inner().ino, 0, u32::MAX).unwrap(),
+            b"Jello world"
+        );
+        std::fs::remove_dir_all(&snapshots).unwrap();
+    }
+
+    #[test]
+    #[instrument]
+    fn destroy_saves_snapshot() {
+        init();
+
+        let snapshots = temp_dir("destroy");
+        let path = snapshots.join("snapshot");
+        // nothing is written without a snapshot path
+        Daniel::new().destroy();
+
+        let mut fs = Daniel::new().with_snapshot_path(&path);
+        let foo = fs.create_path(Path::new("dir/foo"), 0o644).unwrap().into();
+        fs.write(foo, 0, b"hello").unwrap();
+        fs.link(foo, ROOT_INODE.into(), "bar".as_ref()).unwrap();
+        assert!(!path.exists());
+        fs.destroy();
+        drop(fs);
+        assert!(path.exists());
+
+        // it's the same snapshot save writes, hard links and all
+        let mut loaded = Daniel::load(&path).unwrap();
+        let dir = loaded.lookup(ROOT_INODE.into(), "dir".as_ref()).unwrap();
+        let foo = loaded.lookup(dir.inner().ino, "foo".as_ref()).unwrap();
+        assert_eq!(
+            &*loaded.read(foo.inner().ino, 0, u32::MAX).unwrap(),
+            b"hello"
+        );
+        let bar = loaded.lookup(ROOT_INODE.into(), "bar".as_ref()).unwrap();
+        assert_eq!(bar.inner().ino, foo.inner().ino);
+        assert_eq!(bar.inner().nlink, 2);
+        std::fs::remove_dir_all(&snapshots).unwrap();
+    }
+
+    #[test]
+    #[instrument]
+    fn read_only_precedence() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root = ROOT_INODE.into();
+        let locked = fs
+            .create(ROOT_INODE, "locked", 0, 0o444)
+            .unwrap()
+            .inner()
+            .ino;
+        let open = fs.create(ROOT_INODE, "open", 0, 0o644).unwrap().inner().ino;
+        let tmp = fs
+            .mkdir(root, "tmp".as_ref(), 0o1777, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        fs.create(unchecked_inode!(tmp), "theirs", 0, 0o644)
+            .unwrap();
+
+        // a writable mount goes by the permissions of the caller, root isn't held back by them
+        let wronly = super::O_WRONLY;
+        assert_eq!(fs.open(locked, wronly, 1001, 100), Err(super::EACCES));
+        let truncate = AttrPatch {
+            size: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            fs.setattr(locked, truncate, 1001, 100).err(),
+            Some(super::EACCES)
+        );
+        assert!(fs.open(locked, wronly, 0, 0).is_ok());
+        assert!(fs.setattr(locked, truncate, 0, 0).is_ok());
+        assert_eq!(fs.unlink(tmp, "theirs".as_ref(), 1001), Err(super::EACCES));
+        assert_eq!(fs.write(open, 0, b"x"), Ok(1));
+
+        // a read only mount refuses before the permissions are looked at
+        let mut fs = fs.with_read_only(true);
+        assert_eq!(fs.write(locked, 0, b"x"), Err(super::EROFS));
+        assert_eq!(fs.truncate(locked, 0), Err(super::EROFS));
+        assert_eq!(fs.unlink(tmp, "theirs".as_ref(), 1001), Err(super::EROFS));
+        assert_eq!(fs.write(open, 0, b"x"), Err(super::EROFS));
+        assert_eq!(fs.write(STATUS_INODE.into(), 0, b"x"), Err(super::EROFS));
+        assert_eq!(
+            fs.create(ROOT_INODE, "new", 0, 0o644).err(),
+            Some(super::EROFS)
+        );
+        assert_eq!(fs.chmod(locked, 0o644, 0, 0).err(), Some(super::EROFS));
+        assert_eq!(&*fs.read(open, 0, u32::MAX).unwrap(), b"x");
+    }
+
+    #[test]
+    #[instrument]
+    fn immutable() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root = ROOT_INODE.into();
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        fs.create(ROOT_INODE, "bar", 0, 0o644).unwrap();
+
+        // only root may set the flag
+        assert_eq!(
+            fs.set_flags(foo, super::FS_IMMUTABLE_FL, 1000).err(),
+            Some(super::EPERM)
+        );
+        let attr = fs.set_flags(foo, super::FS_IMMUTABLE_FL, 0).unwrap();
+        assert_eq!(attr.inner().flags, super::FS_IMMUTABLE_FL);
+
+        assert_eq!(fs.write(foo, 0, b"x"), Err(super::EPERM));
+        assert_eq!(fs.truncate(foo, 0), Err(super::EPERM));
+        assert_eq!(fs.chmod(foo, 0o600, 0, 0).err(), Some(super::EPERM));
+        assert_eq!(fs.unlink(root, "foo".as_ref(), 0), Err(super::EPERM));
+        assert_eq!(
+            fs.rename(root, "foo".as_ref(), root, "baz".as_ref(), 0),
+            Err(super::EPERM)
+        );
+        // nor can it be replaced by a rename
+        assert_eq!(
+            fs.rename(root, "bar".as_ref(), root, "foo".as_ref(), 0),
+            Err(super::EPERM)
+        );
+
+        assert_eq!(fs.set_flags(foo, 0, 1000).err(), Some(super::EPERM));
+        fs.set_flags(foo, 0, 0).unwrap();
+        assert_eq!(fs.write(foo, 0, b"x"), Ok(1));
+        fs.unlink(root, "foo".as_ref(), 0).unwrap();
+    }
+
+    #[test]
+    #[instrument]
+    fn dir_mtime() {
+        init();
+
+        let start = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let clock = ManualClock::new(start);
+        let mut fs = Daniel::new().with_clock(clock.clone());
+        let root = ROOT_INODE.into();
+        let a = fs
+            .mkdir(root, "a".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        let b = fs
+            .mkdir(root, "b".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        let times = |fs: &mut Daniel, ino| {
+            let attr = fs.getattr(ino, None).unwrap().inner();
+            (attr.mtime, attr.ctime)
+        };
+        assert_eq!(times(&mut fs, a), (start, start));
+
+        let mut now = start;
+        let mut tick = || {
+            now += std::time::Duration::from_secs(1);
+            clock.advance(std::time::Duration::from_secs(1));
+            now
+        };
+
+        let created = tick();
+        fs.create(unchecked_inode!(a), "foo", 0, 0o644).unwrap();
+        assert_eq!(times(&mut fs, a), (created, created));
+        assert_eq!(times(&mut fs, root), (start, start));
+
+        let renamed = tick();
+        fs.rename(a, "foo".as_ref(), b, "bar".as_ref(), 0).unwrap();
+        assert_eq!(times(&mut fs, a), (renamed, renamed));
+        assert_eq!(times(&mut fs, b), (renamed, renamed));
+
+        let removed = tick();
+        fs.unlink(b, "bar".as_ref(), 0).unwrap();
+        assert_eq!(times(&mut fs, b), (removed, removed));
+        assert_eq!(times(&mut fs, a), (renamed, renamed));
+    }
+
+    #[test]
+    #[instrument]
+    fn readdir_large_dir() {
+        init();
+
+        let mut fs = Daniel::new();
+        let names: Vec<String> = (0..10_000).map(|i| format!("file{i}")).collect();
+        for name in &names {
+            fs.create(ROOT_INODE, name, 0, 0o644).unwrap();
+        }
+
+        let start = std::time::Instant::now();
+        let listed = fs.dir_entries_after(ROOT_INODE, 0).unwrap().len();
+        info!(elapsed = ?start.elapsed(), entries = listed, "listed every entry");
+        assert_eq!(listed, names.len() + 3);
+
+        // the directory lists its children without looking any of them up
+        fs.list
+            .map_mut()
+            .retain(|ino, _| *ino == ROOT_INODE || *ino == STATUS_INODE);
+        let entries = fs.dir_entries_after(ROOT_INODE, 0).unwrap();
+        let listed: Vec<&Path> = entries.iter().skip(3).map(|entry| entry.3).collect();
+        assert!(listed.iter().copied().eq(names.iter().map(Path::new)));
+    }
+
+    #[test]
+    #[instrument]
+    fn inode_base() {
+        init();
+
+        let mut fs = Daniel::new().with_inode_base(unchecked_inode!(1001));
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        assert_eq!(foo, 1001);
+        let dir = fs
+            .mkdir(ROOT_INODE.into(), "dir".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        assert_eq!(dir, 1002);
+        assert_eq!(fs.getattr(ROOT_INODE.into(), None).unwrap().inner().ino, 1);
+        assert_eq!(fs.set_next_inode(unchecked_inode!(500)), Err(super::EINVAL));
+
+        // compacting keeps the reserved range free
+        fs.unlink(ROOT_INODE.into(), "foo".as_ref(), 0).unwrap();
+        fs.compact().unwrap();
+        assert_eq!(
+            fs.resolve_following_symlinks("/dir", 0),
+            Some(unchecked_inode!(1001))
+        );
+        let bar = fs.create(ROOT_INODE, "bar", 0, 0o644).unwrap().inner().ino;
+        assert_eq!(bar, 1002);
+    }
+
+    #[test]
+    #[instrument]
+    fn set_next_inode() {
+        init();
+
+        let mut fs = Daniel::new();
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        assert_eq!(fs.next_inode(), unchecked_inode!(foo + 1));
+
+        fs.set_next_inode(unchecked_inode!(100)).unwrap();
+        assert_eq!(fs.next_inode(), unchecked_inode!(100));
+        let bar = fs.create(ROOT_INODE, "bar", 0, 0o644).unwrap().inner().ino;
+        assert_eq!(bar, 100);
+        assert_eq!(
+            fs.lookup(ROOT_INODE.into(), "bar".as_ref())
+                .unwrap()
+                .inner()
+                .ino,
+            100
+        );
+
+        // inodes in use can't be handed out again
+        assert_eq!(fs.set_next_inode(unchecked_inode!(100)), Err(super::EINVAL));
+        assert_eq!(fs.set_next_inode(unchecked_inode!(50)), Err(super::EINVAL));
+        assert_eq!(fs.set_next_inode(super::STATUS_INODE), Err(super::EINVAL));
+        assert_eq!(fs.next_inode(), unchecked_inode!(101));
+
+        // nor can one still held by the kernel after it was removed
+        fs.remember(bar);
+        fs.unlink(ROOT_INODE.into(), "bar".as_ref(), 0).unwrap();
+        assert_eq!(fs.set_next_inode(unchecked_inode!(100)), Err(super::EINVAL));
+    }
+
+    #[test]
+    #[instrument]
+    fn atime_policy() {
+        init();
+
+        let start = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let second = std::time::Duration::from_secs(1);
+        let day = RELATIME_MAX_AGE;
+        let atime = |fs: &mut Daniel, ino| fs.getattr(ino, None).unwrap().inner().atime;
+
+        let mut atimes = Vec::new();
+        for policy in [
+            AtimePolicy::Strict,
+            AtimePolicy::Relatime,
+            AtimePolicy::Noatime,
+        ] {
+            let clock = ManualClock::new(start);
+            let mut fs = Daniel::new()
+                .with_clock(clock.clone())
+                .with_atime_policy(policy);
+            let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+
+            // the atime isn't newer than the mtime yet
+            clock.advance(second);
+            fs.read(foo, 0, 16).unwrap();
+            let first = atime(&mut fs, foo);
+
+            // now it is
+            clock.advance(second);
+            fs.read(foo, 0, 16).unwrap();
+            let again = atime(&mut fs, foo);
+
+            // and it's a day old
+            clock.advance(day);
+            fs.read(foo, 0, 16).unwrap();
+            let stale = atime(&mut fs, foo);
+
+            // until the file is written
+            fs.write(foo, 0, b"x").unwrap();
+            clock.advance(second);
+            fs.read(foo, 0, 16).unwrap();
+            let written = atime(&mut fs, foo);
+
+            atimes.push((policy, [first, again, stale, written]));
+        }
+
+        let after = |by| start + by;
+        assert_eq!(
+            atimes,
+            [
+                (
+                    AtimePolicy::Strict,
+                    [
+                        after(second),
+                        after(second * 2),
+                        after(second * 2 + day),
+                        after(second * 3 + day)
+                    ]
+                ),
+                (
+                    AtimePolicy::Relatime,
+                    [
+                        after(second),
+                        after(second),
+                        after(second * 2 + day),
+                        after(second * 3 + day)
+                    ]
+                ),
+                (AtimePolicy::Noatime, [start; 4]),
+            ]
         );
-        reply.error(ENOSYS);
+        assert_eq!(Daniel::new().atime_policy, AtimePolicy::Relatime);
     }
 
-    fn getlk(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        fh: u64,
-        lock_owner: u64,
-        start: u64,
-        end: u64,
-        typ: i32,
-        pid: u32,
-        reply: fuser::ReplyLock,
-    ) {
-        debug!(
-            "[Not Implemented] getlk(ino: {:#x?}, fh: {}, lock_owner: {}, start: {}, \
-            end: {}, typ: {}, pid: {})",
-            ino, fh, lock_owner, start, end, typ, pid
+    #[test]
+    #[instrument]
+    fn setattr_patch() {
+        init();
+
+        let start = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let clock = ManualClock::new(start);
+        let mut fs = Daniel::new().with_clock(clock.clone());
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        fs.write(foo, 0, b"hello").unwrap();
+
+        let later = start + std::time::Duration::from_secs(60);
+        clock.set(later);
+        let mtime = start - std::time::Duration::from_secs(3600);
+        let patch = AttrPatch {
+            size: Some(2),
+            mode: Some(0o100600),
+            mtime: Some(mtime),
+            ..Default::default()
+        };
+        let (attr, changes) = fs.setattr(foo, patch, 0, 0).unwrap();
+        let attr = attr.inner();
+
+        assert_eq!(
+            changes,
+            AttrChanges {
+                size: true,
+                mode: true,
+                mtime: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            (attr.size, attr.perm, attr.mtime, attr.ctime),
+            (2, 0o600, mtime, later)
+        );
+        assert_eq!(&*fs.read(foo, 0, 16).unwrap(), b"he");
+
+        // nothing changes, so ctime stays put
+        clock.set(later + std::time::Duration::from_secs(60));
+        let (attr, changes) = fs.setattr(foo, patch, 0, 0).unwrap();
+        assert!(!changes.any());
+        assert_eq!(attr.inner().ctime, later);
+    }
+
+    #[test]
+    #[instrument]
+    fn hard_links() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root = u64::from(ROOT_INODE);
+        let dir = fs
+            .mkdir(root, "dir".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        fs.write(foo, 0, b"shared").unwrap();
+
+        let attr = fs.link(foo, dir, "bar".as_ref()).unwrap().inner();
+        assert_eq!((attr.ino, attr.nlink), (foo, 2));
+        assert_eq!(
+            fs.link(dir, root, "loop".as_ref()).err(),
+            Some(super::EPERM)
+        );
+
+        // the data outlives the name it was created under
+        fs.unlink(root, "foo".as_ref(), 0).unwrap();
+        assert_eq!(fs.lookup(root, "foo".as_ref()).err(), Some(super::ENOENT));
+        let attr = fs.lookup(dir, "bar".as_ref()).unwrap().inner();
+        assert_eq!((attr.ino, attr.nlink), (foo, 1));
+        assert_eq!(&*fs.read(foo, 0, 64).unwrap(), b"shared");
+        assert_eq!(
+            fs.mapper.path_of(unchecked_inode!(foo)),
+            Some(Path::new("/dir/bar"))
+        );
+
+        // until the last one goes
+        fs.unlink(dir, "bar".as_ref(), 0).unwrap();
+        assert!(!fs.list.map().contains_key(&unchecked_inode!(foo)));
+        assert!(fs.mapper.path_of(unchecked_inode!(foo)).is_none());
+    }
+
+    #[test]
+    #[instrument]
+    fn hard_links_in_one_directory() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root = u64::from(ROOT_INODE);
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        let attr = fs.link(foo, root, "bar".as_ref()).unwrap().inner();
+        assert_eq!((attr.ino, attr.nlink), (foo, 2));
+
+        let listed = |fs: &Daniel| -> Vec<(u64, PathBuf)> {
+            fs.dir_entries(ROOT_INODE)
+                .unwrap()
+                .into_iter()
+                .skip(2)
+                .filter(|(ino, _, _)| *ino != STATUS_INODE)
+                .map(|(ino, _, name)| (ino.into(), name.to_path_buf()))
+                .collect()
+        };
+        assert_eq!(listed(&fs), [(foo, "foo".into()), (foo, "bar".into())]);
+        for name in ["foo", "bar"] {
+            assert_eq!(fs.lookup(root, name.as_ref()).unwrap().inner().ino, foo);
+        }
+        // both names are the same file, so there's nothing to do
+        fs.rename(root, "foo".as_ref(), root, "bar".as_ref(), 0)
+            .unwrap();
+        assert_eq!(listed(&fs).len(), 2);
+
+        // compacting keeps both names on the one renumbered inode
+        fs.compact().unwrap();
+        let foo = fs.lookup(root, "foo".as_ref()).unwrap().inner().ino;
+        assert_eq!(listed(&fs), [(foo, "foo".into()), (foo, "bar".into())]);
+
+        fs.unlink(root, "foo".as_ref(), 0).unwrap();
+        assert_eq!(listed(&fs), [(foo, "bar".into())]);
+        let attr = fs.lookup(root, "bar".as_ref()).unwrap().inner();
+        assert_eq!((attr.ino, attr.nlink), (foo, 1));
+        assert_eq!(
+            fs.mapper.path_of(unchecked_inode!(foo)),
+            Some(Path::new("/bar"))
+        );
+    }
+
+    #[test]
+    #[instrument]
+    fn whiteouts() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root = u64::from(ROOT_INODE);
+        let gone = fs.whiteout(root, "gone".as_ref()).unwrap().inner();
+        assert_eq!((gone.kind, gone.rdev), (FileType::CharDevice, 0));
+        // any other device number is a plain device
+        fs.mknod(
+            root,
+            "null".as_ref(),
+            libc::S_IFCHR | 0o666,
+            (1 << 8) | 3,
+            0,
+            0,
+        )
+        .unwrap();
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+
+        let listed = |fs: &Daniel| -> Vec<(PathBuf, EntryType)> {
+            fs.dir_entries(ROOT_INODE)
+                .unwrap()
+                .into_iter()
+                .skip(2)
+                .map(|(_, kind, name)| (name.to_path_buf(), kind))
+                .collect()
+        };
+        assert_eq!(
+            listed(&fs),
+            [
+                (STATUS_FILE.into(), EntryType::File),
+                ("gone".into(), EntryType::Whiteout),
+                ("null".into(), EntryType::CharDevice),
+                ("foo".into(), EntryType::File),
+            ]
+        );
+
+        let mut fs = fs.with_hide_whiteouts(true);
+        assert_eq!(
+            listed(&fs),
+            [
+                (STATUS_FILE.into(), EntryType::File),
+                ("null".into(), EntryType::CharDevice),
+                ("foo".into(), EntryType::File),
+            ]
+        );
+        assert_eq!(
+            fs.lookup(root, "gone".as_ref()).unwrap().inner().ino,
+            gone.ino
+        );
+
+        let dir = fs
+            .mkdir(root, "dir".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        assert_eq!(fs.is_opaque(unchecked_inode!(dir)), Ok(false));
+        fs.setxattr(dir, OPAQUE_XATTR.as_ref(), b"y", 0).unwrap();
+        assert_eq!(fs.is_opaque(unchecked_inode!(dir)), Ok(true));
+        assert_eq!(
+            fs.setxattr(foo, OPAQUE_XATTR.as_ref(), b"y", 0),
+            Err(super::ENOTDIR)
+        );
+        assert_eq!(fs.is_opaque(unchecked_inode!(foo)), Err(super::ENOTDIR));
+    }
+
+    #[test]
+    #[instrument]
+    fn path_of() {
+        init();
+
+        let mut fs = Daniel::new();
+        let leaf = fs.create_path(Path::new("/a/b/c/d/leaf"), 0o644).unwrap();
+        let c = fs.resolve_following_symlinks("/a/b/c", 0).unwrap();
+
+        assert_eq!(fs.path_of(leaf), Some(PathBuf::from("/a/b/c/d/leaf")));
+        assert_eq!(fs.path_of(c), Some(PathBuf::from("/a/b/c")));
+        assert_eq!(fs.path_of(ROOT_INODE), Some(PathBuf::from("/")));
+        assert_eq!(
+            fs.path_of(STATUS_INODE),
+            Some(Path::new("/").join(STATUS_FILE))
+        );
+        assert_eq!(
+            fs.resolve_following_symlinks(fs.path_of(leaf).unwrap(), 0),
+            Some(leaf)
+        );
+
+        // follows renames of the directories above
+        let root = u64::from(ROOT_INODE);
+        fs.rename(root, "a".as_ref(), root, "z".as_ref(), 0)
+            .unwrap();
+        assert_eq!(fs.path_of(leaf), Some(PathBuf::from("/z/b/c/d/leaf")));
+
+        assert_eq!(fs.path_of(unchecked_inode!(9999)), None);
+        let d = fs.resolve_following_symlinks("/z/b/c/d", 0).unwrap();
+        fs.unlink(d.into(), "leaf".as_ref(), 0).unwrap();
+        assert_eq!(fs.path_of(leaf), None);
+    }
+
+    #[test]
+    #[instrument]
+    fn sparse_files() {
+        init();
+
+        let mut fs = Daniel::new();
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        fs.write(foo, 0, b"head").unwrap();
+
+        let gib = 1 << 30;
+        let far = 1 << 29;
+        fs.truncate(foo, gib).unwrap();
+        fs.write(foo, far as i64, b"x").unwrap();
+
+        // only the five written bytes are kept
+        let file = fs.file(unchecked_inode!(foo)).unwrap();
+        assert_eq!(file.storage().stored_len(), 5);
+        let attr = fs.getattr(foo, None).unwrap().inner();
+        assert_eq!((attr.size, attr.blocks), (gib, 1));
+
+        assert_eq!(&*fs.read(foo, 0, 8).unwrap(), b"head\0\0\0\0");
+        assert_eq!(&*fs.read(foo, far as i64 - 2, 5).unwrap(), b"\0\0x\0\0");
+        assert_eq!(&*fs.read(foo, gib as i64 - 2, 16).unwrap(), b"\0\0");
+
+        // fallocate grows it the same way
+        fs.fallocate(foo, 0, 2 * gib as i64, 0).unwrap();
+        assert_eq!(fs.getattr(foo, None).unwrap().inner().size, 2 * gib);
+        fs.fallocate(foo, 0, 4 * gib as i64, FALLOC_FL_KEEP_SIZE)
+            .unwrap();
+        assert_eq!(fs.getattr(foo, None).unwrap().inner().size, 2 * gib);
+        assert_eq!(fs.fallocate(foo, 0, 1, 0x02), Err(super::EOPNOTSUPP));
+
+        fs.truncate(foo, 2).unwrap();
+        assert_eq!(&*fs.read(foo, 0, 8).unwrap(), b"he");
+        let file = fs.file(unchecked_inode!(foo)).unwrap();
+        assert_eq!(file.storage().stored_len(), 2);
+    }
+
+    #[test]
+    #[instrument]
+    fn sparse_block_counts() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root = u64::from(ROOT_INODE);
+        let size = 1 << 30;
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        fs.fallocate(foo, 0, size as i64, 0).unwrap();
+        fs.write(foo, 1 << 20, &[1; 4096]).unwrap();
+
+        // `du` counts the one written extent, `ls -l` the whole size
+        for attr in [
+            fs.getattr(foo, None).unwrap().inner(),
+            fs.lookup(root, "foo".as_ref()).unwrap().inner(),
+        ] {
+            assert_eq!((attr.size, attr.blocks), (size, 8));
+            assert!(attr.blocks * 512 < attr.size / 1000);
+        }
+
+        // storage handed over as is counts the same
+        let storage = fs
+            .file(unchecked_inode!(foo))
+            .unwrap()
+            .storage()
+            .box_clone();
+        let ino = fs.mapper.next_inode();
+        fs.push(DirEntry::File(File::with_storage(
+            "bar".into(),
+            ROOT_INODE,
+            ino,
+            0o644,
+            storage,
+        )))
+        .unwrap();
+        let attr = fs.getattr(ino.into(), None).unwrap().inner();
+        assert_eq!((attr.size, attr.blocks), (size, 8));
+    }
+
+    #[test]
+    #[instrument]
+    fn rename_over_open_file() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root = u64::from(ROOT_INODE);
+        let target = fs
+            .create(ROOT_INODE, "target", 0, 0o644)
+            .unwrap()
+            .inner()
+            .ino;
+        fs.write(target, 0, b"old contents").unwrap();
+        let source = fs
+            .create(ROOT_INODE, "source", 0, 0o644)
+            .unwrap()
+            .inner()
+            .ino;
+        fs.write(source, 0, b"new contents").unwrap();
+        let fh = fs.open(target, 0, 0, 0).unwrap();
+
+        fs.rename(root, "source".as_ref(), root, "target".as_ref(), 0)
+            .unwrap();
+        let attr = fs.lookup(root, "target".as_ref()).unwrap().inner();
+        assert_eq!(attr.ino, source);
+        assert_eq!(&*fs.read(source, 0, 64).unwrap(), b"new contents");
+
+        // the handle opened before the rename still reads the file it replaced
+        assert_eq!(fs.check_handle(target, fh), Ok(()));
+        assert_eq!(&*fs.read(target, 0, 64).unwrap(), b"old contents");
+        assert_eq!(fs.getattr(target, None).unwrap().inner().nlink, 0);
+        assert!(fs.orphans.contains(&unchecked_inode!(target)));
+
+        // which is freed once it's closed
+        fs.release(target, fh).unwrap();
+        assert!(!fs.list.map().contains_key(&unchecked_inode!(target)));
+        assert!(fs.orphans.is_empty() && fs.open_files.is_empty());
+        assert_eq!(fs.release(target, fh), Err(super::ESTALE));
+    }
+
+    #[test]
+    fn span_fields() {
+        struct Captured(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Captured {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let writer = Arc::clone(&captured);
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_span_events(FmtSpan::CLOSE)
+            .with_writer(move || Captured(Arc::clone(&writer)))
+            .finish();
+
+        let mut fs = Daniel::new();
+        let leaf = fs.create_path(Path::new("/a/b/leaf"), 0o644).unwrap();
+        let b = fs.resolve_following_symlinks("/a/b", 0).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            // the spans the handlers open
+            let empty = tracing::field::Empty;
+            tracing::info_span!("getattr", path = empty, kind = empty)
+                .in_scope(|| fs.record_entry(leaf));
+            tracing::info_span!("lookup", path = empty, kind = empty)
+                .in_scope(|| fs.record_entry(b));
+        });
+
+        let output = String::from_utf8(captured.lock().unwrap().clone()).unwrap();
+        assert!(
+            output.contains("getattr{path=/a/b/leaf kind=file}"),
+            "{output}"
+        );
+        assert!(output.contains("lookup{path=/a/b kind=dir}"), "{output}");
+    }
+
+    #[test]
+    #[instrument]
+    fn latency_histograms() {
+        init();
+
+        let start = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let clock = ManualClock::new(start);
+        // each setattr moves the clock along by the next of these while it runs
+        let steps = Arc::new(Mutex::new(vec![
+            std::time::Duration::from_secs(5),
+            std::time::Duration::from_millis(50),
+            std::time::Duration::from_micros(5),
+        ]));
+        let mut fs = Daniel::new()
+            .with_clock(clock.clone())
+            .with_on_attr_change({
+                let clock = clock.clone();
+                let steps = Arc::clone(&steps);
+                move |_, _| clock.advance(steps.lock().unwrap().pop().unwrap_or_default())
+            });
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        for perm in [0o600, 0o640, 0o644] {
+            let patch = AttrPatch {
+                mode: Some(0o100000 | perm),
+                ..Default::default()
+            };
+            fs.setattr(foo, patch, 0, 0).unwrap();
+        }
+        fs.lookup(ROOT_INODE.into(), "foo".as_ref()).unwrap();
+        fs.lookup(ROOT_INODE.into(), "missing".as_ref())
+            .unwrap_err();
+
+        let setattr = fs.metrics().histogram(Op::Setattr);
+        assert_eq!(setattr.counts(), [1, 0, 0, 0, 1, 0, 1]);
+        assert_eq!(setattr.count(), 3);
+        assert_eq!(setattr.percentile(0.5), Some(LATENCY_BUCKETS[4]));
+        assert_eq!(setattr.percentile(1.0), None);
+
+        // nothing moved the clock during these, failures are timed too
+        let lookup = fs.metrics().histogram(Op::Lookup);
+        assert_eq!(lookup.counts(), [2, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(fs.metrics().histogram(Op::Create).count(), 1);
+        assert_eq!(fs.metrics().histogram(Op::Rename).percentile(0.5), None);
+    }
+
+    #[test]
+    #[instrument]
+    fn getattr_removed() {
+        init();
+
+        let mut fs = Daniel::new();
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        assert!(fs.getattr(foo, None).is_ok());
+
+        fs.unlink(ROOT_INODE.into(), "foo".as_ref(), 0).unwrap();
+        assert_eq!(fs.getattr(foo, None).unwrap_err(), super::ENOENT);
+        assert_eq!(fs.getattr(u64::MAX - 1, None).unwrap_err(), super::ENOENT);
+    }
+
+    #[test]
+    #[instrument]
+    fn dir_read_as_listing() {
+        init();
+
+        let mut fs = Daniel::new();
+        let dir = fs
+            .mkdir(ROOT_INODE.into(), "dir".as_ref(), 0o755, 0, 0, 0)
+            .unwrap();
+        let dir = dir.inner().ino;
+        assert_eq!(fs.read(dir, 0, 4096).unwrap_err(), super::EISDIR);
+
+        let mut fs = fs.with_dir_read_as_listing(true);
+        assert!(fs.read(dir, 0, 4096).unwrap().is_empty());
+        for name in ["foo", "bar"] {
+            fs.create(unchecked_inode!(dir), name, 0, 0o644).unwrap();
+        }
+        fs.mkdir(dir, "sub".as_ref(), 0o755, 0, 0, 0).unwrap();
+
+        assert_eq!(&*fs.read(dir, 0, 4096).unwrap(), b"foo\nbar\nsub\n");
+        assert_eq!(&*fs.read(dir, 4, 5).unwrap(), b"bar\ns");
+        assert!(fs.read(dir, 100, 10).unwrap().is_empty());
+        let root = fs.read(ROOT_INODE.into(), 0, 4096).unwrap().into_owned();
+        assert_eq!(root, format!("{STATUS_FILE}\ndir\n").into_bytes());
+        // files read as before
+        let foo = fs.lookup(dir, "foo".as_ref()).unwrap().inner().ino;
+        assert!(fs.read(foo, 0, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    #[instrument]
+    fn permission_classes() {
+        init();
+
+        let (r, w, x) = (super::R_OK, super::W_OK, super::X_OK);
+        let mut fs = Daniel::new();
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o640).unwrap().inner().ino;
+        fs.chown(foo, Some(1000), Some(100), 0).unwrap();
+        let attr = fs.getattr(foo, None).unwrap().inner();
+
+        // the owner gets rw-
+        assert!(check_permission(&attr, 1000, 100, r | w));
+        assert!(!check_permission(&attr, 1000, 100, x));
+        // the owner class applies even when the group would grant less or more
+        assert!(check_permission(&attr, 1000, 5, w));
+        // the group gets r--
+        assert!(check_permission(&attr, 1001, 100, r));
+        assert!(!check_permission(&attr, 1001, 100, w));
+        // everyone else gets ---
+        assert!(!check_permission(&attr, 1001, 101, r));
+        assert!(check_permission(&attr, 1001, 101, 0));
+        // root reads and writes anything but only executes with some execute bit
+        assert!(check_permission(&attr, 0, 0, r | w));
+        assert!(!check_permission(&attr, 0, 0, x));
+        fs.chmod(foo, 0o601, 0, 0).unwrap();
+        let attr = fs.getattr(foo, None).unwrap().inner();
+        assert!(check_permission(&attr, 0, 0, x));
+        assert!(check_permission(&attr, 1001, 101, x));
+
+        assert_eq!(fs.access(foo, w, 1001, 101).unwrap_err(), super::EACCES);
+        assert!(fs.access(foo, r | w, 1000, 100).is_ok());
+        assert_eq!(fs.access(u64::MAX - 1, r, 0, 0).unwrap_err(), super::ENOENT);
+
+        let (wronly, rdwr, trunc) = (super::O_WRONLY, super::O_RDWR, super::O_TRUNC);
+        fs.chmod(foo, 0o640, 0, 0).unwrap();
+        assert!(fs.open(foo, 0, 1001, 100).is_ok());
+        assert_eq!(fs.open(foo, wronly, 1001, 100).unwrap_err(), super::EACCES);
+        assert_eq!(fs.open(foo, trunc, 1001, 100).unwrap_err(), super::EACCES);
+        assert_eq!(fs.open(foo, 0, 1001, 101).unwrap_err(), super::EACCES);
+        assert!(fs.open(foo, rdwr, 1000, 100).is_ok());
+        assert!(fs.open(foo, rdwr | trunc, 0, 0).is_ok());
+    }
+
+    #[test]
+    #[instrument]
+    fn flags_ioctl() {
+        init();
+
+        let (get, set) = (super::FS_IOC_GETFLAGS, super::FS_IOC_SETFLAGS);
+        let (immutable, append) = (super::FS_IMMUTABLE_FL, super::FS_APPEND_FL);
+        let mut fs = Daniel::new();
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        fs.write(foo, 0, b"hello").unwrap();
+        assert_eq!(fs.ioctl(foo, get, &[], 0).unwrap(), 0u32.to_ne_bytes());
+
+        fs.ioctl(foo, set, &immutable.to_ne_bytes(), 0).unwrap();
+        assert_eq!(fs.ioctl(foo, get, &[], 0).unwrap(), immutable.to_ne_bytes());
+        assert_eq!(fs.write(foo, 5, b"!"), Err(super::EPERM));
+
+        // an append only file only grows from its end
+        fs.ioctl(foo, set, &append.to_ne_bytes(), 0).unwrap();
+        assert_eq!(fs.write(foo, 5, b" world"), Ok(6));
+        assert_eq!(fs.write(foo, 0, b"j"), Err(super::EPERM));
+        assert_eq!(fs.truncate(foo, 0), Err(super::EPERM));
+        assert_eq!(
+            fs.unlink(ROOT_INODE.into(), "foo".as_ref(), 0),
+            Err(super::EPERM)
+        );
+
+        assert_eq!(
+            fs.ioctl(foo, set, &0u32.to_ne_bytes(), 1000),
+            Err(super::EPERM)
+        );
+        assert_eq!(
+            fs.ioctl(foo, set, &0x80u32.to_ne_bytes(), 0),
+            Err(super::EOPNOTSUPP)
+        );
+        assert_eq!(fs.ioctl(foo, set, &[0; 2], 0), Err(super::EINVAL));
+        assert_eq!(fs.ioctl(foo, 0x5401, &[], 0), Err(super::ENOTTY));
+        fs.ioctl(foo, set, &0u32.to_ne_bytes(), 0).unwrap();
+        fs.unlink(ROOT_INODE.into(), "foo".as_ref(), 0).unwrap();
+    }
+
+    #[test]
+    #[instrument]
+    fn sweep() {
+        init();
+
+        let mut fs = Daniel::new().with_gc_interval(std::time::Duration::from_secs(1));
+        let root = ROOT_INODE.into();
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        let bar = fs.create(ROOT_INODE, "bar", 0, 0o644).unwrap().inner().ino;
+        fs.write(foo, 0, &[1; 4096]).unwrap();
+        fs.truncate(foo, 1).unwrap();
+        for ino in [foo, bar] {
+            fs.remember(ino);
+        }
+        fs.unlink(root, "foo".as_ref(), 0).unwrap();
+        fs.unlink(root, "bar".as_ref(), 0).unwrap();
+        assert_eq!(fs.sweep(), 0);
+
+        // the kernel let go of foo without a forget
+        fs.lookup_counts().remove(&unchecked_inode!(foo));
+        assert_eq!(fs.sweep(), 1);
+        assert_eq!(fs.getattr(foo, None).unwrap_err(), super::ENOENT);
+        assert!(!fs.orphans.contains(&unchecked_inode!(foo)));
+        assert!(fs.getattr(bar, None).is_ok());
+        assert_eq!(fs.sweep(), 0);
+
+        fs.forget(bar, 1);
+        assert_eq!(fs.getattr(bar, None).unwrap_err(), super::ENOENT);
+    }
+
+    #[test]
+    #[instrument]
+    fn mapper_orders() {
+        init();
+
+        let mut fs = Daniel::new();
+        let b = fs.create_path(Path::new("/b"), 0o644).unwrap();
+        let c = fs.create_path(Path::new("/a/c"), 0o644).unwrap();
+        let a = fs
+            .lookup(ROOT_INODE.into(), "a".as_ref())
+            .unwrap()
+            .inner()
+            .ino;
+        fs.link(b.into(), a, "d".as_ref()).unwrap();
+
+        let by_path: Vec<_> = fs.mapper.iter_by_path().collect();
+        let status = Path::new("/").join(STATUS_FILE);
+        let mut expected = [
+            (Path::new("/"), ROOT_INODE),
+            (status.as_path(), STATUS_INODE),
+            (Path::new("/a"), unchecked_inode!(a)),
+            (Path::new("/a/c"), c),
+            (Path::new("/a/d"), b),
+            (Path::new("/b"), b),
+        ];
+        expected.sort_by_key(|(path, _)| *path);
+        assert_eq!(by_path, expected);
+
+        let by_inode: Vec<_> = fs.mapper.iter_by_inode().collect();
+        expected.sort_by_key(|(path, ino)| (*ino, *path));
+        assert_eq!(by_inode, expected);
+        assert!(by_inode.is_sorted_by_key(|(_, ino)| *ino));
+        assert!(by_path.is_sorted_by_key(|(path, _)| *path));
+    }
+
+    #[test]
+    #[instrument]
+    fn batch_forget() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root = ROOT_INODE.into();
+        let [foo, bar, baz] = ["foo", "bar", "baz"].map(|name| {
+            let ino = fs.create(ROOT_INODE, name, 0, 0o644).unwrap().inner().ino;
+            // counted the way the lookup handler counts its replies
+            for _ in 0..3 {
+                fs.lookup(root, name.as_ref()).unwrap();
+                fs.remember(ino);
+            }
+            ino
+        });
+        fs.unlink(root, "foo".as_ref(), 0).unwrap();
+        fs.unlink(root, "bar".as_ref(), 0).unwrap();
+
+        // foo is forgotten entirely, in two parts of the same batch
+        fs.batch_forget([(foo, 2), (bar, 2), (baz, 3), (foo, 1)]);
+        let counts = fs.lookup_counts().clone();
+        assert_eq!(counts.get(&unchecked_inode!(foo)), None);
+        assert_eq!(counts.get(&unchecked_inode!(bar)), Some(&1));
+        assert_eq!(counts.get(&unchecked_inode!(baz)), None);
+
+        // only the removed one that's no longer referenced is freed
+        assert_eq!(fs.getattr(foo, None).unwrap_err(), super::ENOENT);
+        assert!(fs.getattr(bar, None).is_ok());
+        assert!(fs.getattr(baz, None).is_ok());
+
+        fs.batch_forget([(bar, 5)]);
+        assert_eq!(fs.getattr(bar, None).unwrap_err(), super::ENOENT);
+    }
+
+    #[test]
+    #[instrument]
+    fn metrics_dir() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root = ROOT_INODE.into();
+        assert_eq!(
+            fs.lookup(root, METRICS_DIR.as_ref()).unwrap_err(),
+            super::ENOENT
+        );
+
+        let mut fs = fs.with_metrics_dir(true);
+        let inodes = fs.status().inodes;
+        let dir = fs.lookup(root, METRICS_DIR.as_ref()).unwrap().inner();
+        assert_eq!(
+            (dir.ino, dir.kind),
+            (METRICS_INODE.into(), FileType::Directory)
+        );
+        assert_eq!(fs.status().inodes, inodes);
+        let listed = fs.dir_entries_after(ROOT_INODE, 0).unwrap();
+        let last = listed.last().unwrap();
+        assert_eq!((last.1, last.3), (METRICS_INODE, Path::new(METRICS_DIR)));
+        assert!(fs.dir_entries_after(ROOT_INODE, last.0).unwrap().is_empty());
+
+        let names: Vec<_> = fs
+            .dir_entries_after(METRICS_INODE, 0)
+            .unwrap()
+            .into_iter()
+            .map(|(_, _, kind, name)| (kind, name.to_path_buf()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                (EntryType::Directory, ".".into()),
+                (EntryType::Directory, "..".into()),
+                (EntryType::File, "reads".into()),
+                (EntryType::File, "writes".into()),
+                (EntryType::File, "lookups".into()),
+            ]
+        );
+
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        fs.write(foo, 0, b"a").unwrap();
+        fs.write(foo, 1, b"b").unwrap();
+        let writes = fs
+            .lookup(METRICS_INODE.into(), "writes".as_ref())
+            .unwrap()
+            .inner();
+        assert_eq!(fs.getattr(writes.ino, None).unwrap().inner().size, 2);
+        let fh = fs.open(writes.ino, 0, 1000, 1000).unwrap();
+        fs.check_handle(writes.ino, fh).unwrap();
+        assert_eq!(&*fs.read(writes.ino, 0, 4096).unwrap(), b"2\n");
+
+        assert_eq!(fs.write(writes.ino, 0, b"0"), Err(super::EACCES));
+        assert_eq!(
+            fs.read(METRICS_INODE.into(), 0, 10).unwrap_err(),
+            super::EISDIR
+        );
+        assert_eq!(
+            fs.lookup(METRICS_INODE.into(), "missing".as_ref())
+                .unwrap_err(),
+            super::ENOENT
         );
-        reply.error(ENOSYS);
     }
 
-    fn setlk(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        fh: u64,
-        lock_owner: u64,
-        start: u64,
-        end: u64,
-        typ: i32,
-        pid: u32,
-        sleep: bool,
-        reply: fuser::ReplyEmpty,
-    ) {
-        debug!(
-            "[Not Implemented] setlk(ino: {:#x?}, fh: {}, lock_owner: {}, start: {}, \
-            end: {}, typ: {}, pid: {}, sleep: {})",
-            ino, fh, lock_owner, start, end, typ, pid, sleep
-        );
-        reply.error(ENOSYS);
+    #[test]
+    #[instrument]
+    fn link_directory() {
+        init();
+
+        let mut fs = Daniel::new().with_metrics_dir(true);
+        let root = u64::from(ROOT_INODE);
+        let dir = fs
+            .mkdir(root, "dir".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner();
+        for ino in [dir.ino, root, METRICS_INODE.into()] {
+            assert_eq!(
+                fs.link(ino, root, "alias".as_ref()).err(),
+                Some(super::EPERM)
+            );
+        }
+        assert_eq!(fs.lookup(root, "alias".as_ref()).err(), Some(super::ENOENT));
+        assert_eq!(fs.getattr(dir.ino, None).unwrap().inner().nlink, dir.nlink);
+
+        // files and symlinks still can be
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        let link = fs
+            .symlink(root, "link".as_ref(), Path::new("foo"), 0, 0)
+            .unwrap()
+            .inner()
+            .ino;
+        for (ino, name) in [(foo, "foo2"), (link, "link2")] {
+            let attr = fs.link(ino, dir.ino, name.as_ref()).unwrap().inner();
+            assert_eq!((attr.ino, attr.nlink), (ino, 2));
+            assert_eq!(fs.lookup(dir.ino, name.as_ref()).unwrap().inner().ino, ino);
+        }
+    }
+
+    #[test]
+    #[instrument]
+    fn replace_contents() {
+        init();
+
+        let start = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let clock = ManualClock::new(start);
+        let mut fs = Daniel::new().with_clock(clock.clone());
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        fs.write(foo, 0, b"a much longer first version").unwrap();
+        let hash = fs.content_hash(foo).unwrap();
+
+        clock.advance(std::time::Duration::from_secs(5));
+        let replaced = start + std::time::Duration::from_secs(5);
+        fs.replace_contents(foo, b"short").unwrap();
+        assert_eq!(&*fs.read(foo, 0, 4096).unwrap(), b"short");
+        let attr = fs.getattr(foo, None).unwrap().inner();
+        assert_eq!((attr.size, attr.mtime, attr.ctime), (5, replaced, replaced));
+        assert_ne!(fs.content_hash(foo), Some(hash));
+
+        let dir = fs
+            .mkdir(ROOT_INODE.into(), "dir".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner();
+        assert_eq!(fs.replace_contents(dir.ino, b"x"), Err(super::EISDIR));
+        fs.set_flags(foo, super::FS_IMMUTABLE_FL, 0).unwrap();
+        assert_eq!(fs.replace_contents(foo, b"x"), Err(super::EPERM));
+        assert_eq!(&*fs.read(foo, 0, 4096).unwrap(), b"short");
+    }
+
+    /// in memory storage that fails to allocate past `budget` bytes
+    #[derive(Debug, Clone, Default)]
+    struct Budgeted {
+        inner: Memory,
+        budget: usize,
     }
 
-    fn bmap(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        blocksize: u32,
-        idx: u64,
-        reply: fuser::ReplyBmap,
-    ) {
-        debug!(
-            "[Not Implemented] bmap(ino: {:#x?}, blocksize: {}, idx: {})",
-            ino, blocksize, idx,
-        );
-        reply.error(ENOSYS);
+    impl Storage for Budgeted {
+        fn read(&self, offset: u64, len: usize) -> std::borrow::Cow<'_, [u8]> {
+            self.inner.read(offset, len)
+        }
+
+        fn write(&mut self, offset: u64, data: &[u8]) -> usize {
+            self.inner.write(offset, data)
+        }
+
+        fn truncate(&mut self, len: u64) {
+            self.inner.truncate(len)
+        }
+
+        fn len(&self) -> u64 {
+            self.inner.len()
+        }
+
+        fn box_clone(&self) -> Box<dyn Storage> {
+            Box::new(self.clone())
+        }
+
+        fn try_reserve(&mut self, len: usize) -> Result<(), std::collections::TryReserveError> {
+            match len <= self.budget {
+                true => self.inner.try_reserve(len),
+                // the error the allocator gives for a request it can never satisfy
+                false => Vec::<u8>::new().try_reserve(usize::MAX),
+            }
+        }
     }
 
-    fn ioctl(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        fh: u64,
-        flags: u32,
-        cmd: u32,
-        in_data: &[u8],
-        out_size: u32,
-        reply: fuser::ReplyIoctl,
-    ) {
-        debug!(
-            "[Not Implemented] ioctl(ino: {:#x?}, fh: {}, flags: {}, cmd: {}, \
-            in_data.len(): {}, out_size: {})",
+    #[test]
+    #[instrument]
+    fn out_of_memory() {
+        init();
+
+        let mut fs = Daniel::new();
+        let ino = fs.mapper.next_inode();
+        let storage = Budgeted {
+            budget: 8,
+            ..Default::default()
+        };
+        fs.push(DirEntry::File(File::with_storage(
+            "foo".into(),
+            ROOT_INODE,
             ino,
-            fh,
-            flags,
-            cmd,
-            in_data.len(),
-            out_size,
+            0o644,
+            Box::new(storage),
+        )))
+        .unwrap();
+        let ino = u64::from(ino);
+
+        fs.write(ino, 0, b"12345678").unwrap();
+        assert_eq!(fs.write(ino, 8, b"9"), Err(super::ENOMEM));
+        assert_eq!(fs.truncate(ino, 9), Err(super::ENOMEM));
+        assert_eq!(fs.fallocate(ino, 0, 9, 0), Err(super::ENOMEM));
+        assert_eq!(fs.replace_contents(ino, b"123456789"), Err(super::ENOMEM));
+        // nothing was changed by the failed calls
+        assert_eq!(&*fs.read(ino, 0, 4096).unwrap(), b"12345678");
+        assert_eq!(fs.getattr(ino, None).unwrap().inner().size, 8);
+
+        // regular memory storage refuses what can never be allocated rather than aborting
+        let foo = fs.create(ROOT_INODE, "bar", 0, 0o644).unwrap().inner().ino;
+        assert!(
+            fs.file_mut(unchecked_inode!(foo))
+                .unwrap()
+                .try_reserve(0, u64::MAX)
+                .is_err()
         );
-        reply.error(ENOSYS);
+        assert!(Memory::default().try_reserve(usize::MAX).is_err());
     }
 
-    fn fallocate(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        fh: u64,
-        offset: i64,
-        length: i64,
-        mode: i32,
-        reply: fuser::ReplyEmpty,
-    ) {
-        debug!(
-            "[Not Implemented] fallocate(ino: {:#x?}, fh: {}, offset: {}, \
-            length: {}, mode: {})",
-            ino, fh, offset, length, mode
+    #[test]
+    #[instrument]
+    fn handles_for() {
+        init();
+
+        let mut fs = Daniel::new();
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        assert_eq!(fs.handles_for(foo), []);
+
+        let read = fs.open(foo, libc::O_RDONLY, 0, 0).unwrap();
+        let write = fs.open(foo, libc::O_WRONLY | libc::O_APPEND, 0, 0).unwrap();
+        assert_ne!(read, write);
+        let handles = fs.handles_for(foo);
+        assert_eq!(
+            handles,
+            [
+                OpenHandle {
+                    fh: read,
+                    flags: libc::O_RDONLY
+                },
+                OpenHandle {
+                    fh: write,
+                    flags: libc::O_WRONLY | libc::O_APPEND
+                },
+            ]
+        );
+        assert_eq!(handles[0].to_string(), format!("fh {read:#x} r flags 0o0"));
+        assert_eq!(
+            handles[1].to_string(),
+            format!(
+                "fh {write:#x} w flags {:#o}",
+                libc::O_WRONLY | libc::O_APPEND
+            )
         );
-        reply.error(ENOSYS);
-    }
 
-    fn lseek(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        fh: u64,
-        offset: i64,
-        whence: i32,
-        reply: fuser::ReplyLseek,
-    ) {
-        debug!(
-            "[Not Implemented] lseek(ino: {:#x?}, fh: {}, offset: {}, whence: {})",
-            ino, fh, offset, whence
+        // handles opened with the same flags are still told apart by their `fh`
+        let again = fs.open(foo, libc::O_RDONLY, 0, 0).unwrap();
+        assert_ne!(again, read);
+        fs.release(foo, read).unwrap();
+        assert_eq!(
+            fs.handles_for(foo),
+            [
+                OpenHandle {
+                    fh: write,
+                    flags: libc::O_WRONLY | libc::O_APPEND
+                },
+                OpenHandle {
+                    fh: again,
+                    flags: libc::O_RDONLY
+                },
+            ]
+        );
+        fs.release(foo, again).unwrap();
+        assert_eq!(
+            fs.handles_for(foo),
+            [OpenHandle {
+                fh: write,
+                flags: libc::O_WRONLY | libc::O_APPEND
+            }]
         );
-        reply.error(ENOSYS);
+        fs.release(foo, write).unwrap();
+        assert_eq!(fs.handles_for(foo), []);
     }
 
-    fn copy_file_range(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino_in: u64,
-        fh_in: u64,
-        offset_in: i64,
-        ino_out: u64,
-        fh_out: u64,
-        offset_out: i64,
-        len: u64,
-        flags: u32,
-        reply: fuser::ReplyWrite,
-    ) {
-        debug!(
-            "[Not Implemented] copy_file_range(ino_in: {:#x?}, fh_in: {}, \
-            offset_in: {}, ino_out: {:#x?}, fh_out: {}, offset_out: {}, \
-            len: {}, flags: {})",
-            ino_in, fh_in, offset_in, ino_out, fh_out, offset_out, len, flags
+    #[test]
+    #[instrument]
+    fn rmdir_nlink() {
+        init();
+
+        let mut fs = Daniel::new();
+        let root = u64::from(ROOT_INODE);
+        let parent = fs
+            .mkdir(root, "parent".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner();
+        assert_eq!(parent.nlink, 2);
+        let nlink = |fs: &mut Daniel, ino| fs.getattr(ino, None).unwrap().inner().nlink;
+        assert_eq!(nlink(&mut fs, root), 3);
+
+        for name in ["a", "b"] {
+            fs.mkdir(parent.ino, name.as_ref(), 0o755, 0, 0, 0).unwrap();
+        }
+        fs.create(unchecked_inode!(parent.ino), "file", 0, 0o644)
+            .unwrap();
+        assert_eq!(nlink(&mut fs, parent.ino), 4);
+
+        fs.rmdir(parent.ino, "a".as_ref(), 0).unwrap();
+        assert_eq!(nlink(&mut fs, parent.ino), 3);
+        assert_eq!(
+            fs.lookup(parent.ino, "a".as_ref()).err(),
+            Some(super::ENOENT)
+        );
+
+        assert_eq!(
+            fs.rmdir(parent.ino, "file".as_ref(), 0),
+            Err(super::ENOTDIR)
         );
-        reply.error(ENOSYS);
+        assert_eq!(fs.rmdir(root, "parent".as_ref(), 0), Err(super::ENOTEMPTY));
+        assert_eq!(fs.rmdir(root, "missing".as_ref(), 0), Err(super::ENOENT));
+        assert_eq!(nlink(&mut fs, parent.ino), 3);
+
+        // moving a directory moves its `..` along with it
+        fs.rename(parent.ino, "b".as_ref(), root, "b".as_ref(), 0)
+            .unwrap();
+        assert_eq!(nlink(&mut fs, parent.ino), 2);
+        assert_eq!(nlink(&mut fs, root), 4);
     }
-}
 
-#[cfg(test)]
-mod test {
-    use std::ops::ControlFlow;
+    /// xorshift, enough to pick operations reproducibly
+    struct Rng(u64);
 
-    use tracing::{info, instrument, level_filters::LevelFilter};
-    use tracing_subscriber::{fmt::format::FmtSpan, util::SubscriberInitExt};
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
 
-    use crate::{
-        filesystem::{DirEntry, Directory, EntryType, File},
-        unchecked_inode,
-    };
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn pick<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+            match items.is_empty() {
+                true => None,
+                false => items.get(self.below(items.len())),
+            }
+        }
+    }
 
-    use super::{Daniel, ROOT_INODE};
+    /// runs one random operation on `fs`, failing is fine as long as nothing panics
+    fn random_operation(fs: &mut Daniel, rng: &mut Rng) {
+        let names = ["a", "b", "c", "d"].map(std::ffi::OsStr::new);
+        let mut dirs = Vec::new();
+        let mut files = Vec::new();
+        for (ino, entry) in fs.list.map() {
+            match entry.as_directory() {
+                Some(_) => dirs.push(*ino),
+                None if *ino != STATUS_INODE => files.push(*ino),
+                None => {}
+            }
+        }
+        // the map's order changes from run to run
+        dirs.sort();
+        files.sort();
+        let dir = *rng.pick(&dirs).unwrap();
+        let name = *rng.pick(&names).unwrap();
 
-    fn init() {
-        let _ = tracing_subscriber::FmtSubscriber::builder()
-            .with_ansi(true)
-            .with_max_level(LevelFilter::INFO)
-            .with_span_events(FmtSpan::ACTIVE)
-            .finish()
-            .try_init();
+        match rng.below(8) {
+            0 => _ = fs.create(dir, name, 0, 0o644),
+            1 => _ = fs.mkdir(dir.into(), name, 0o755, 0, 0, 0),
+            2 => _ = fs.unlink(dir.into(), name, 0),
+            3 => _ = fs.rmdir(dir.into(), name, 0),
+            4 => {
+                let newdir = *rng.pick(&dirs).unwrap();
+                let newname = *rng.pick(&names).unwrap();
+                _ = fs.rename(dir.into(), name, newdir.into(), newname, 0);
+            }
+            5 => {
+                if let Some(file) = rng.pick(&files) {
+                    _ = fs.link((*file).into(), dir.into(), name);
+                }
+            }
+            6 => {
+                let Some(file) = rng.pick(&files).copied() else {
+                    return;
+                };
+                let offset = rng.below(64) as i64;
+                let data = vec![rng.next() as u8; rng.below(32)];
+                fs.write(file.into(), offset, &data).unwrap();
+                let read = fs.read(file.into(), offset, data.len() as u32).unwrap();
+                assert_eq!(*read, data);
+            }
+            _ => {
+                let Some(file) = rng.pick(&files).copied() else {
+                    return;
+                };
+                let size = fs.file(file).unwrap().attr().inner().size;
+                let offset = rng.below(128) as i64;
+                let len = fs.read(file.into(), offset, 64).unwrap().len() as u64;
+                assert_eq!(len, size.saturating_sub(offset as u64).min(64));
+            }
+        }
+    }
+
+    /// the tree, the mapper and the link counts all agree with each other
+    fn check_invariants(fs: &Daniel) {
+        let map = fs.list.map();
+        let mut names: HashMap<Inode, u32> = HashMap::new();
+        for ((parent, name), ino) in fs.mapper.map() {
+            if *ino == ROOT_INODE {
+                continue;
+            }
+            let listed = fs.directory(*parent).unwrap().get(name);
+            assert_eq!(
+                listed.map(|child| child.ino),
+                Some(*ino),
+                "{name:?} in {parent:?}"
+            );
+            *names.entry(*ino).or_default() += 1;
+        }
+        // nothing was looked up or opened, so a removed entry is gone right away
+        assert_eq!(map.len(), names.len() + 1);
+
+        for (ino, entry) in map {
+            let attr = entry.attr().inner();
+            if *ino != ROOT_INODE {
+                assert!(*ino < fs.mapper.next_inode() || *ino == STATUS_INODE);
+                assert_eq!(fs.mapper.get_map(entry.parent(), entry.name()), Some(ino));
+                let parent = fs.mapper.path_of(entry.parent()).unwrap();
+                assert_eq!(
+                    fs.mapper.path_of(*ino),
+                    Some(parent.join(entry.name()).as_path())
+                );
+            }
+
+            match entry.as_directory() {
+                Some(dir) => {
+                    for (name, listed) in dir.entries() {
+                        assert!(map.contains_key(&listed.ino), "{name:?} in {ino:?}");
+                        assert_eq!(fs.mapper.get_map(*ino, name), Some(&listed.ino));
+                    }
+                    let subdirs = dir
+                        .entries()
+                        .values()
+                        .filter(|child| child.kind == EntryType::Directory)
+                        .count();
+                    assert_eq!(attr.nlink as usize, 2 + subdirs, "{ino:?}");
+                }
+                None => {
+                    assert_eq!(attr.nlink, names[ino], "{ino:?}");
+                    let file = entry.as_file().unwrap();
+                    assert_eq!(attr.size, file.storage().len(), "{ino:?}");
+                }
+            }
+        }
     }
 
     #[test]
     #[instrument]
-    pub fn default() {
+    fn random_operations() {
         init();
-        let fs = Daniel::default();
-
-        assert_eq!(fs.mapper.map().len(), 1);
-        assert_eq!(fs.list.map().len(), 1);
 
-        info!(%fs);
+        for seed in 1..=32u64 {
+            let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+            let mut fs = Daniel::new();
+            for step in 0..500 {
+                random_operation(&mut fs, &mut rng);
+                check_invariants(&fs);
+                if step % 100 == 0 {
+                    info!(seed, step, inodes = fs.status().inodes, "random operations");
+                }
+            }
+        }
     }
 
     #[test]
     #[instrument]
-    pub fn empty_dir() {
+    fn ttl_overrides() {
         init();
 
-        let mut fs = Daniel::default();
-        fs.push(DirEntry::Directory(Directory::new(
-            ROOT_INODE,
-            "foo".into(),
-            unchecked_inode!(2),
-            0o755,
-        )));
+        let second = std::time::Duration::from_secs(1);
+        let minute = std::time::Duration::from_secs(60);
+        let mut fs = Daniel::new().with_metrics_dir(true);
+        let root = u64::from(ROOT_INODE);
+        let imported = fs
+            .mkdir(root, "imported".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner();
+        let inner = fs
+            .mkdir(imported.ino, "inner".as_ref(), 0o755, 0, 0, 0)
+            .unwrap()
+            .inner();
+        let foo = fs
+            .create(unchecked_inode!(inner.ino), "foo", 0, 0o644)
+            .unwrap()
+            .inner()
+            .ino;
+        assert_eq!(fs.ttl(foo), super::DEFAULT_TTL);
 
-        assert_eq!(fs.mapper.map().len(), 2);
-        assert_eq!(fs.list.map().len(), 2);
+        // the whole subtree picks it up unless something in it overrides it again
+        fs.set_ttl(imported.ino, Some(minute)).unwrap();
+        assert_eq!(fs.ttl(foo), minute);
+        assert_eq!(fs.ttl(root), super::DEFAULT_TTL);
+        fs.set_ttl(foo, Some(second * 5)).unwrap();
+        assert_eq!(fs.ttl(foo), second * 5);
+        assert_eq!(fs.ttl(inner.ino), minute);
+        fs.set_ttl(imported.ino, None).unwrap();
+        assert_eq!(fs.ttl(inner.ino), super::DEFAULT_TTL);
 
-        info!(%fs);
+        let mut fs = fs.with_ttl(minute);
+        assert_eq!(fs.ttl(inner.ino), minute);
+        assert_eq!(fs.ttl(STATUS_INODE.into()), std::time::Duration::ZERO);
+        assert_eq!(fs.ttl(METRICS_INODE.into()), std::time::Duration::ZERO);
+        fs.set_ttl(root, Some(minute)).unwrap();
+        assert_eq!(fs.ttl(STATUS_INODE.into()), std::time::Duration::ZERO);
+        assert_eq!(fs.set_ttl(u64::MAX - 100, None), Err(super::ENOENT));
     }
 
     #[test]
     #[instrument]
-    pub fn filled_dir() {
+    fn readdir_snapshots() {
         init();
 
-        let _s = tracing::info_span!("filled_dir");
-        let _s = _s.entered();
-
-        let mut fs = Daniel::default();
+        let mut fs = Daniel::new().with_readdir_snapshots(true);
+        let root = u64::from(ROOT_INODE);
+        for name in ["a", "b", "c"] {
+            fs.create(ROOT_INODE, name, 0, 0o644).unwrap();
+        }
+        let names = |entries: Vec<(u64, Inode, EntryType, PathBuf)>| -> Vec<PathBuf> {
+            entries.into_iter().map(|(_, _, _, name)| name).collect()
+        };
+        let expected: Vec<PathBuf> = [".", "..", STATUS_FILE, "a", "b", "c"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
 
-        fs.push(DirEntry::Directory(Directory::new(
-            ROOT_INODE,
-            "foo".into(),
-            unchecked_inode!(2),
-            0o755,
-        )));
+        fs.opendir(root).unwrap();
+        let first = fs.readdir_after(ROOT_INODE, 0).unwrap();
+        let mut seen = names(first[..4].to_vec());
+        let cookie = first[3].0;
 
-        let map = &fs.mapper;
-        let list = &fs.list;
+        fs.create(ROOT_INODE, "late", 0, 0o644).unwrap();
+        fs.unlink(root, "b".as_ref(), 0).unwrap();
+        seen.extend(names(fs.readdir_after(ROOT_INODE, cookie).unwrap()));
+        assert_eq!(seen, expected);
 
-        info!(%map);
-        info!(%list);
+        // a second handle shares the snapshot, it's gone once both are released
+        fs.opendir(root).unwrap();
+        fs.releasedir(root);
+        assert_eq!(names(fs.readdir_after(ROOT_INODE, 0).unwrap()), expected);
+        fs.releasedir(root);
+        let current: Vec<PathBuf> = [".", "..", STATUS_FILE, "a", "c", "late"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(names(fs.readdir_after(ROOT_INODE, 0).unwrap()), current);
 
-        let entry = fs
-            .list
-            .map_mut()
-            .get_mut(&unchecked_inode!(2))
-            .unwrap()
-            .directory_mut();
+        // open(2) with O_DIRECTORY takes a snapshot only once it's permitted, and release drops it
+        let private = fs.mkdir(root, "private".as_ref(), 0o700, 0, 0, 0).unwrap();
+        let private = private.inner().ino;
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY;
+        assert_eq!(fs.open(private, flags, 1000, 1000), Err(super::EACCES));
+        assert!(fs.dir_snapshots.is_empty());
+        let fh = fs.open(private, flags, 0, 0).unwrap();
+        assert_eq!(fs.dir_snapshots.len(), 1);
+        fs.release(private, fh).unwrap();
+        assert!(fs.dir_snapshots.is_empty());
+    }
 
-        entry.insert(unchecked_inode!(3), EntryType::File);
+    #[test]
+    #[instrument]
+    fn dont_mask() {
+        init();
 
-        fs.push(DirEntry::File(File::new(
-            "bar".into(),
-            unchecked_inode!(2),
-            unchecked_inode!(3),
-            0o655,
-        )));
+        // the kernel already took the umask off, it's only passed along
+        let mut fs = Daniel::new();
+        let root = u64::from(ROOT_INODE);
+        let dir = fs.mkdir(root, "a".as_ref(), 0o775, 0o022, 0, 0).unwrap();
+        assert_eq!(dir.inner().perm, 0o775);
 
-        assert_eq!(fs.mapper.map().len(), 3);
-        assert_eq!(fs.list.map().len(), 3);
+        fs.negotiate_dont_mask(true);
+        let dir = fs.mkdir(root, "b".as_ref(), 0o775, 0o022, 0, 0).unwrap();
+        assert_eq!(dir.inner().perm, 0o755);
+        assert_eq!(fs.masked(0o100666, 0o027), 0o640);
 
-        info!(%fs);
+        fs.negotiate_dont_mask(false);
+        assert_eq!(fs.masked(0o100666, 0o027), 0o666);
     }
 
     #[test]
     #[instrument]
-    fn readdir() {
+    fn require_utf8_names() {
+        use std::os::unix::ffi::OsStrExt;
+
         init();
 
-        let _s = tracing::info_span!("readdir");
-        let _s = _s.entered();
+        let bad = std::ffi::OsStr::from_bytes(b"bad\xff");
+        let root = u64::from(ROOT_INODE);
+        // any bytes will do by default
+        let mut fs = Daniel::new();
+        let ino = fs.create(ROOT_INODE, bad, 0, 0o644).unwrap().inner().ino;
+        assert_eq!(fs.lookup(root, bad).unwrap().inner().ino, ino);
+        fs.mkdir(
+            root,
+            std::ffi::OsStr::from_bytes(b"dir\xfe"),
+            0o755,
+            0,
+            0,
+            0,
+        )
+        .unwrap();
+
+        let mut fs = Daniel::new().with_require_utf8_names(true);
+        assert_eq!(
+            fs.create(ROOT_INODE, bad, 0, 0o644).err(),
+            Some(super::EINVAL)
+        );
+        assert_eq!(
+            fs.mkdir(root, bad, 0o755, 0, 0, 0).err(),
+            Some(super::EINVAL)
+        );
+        assert_eq!(
+            fs.symlink(root, bad, Path::new("foo"), 0, 0).err(),
+            Some(super::EINVAL)
+        );
+        let foo = fs.create(ROOT_INODE, "foo", 0, 0o644).unwrap().inner().ino;
+        assert_eq!(fs.link(foo, root, bad).err(), Some(super::EINVAL));
+        assert_eq!(
+            fs.rename(root, "foo".as_ref(), root, bad, 0),
+            Err(super::EINVAL)
+        );
+        assert_eq!(fs.lookup(root, bad).err(), Some(super::ENOENT));
+
+        fs.rename(root, "foo".as_ref(), root, "bär".as_ref(), 0)
+            .unwrap();
+        assert_eq!(fs.lookup(root, "bär".as_ref()).unwrap().inner().ino, foo);
+    }
 
+    #[test]
+    #[instrument]
+    fn export_on_rename() {
+        init();
+
+        let host = temp_dir("export");
         let mut fs = Daniel::new();
+        let root = u64::from(ROOT_INODE);
+        let export = fs.create_dir_path(Path::new("export")).unwrap();
+        let foo = fs.create_path(Path::new("foo"), 0o644).unwrap();
+        assert_eq!(fs.set_export_dir(foo, &host), Err(super::ENOTDIR));
+        fs.set_export_dir(export, &host).unwrap();
+        fs.write(foo.into(), 0, b"staged").unwrap();
 
-        fs.push(DirEntry::File(File::new(
-            "foo".into(),
-            ROOT_INODE,
-            unchecked_inode!(2),
-            0o655,
-        )));
+        fs.rename(root, "foo".as_ref(), export.into(), "out".as_ref(), 0)
+            .unwrap();
+        assert_eq!(std::fs::read(host.join("out")).unwrap(), b"staged");
+        assert_eq!(fs.lookup(root, "foo".as_ref()).err(), Some(super::ENOENT));
+        assert!(fs.directory(export).unwrap().entries().is_empty());
+        assert!(!fs.list.map().contains_key(&foo));
 
-        let entry = fs.readdir(ROOT_INODE.into(), 0, 0);
-        match entry {
-            ControlFlow::Continue(entry) => match entry {
-                DirEntry::Directory(directory) => {
-                    let first = directory.entries().values().next().unwrap();
-                    assert_eq!(first, &EntryType::File);
-                }
-                DirEntry::File(_file) => panic!("expected ROOT dir found file"),
-            },
-            ControlFlow::Break(_) => panic!(),
-        }
+        // directories are moved in as usual
+        fs.create_dir_path(Path::new("dir")).unwrap();
+        fs.rename(root, "dir".as_ref(), export.into(), "dir".as_ref(), 0)
+            .unwrap();
+        assert!(fs.lookup(export.into(), "dir".as_ref()).is_ok());
+
+        // a file can't replace a directory there either, nothing is written before that's known
+        fs.create_path(Path::new("baz"), 0o644).unwrap();
+        assert_eq!(
+            fs.rename(root, "baz".as_ref(), export.into(), "dir".as_ref(), 0),
+            Err(super::EISDIR)
+        );
+        assert!(!host.join("dir").exists());
+
+        // and what's already on the host is never overwritten
+        std::fs::write(host.join("taken"), b"host data").unwrap();
+        assert_eq!(
+            fs.rename(root, "baz".as_ref(), export.into(), "taken".as_ref(), 0),
+            Err(super::EEXIST)
+        );
+        assert_eq!(std::fs::read(host.join("taken")).unwrap(), b"host data");
+        assert!(fs.lookup(root, "baz".as_ref()).is_ok());
+
+        // the file stays put when it can't be written out
+        std::fs::remove_dir_all(&host).unwrap();
+        let bar = fs.create_path(Path::new("bar"), 0o644).unwrap();
+        assert_eq!(
+            fs.rename(root, "bar".as_ref(), export.into(), "bar".as_ref(), 0),
+            Err(super::EIO)
+        );
+        assert_eq!(
+            fs.lookup(root, "bar".as_ref()).unwrap().inner().ino,
+            u64::from(bar)
+        );
+        assert_eq!(
+            fs.lookup(export.into(), "bar".as_ref()).err(),
+            Some(super::ENOENT)
+        );
     }
 }
@@ -0,0 +1,111 @@
+/// the errors `Daniel` hands back to the kernel, named after the libc constants they map to
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Errno {
+    EPERM,
+    ENOENT,
+    EIO,
+    EAGAIN,
+    EACCES,
+    EEXIST,
+    ENOTDIR,
+    EISDIR,
+    EINVAL,
+    ENOSPC,
+    EROFS,
+    ERANGE,
+    ENOSYS,
+    ENOTEMPTY,
+    ENODATA,
+    ESTALE,
+    EMLINK,
+    EOPNOTSUPP,
+    ELOOP,
+    ENOTTY,
+    ENOMEM,
+    EBUSY,
+}
+
+impl Errno {
+    pub fn as_i32(self) -> i32 {
+        match self {
+            Errno::EPERM => libc::EPERM,
+            Errno::ENOENT => libc::ENOENT,
+            Errno::EIO => libc::EIO,
+            Errno::EAGAIN => libc::EAGAIN,
+            Errno::EACCES => libc::EACCES,
+            Errno::EEXIST => libc::EEXIST,
+            Errno::ENOTDIR => libc::ENOTDIR,
+            Errno::EISDIR => libc::EISDIR,
+            Errno::EINVAL => libc::EINVAL,
+            Errno::ENOSPC => libc::ENOSPC,
+            Errno::EROFS => libc::EROFS,
+            Errno::ERANGE => libc::ERANGE,
+            Errno::ENOSYS => libc::ENOSYS,
+            Errno::ENOTEMPTY => libc::ENOTEMPTY,
+            Errno::ENODATA => libc::ENODATA,
+            Errno::ESTALE => libc::ESTALE,
+            Errno::EMLINK => libc::EMLINK,
+            Errno::EOPNOTSUPP => libc::EOPNOTSUPP,
+            Errno::ELOOP => libc::ELOOP,
+            Errno::ENOTTY => libc::ENOTTY,
+            Errno::ENOMEM => libc::ENOMEM,
+            Errno::EBUSY => libc::EBUSY,
+        }
+    }
+}
+
+impl From<Errno> for i32 {
+    fn from(value: Errno) -> Self {
+        value.as_i32()
+    }
+}
+
+impl std::fmt::Display for Errno {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?} ({})", self.as_i32())
+    }
+}
+
+impl std::error::Error for Errno {}
+
+#[cfg(test)]
+mod test {
+    use super::Errno;
+
+    #[test]
+    fn as_i32() {
+        let expected = [
+            (Errno::EPERM, libc::EPERM),
+            (Errno::ENOENT, libc::ENOENT),
+            (Errno::EIO, libc::EIO),
+            (Errno::EAGAIN, libc::EAGAIN),
+            (Errno::EACCES, libc::EACCES),
+            (Errno::EEXIST, libc::EEXIST),
+            (Errno::ENOTDIR, libc::ENOTDIR),
+            (Errno::EISDIR, libc::EISDIR),
+            (Errno::EINVAL, libc::EINVAL),
+            (Errno::ENOSPC, libc::ENOSPC),
+            (Errno::EROFS, libc::EROFS),
+            (Errno::ERANGE, libc::ERANGE),
+            (Errno::ENOSYS, libc::ENOSYS),
+            (Errno::ENOTEMPTY, libc::ENOTEMPTY),
+            (Errno::ENODATA, libc::ENODATA),
+            (Errno::ESTALE, libc::ESTALE),
+            (Errno::EMLINK, libc::EMLINK),
+            (Errno::EOPNOTSUPP, libc::EOPNOTSUPP),
+            (Errno::ELOOP, libc::ELOOP),
+            (Errno::ENOTTY, libc::ENOTTY),
+            (Errno::ENOMEM, libc::ENOMEM),
+            (Errno::EBUSY, libc::EBUSY),
+        ];
+        for (errno, value) in expected {
+            assert_eq!(errno.as_i32(), value, "{errno:?}");
+            assert_eq!(i32::from(errno), value);
+        }
+
+        // the values the constants used to be hardcoded as
+        assert_eq!(Errno::ENOENT.as_i32(), 2);
+        assert_eq!(Errno::ESTALE.as_i32(), 116);
+    }
+}
@@ -1,11 +1,19 @@
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, TryReserveError},
+    ffi::OsString,
+    os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    time::{Duration, SystemTime},
 };
 
 use fuser::{FileAttr, FileType};
 
-use super::{FileAttribute, Inode, ROOT_INODE};
+use super::{
+    FileAttribute, Inode, MIN_HOLE, Memory, MmapStorage, ROOT_INODE, STATUS_FILE, STATUS_INODE,
+    Sparse, Storage, is_whiteout,
+};
 
 #[derive(Debug, Clone)]
 pub enum DirEntry {
@@ -28,35 +36,64 @@ impl DirEntry {
     pub fn kind(&self) -> FileType {
         match self {
             DirEntry::Directory(_) => FileType::Directory,
-            DirEntry::File(_) => FileType::RegularFile,
+            DirEntry::File(file) => file.attr.inner().kind,
+        }
+    }
+
+    pub fn name(&self) -> &Path {
+        match self {
+            DirEntry::Directory(directory) => directory.name(),
+            DirEntry::File(file) => file.name(),
+        }
+    }
+
+    pub fn parent(&self) -> Inode {
+        match self {
+            DirEntry::Directory(directory) => directory.parent(),
+            DirEntry::File(file) => file.parent(),
         }
     }
 
-    pub fn directory(&self) -> &Directory {
+    /// moves the entry under `parent` as `name`, the caller is responsible for updating the parent
+    /// directories
+    pub fn rename(&mut self, parent: Inode, name: PathBuf) {
         match self {
-            DirEntry::Directory(directory) => directory,
-            DirEntry::File(_) => panic!(),
+            DirEntry::Directory(directory) => {
+                directory.parent = parent;
+                directory.name = name;
+            }
+            DirEntry::File(file) => {
+                file.parent = parent;
+                file.name = name;
+            }
         }
     }
 
-    pub fn directory_mut(&mut self) -> &mut Directory {
+    pub fn as_directory(&self) -> Option<&Directory> {
         match self {
-            DirEntry::Directory(directory) => directory,
-            DirEntry::File(_) => panic!(),
+            DirEntry::Directory(directory) => Some(directory),
+            DirEntry::File(_) => None,
         }
     }
 
-    pub fn file(&self) -> &File {
+    pub fn as_directory_mut(&mut self) -> Option<&mut Directory> {
         match self {
-            DirEntry::Directory(_) => panic!(),
-            DirEntry::File(file) => file,
+            DirEntry::Directory(directory) => Some(directory),
+            DirEntry::File(_) => None,
         }
     }
 
-    pub fn file_mut(&mut self) -> &mut File {
+    pub fn as_file(&self) -> Option<&File> {
         match self {
-            DirEntry::Directory(_) => panic!(),
-            DirEntry::File(file) => file,
+            DirEntry::Directory(_) => None,
+            DirEntry::File(file) => Some(file),
+        }
+    }
+
+    pub fn as_file_mut(&mut self) -> Option<&mut File> {
+        match self {
+            DirEntry::Directory(_) => None,
+            DirEntry::File(file) => Some(file),
         }
     }
 
@@ -73,6 +110,45 @@ impl DirEntry {
             DirEntry::File(file) => &mut file.attr,
         }
     }
+
+    /// how its parent lists it, a whiteout is told apart from other character devices
+    pub fn entry_type(&self) -> EntryType {
+        match self {
+            DirEntry::File(file) if file.is_whiteout() => EntryType::Whiteout,
+            _ => self.kind().into(),
+        }
+    }
+
+    pub fn xattrs(&self) -> &Xattrs {
+        match self {
+            DirEntry::Directory(dir) => &dir.xattrs,
+            DirEntry::File(file) => &file.xattrs,
+        }
+    }
+
+    pub fn xattrs_mut(&mut self) -> &mut Xattrs {
+        match self {
+            DirEntry::Directory(dir) => &mut dir.xattrs,
+            DirEntry::File(file) => &mut file.xattrs,
+        }
+    }
+
+    /// how long the kernel may cache the entry and everything below it, see [`Daniel::ttl`]
+    ///
+    /// [`Daniel::ttl`]: super::Daniel::ttl
+    pub fn ttl(&self) -> Option<Duration> {
+        match self {
+            DirEntry::Directory(dir) => dir.ttl,
+            DirEntry::File(file) => file.ttl,
+        }
+    }
+
+    pub fn set_ttl(&mut self, ttl: Option<Duration>) {
+        match self {
+            DirEntry::Directory(dir) => dir.ttl = ttl,
+            DirEntry::File(file) => file.ttl = ttl,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -80,20 +156,239 @@ pub struct File {
     parent: Inode,
     attr: FileAttribute,
     name: PathBuf,
+
+    data: Box<dyn Storage>,
+    /// cached hash of `data`, cleared whenever `data` changes
+    hash: OnceLock<u64>,
+    xattrs: Xattrs,
+    /// the contents were dropped to stay under the memory budget and haven't been written since
+    evicted: bool,
+    ttl: Option<Duration>,
 }
 
+/// extended attributes by name
+pub type Xattrs = BTreeMap<OsString, Vec<u8>>;
+
 impl File {
     pub fn new(name: PathBuf, parent: Inode, inode: Inode, perms: u16) -> Self {
+        let mut attr = FileAttribute::new(inode.into(), FileType::RegularFile, perms);
+        // the name it's created with, hard links add more
+        attr.inner_mut().nlink = 1;
+
         Self {
             name,
             parent,
-            attr: FileAttribute::new(inode.into(), FileType::RegularFile, perms),
+            attr,
+
+            data: Box::new(Memory::default()),
+            hash: OnceLock::new(),
+            xattrs: Xattrs::new(),
+            evicted: false,
+            ttl: None,
         }
     }
 
+    /// a device node, fifo or socket, `rdev` is only meaningful for devices
+    pub fn node(
+        name: PathBuf,
+        parent: Inode,
+        inode: Inode,
+        perms: u16,
+        kind: FileType,
+        rdev: u32,
+    ) -> Self {
+        let mut file = Self::new(name, parent, inode, perms);
+        let attr = file.attr.inner_mut();
+        attr.kind = kind;
+        attr.rdev = rdev;
+
+        file
+    }
+
+    /// a symlink stores its target as its contents so its size is the length of the target
+    pub fn symlink(name: PathBuf, parent: Inode, inode: Inode, target: &Path) -> Self {
+        let mut file = Self::new(name, parent, inode, 0o777);
+        file.attr.inner_mut().kind = FileType::Symlink;
+        file.data = Box::new(Memory::new(Arc::new(
+            target.as_os_str().as_bytes().to_vec(),
+        )));
+        file.attr.set_size(file.data.len());
+
+        file
+    }
+
+    /// a regular file keeping its contents in `storage` instead of in memory
+    pub fn with_storage(
+        name: PathBuf,
+        parent: Inode,
+        inode: Inode,
+        perms: u16,
+        storage: Box<dyn Storage>,
+    ) -> Self {
+        let mut file = Self::new(name, parent, inode, perms);
+        file.data = storage;
+        // a sparse storage only counts the blocks it has
+        file.modified();
+
+        file
+    }
+
+    /// a read only regular file serving the contents of the host file at `path` from a mapping
+    pub fn from_mmap(
+        name: PathBuf,
+        parent: Inode,
+        inode: Inode,
+        path: impl AsRef<Path>,
+    ) -> std::io::Result<Self> {
+        let storage = MmapStorage::open(path)?;
+        Ok(Self::with_storage(
+            name,
+            parent,
+            inode,
+            0o444,
+            Box::new(storage),
+        ))
+    }
+
     pub fn parent(&self) -> Inode {
         self.parent
     }
+
+    pub fn kind(&self) -> FileType {
+        self.attr.inner().kind
+    }
+
+    pub fn data(&self) -> Cow<'_, [u8]> {
+        self.data.read(0, self.data.len() as usize)
+    }
+
+    pub fn storage(&self) -> &dyn Storage {
+        self.data.as_ref()
+    }
+
+    /// the contents behind an `Arc`, shared with this file when its storage keeps them in one
+    pub fn shared_data(&self) -> Arc<Vec<u8>> {
+        match self.data.shared() {
+            Some(data) => data,
+            None => Arc::new(self.data().into_owned()),
+        }
+    }
+
+    /// replaces the contents with `data` kept in memory, without copying it
+    pub fn share(&mut self, data: Arc<Vec<u8>>) {
+        self.data = Box::new(Memory::new(data));
+        self.evicted = false;
+        self.modified();
+    }
+
+    /// returns at most `size` bytes starting at `offset`, never reading past the end of the file
+    pub fn read(&self, offset: u64, size: u32) -> Cow<'_, [u8]> {
+        self.data.read(offset, size as usize)
+    }
+
+    /// writes `data` at `offset`, zero filling any gap past the current end of the file
+    pub fn write(&mut self, offset: u64, data: &[u8]) -> usize {
+        self.leave_hole(offset);
+        let written = self.data.write(offset, data);
+        self.evicted = false;
+        self.modified();
+
+        written
+    }
+
+    pub fn truncate(&mut self, size: u64) {
+        self.leave_hole(size);
+        self.data.truncate(size);
+        self.evicted = false;
+        self.modified();
+    }
+
+    /// allocates what writing at `offset` up to `end` takes, see [`Storage::try_reserve`]. nothing
+    /// is needed when the contents are or will be [`Sparse`], it only keeps the written bytes
+    pub fn try_reserve(&mut self, offset: u64, end: u64) -> Result<(), TryReserveError> {
+        let len = self.data.len();
+        if offset.saturating_sub(len) >= MIN_HOLE || self.data.sparse() || self.data.read_only() {
+            return Ok(());
+        }
+
+        self.data
+            .try_reserve(usize::try_from(end).unwrap_or(usize::MAX))
+    }
+
+    /// at most how many more bytes the contents take up once `offset..end` is written, see
+    /// [`Storage::growth`]
+    pub fn growth(&self, offset: u64, end: u64) -> u64 {
+        let len = self.data.len();
+        if self.data.read_only() {
+            return 0;
+        }
+        // the contents move to sparse storage, only the written bytes are new
+        if offset.saturating_sub(len) >= MIN_HOLE && !self.data.sparse() {
+            return end - offset;
+        }
+
+        self.data.growth(offset, end)
+    }
+
+    /// moves the contents to [`Sparse`] storage when growing the file to `offset` would leave a
+    /// gap of at least [`MIN_HOLE`], so the gap isn't filled with zeros
+    fn leave_hole(&mut self, offset: u64) {
+        let len = self.data.len();
+        if offset.saturating_sub(len) < MIN_HOLE || self.data.sparse() || self.data.read_only() {
+            return;
+        }
+
+        let sparse = Sparse::new(&self.data.read(0, len as usize));
+        self.data = Box::new(sparse);
+    }
+
+    /// 64 bit FNV-1a of the contents, so the value stays the same across builds and runs
+    pub fn content_hash(&self) -> u64 {
+        *self.hash.get_or_init(|| {
+            self.data()
+                .iter()
+                .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                    (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+                })
+        })
+    }
+
+    /// keeps the size and blocks in step with the contents, the times are left to [`File::touch`]
+    fn modified(&mut self) {
+        self.hash = OnceLock::new();
+
+        self.attr.set_size(self.data.len());
+        self.attr.inner_mut().blocks = self.data.stored_len().div_ceil(512);
+    }
+
+    /// drops the contents, leaving an empty file with the rest of its metadata intact
+    pub fn evict(&mut self) {
+        self.data.truncate(0);
+        self.evicted = true;
+        self.modified();
+    }
+
+    pub fn is_evicted(&self) -> bool {
+        self.evicted
+    }
+
+    /// gives capacity the contents don't use back to the allocator, see [`Storage::shrink`]
+    pub fn shrink(&mut self) {
+        self.data.shrink();
+    }
+
+    /// an overlayfs whiteout, see [`is_whiteout`]
+    pub fn is_whiteout(&self) -> bool {
+        let attr = self.attr.inner();
+        is_whiteout(attr.kind, attr.rdev)
+    }
+
+    /// marks the contents as changed at `now`
+    pub fn touch(&mut self, now: SystemTime) {
+        let attr = self.attr.inner_mut();
+        attr.mtime = now;
+        attr.ctime = now;
+    }
 }
 
 impl File {
@@ -125,6 +420,29 @@ impl std::fmt::Display for File {
 pub enum EntryType {
     File,
     Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+    /// a 0/0 character device marking an entry removed from an overlay's lower layer
+    Whiteout,
+}
+
+impl EntryType {
+    /// a short lowercase name for logs
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntryType::File => "file",
+            EntryType::Directory => "dir",
+            EntryType::Symlink => "symlink",
+            EntryType::CharDevice => "chardev",
+            EntryType::BlockDevice => "blockdev",
+            EntryType::Fifo => "fifo",
+            EntryType::Socket => "socket",
+            EntryType::Whiteout => "whiteout",
+        }
+    }
 }
 
 impl From<EntryType> for FileType {
@@ -132,29 +450,69 @@ impl From<EntryType> for FileType {
         match value {
             EntryType::File => FileType::RegularFile,
             EntryType::Directory => FileType::Directory,
+            EntryType::Symlink => FileType::Symlink,
+            EntryType::CharDevice | EntryType::Whiteout => FileType::CharDevice,
+            EntryType::BlockDevice => FileType::BlockDevice,
+            EntryType::Fifo => FileType::NamedPipe,
+            EntryType::Socket => FileType::Socket,
         }
     }
 }
 
-impl TryFrom<FileType> for EntryType {
-    type Error = ();
-    fn try_from(value: FileType) -> Result<Self, Self::Error> {
+/// a 0/0 character device maps to [`EntryType::CharDevice`], only [`DirEntry::entry_type`] can
+/// tell it's a whiteout
+impl From<FileType> for EntryType {
+    fn from(value: FileType) -> Self {
         match value {
-            FileType::Directory => Ok(EntryType::Directory),
-            FileType::RegularFile => Ok(EntryType::File),
-
-            _ => Err(()),
+            FileType::Directory => EntryType::Directory,
+            FileType::RegularFile => EntryType::File,
+            FileType::Symlink => EntryType::Symlink,
+            FileType::CharDevice => EntryType::CharDevice,
+            FileType::BlockDevice => EntryType::BlockDevice,
+            FileType::NamedPipe => EntryType::Fifo,
+            FileType::Socket => EntryType::Socket,
         }
     }
 }
 
+/// maps the `S_IFMT` bits of a mode to the type they describe
+pub fn file_type_from_mode(mode: u32) -> Option<FileType> {
+    match mode & 0o170000 {
+        0o010000 => Some(FileType::NamedPipe),
+        0o020000 => Some(FileType::CharDevice),
+        0o040000 => Some(FileType::Directory),
+        0o060000 => Some(FileType::BlockDevice),
+        0o100000 => Some(FileType::RegularFile),
+        0o120000 => Some(FileType::Symlink),
+        0o140000 => Some(FileType::Socket),
+        _ => None,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Directory {
     parent: Inode,
     name: PathBuf,
     attr: FileAttribute,
+    /// permission bits new children are limited to, on top of the umask
+    default_child_mode: Option<u16>,
+    xattrs: Xattrs,
+    ttl: Option<Duration>,
+
+    /// keyed by name, a hard linked file may be listed under several
+    entries: HashMap<PathBuf, Child>,
+    next_seq: u64,
+}
 
-    entries: HashMap<Inode, EntryType>,
+/// what a directory keeps about each of its children, enough to list them without looking them
+/// up in the [`DirList`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Child {
+    pub ino: Inode,
+    pub kind: EntryType,
+    /// when the child was added, later children have larger numbers. readdir cookies are built
+    /// from these so they stay valid while other children come and go
+    pub seq: u64,
 }
 
 #[derive(Debug)]
@@ -165,12 +523,20 @@ pub enum DirectoryInodes {
 
 impl Directory {
     pub fn new(parent: Inode, name: PathBuf, inode: Inode, perms: u16) -> Self {
+        let mut attr = FileAttribute::new(inode.into(), FileType::Directory, perms);
+        // its name and its own `.`, each subdirectory's `..` adds one more
+        attr.inner_mut().nlink = 2;
+
         Self {
             parent,
             name,
-            attr: FileAttribute::new(inode.into(), FileType::Directory, perms),
+            attr,
+            default_child_mode: None,
+            xattrs: Xattrs::new(),
+            ttl: None,
 
             entries: HashMap::default(),
+            next_seq: 0,
         }
     }
 
@@ -182,23 +548,79 @@ impl Directory {
         &self.name
     }
 
-    pub fn push(&mut self, inode: Inode, entry: EntryType) {
-        self.entries.insert(inode, entry);
+    pub fn default_child_mode(&self) -> Option<u16> {
+        self.default_child_mode
+    }
+
+    pub fn set_default_child_mode(&mut self, mode: Option<u16>) {
+        self.default_child_mode = mode;
+    }
+
+    /// the permissions a child created with `perms` ends up with
+    pub fn child_perms(&self, perms: u16) -> u16 {
+        match self.default_child_mode {
+            Some(mode) => perms & mode,
+            None => perms,
+        }
+    }
+
+    pub fn push(&mut self, inode: Inode, kind: EntryType, name: impl Into<PathBuf>) {
+        self.insert(inode, kind, name);
+    }
+
+    /// a name that's already there keeps its sequence number
+    pub fn insert(&mut self, inode: Inode, kind: EntryType, name: impl Into<PathBuf>) {
+        let name = name.into();
+        match self.entries.get_mut(&name) {
+            Some(child) => {
+                child.ino = inode;
+                child.kind = kind;
+            }
+            None => {
+                self.next_seq += 1;
+                let seq = self.next_seq;
+                self.entries.insert(
+                    name,
+                    Child {
+                        ino: inode,
+                        kind,
+                        seq,
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn get(&self, name: impl AsRef<Path>) -> Option<&Child> {
+        self.entries.get(name.as_ref())
+    }
+
+    pub fn remove(&mut self, name: impl AsRef<Path>) -> Option<Child> {
+        self.entries.remove(name.as_ref())
     }
 
-    pub fn insert(&mut self, inode: Inode, entry: EntryType) {
-        self.entries.insert(inode, entry);
+    /// whether `inode` is listed under any name
+    pub fn contains(&self, inode: Inode) -> bool {
+        self.entries.values().any(|child| child.ino == inode)
     }
 
-    pub fn get(&self, inode: &Inode) -> Option<&EntryType> {
-        self.entries.get(inode)
+    /// when `name` was added to the directory, see [`Child::seq`]
+    pub fn seq(&self, name: impl AsRef<Path>) -> Option<u64> {
+        self.entries.get(name.as_ref()).map(|child| child.seq)
     }
 
     pub fn attr(&self) -> FileAttribute {
         self.attr
     }
 
-    pub fn entries(&self) -> &HashMap<Inode, EntryType> {
+    /// a child was added or removed
+    pub fn touch(&mut self, now: SystemTime) {
+        let attr = self.attr.inner_mut();
+        attr.mtime = now;
+        attr.ctime = now;
+    }
+
+    pub fn entries(&self) -> &HashMap<PathBuf, Child> {
         &self.entries
     }
 }
@@ -215,8 +637,8 @@ impl std::fmt::Display for Directory {
         )?;
 
         if !self.entries.is_empty() {
-            for ino in &self.entries {
-                writeln!(f, "entry: {ino:?} ")?;
+            for (name, child) in &self.entries {
+                writeln!(f, "entry: {name:?} {child:?} ")?;
             }
         } else {
             writeln!(f, "entries: []")?;
@@ -226,19 +648,14 @@ impl std::fmt::Display for Directory {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DirList {
     map: HashMap<Inode, DirEntry>,
 }
 
 impl Default for DirList {
     fn default() -> Self {
-        let mut map = HashMap::new();
-        map.insert(
-            ROOT_INODE,
-            DirEntry::Directory(Directory::new(ROOT_INODE, "/".into(), ROOT_INODE, 0o755)),
-        );
-        Self { map }
+        Self::with_root(0, 0, 0o755)
     }
 }
 
@@ -247,6 +664,22 @@ impl DirList {
         Self::default()
     }
 
+    pub fn with_root(uid: u32, gid: u32, perms: u16) -> Self {
+        let mut root = Directory::new(ROOT_INODE, "/".into(), ROOT_INODE, perms);
+        let attr = root.attr.inner_mut();
+        attr.uid = uid;
+        attr.gid = gid;
+
+        // its contents are generated on every read, see `Daniel::status`
+        let status = File::new(STATUS_FILE.into(), ROOT_INODE, STATUS_INODE, 0o444);
+        root.insert(STATUS_INODE, EntryType::File, STATUS_FILE);
+
+        let mut map = HashMap::new();
+        map.insert(ROOT_INODE, DirEntry::Directory(root));
+        map.insert(STATUS_INODE, DirEntry::File(status));
+        Self { map }
+    }
+
     pub fn map(&self) -> &HashMap<Inode, DirEntry> {
         &self.map
     }
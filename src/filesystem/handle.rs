@@ -0,0 +1,58 @@
+use super::Inode;
+
+/// what an `fh` handed out by `open`/`opendir` refers to, every open gets an `fh` of its own.
+/// the generation the inode had when it was opened tells a handle to an inode that has since been
+/// removed or reused apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileHandle {
+    pub inode: Inode,
+    pub generation: u32,
+    /// the open(2) flags it was opened with
+    pub flags: i32,
+}
+
+impl FileHandle {
+    pub fn new(inode: Inode, generation: u32, flags: i32) -> Self {
+        Self {
+            inode,
+            generation,
+            flags,
+        }
+    }
+
+    /// whether the handle was opened on `inode` while it had `generation`
+    pub fn matches(&self, inode: Inode, generation: u32) -> bool {
+        self.inode == inode && self.generation == generation
+    }
+}
+
+/// a handle open on an inode and the open(2) flags it was opened with, as
+/// [`Daniel::handles_for`](super::Daniel::handles_for) reports it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenHandle {
+    pub fh: u64,
+    pub flags: i32,
+}
+
+impl OpenHandle {
+    /// `r`, `w` or `rw` for the access mode in `flags`
+    pub fn mode(&self) -> &'static str {
+        match self.flags & libc::O_ACCMODE {
+            libc::O_WRONLY => "w",
+            libc::O_RDWR => "rw",
+            _ => "r",
+        }
+    }
+}
+
+impl std::fmt::Display for OpenHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "fh {:#x} {} flags {:#o}",
+            self.fh,
+            self.mode(),
+            self.flags
+        )
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::NAME_MAX;
+
+/// something that would stop a host directory from being imported as is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportProblem {
+    /// a name longer than [`NAME_MAX`] bytes
+    NameTooLong(PathBuf),
+    /// a directory with more entries than a directory may hold
+    TooManyEntries(PathBuf),
+    OverQuota {
+        needed: u64,
+        available: u64,
+    },
+    TooManyInodes {
+        needed: u64,
+        available: u64,
+    },
+}
+
+/// what importing a host directory would take, found without creating anything
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportPlan {
+    /// the size of every file and symlink below the root
+    pub bytes: u64,
+    /// how many entries below the root would be created, the root itself isn't one
+    pub entries: u64,
+    pub problems: Vec<ImportProblem>,
+}
+
+impl ImportPlan {
+    /// walks `root` without following symlinks, flagging names and directories that wouldn't fit
+    pub fn scan(root: &Path, max_entries_per_dir: Option<usize>) -> io::Result<Self> {
+        let mut plan = Self::default();
+        plan.scan_dir(root, max_entries_per_dir)?;
+        Ok(plan)
+    }
+
+    fn scan_dir(&mut self, dir: &Path, max_entries_per_dir: Option<usize>) -> io::Result<()> {
+        let mut held = 0;
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            held += 1;
+            self.entries += 1;
+
+            if entry.file_name().len() > NAME_MAX as usize {
+                self.problems.push(ImportProblem::NameTooLong(path.clone()));
+            }
+
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                self.scan_dir(&path, max_entries_per_dir)?;
+            } else {
+                self.bytes += metadata.len();
+            }
+        }
+
+        if max_entries_per_dir.is_some_and(|max| held > max) {
+            self.problems
+                .push(ImportProblem::TooManyEntries(dir.to_path_buf()));
+        }
+
+        Ok(())
+    }
+
+    /// flags the plan if it needs more than the bytes or inodes left, `None` is unlimited
+    pub fn check_capacity(&mut self, available_bytes: Option<u64>, available_inodes: Option<u64>) {
+        if let Some(available) = available_bytes
+            && self.bytes > available
+        {
+            self.problems.push(ImportProblem::OverQuota {
+                needed: self.bytes,
+                available,
+            });
+        }
+
+        if let Some(available) = available_inodes
+            && self.entries > available
+        {
+            self.problems.push(ImportProblem::TooManyInodes {
+                needed: self.entries,
+                available,
+            });
+        }
+    }
+
+    pub fn fits(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
@@ -0,0 +1,76 @@
+use std::{collections::VecDeque, path::PathBuf, time::SystemTime};
+
+use super::Inode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalOp {
+    Create,
+    Mkdir,
+    Unlink,
+    Link,
+    Rmdir,
+    Rename,
+    Write,
+    Setattr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    pub timestamp: SystemTime,
+    pub op: JournalOp,
+    pub inode: Inode,
+    pub name: PathBuf,
+}
+
+/// a ring buffer of the most recent mutations, a capacity of 0 records nothing
+#[derive(Debug, Default)]
+pub struct Journal {
+    capacity: usize,
+    entries: VecDeque<JournalEntry>,
+}
+
+impl Journal {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// `now` comes from the filesystem's clock so the timestamps agree with the inode times
+    pub fn record(
+        &mut self,
+        now: SystemTime,
+        op: JournalOp,
+        inode: Inode,
+        name: impl Into<PathBuf>,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(JournalEntry {
+            timestamp: now,
+            op,
+            inode,
+            name: name.into(),
+        });
+        // kept in one piece so `entries` can hand out a slice
+        self.entries.make_contiguous();
+    }
+
+    /// oldest first
+    pub fn entries(&self) -> &[JournalEntry] {
+        let (entries, rest) = self.entries.as_slices();
+        debug_assert!(rest.is_empty());
+        entries
+    }
+}
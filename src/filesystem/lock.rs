@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+
+use super::Inode;
+
+pub const F_RDLCK: i32 = 0;
+pub const F_WRLCK: i32 = 1;
+pub const F_UNLCK: i32 = 2;
+
+/// a posix byte-range lock, `end` is inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lock {
+    pub owner: u64,
+    pub start: u64,
+    pub end: u64,
+    pub typ: i32,
+    pub pid: u32,
+}
+
+impl Lock {
+    pub fn new(owner: u64, start: u64, end: u64, typ: i32, pid: u32) -> Self {
+        Self {
+            owner,
+            start,
+            end,
+            typ,
+            pid,
+        }
+    }
+
+    pub fn overlaps(&self, other: &Lock) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+
+    /// two locks conflict if they belong to different owners, overlap and at least one is a write lock
+    pub fn conflicts(&self, other: &Lock) -> bool {
+        self.owner != other.owner
+            && self.overlaps(other)
+            && (self.typ == F_WRLCK || other.typ == F_WRLCK)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LockTable {
+    locks: BTreeMap<Inode, Vec<Lock>>,
+}
+
+impl LockTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn locks(&self, ino: &Inode) -> &[Lock] {
+        self.locks.get(ino).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn conflict(&self, ino: &Inode, lock: &Lock) -> Option<&Lock> {
+        self.locks(ino).iter().find(|held| held.conflicts(lock))
+    }
+
+    /// records `lock`, replacing whatever range the owner already held, or removes the range if
+    /// `lock.typ` is `F_UNLCK`
+    pub fn set(&mut self, ino: Inode, lock: Lock) -> Result<(), Lock> {
+        if lock.typ != F_UNLCK
+            && let Some(held) = self.conflict(&ino, &lock)
+        {
+            return Err(*held);
+        }
+
+        let held = self.locks.entry(ino).or_default();
+        let mut kept = Vec::with_capacity(held.len());
+        for old in held.drain(..) {
+            if old.owner != lock.owner || !old.overlaps(&lock) {
+                kept.push(old);
+                continue;
+            }
+
+            if old.start < lock.start {
+                kept.push(Lock {
+                    end: lock.start - 1,
+                    ..old
+                });
+            }
+
+            if old.end > lock.end {
+                kept.push(Lock {
+                    start: lock.end + 1,
+                    ..old
+                });
+            }
+        }
+
+        if lock.typ != F_UNLCK {
+            kept.push(lock);
+        }
+
+        if kept.is_empty() {
+            self.locks.remove(&ino);
+        } else {
+            *held = kept;
+        }
+
+        Ok(())
+    }
+
+    /// drops every lock `owner` holds on `ino`
+    pub fn release(&mut self, ino: &Inode, owner: u64) {
+        if let Some(held) = self.locks.get_mut(ino) {
+            held.retain(|lock| lock.owner != owner);
+            if held.is_empty() {
+                self.locks.remove(ino);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, ino: &Inode) {
+        self.locks.remove(ino);
+    }
+}
@@ -3,14 +3,17 @@
 use std::{
     collections::{BTreeMap, btree_map::Iter},
     num::NonZeroU64,
-    ops::Add,
+    ops::{Add, Bound},
     path::{Path, PathBuf},
     time::SystemTime,
 };
 
 use fuser::{FileAttr, FileType};
 
-use super::ROOT_INODE;
+use super::{
+    Errno::{self, EINVAL},
+    ROOT_INODE, STATUS_FILE, STATUS_INODE,
+};
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Hash)]
 pub struct Inode(NonZeroU64);
@@ -61,32 +64,46 @@ macro_rules! nonzero_u64 {
 #[macro_export]
 macro_rules! unchecked_inode {
     ($val:expr) => {{
-        assert!($val != 0);
+        // evaluated once, the expression may have side effects
+        let val: u64 = $val;
+        assert!(val != 0);
         use super::Inode;
         use std::num::NonZeroU64;
-        Inode::new(NonZeroU64::new($val).unwrap())
+        Inode::new(NonZeroU64::new(val).unwrap())
     }};
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct InodeMapper {
+    /// absolute path of every inode, eg. `/a/foo`
     paths: BTreeMap<PathBuf, Inode>,
+    /// the reverse of `paths`
+    full_paths: BTreeMap<Inode, PathBuf>,
     map: BTreeMap<(Inode, PathBuf), Inode>,
     /// if inode is removed, it sets it to that inode, else its the last inode + 1
     next_inode: Inode,
+    /// the lowest inode handed out, the ones between the root and it are reserved
+    base: Inode,
 }
 
 impl Default for InodeMapper {
     fn default() -> Self {
         let mut map = BTreeMap::new();
         map.insert((ROOT_INODE, "/".into()), ROOT_INODE);
+        map.insert((ROOT_INODE, STATUS_FILE.into()), STATUS_INODE);
         let mut paths = BTreeMap::new();
         paths.insert("/".into(), ROOT_INODE);
+        let mut full_paths = BTreeMap::new();
+        full_paths.insert(ROOT_INODE, "/".into());
+        paths.insert(Path::new("/").join(STATUS_FILE), STATUS_INODE);
+        full_paths.insert(STATUS_INODE, Path::new("/").join(STATUS_FILE));
 
         Self {
             paths,
+            full_paths,
             map,
             next_inode: unchecked_inode!(2),
+            base: unchecked_inode!(2),
         }
     }
 }
@@ -96,62 +113,193 @@ impl InodeMapper {
         Self::default()
     }
 
-    pub fn map(&self) -> Iter<(Inode, PathBuf), Inode> {
+    /// hands out inodes from `base` on and never the ones between the root and it, a base at or
+    /// below the root reserves nothing
+    pub fn with_base(mut self, base: Inode) -> Self {
+        self.base = base.max(unchecked_inode!(2));
+        self.next_inode = self.next_inode.max(self.base);
+        self
+    }
+
+    pub fn base(&self) -> Inode {
+        self.base
+    }
+
+    /// ordered by parent and name, see [`InodeMapper::iter_by_path`] and
+    /// [`InodeMapper::iter_by_inode`] for orders that make sense to show
+    pub fn map(&self) -> Iter<'_, (Inode, PathBuf), Inode> {
         self.map.iter()
     }
 
+    /// the absolute path of every name along with its inode, ordered by path. a hard linked inode
+    /// comes up once for each of its names
+    pub fn iter_by_path(&self) -> impl Iterator<Item = (&Path, Inode)> {
+        self.paths
+            .iter()
+            .map(|(path, inode)| (path.as_path(), *inode))
+    }
+
+    /// the same as [`InodeMapper::iter_by_path`] ordered by inode, the names of an inode by path
+    pub fn iter_by_inode(&self) -> impl Iterator<Item = (&Path, Inode)> {
+        let mut names: Vec<_> = self.iter_by_path().collect();
+        names.sort_by_key(|(path, inode)| (*inode, *path));
+        names.into_iter()
+    }
+
+    /// the absolute path of `name` inside of `parent`
+    fn full_path(&self, parent: Inode, name: impl AsRef<Path>) -> PathBuf {
+        self.full_paths
+            .get(&parent)
+            .map(PathBuf::as_path)
+            .unwrap_or(Path::new("/"))
+            .join(name)
+    }
+
     pub fn insert(&mut self, parent: Inode, path: impl AsRef<Path>, inode: Inode) {
         self.map
             .insert((parent, path.as_ref().to_path_buf()), inode);
 
-        self.paths.insert(path.as_ref().to_path_buf(), inode);
+        let full = self.full_path(parent, path);
+        self.paths.insert(full.clone(), inode);
+        self.full_paths.insert(inode, full);
 
         self.next_inode = self.next_inode.add(nonzero_u64!(1));
     }
 
     pub fn remove(&mut self, parent: Inode, path: impl AsRef<Path>) {
-        self.map.remove(&(parent, path.as_ref().to_path_buf()));
-        self.paths.remove(&path.as_ref().to_path_buf());
+        let Some(inode) = self.map.remove(&(parent, path.as_ref().to_path_buf())) else {
+            return;
+        };
+
+        if let Some(full) = self.full_paths.remove(&inode) {
+            self.paths.remove(&full);
+        }
+    }
+
+    /// gives `inode` another name, its path stays the one it already has
+    pub fn link(&mut self, parent: Inode, path: impl AsRef<Path>, inode: Inode) {
+        self.map
+            .insert((parent, path.as_ref().to_path_buf()), inode);
+        let full = self.full_path(parent, path);
+        self.paths.insert(full, inode);
+    }
+
+    /// drops one name of an inode that has others. if it was the name [`InodeMapper::path_of`]
+    /// reports, another one takes over and is returned
+    pub fn unlink(&mut self, parent: Inode, path: impl AsRef<Path>) -> Option<(Inode, PathBuf)> {
+        let inode = self.map.remove(&(parent, path.as_ref().to_path_buf()))?;
+        let full = self.full_path(parent, path);
+        self.paths.remove(&full);
+        if self.full_paths.get(&inode) != Some(&full) {
+            return None;
+        }
+
+        let other = self
+            .map
+            .iter()
+            .find(|(_, linked)| **linked == inode)
+            .map(|((parent, name), _)| (*parent, name.clone()));
+        match &other {
+            Some((parent, name)) => {
+                let full = self.full_path(*parent, name);
+                self.full_paths.insert(inode, full);
+            }
+            None => _ = self.full_paths.remove(&inode),
+        }
+
+        other
+    }
+
+    /// moves `inode` to a new key without allocating a new inode, the absolute paths of everything
+    /// below it move along
+    pub fn rename(
+        &mut self,
+        parent: Inode,
+        path: impl AsRef<Path>,
+        new_parent: Inode,
+        new_path: impl AsRef<Path>,
+    ) {
+        let Some(inode) = self.map.remove(&(parent, path.as_ref().to_path_buf())) else {
+            return;
+        };
+        // a hard linked file keeps its other names, so only this one moves
+        let old = self.full_path(parent, &path);
+        self.paths.remove(&old);
+        self.full_paths.remove(&inode);
+
+        self.map
+            .insert((new_parent, new_path.as_ref().to_path_buf()), inode);
+        let full = self.full_path(new_parent, new_path);
+        self.paths.insert(full.clone(), inode);
+        self.full_paths.insert(inode, full.clone());
+
+        // paths order component by component, so everything below `old` directly follows it
+        let descendants: Vec<(PathBuf, Inode)> = self
+            .paths
+            .range::<Path, _>((Bound::Excluded(old.as_path()), Bound::Unbounded))
+            .take_while(|(path, _)| path.starts_with(&old))
+            .map(|(path, inode)| (path.clone(), *inode))
+            .collect();
+        for (path, inode) in descendants {
+            self.paths.remove(&path);
+            let moved = full.join(path.strip_prefix(&old).expect("path is below the old one"));
+            self.paths.insert(moved.clone(), inode);
+            // a hard linked file whose path is one of its other names keeps it
+            if self.full_paths.get(&inode) == Some(&path) {
+                self.full_paths.insert(inode, moved);
+            }
+        }
     }
 
     pub fn get_map(&self, parent: Inode, path: impl AsRef<Path>) -> Option<&Inode> {
         self.map.get(&(parent, path.as_ref().to_path_buf()))
     }
 
+    /// resolves an absolute path like `/a/foo`
     pub fn get_path(&self, path: impl AsRef<Path>) -> Option<&Inode> {
         self.paths.get(&path.as_ref().to_path_buf())
     }
 
+    pub fn path_of(&self, inode: Inode) -> Option<&Path> {
+        self.full_paths.get(&inode).map(PathBuf::as_path)
+    }
+
     pub fn next_inode(&self) -> Inode {
         self.next_inode
     }
+
+    /// the largest inode with a path, the status file aside
+    pub fn max_inode(&self) -> Inode {
+        *self
+            .full_paths
+            .keys()
+            .rev()
+            .find(|ino| **ino != STATUS_INODE)
+            .unwrap_or(&ROOT_INODE)
+    }
+
+    /// makes `inode` the next one handed out, `EINVAL` if it's already in use, below one that is
+    /// or reserved
+    pub fn set_next_inode(&mut self, inode: Inode) -> Result<(), Errno> {
+        if inode <= self.max_inode() || inode < self.base || inode == STATUS_INODE {
+            return Err(EINVAL);
+        }
+
+        self.next_inode = inode;
+        Ok(())
+    }
 }
 
 impl std::fmt::Display for InodeMapper {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "InodeMapper ")?;
-        let mut iter = self.map.iter();
-        let mut len = iter.len();
-
-        loop {
-            let sep = if len > 1 { "," } else { " " };
-            match iter.next() {
-                Some(((parent, path), inode)) => write!(
-                    f,
-                    "path: {:?} parent: {:?} inode: {:?}{sep} ",
-                    path.display(),
-                    parent,
-                    inode
-                )?,
-                None => {
-                    write!(f, " ")?;
-                    break;
-                }
-            }
-            len -= 1;
+        let mut iter = self.iter_by_path().peekable();
+        while let Some((path, inode)) = iter.next() {
+            let sep = if iter.peek().is_some() { "," } else { " " };
+            write!(f, "path: {:?} inode: {:?}{sep} ", path.display(), inode)?;
         }
 
-        Ok(())
+        write!(f, " ")
     }
 }
 
@@ -195,6 +343,87 @@ impl FileAttribute {
     pub fn inner_mut(&mut self) -> &mut FileAttr {
         &mut self.0
     }
+
+    /// sets the size and the number of 512 byte blocks needed to hold it
+    pub fn set_size(&mut self, size: u64) {
+        self.0.size = size;
+        self.0.blocks = size.div_ceil(512);
+    }
+
+    /// sets every field of `patch` and returns the ones whose value actually changed. ctime is
+    /// left to the caller unless the patch sets it
+    pub fn apply(&mut self, patch: AttrPatch) -> AttrChanges {
+        fn set<T: PartialEq>(field: &mut T, value: Option<T>) -> bool {
+            match value {
+                Some(value) if *field != value => {
+                    *field = value;
+                    true
+                }
+                _ => false,
+            }
+        }
+
+        let size = patch.size.is_some_and(|size| size != self.0.size);
+        if let Some(size) = patch.size {
+            self.set_size(size);
+        }
+        let attr = &mut self.0;
+
+        AttrChanges {
+            mode: set(
+                &mut attr.perm,
+                patch.mode.map(|mode| (mode & 0o7777) as u16),
+            ),
+            uid: set(&mut attr.uid, patch.uid),
+            gid: set(&mut attr.gid, patch.gid),
+            size,
+            atime: set(&mut attr.atime, patch.atime),
+            mtime: set(&mut attr.mtime, patch.mtime),
+            ctime: set(&mut attr.ctime, patch.ctime),
+            crtime: set(&mut attr.crtime, patch.crtime),
+            flags: set(&mut attr.flags, patch.flags),
+        }
+    }
+}
+
+/// the settable attributes of an entry, `None` leaves that one as it is
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AttrPatch {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub size: Option<u64>,
+    pub atime: Option<SystemTime>,
+    pub mtime: Option<SystemTime>,
+    pub ctime: Option<SystemTime>,
+    pub crtime: Option<SystemTime>,
+    pub flags: Option<u32>,
+}
+
+impl AttrPatch {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// which attributes [`FileAttribute::apply`] changed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AttrChanges {
+    pub mode: bool,
+    pub uid: bool,
+    pub gid: bool,
+    pub size: bool,
+    pub atime: bool,
+    pub mtime: bool,
+    pub ctime: bool,
+    pub crtime: bool,
+    pub flags: bool,
+}
+
+impl AttrChanges {
+    pub fn any(&self) -> bool {
+        *self != Self::default()
+    }
 }
 
 #[derive(Default, Debug)]
@@ -0,0 +1,47 @@
+/// the virtual xattr holding the content type sniffed from a file's leading bytes
+pub const MIME_XATTR: &str = "user.daniel.mime";
+
+/// magic numbers at the start of a file and the content type they identify
+const MAGIC: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"\x7fELF", "application/x-elf"),
+    (b"OggS", "audio/ogg"),
+    (b"fLaC", "audio/flac"),
+    (b"ID3", "audio/mpeg"),
+    (b"\x1aE\xdf\xa3", "video/webm"),
+];
+
+/// guesses the content type of `data`, falling back to text or a generic binary type when no
+/// magic number matches, an empty file has no type
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+    if data.is_empty() {
+        return None;
+    }
+
+    if let Some((_, mime)) = MAGIC.iter().find(|(magic, _)| data.starts_with(magic)) {
+        return Some(mime);
+    }
+
+    // RIFF containers name their format at offset 8
+    if data.starts_with(b"RIFF") && data.len() >= 12 {
+        match &data[8..12] {
+            b"WEBP" => return Some("image/webp"),
+            b"WAVE" => return Some("audio/wav"),
+            _ => {}
+        }
+    }
+
+    // cutting the head off can split a character, which doesn't make it binary
+    let head = &data[..data.len().min(512)];
+    match std::str::from_utf8(head) {
+        Ok(_) => Some("text/plain"),
+        Err(err) if err.error_len().is_none() => Some("text/plain"),
+        Err(_) => Some("application/octet-stream"),
+    }
+}
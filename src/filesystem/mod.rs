@@ -1,7 +1,40 @@
+pub mod archive;
+pub mod buffer;
+pub mod cache;
+pub mod clock;
 pub mod daniel;
+pub mod errno;
 pub mod file_types;
+pub mod handle;
+pub mod import;
+pub mod journal;
+pub mod lock;
 pub mod metadata;
+pub mod mime;
+#[cfg(test)]
+mod mounted;
+pub mod notify;
+pub mod overlay;
+pub mod persist;
+pub mod shared;
+pub mod stats;
+pub mod storage;
 
+pub use archive::*;
+pub use buffer::*;
+pub use cache::*;
+pub use clock::*;
 pub use daniel::*;
+pub use errno::*;
 pub use file_types::*;
+pub use handle::*;
+pub use import::*;
+pub use journal::*;
+pub use lock::*;
 pub use metadata::*;
+pub use mime::*;
+pub use notify::*;
+pub use overlay::*;
+pub use shared::*;
+pub use stats::*;
+pub use storage::*;
@@ -0,0 +1,172 @@
+//! tests that go through a real mount and the kernel instead of calling into [`Daniel`] directly,
+//! they catch handlers that are wired up wrong. they're skipped where fuse isn't available
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use fuser::BackgroundSession;
+use tracing::{info, instrument};
+
+use super::{
+    Daniel, STATUS_FILE,
+    daniel::test::{init, temp_dir},
+};
+
+/// whether a mount can actually be made here, needs the fuse device and a setuid helper
+pub(crate) fn fuse_available() -> bool {
+    let helper = std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path)
+            .any(|dir| dir.join("fusermount3").exists() || dir.join("fusermount").exists())
+    });
+    Path::new("/dev/fuse").exists() && helper
+}
+
+/// `fs` mounted on a directory of its own until it's dropped
+struct Mount {
+    path: PathBuf,
+    session: Option<BackgroundSession>,
+}
+
+impl Mount {
+    /// `None` if fuse isn't available or mounting isn't permitted, eg. in a container without
+    /// the capability
+    fn new(test: &str, fs: Daniel) -> Option<Self> {
+        if !fuse_available() {
+            info!("fuse isn't available, skipping");
+            return None;
+        }
+
+        let path = temp_dir(test);
+        let session = match fs.spawn(&path, &[]) {
+            Ok(session) => session,
+            Err(err) => {
+                info!(%err, "mounting isn't permitted here, skipping");
+                _ = fs::remove_dir(&path);
+                return None;
+            }
+        };
+
+        Some(Self {
+            path,
+            session: Some(session),
+        })
+    }
+
+    fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.path.join(path)
+    }
+
+    /// the names `readdir` lists in `dir`, which must not repeat
+    fn list(&self, dir: impl AsRef<Path>) -> BTreeSet<String> {
+        let mut names = BTreeSet::new();
+        for entry in fs::read_dir(self.join(dir)).unwrap() {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            assert!(names.insert(name.clone()), "{name} was listed twice");
+        }
+
+        names
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        // unmounts
+        self.session.take();
+        _ = fs::remove_dir(&self.path);
+    }
+}
+
+#[test]
+#[instrument]
+fn spawn() {
+    init();
+
+    let Some(mount) = Mount::new("spawn", Daniel::new()) else {
+        return;
+    };
+    let status = mount.join(STATUS_FILE);
+    assert!(fs::metadata(&status).is_ok());
+
+    drop(mount);
+    assert!(fs::metadata(&status).is_err());
+}
+
+#[test]
+#[instrument]
+fn create_read_back() {
+    init();
+
+    let Some(mount) = Mount::new("create-read-back", Daniel::new()) else {
+        return;
+    };
+    let foo = mount.join("foo");
+
+    fs::write(&foo, b"hello").unwrap();
+    assert_eq!(fs::read(&foo).unwrap(), b"hello");
+    assert_eq!(fs::metadata(&foo).unwrap().len(), 5);
+
+    let mut file = fs::OpenOptions::new().append(true).open(&foo).unwrap();
+    file.write_all(b" world").unwrap();
+    drop(file);
+    assert_eq!(fs::read_to_string(&foo).unwrap(), "hello world");
+
+    fs::create_dir(mount.join("a")).unwrap();
+    fs::write(mount.join("a/bar"), b"nested").unwrap();
+    assert_eq!(fs::read(mount.join("a/bar")).unwrap(), b"nested");
+}
+
+#[test]
+#[instrument]
+fn readdir_listing() {
+    init();
+
+    let Some(mount) = Mount::new("readdir-listing", Daniel::new()) else {
+        return;
+    };
+
+    fs::create_dir(mount.join("a")).unwrap();
+    assert!(mount.list("a").is_empty());
+
+    // enough that the kernel needs several readdir calls to get them all
+    let mut expected = BTreeSet::from([STATUS_FILE.to_string(), "a".to_string()]);
+    for i in 0..500 {
+        let name = format!("file{i:03}");
+        fs::write(mount.join(&name), name.as_bytes()).unwrap();
+        expected.insert(name);
+    }
+    assert_eq!(mount.list(""), expected);
+
+    for entry in fs::read_dir(mount.join("")).unwrap() {
+        let entry = entry.unwrap();
+        let kind = entry.file_type().unwrap();
+        assert_eq!(kind.is_dir(), entry.file_name() == "a");
+    }
+}
+
+#[test]
+#[instrument]
+fn unlink() {
+    init();
+
+    let Some(mount) = Mount::new("unlink", Daniel::new()) else {
+        return;
+    };
+    let foo = mount.join("foo");
+    fs::write(&foo, b"gone soon").unwrap();
+    fs::write(mount.join("bar"), b"stays").unwrap();
+
+    fs::remove_file(&foo).unwrap();
+    assert_eq!(
+        fs::metadata(&foo).unwrap_err().kind(),
+        std::io::ErrorKind::NotFound
+    );
+    assert_eq!(
+        mount.list(""),
+        BTreeSet::from([STATUS_FILE.to_string(), "bar".to_string()])
+    );
+    assert_eq!(fs::read(mount.join("bar")).unwrap(), b"stays");
+}
@@ -0,0 +1,94 @@
+use std::{
+    ffi::{OsStr, OsString},
+    io,
+    sync::{Arc, OnceLock},
+};
+
+use fuser::FileAttr;
+
+use super::Inode;
+
+/// the kernel cache invalidations and poll wakeups `Daniel` needs, implemented by
+/// [`fuser::Notifier`] and by mocks in tests
+pub trait Notify: Send + Sync {
+    fn inval_inode(&self, ino: u64) -> io::Result<()>;
+    fn inval_entry(&self, parent: u64, name: &OsStr) -> io::Result<()>;
+    /// wakes whoever is polling through the kernel poll handle `kh`
+    fn poll(&self, kh: u64) -> io::Result<()>;
+}
+
+impl Notify for fuser::Notifier {
+    fn inval_inode(&self, ino: u64) -> io::Result<()> {
+        fuser::Notifier::inval_inode(self, ino, 0, 0)
+    }
+
+    fn inval_entry(&self, parent: u64, name: &OsStr) -> io::Result<()> {
+        fuser::Notifier::inval_entry(self, parent, name)
+    }
+
+    fn poll(&self, kh: u64) -> io::Result<()> {
+        fuser::Notifier::poll(self, kh)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
+    Inode(u64),
+    Entry(u64, OsString),
+    /// a kernel poll handle whose file became ready
+    Poll(u64),
+}
+
+/// the notifier only exists once the session does, which is after `Daniel` has been moved into it,
+/// so the slot is shared and filled in afterwards
+#[derive(Clone, Default)]
+pub struct NotifierSlot(Arc<OnceLock<Box<dyn Notify>>>);
+
+impl NotifierSlot {
+    pub fn set(&self, notifier: impl Notify + 'static) {
+        _ = self.0.set(Box::new(notifier));
+    }
+
+    pub fn get(&self) -> Option<&dyn Notify> {
+        self.0.get().map(Box::as_ref)
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0.get().is_some()
+    }
+}
+
+impl std::fmt::Debug for NotifierSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("NotifierSlot").field(&self.is_set()).finish()
+    }
+}
+
+/// a callback run with the new attributes whenever `setattr` changes the permissions or ownership
+/// of an inode
+#[derive(Default)]
+pub struct AttrObserver(Option<AttrCallback>);
+
+pub type AttrCallback = Box<dyn FnMut(Inode, &FileAttr) + Send + Sync>;
+
+impl AttrObserver {
+    pub fn new(callback: impl FnMut(Inode, &FileAttr) + Send + Sync + 'static) -> Self {
+        Self(Some(Box::new(callback)))
+    }
+
+    pub fn notify(&mut self, ino: Inode, attr: &FileAttr) {
+        if let Some(callback) = &mut self.0 {
+            callback(ino, attr);
+        }
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0.is_some()
+    }
+}
+
+impl std::fmt::Debug for AttrObserver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("AttrObserver").field(&self.is_set()).finish()
+    }
+}
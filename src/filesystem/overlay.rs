@@ -0,0 +1,16 @@
+//! the markers overlayfs keeps in its upper layer, so `daniel` can be used as one
+
+use fuser::FileType;
+
+/// set to `y` on a directory that hides everything below it in the lower layers
+pub const OPAQUE_XATTR: &str = "trusted.overlay.opaque";
+
+/// a removed lower layer entry is covered by a character device with device number 0/0
+pub fn is_whiteout(kind: FileType, rdev: u32) -> bool {
+    kind == FileType::CharDevice && rdev == 0
+}
+
+/// whether the value of [`OPAQUE_XATTR`] makes a directory opaque
+pub fn is_opaque(value: &[u8]) -> bool {
+    value == b"y"
+}
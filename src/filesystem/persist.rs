@@ -0,0 +1,455 @@
+use std::{
+    ffi::OsString,
+    io,
+    num::NonZeroU64,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use fuser::FileType;
+
+use super::{DirEntry, Directory, File, Inode};
+
+/// what every snapshot file starts with, files without it are from version 1
+pub const MAGIC: [u8; 8] = *b"DANIELFS";
+
+/// the version [`encode`] writes
+pub const VERSION: u32 = 3;
+
+/// another name of a hard linked file, the file itself is saved as an entry under one of its names
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub ino: Inode,
+    pub parent: Inode,
+    pub name: PathBuf,
+}
+
+/// what [`decode`] reads back, the entries keep the inodes they were saved with
+#[derive(Debug)]
+pub struct Saved {
+    pub entries: Vec<DirEntry>,
+    pub links: Vec<Link>,
+}
+
+/// the magic and version at the start of a snapshot, the crc32 of the payload follows the
+/// payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotHeader {
+    pub version: u32,
+}
+
+impl SnapshotHeader {
+    pub const LEN: usize = MAGIC.len() + 4;
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&MAGIC);
+        put_u32(out, self.version);
+    }
+
+    /// the header at the start of `data` and the rest of it, version 1 snapshots have no header
+    pub fn parse(data: &[u8]) -> (Self, &[u8]) {
+        match data.strip_prefix(&MAGIC) {
+            Some(rest) if rest.len() >= 4 => {
+                let (version, rest) = rest.split_at(4);
+                let version = u32::from_le_bytes(version.try_into().unwrap());
+                (Self { version }, rest)
+            }
+            _ => (Self { version: 1 }, data),
+        }
+    }
+}
+
+/// the tree as saved by [`Daniel::save`](super::Daniel::save), a [`SnapshotHeader`], the payload
+/// and the crc32 of the payload. every number is little endian
+///
+/// the payload is a count followed by that many entries, parents before their children. an entry
+/// is its inode, parent, name, kind, permissions, owner, group, rdev, flags, the four times, the
+/// xattrs, the default child mode of a directory and the contents of anything else. a count of
+/// [`Link`]s follows, each its inode, parent and name, versions before 3 have none
+pub fn encode<'a>(entries: impl ExactSizeIterator<Item = &'a DirEntry>, links: &[Link]) -> Vec<u8> {
+    let mut out = Vec::new();
+    SnapshotHeader { version: VERSION }.encode(&mut out);
+    put_u64(&mut out, entries.len() as u64);
+    for entry in entries {
+        encode_entry(&mut out, entry);
+    }
+    put_u64(&mut out, links.len() as u64);
+    for link in links {
+        put_u64(&mut out, link.ino.into());
+        put_u64(&mut out, link.parent.into());
+        put_bytes(&mut out, link.name.as_os_str().as_bytes());
+    }
+    let checksum = crc32(&out[SnapshotHeader::LEN..]);
+    put_u32(&mut out, checksum);
+
+    out
+}
+
+/// the tree saved by [`encode`]. version 1 snapshots, a bare payload without a header or checksum,
+/// are still read
+pub fn decode(data: &[u8]) -> io::Result<Saved> {
+    let (header, rest) = SnapshotHeader::parse(data);
+    let payload = match header.version {
+        1 => rest,
+        2 | 3 => {
+            let Some(split) = rest.len().checked_sub(4) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "snapshot is too short to hold a checksum",
+                ));
+            };
+            let (payload, checksum) = rest.split_at(split);
+            let checksum = u32::from_le_bytes(checksum.try_into().unwrap());
+            if crc32(payload) != checksum {
+                return Err(invalid(
+                    "snapshot checksum doesn't match, the file is corrupt or truncated",
+                ));
+            }
+            payload
+        }
+        version => {
+            return Err(invalid(format!(
+                "snapshot version {version} isn't supported, versions 1 to {VERSION} are"
+            )));
+        }
+    };
+
+    decode_payload(payload, header.version)
+}
+
+fn decode_payload(data: &[u8], version: u32) -> io::Result<Saved> {
+    let mut reader = Reader { data };
+    let count = reader.u64()?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        entries.push(decode_entry(&mut reader)?);
+    }
+    let mut links = Vec::new();
+    if version >= 3 {
+        for _ in 0..reader.u64()? {
+            links.push(Link {
+                ino: reader.inode()?,
+                parent: reader.inode()?,
+                name: PathBuf::from(OsString::from_vec(reader.bytes()?.to_vec())),
+            });
+        }
+    }
+    if !reader.data.is_empty() {
+        return Err(invalid("trailing data after the last entry"));
+    }
+
+    Ok(Saved { entries, links })
+}
+
+fn encode_entry(out: &mut Vec<u8>, entry: &DirEntry) {
+    let attr = entry.attr().inner();
+    put_u64(out, attr.ino);
+    put_u64(out, entry.parent().into());
+    put_bytes(out, entry.name().as_os_str().as_bytes());
+    out.push(kind_to_u8(attr.kind));
+    put_u32(out, attr.perm as u32);
+    put_u32(out, attr.uid);
+    put_u32(out, attr.gid);
+    put_u32(out, attr.rdev);
+    put_u32(out, attr.flags);
+    for time in [attr.atime, attr.mtime, attr.ctime, attr.crtime] {
+        put_time(out, time);
+    }
+
+    put_u64(out, entry.xattrs().len() as u64);
+    for (name, value) in entry.xattrs() {
+        put_bytes(out, name.as_bytes());
+        put_bytes(out, value);
+    }
+
+    match entry {
+        DirEntry::Directory(dir) => match dir.default_child_mode() {
+            Some(mode) => {
+                out.push(1);
+                put_u32(out, mode as u32);
+            }
+            None => out.push(0),
+        },
+        DirEntry::File(file) => put_bytes(out, &file.data()),
+    }
+}
+
+fn decode_entry(reader: &mut Reader<'_>) -> io::Result<DirEntry> {
+    let ino = reader.inode()?;
+    let parent = reader.inode()?;
+    let name = PathBuf::from(OsString::from_vec(reader.bytes()?.to_vec()));
+    let kind = kind_from_u8(reader.u8()?)?;
+    let perm = reader.u32()? as u16;
+
+    let mut entry = match kind {
+        FileType::Directory => DirEntry::Directory(Directory::new(parent, name, ino, perm)),
+        kind => DirEntry::File(File::node(name, parent, ino, perm, kind, 0)),
+    };
+    let attr = entry.attr_mut().inner_mut();
+    attr.uid = reader.u32()?;
+    attr.gid = reader.u32()?;
+    attr.rdev = reader.u32()?;
+    attr.flags = reader.u32()?;
+    attr.atime = reader.time()?;
+    attr.mtime = reader.time()?;
+    attr.ctime = reader.time()?;
+    attr.crtime = reader.time()?;
+
+    for _ in 0..reader.u64()? {
+        let name = OsString::from_vec(reader.bytes()?.to_vec());
+        let value = reader.bytes()?.to_vec();
+        entry.xattrs_mut().insert(name, value);
+    }
+
+    match &mut entry {
+        DirEntry::Directory(dir) => {
+            if reader.u8()? != 0 {
+                dir.set_default_child_mode(Some(reader.u32()? as u16));
+            }
+        }
+        DirEntry::File(file) => file.share(Arc::new(reader.bytes()?.to_vec())),
+    }
+
+    Ok(entry)
+}
+
+fn kind_to_u8(kind: FileType) -> u8 {
+    match kind {
+        FileType::NamedPipe => 0,
+        FileType::CharDevice => 1,
+        FileType::BlockDevice => 2,
+        FileType::Directory => 3,
+        FileType::RegularFile => 4,
+        FileType::Symlink => 5,
+        FileType::Socket => 6,
+    }
+}
+
+fn kind_from_u8(kind: u8) -> io::Result<FileType> {
+    Ok(match kind {
+        0 => FileType::NamedPipe,
+        1 => FileType::CharDevice,
+        2 => FileType::BlockDevice,
+        3 => FileType::Directory,
+        4 => FileType::RegularFile,
+        5 => FileType::Symlink,
+        6 => FileType::Socket,
+        _ => return Err(invalid(format!("unknown file type {kind}"))),
+    })
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_u64(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// seconds relative to the epoch, negative before it, and the nanoseconds after those
+fn put_time(out: &mut Vec<u8>, time: SystemTime) {
+    let (secs, nanos) = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+        Err(err) => {
+            let before = err.duration();
+            match before.subsec_nanos() {
+                0 => (-(before.as_secs() as i64), 0),
+                nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        }
+    };
+    put_u64(out, secs as u64);
+    put_u32(out, nanos);
+}
+
+/// the crc32 used by zlib and png
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xedb8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+
+    !crc
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "snapshot ends in the middle of an entry",
+            ));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn inode(&mut self) -> io::Result<Inode> {
+        NonZeroU64::new(self.u64()?)
+            .map(Inode::new)
+            .ok_or_else(|| invalid("inode 0"))
+    }
+
+    fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = usize::try_from(self.u64()?).map_err(|_| invalid("length too large"))?;
+        self.take(len)
+    }
+
+    fn time(&mut self) -> io::Result<SystemTime> {
+        let secs = self.u64()? as i64;
+        let nanos = self.u32()?;
+        if nanos >= 1_000_000_000 {
+            return Err(invalid("nanoseconds out of range"));
+        }
+
+        let time = match secs {
+            0.. => UNIX_EPOCH.checked_add(Duration::new(secs as u64, nanos)),
+            _ => UNIX_EPOCH
+                .checked_sub(Duration::from_secs(secs.unsigned_abs()))
+                .and_then(|time| time.checked_add(Duration::from_nanos(nanos as u64))),
+        };
+        time.ok_or_else(|| invalid("time out of range"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, num::NonZeroU64, path::Path};
+
+    use super::{Link, MAGIC, SnapshotHeader, VERSION, crc32, decode, encode};
+    use crate::filesystem::{DirEntry, Directory, File, Inode};
+
+    fn inode(ino: u64) -> Inode {
+        Inode::new(NonZeroU64::new(ino).unwrap())
+    }
+
+    fn tree() -> Vec<DirEntry> {
+        let root = Directory::new(inode(1), "/".into(), inode(1), 0o755);
+        let mut file = File::new("foo".into(), inode(1), inode(2), 0o644);
+        file.write(0, b"hello");
+        vec![DirEntry::Directory(root), DirEntry::File(file)]
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = encode(tree().iter(), &[]);
+        assert!(data.starts_with(&MAGIC));
+        assert_eq!(
+            SnapshotHeader::parse(&data).0,
+            SnapshotHeader { version: VERSION }
+        );
+
+        let saved = decode(&data).unwrap();
+        assert_eq!(saved.entries.len(), 2);
+        assert_eq!(saved.entries[1].name(), Path::new("foo"));
+        assert_eq!(&*saved.entries[1].as_file().unwrap().data(), b"hello");
+        assert!(saved.links.is_empty());
+
+        let links = [Link {
+            ino: inode(2),
+            parent: inode(1),
+            name: "bar".into(),
+        }];
+        let saved = decode(&encode(tree().iter(), &links)).unwrap();
+        assert_eq!(saved.entries.len(), 2);
+        assert_eq!(saved.links, links);
+    }
+
+    #[test]
+    fn version_1() {
+        let data = encode(tree().iter(), &[]);
+        // without the count of links version 3 added
+        let payload = &data[SnapshotHeader::LEN..data.len() - 12];
+        let entries = decode(payload).unwrap().entries;
+        assert_eq!(&*entries[1].as_file().unwrap().data(), b"hello");
+    }
+
+    #[test]
+    fn version_2() {
+        let data = encode(tree().iter(), &[]);
+        let mut old = Vec::new();
+        SnapshotHeader { version: 2 }.encode(&mut old);
+        let payload = &data[SnapshotHeader::LEN..data.len() - 12];
+        old.extend_from_slice(payload);
+        old.extend_from_slice(&crc32(payload).to_le_bytes());
+
+        let saved = decode(&old).unwrap();
+        assert_eq!(&*saved.entries[1].as_file().unwrap().data(), b"hello");
+        assert!(saved.links.is_empty());
+    }
+
+    #[test]
+    fn truncated() {
+        let data = encode(tree().iter(), &[]);
+        for len in [data.len() - 1, data.len() / 2, SnapshotHeader::LEN + 2, 3] {
+            assert!(decode(&data[..len]).is_err(), "{len} bytes decoded");
+        }
+    }
+
+    #[test]
+    fn tampered() {
+        let mut data = encode(tree().iter(), &[]);
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert_eq!(
+            decode(&data).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        // a flipped bit in the payload is caught too
+        let mut data = encode(tree().iter(), &[]);
+        data[SnapshotHeader::LEN + 9] ^= 1;
+        assert_eq!(
+            decode(&data).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn unknown_version() {
+        let mut data = encode(tree().iter(), &[]);
+        data[MAGIC.len()..SnapshotHeader::LEN].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let err = decode(&data).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("version 4"), "{err}");
+    }
+}
@@ -0,0 +1,583 @@
+use std::{
+    ffi::c_int,
+    path::Path,
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use tracing::info;
+
+use crate::unchecked_inode;
+
+use super::{Daniel, Errno, FileAttribute};
+
+/// a [`Daniel`] behind a lock so it can be reached while it's mounted, eg. by the control
+/// socket. `lookup` and `getattr` only take a read lock so they run alongside each other, every
+/// other request takes the write lock for as long as it takes
+#[derive(Debug, Clone)]
+pub struct SharedDaniel(Arc<RwLock<Daniel>>);
+
+impl SharedDaniel {
+    pub fn new(fs: Daniel) -> Self {
+        Self(Arc::new(RwLock::new(fs)))
+    }
+
+    /// the filesystem itself, requests from the kernel wait while it's write locked
+    pub fn handle(&self) -> Arc<RwLock<Daniel>> {
+        Arc::clone(&self.0)
+    }
+
+    /// like [`Daniel::spawn`], along with the sweeps of [`Daniel::with_gc_interval`]
+    pub fn spawn(
+        self,
+        mountpoint: impl AsRef<Path>,
+        options: &[fuser::MountOption],
+    ) -> std::io::Result<fuser::BackgroundSession> {
+        let (notifier, gc_interval) = {
+            let fs = self.read_lock();
+            (fs.notifier_slot(), fs.gc_interval())
+        };
+        let fs = Arc::downgrade(&self.0);
+        let session = fuser::spawn_mount2(self, mountpoint, options)?;
+        notifier.set(session.notifier());
+        if let Some(interval) = gc_interval {
+            spawn_gc(fs, interval);
+        }
+        Ok(session)
+    }
+
+    /// [`Daniel::try_lookup`] under the read lock, along with how long the kernel may cache the
+    /// entry and its generation. `None` when it needs the write lock
+    fn try_lookup(
+        &self,
+        parent: u64,
+        name: &std::ffi::OsStr,
+    ) -> Option<Result<(FileAttribute, Duration, u32), Errno>> {
+        let fs = self.read_lock();
+        let attr = match fs.try_lookup(parent, name)? {
+            Ok(attr) => attr,
+            Err(err) => return Some(Err(err)),
+        };
+        let ino = attr.inner().ino;
+        // counted before the read lock is dropped so a forget can't free it in between
+        fs.remember(ino);
+        Some(Ok((
+            attr,
+            fs.ttl(ino),
+            fs.generation(unchecked_inode!(ino)),
+        )))
+    }
+
+    /// [`Daniel::try_getattr`] under the read lock, along with how long the kernel may cache it
+    fn try_getattr(&self, ino: u64) -> Option<Result<(FileAttribute, Duration), Errno>> {
+        let fs = self.read_lock();
+        Some(fs.try_getattr(ino)?.map(|attr| (attr, fs.ttl(ino))))
+    }
+
+    /// a panic in one request doesn't take the filesystem down with it
+    fn read_lock(&self) -> RwLockReadGuard<'_, Daniel> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_lock(&self) -> RwLockWriteGuard<'_, Daniel> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// runs [`Daniel::sweep`] every `interval` under the write lock, it only holds on to `fs` while
+/// sweeping and stops once everything else has let go of it
+fn spawn_gc(fs: Weak<RwLock<Daniel>>, interval: Duration) -> JoinHandle<()> {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+            let Some(fs) = fs.upgrade() else {
+                info!("filesystem dropped, stopping the sweeps");
+                return;
+            };
+            fs.write().unwrap_or_else(PoisonError::into_inner).sweep();
+        }
+    })
+}
+
+/// implements each listed [`fuser::Filesystem`] method by write locking the filesystem and calling
+/// the one on [`Daniel`]
+macro_rules! delegate {
+    ($(fn $name:ident(&mut self $(, $arg:ident: $ty:ty)* $(,)?) $(-> $ret:ty)?;)*) => {
+        $(
+            fn $name(&mut self $(, $arg: $ty)*) $(-> $ret)? {
+                fuser::Filesystem::$name(&mut *self.write_lock() $(, $arg)*)
+            }
+        )*
+    };
+}
+
+impl fuser::Filesystem for SharedDaniel {
+    fn lookup(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        reply: fuser::ReplyEntry,
+    ) {
+        match self.try_lookup(parent, name) {
+            Some(Ok((attr, ttl, generation))) => {
+                reply.entry(&ttl, &attr.inner(), generation as u64);
+            }
+            Some(Err(err)) => reply.error(err.as_i32()),
+            None => {
+                fuser::Filesystem::lookup(&mut *self.write_lock(), req, parent, name, reply);
+            }
+        }
+    }
+
+    fn getattr(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        fh: Option<u64>,
+        reply: fuser::ReplyAttr,
+    ) {
+        match self.try_getattr(ino) {
+            Some(Ok((attr, ttl))) => reply.attr(&ttl, &attr.inner()),
+            Some(Err(err)) => reply.error(err.as_i32()),
+            None => {
+                fuser::Filesystem::getattr(&mut *self.write_lock(), req, ino, fh, reply);
+            }
+        }
+    }
+
+    delegate! {
+        fn create(
+            &mut self,
+            req: &fuser::Request<'_>,
+            parent: u64,
+            name: &std::ffi::OsStr,
+            mode: u32,
+            umask: u32,
+            flags: i32,
+            reply: fuser::ReplyCreate,
+        );
+        fn mkdir(
+            &mut self,
+            req: &fuser::Request<'_>,
+            parent: u64,
+            name: &std::ffi::OsStr,
+            mode: u32,
+            umask: u32,
+            reply: fuser::ReplyEntry,
+        );
+        fn readdir(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            fh: u64,
+            offset: i64,
+            reply: fuser::ReplyDirectory,
+        );
+        fn access(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            mask: i32,
+            reply: fuser::ReplyEmpty,
+        );
+        fn unlink(
+            &mut self,
+            req: &fuser::Request<'_>,
+            parent: u64,
+            name: &std::ffi::OsStr,
+            reply: fuser::ReplyEmpty,
+        );
+        fn init(
+            &mut self,
+            req: &fuser::Request<'_>,
+            config: &mut fuser::KernelConfig,
+        ) -> Result<(), c_int>;
+        fn destroy(&mut self);
+        fn forget(&mut self, req: &fuser::Request<'_>, ino: u64, nlookup: u64);
+        fn batch_forget(&mut self, req: &fuser::Request<'_>, nodes: &[fuser::fuse_forget_one]);
+        fn setattr(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            mode: Option<u32>,
+            uid: Option<u32>,
+            gid: Option<u32>,
+            size: Option<u64>,
+            atime: Option<fuser::TimeOrNow>,
+            mtime: Option<fuser::TimeOrNow>,
+            ctime: Option<std::time::SystemTime>,
+            fh: Option<u64>,
+            crtime: Option<std::time::SystemTime>,
+            chgtime: Option<std::time::SystemTime>,
+            bkuptime: Option<std::time::SystemTime>,
+            flags: Option<u32>,
+            reply: fuser::ReplyAttr,
+        );
+        fn readlink(&mut self, req: &fuser::Request<'_>, ino: u64, reply: fuser::ReplyData);
+        fn mknod(
+            &mut self,
+            req: &fuser::Request<'_>,
+            parent: u64,
+            name: &std::ffi::OsStr,
+            mode: u32,
+            umask: u32,
+            rdev: u32,
+            reply: fuser::ReplyEntry,
+        );
+        fn rmdir(
+            &mut self,
+            req: &fuser::Request<'_>,
+            parent: u64,
+            name: &std::ffi::OsStr,
+            reply: fuser::ReplyEmpty,
+        );
+        fn symlink(
+            &mut self,
+            req: &fuser::Request<'_>,
+            parent: u64,
+            link_name: &std::ffi::OsStr,
+            target: &Path,
+            reply: fuser::ReplyEntry,
+        );
+        fn rename(
+            &mut self,
+            req: &fuser::Request<'_>,
+            parent: u64,
+            name: &std::ffi::OsStr,
+            newparent: u64,
+            newname: &std::ffi::OsStr,
+            flags: u32,
+            reply: fuser::ReplyEmpty,
+        );
+        fn link(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            newparent: u64,
+            newname: &std::ffi::OsStr,
+            reply: fuser::ReplyEntry,
+        );
+        fn open(&mut self, req: &fuser::Request<'_>, ino: u64, flags: i32, reply: fuser::ReplyOpen);
+        fn read(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            fh: u64,
+            offset: i64,
+            size: u32,
+            flags: i32,
+            lock_owner: Option<u64>,
+            reply: fuser::ReplyData,
+        );
+        fn write(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            fh: u64,
+            offset: i64,
+            data: &[u8],
+            write_flags: u32,
+            flags: i32,
+            lock_owner: Option<u64>,
+            reply: fuser::ReplyWrite,
+        );
+        fn poll(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            fh: u64,
+            ph: fuser::PollHandle,
+            events: u32,
+            flags: u32,
+            reply: fuser::ReplyPoll,
+        );
+        fn flush(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            fh: u64,
+            lock_owner: u64,
+            reply: fuser::ReplyEmpty,
+        );
+        fn release(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            fh: u64,
+            flags: i32,
+            lock_owner: Option<u64>,
+            flush: bool,
+            reply: fuser::ReplyEmpty,
+        );
+        fn fsync(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            fh: u64,
+            datasync: bool,
+            reply: fuser::ReplyEmpty,
+        );
+        fn opendir(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            flags: i32,
+            reply: fuser::ReplyOpen,
+        );
+        fn readdirplus(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            fh: u64,
+            offset: i64,
+            reply: fuser::ReplyDirectoryPlus,
+        );
+        fn releasedir(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            fh: u64,
+            flags: i32,
+            reply: fuser::ReplyEmpty,
+        );
+        fn fsyncdir(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            fh: u64,
+            datasync: bool,
+            reply: fuser::ReplyEmpty,
+        );
+        fn statfs(&mut self, req: &fuser::Request<'_>, ino: u64, reply: fuser::ReplyStatfs);
+        fn setxattr(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            name: &std::ffi::OsStr,
+            value: &[u8],
+            flags: i32,
+            position: u32,
+            reply: fuser::ReplyEmpty,
+        );
+        fn getxattr(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            name: &std::ffi::OsStr,
+            size: u32,
+            reply: fuser::ReplyXattr,
+        );
+        fn listxattr(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            size: u32,
+            reply: fuser::ReplyXattr,
+        );
+        fn removexattr(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            name: &std::ffi::OsStr,
+            reply: fuser::ReplyEmpty,
+        );
+        fn getlk(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            fh: u64,
+            lock_owner: u64,
+            start: u64,
+            end: u64,
+            typ: i32,
+            pid: u32,
+            reply: fuser::ReplyLock,
+        );
+        fn setlk(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            fh: u64,
+            lock_owner: u64,
+            start: u64,
+            end: u64,
+            typ: i32,
+            pid: u32,
+            sleep: bool,
+            reply: fuser::ReplyEmpty,
+        );
+        fn bmap(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            blocksize: u32,
+            idx: u64,
+            reply: fuser::ReplyBmap,
+        );
+        fn ioctl(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            fh: u64,
+            flags: u32,
+            cmd: u32,
+            in_data: &[u8],
+            out_size: u32,
+            reply: fuser::ReplyIoctl,
+        );
+        fn fallocate(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            fh: u64,
+            offset: i64,
+            length: i64,
+            mode: i32,
+            reply: fuser::ReplyEmpty,
+        );
+        fn lseek(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino: u64,
+            fh: u64,
+            offset: i64,
+            whence: i32,
+            reply: fuser::ReplyLseek,
+        );
+        fn copy_file_range(
+            &mut self,
+            req: &fuser::Request<'_>,
+            ino_in: u64,
+            fh_in: u64,
+            offset_in: i64,
+            ino_out: u64,
+            fh_out: u64,
+            offset_out: i64,
+            len: u64,
+            flags: u32,
+            reply: fuser::ReplyWrite,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        path::Path,
+        sync::{Arc, Barrier},
+        thread,
+        time::Duration,
+    };
+
+    use fuser::FileType;
+
+    use super::SharedDaniel;
+    use crate::filesystem::{Daniel, Errno, ROOT_INODE};
+
+    #[test]
+    fn concurrent_lookups() {
+        const THREADS: usize = 8;
+        const ROUNDS: usize = 50;
+
+        let mut fs = Daniel::new();
+        let files: Vec<(String, u64)> = (0..16)
+            .map(|i| {
+                let ino = fs
+                    .create_path(Path::new(&format!("/dir{i}/file")), 0o644)
+                    .unwrap();
+                (format!("dir{i}"), ino.into())
+            })
+            .collect();
+        let fs = SharedDaniel::new(fs).handle();
+
+        // every reader holds the read lock at the barrier at once, exclusive locking would hang here
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let readers: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let fs = Arc::clone(&fs);
+                let barrier = Arc::clone(&barrier);
+                let files = files.clone();
+                thread::spawn(move || {
+                    let shared = fs.read().unwrap();
+                    barrier.wait();
+                    drop(shared);
+
+                    for _ in 0..ROUNDS {
+                        let fs = fs.read().unwrap();
+                        for (dir, file) in &files {
+                            let dir = fs.try_lookup(ROOT_INODE.into(), dir.as_ref());
+                            let dir = dir.unwrap().unwrap().inner();
+                            assert_eq!(dir.kind, FileType::Directory);
+
+                            let attr = fs.try_lookup(dir.ino, "file".as_ref());
+                            assert_eq!(attr.unwrap().unwrap().inner().ino, *file);
+                            fs.remember(*file);
+                            let attr = fs.try_getattr(*file).unwrap().unwrap();
+                            assert_eq!(attr.inner().kind, FileType::RegularFile);
+                        }
+                    }
+                })
+            })
+            .collect();
+        // mutations interleave with the readers
+        let writer = thread::spawn({
+            let fs = Arc::clone(&fs);
+            move || {
+                for i in 0..ROUNDS {
+                    let path = format!("/new/file{i}");
+                    fs.write()
+                        .unwrap()
+                        .create_path(Path::new(&path), 0o644)
+                        .unwrap();
+                }
+            }
+        });
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        writer.join().unwrap();
+
+        let mut fs = fs.write().unwrap();
+        let lookups = THREADS * ROUNDS * files.len() * 2;
+        assert_eq!(fs.status().ops.lookups, lookups as u64);
+        assert_eq!(
+            fs.list_dir(fs.resolve_following_symlinks("/new", 0).unwrap())
+                .unwrap()
+                .len(),
+            ROUNDS
+        );
+
+        // every reference the readers counted has to be forgotten before a removed file goes
+        let (dir, file) = &files[0];
+        let dir = fs
+            .try_lookup(ROOT_INODE.into(), dir.as_ref())
+            .unwrap()
+            .unwrap();
+        fs.unlink(dir.inner().ino, "file".as_ref(), 0).unwrap();
+        fs.forget(*file, (THREADS * ROUNDS - 1) as u64);
+        assert!(fs.try_getattr(*file).unwrap().is_ok());
+        fs.forget(*file, 1);
+        assert_eq!(fs.try_getattr(*file).unwrap().unwrap_err(), Errno::ENOENT);
+    }
+
+    #[test]
+    fn fast_path_ttl() {
+        let minute = Duration::from_secs(60);
+        let mut fs = Daniel::new();
+        let foo = fs.create_path(Path::new("/dir/foo"), 0o644).unwrap();
+        let dir = fs.resolve_following_symlinks("/dir", 0).unwrap().into();
+        fs.set_ttl(dir, Some(minute)).unwrap();
+        let fs = SharedDaniel::new(fs);
+
+        // the read locked paths hand out the same ttl the write locked ones would
+        let (attr, ttl, _) = fs.try_lookup(dir, "foo".as_ref()).unwrap().unwrap();
+        assert_eq!((attr.inner().ino, ttl), (u64::from(foo), minute));
+        let (_, ttl) = fs.try_getattr(foo.into()).unwrap().unwrap();
+        assert_eq!(ttl, minute);
+        let (_, ttl) = fs.try_getattr(ROOT_INODE.into()).unwrap().unwrap();
+        assert_eq!(ttl, fs.read_lock().ttl(ROOT_INODE.into()));
+        assert_ne!(ttl, minute);
+    }
+}
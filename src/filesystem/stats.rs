@@ -0,0 +1,318 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use super::{Inode, Storage};
+
+/// the block size `statfs` reports in, the same 512 byte blocks `st_blocks` counts
+pub const BLOCK_SIZE: u32 = 512;
+/// the capacity reported without a byte quota, a PiB
+pub const UNLIMITED_BYTES: u64 = 1 << 50;
+/// the inode count reported without an inode limit
+pub const UNLIMITED_INODES: u64 = 1 << 32;
+/// the longest name an entry may have, in bytes
+pub const NAME_MAX: u32 = 255;
+
+/// how many times each operation has been served since mount
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OpCounters {
+    pub reads: u64,
+    pub writes: u64,
+    pub lookups: u64,
+}
+
+impl OpCounters {
+    /// the name of each counter, as the status file has them
+    pub const NAMES: [&str; 3] = ["reads", "writes", "lookups"];
+
+    pub fn get(&self, name: &str) -> Option<u64> {
+        match name {
+            "reads" => Some(self.reads),
+            "writes" => Some(self.writes),
+            "lookups" => Some(self.lookups),
+            _ => None,
+        }
+    }
+}
+
+/// [`OpCounters`] that can be bumped through a shared reference, so requests served under a read
+/// lock are counted too
+#[derive(Debug, Default)]
+pub struct SharedOpCounters {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    lookups: AtomicU64,
+}
+
+impl SharedOpCounters {
+    pub fn read(&self) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn write(&self) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn lookup(&self) {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn load(&self) -> OpCounters {
+        OpCounters {
+            reads: self.reads.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            lookups: self.lookups.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// the upper bounds of the latency buckets, anything slower lands in one last bucket of its own
+pub const LATENCY_BUCKETS: [Duration; 6] = [
+    Duration::from_micros(10),
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+];
+
+/// the operations [`Metrics`] times
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Op {
+    Lookup,
+    Getattr,
+    Read,
+    Write,
+    Create,
+    Unlink,
+    Rename,
+    Setattr,
+}
+
+impl Op {
+    pub const ALL: [Op; 8] = [
+        Op::Lookup,
+        Op::Getattr,
+        Op::Read,
+        Op::Write,
+        Op::Create,
+        Op::Unlink,
+        Op::Rename,
+        Op::Setattr,
+    ];
+}
+
+/// how many times an operation took as long as each of [`LATENCY_BUCKETS`], the last count is
+/// for the ones slower than all of them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Histogram {
+    counts: [u64; LATENCY_BUCKETS.len() + 1],
+}
+
+impl Histogram {
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// how many times the operation was timed
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// the upper bound of the bucket the `p`th percentile falls in, `p` being in `0.0..=1.0`.
+    /// `None` if nothing was timed or it's slower than the last bound
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let rank = (p.clamp(0.0, 1.0) * self.count() as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return LATENCY_BUCKETS.get(bucket).copied();
+            }
+        }
+
+        None
+    }
+}
+
+/// a [`Histogram`] of each [`Op`], recorded through a shared reference like [`SharedOpCounters`]
+#[derive(Debug, Default)]
+pub struct Metrics {
+    latencies: [[AtomicU64; LATENCY_BUCKETS.len() + 1]; Op::ALL.len()],
+}
+
+impl Metrics {
+    pub fn record(&self, op: Op, took: Duration) {
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| took <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.latencies[op as usize][bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn histogram(&self, op: Op) -> Histogram {
+        Histogram {
+            counts: self.latencies[op as usize]
+                .each_ref()
+                .map(|count| count.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// the live contents of the status file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status {
+    pub inodes: usize,
+    pub used_bytes: u64,
+    pub uptime_secs: u64,
+    pub ops: OpCounters,
+}
+
+impl Status {
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"inodes\":{},\"used_bytes\":{},\"uptime_secs\":{},\"ops\":{{\"reads\":{},\"writes\":{},\"lookups\":{}}}}}\n",
+            self.inodes,
+            self.used_bytes,
+            self.uptime_secs,
+            self.ops.reads,
+            self.ops.writes,
+            self.ops.lookups
+        )
+    }
+}
+
+/// the bytes file contents take up and fallocate reserved past the end of files, kept up to date
+/// as they change. a hard linked file is one inode so it's counted once, contents a reflink shares
+/// between files are counted once and the holes of sparse files aren't counted at all
+#[derive(Debug, Default)]
+pub struct SpaceUsage {
+    total: u64,
+    /// how many counted files share each `Arc` of contents, by its address, and the bytes it held
+    /// when it was first counted
+    shared: HashMap<usize, (u64, u64)>,
+    /// the offset fallocate reserved space up to for each file
+    reserved: HashMap<Inode, u64>,
+}
+
+impl SpaceUsage {
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// whether `storage` shares its contents with another counted file
+    pub fn is_shared(&self, storage: &dyn Storage) -> bool {
+        address(storage)
+            .and_then(|addr| self.shared.get(&addr))
+            .is_some_and(|(files, _)| *files > 1)
+    }
+
+    /// starts counting `storage` as the contents of `ino`, along with what's reserved past its end
+    pub fn count(&mut self, ino: Inode, storage: &dyn Storage) {
+        self.total += self.reserved_past(ino, storage.len());
+        let Some(addr) = address(storage) else {
+            self.total += storage.stored_len();
+            return;
+        };
+
+        let (files, len) = self.shared.entry(addr).or_insert((0, storage.stored_len()));
+        if *files == 0 {
+            self.total += *len;
+        }
+        *files += 1;
+    }
+
+    /// stops counting `storage` as the contents of `ino`, it has to be what was counted
+    pub fn uncount(&mut self, ino: Inode, storage: &dyn Storage) {
+        self.total = self
+            .total
+            .saturating_sub(self.reserved_past(ino, storage.len()));
+        let Some(addr) = address(storage) else {
+            self.total = self.total.saturating_sub(storage.stored_len());
+            return;
+        };
+
+        let Some((files, len)) = self.shared.get_mut(&addr) else {
+            return;
+        };
+        *files -= 1;
+        if *files == 0 {
+            self.total = self.total.saturating_sub(*len);
+            self.shared.remove(&addr);
+        }
+    }
+
+    /// reserves the space up to `end` for `ino`, which is `len` bytes long
+    pub fn reserve(&mut self, ino: Inode, len: u64, end: u64) {
+        let before = self.reserved_past(ino, len);
+        let reserved = self.reserved.entry(ino).or_default();
+        *reserved = (*reserved).max(end);
+        self.total += self.reserved_past(ino, len) - before;
+    }
+
+    /// drops what's reserved for `ino`, which is `len` bytes long
+    pub fn unreserve(&mut self, ino: Inode, len: u64) {
+        self.total = self.total.saturating_sub(self.reserved_past(ino, len));
+        self.reserved.remove(&ino);
+    }
+
+    /// how much of what's reserved for `ino` lies past its end at `len`
+    pub fn reserved_past(&self, ino: Inode, len: u64) -> u64 {
+        self.reserved
+            .get(&ino)
+            .map_or(0, |end| end.saturating_sub(len))
+    }
+
+    pub fn remap(&mut self, renumbered: &HashMap<Inode, Inode>) {
+        self.reserved = std::mem::take(&mut self.reserved)
+            .into_iter()
+            .filter_map(|(ino, end)| Some((*renumbered.get(&ino)?, end)))
+            .collect();
+    }
+}
+
+/// the address of the `Arc` `storage` keeps its contents in, if it does
+fn address(storage: &dyn Storage) -> Option<usize> {
+    storage
+        .shared()
+        .map(|data| Arc::as_ptr(&data) as *const u8 as usize)
+}
+
+/// what `statfs` replies with, in [`BLOCK_SIZE`] blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatFs {
+    pub blocks: u64,
+    pub bfree: u64,
+    pub bavail: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub bsize: u32,
+    pub namelen: u32,
+    pub frsize: u32,
+}
+
+impl StatFs {
+    /// the capacity left under `max_bytes` and `max_inodes` given what `status` has in use, a
+    /// missing limit is reported as a large synthetic one
+    pub fn new(status: &Status, max_bytes: Option<u64>, max_inodes: Option<u64>) -> Self {
+        let max_bytes = max_bytes.unwrap_or(UNLIMITED_BYTES);
+        let files = max_inodes.unwrap_or(UNLIMITED_INODES);
+        let bfree = max_bytes.saturating_sub(status.used_bytes) / BLOCK_SIZE as u64;
+
+        Self {
+            blocks: max_bytes / BLOCK_SIZE as u64,
+            bfree,
+            bavail: bfree,
+            files,
+            ffree: files.saturating_sub(status.inodes as u64),
+            bsize: BLOCK_SIZE,
+            namelen: NAME_MAX,
+            frsize: BLOCK_SIZE,
+        }
+    }
+}
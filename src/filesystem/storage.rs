@@ -0,0 +1,622 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, TryReserveError},
+    fmt, fs, io,
+    os::fd::AsRawFd,
+    path::Path,
+    ptr, slice,
+    sync::Arc,
+};
+
+/// where a file keeps its bytes, `File` handles the metadata and leaves the contents to this
+pub trait Storage: fmt::Debug + Send + Sync {
+    /// at most `len` bytes starting at `offset`, never reading past the end
+    fn read(&self, offset: u64, len: usize) -> Cow<'_, [u8]>;
+    /// writes `data` at `offset`, zero filling any gap past the current end
+    fn write(&mut self, offset: u64, data: &[u8]) -> usize;
+    fn truncate(&mut self, len: u64);
+    fn len(&self) -> u64;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// how many bytes the contents actually take up, which is what `blocks` reports
+    fn stored_len(&self) -> u64 {
+        self.len()
+    }
+
+    /// at most how many more bytes the contents take up once `offset..end` is written, zero
+    /// filling any gap before it. overwriting in place takes nothing more
+    fn growth(&self, _offset: u64, end: u64) -> u64 {
+        end.saturating_sub(self.len())
+    }
+
+    /// a copy of the storage, implementations should share the contents where they can
+    fn box_clone(&self) -> Box<dyn Storage>;
+
+    /// the contents behind an `Arc` if that's how they're kept, so copies can share them
+    fn shared(&self) -> Option<Arc<Vec<u8>>> {
+        None
+    }
+
+    /// writes and truncates are refused with `EROFS` instead of reaching the storage
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// holes past the end are kept as holes instead of being filled with zeros
+    fn sparse(&self) -> bool {
+        false
+    }
+
+    /// gives capacity the contents don't use back to the allocator
+    fn shrink(&mut self) {}
+
+    /// allocates what growing the contents to `len` bytes takes ahead of the write or truncate
+    /// that does it, so running out of memory is an error instead of an abort
+    fn try_reserve(&mut self, _len: usize) -> Result<(), TryReserveError> {
+        Ok(())
+    }
+}
+
+impl Clone for Box<dyn Storage> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// contents kept in memory, shared copy-on-write between files with identical contents
+#[derive(Debug, Clone, Default)]
+pub struct Memory(Arc<Vec<u8>>);
+
+impl Memory {
+    pub fn new(data: Arc<Vec<u8>>) -> Self {
+        Self(data)
+    }
+}
+
+impl Storage for Memory {
+    fn read(&self, offset: u64, len: usize) -> Cow<'_, [u8]> {
+        let start = (offset as usize).min(self.0.len());
+        let end = start.saturating_add(len).min(self.0.len());
+        Cow::Borrowed(&self.0[start..end])
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) -> usize {
+        let offset = offset as usize;
+        let end = offset + data.len();
+        let buf = Arc::make_mut(&mut self.0);
+        if end > buf.len() {
+            buf.resize(end, 0);
+        }
+        buf[offset..end].copy_from_slice(data);
+
+        data.len()
+    }
+
+    fn truncate(&mut self, len: u64) {
+        let len = len as usize;
+        // shared contents are only copied as far as they're kept, emptying them copies nothing
+        if Arc::get_mut(&mut self.0).is_none() && len <= self.0.len() {
+            self.0 = Arc::new(self.0[..len].to_vec());
+            return;
+        }
+
+        Arc::make_mut(&mut self.0).resize(len, 0);
+    }
+
+    fn len(&self) -> u64 {
+        self.0.len() as u64
+    }
+
+    fn box_clone(&self) -> Box<dyn Storage> {
+        Box::new(self.clone())
+    }
+
+    fn shared(&self) -> Option<Arc<Vec<u8>>> {
+        Some(Arc::clone(&self.0))
+    }
+
+    /// shared contents are left alone, shrinking them would mean copying them
+    fn shrink(&mut self) {
+        if let Some(data) = Arc::get_mut(&mut self.0) {
+            data.shrink_to_fit();
+        }
+    }
+
+    /// shared contents are copied here rather than by the write, which can't fail
+    fn try_reserve(&mut self, len: usize) -> Result<(), TryReserveError> {
+        if let Some(data) = Arc::get_mut(&mut self.0) {
+            return data.try_reserve(len.saturating_sub(data.len()));
+        }
+
+        let mut data = Vec::new();
+        data.try_reserve_exact(len.max(self.0.len()))?;
+        data.extend_from_slice(&self.0);
+        self.0 = Arc::new(data);
+        Ok(())
+    }
+}
+
+/// chunks smaller than this are stored as is, compressing them doesn't pay
+pub const MIN_COMPRESS: usize = 4096;
+
+/// [`Compressed`] packs its contents in chunks of this many bytes, so a write only repacks the
+/// chunks it touches instead of the whole file
+pub const COMPRESS_CHUNK: usize = 64 * 1024;
+
+/// one chunk of [`Compressed`] contents, packed if that made it smaller
+#[derive(Debug, Clone, Default)]
+struct Chunk {
+    data: Vec<u8>,
+    packed: bool,
+}
+
+impl Chunk {
+    fn new(contents: Vec<u8>) -> Self {
+        if contents.len() >= MIN_COMPRESS {
+            let packed = pack(&contents);
+            if packed.len() < contents.len() {
+                return Self {
+                    data: packed,
+                    packed: true,
+                };
+            }
+        }
+
+        Self {
+            data: contents,
+            packed: false,
+        }
+    }
+
+    fn contents(&self) -> Cow<'_, [u8]> {
+        match self.packed {
+            true => Cow::Owned(unpack(&self.data)),
+            false => Cow::Borrowed(&self.data),
+        }
+    }
+
+    /// unpacks the chunk, lets `f` change it and packs it again
+    fn update(&mut self, f: impl FnOnce(&mut Vec<u8>)) {
+        let mut contents = self.contents().into_owned();
+        f(&mut contents);
+        *self = Self::new(contents);
+    }
+}
+
+/// contents compressed with packbits run length encoding in chunks of [`COMPRESS_CHUNK`] bytes,
+/// every access unpacks only the chunks it touches. every chunk but the last is full
+#[derive(Debug, Clone, Default)]
+pub struct Compressed {
+    chunks: Vec<Chunk>,
+    len: u64,
+}
+
+impl Compressed {
+    /// grows or shrinks the contents to `len` bytes, growing fills with zeros
+    fn resize(&mut self, len: u64) {
+        let len = len as usize;
+        let old = self.len as usize;
+        if len < old {
+            self.chunks.truncate(len.div_ceil(COMPRESS_CHUNK));
+            let tail = len % COMPRESS_CHUNK;
+            if let Some(last) = self.chunks.last_mut().filter(|_| tail != 0) {
+                last.update(|contents| contents.truncate(tail));
+            }
+        } else if len > old {
+            let tail = old % COMPRESS_CHUNK;
+            if let Some(last) = self.chunks.last_mut().filter(|_| tail != 0) {
+                let grown = len.min(old - tail + COMPRESS_CHUNK) - (old - tail);
+                last.update(|contents| contents.resize(grown, 0));
+            }
+            let mut at = self.chunks.len() * COMPRESS_CHUNK;
+            while at < len {
+                let size = (len - at).min(COMPRESS_CHUNK);
+                self.chunks.push(Chunk::new(vec![0; size]));
+                at += size;
+            }
+        }
+
+        self.len = len as u64;
+    }
+}
+
+impl Storage for Compressed {
+    fn read(&self, offset: u64, len: usize) -> Cow<'_, [u8]> {
+        let start = offset.min(self.len) as usize;
+        let end = start.saturating_add(len).min(self.len as usize);
+        if start == end {
+            return Cow::Borrowed(&[]);
+        }
+
+        let first = start / COMPRESS_CHUNK;
+        let last = (end - 1) / COMPRESS_CHUNK;
+        let range = |i: usize| {
+            let base = i * COMPRESS_CHUNK;
+            start.max(base) - base..end.min(base + COMPRESS_CHUNK) - base
+        };
+        if first == last {
+            return match self.chunks[first].contents() {
+                Cow::Borrowed(data) => Cow::Borrowed(&data[range(first)]),
+                Cow::Owned(data) => Cow::Owned(data[range(first)].to_vec()),
+            };
+        }
+
+        let mut out = Vec::with_capacity(end - start);
+        for i in first..=last {
+            out.extend_from_slice(&self.chunks[i].contents()[range(i)]);
+        }
+
+        Cow::Owned(out)
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) -> usize {
+        let start = offset as usize;
+        let end = start + data.len();
+        if end as u64 > self.len {
+            self.resize(end as u64);
+        }
+
+        let mut at = start;
+        while at < end {
+            let i = at / COMPRESS_CHUNK;
+            let within = at - i * COMPRESS_CHUNK;
+            let n = (end - at).min(COMPRESS_CHUNK - within);
+            let src = &data[at - start..at - start + n];
+            self.chunks[i].update(|contents| contents[within..within + n].copy_from_slice(src));
+            at += n;
+        }
+
+        data.len()
+    }
+
+    fn truncate(&mut self, len: u64) {
+        self.resize(len);
+    }
+
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn stored_len(&self) -> u64 {
+        self.chunks
+            .iter()
+            .map(|chunk| chunk.data.len() as u64)
+            .sum()
+    }
+
+    /// the overwritten bytes may not pack as well as what they replace
+    fn growth(&self, offset: u64, end: u64) -> u64 {
+        end.saturating_sub(self.len) + end.min(self.len) - offset.min(self.len)
+    }
+
+    fn box_clone(&self) -> Box<dyn Storage> {
+        Box::new(self.clone())
+    }
+
+    fn shrink(&mut self) {
+        self.chunks.shrink_to_fit();
+        for chunk in &mut self.chunks {
+            chunk.data.shrink_to_fit();
+        }
+    }
+
+    /// writes unpack one chunk at a time, so this checks there's room for one besides growing the
+    /// chunk list
+    fn try_reserve(&mut self, len: usize) -> Result<(), TryReserveError> {
+        self.chunks.try_reserve(
+            len.div_ceil(COMPRESS_CHUNK)
+                .saturating_sub(self.chunks.len()),
+        )?;
+        Vec::<u8>::new().try_reserve_exact(len.min(COMPRESS_CHUNK))
+    }
+}
+
+/// a gap past the end of a file at least this large turns its contents into [`Sparse`] storage
+pub const MIN_HOLE: u64 = 1 << 20;
+
+/// only the written ranges of the contents are kept, everything between them reads as zeros
+#[derive(Debug, Clone, Default)]
+pub struct Sparse {
+    /// written ranges by offset, they never overlap or touch
+    extents: BTreeMap<u64, Vec<u8>>,
+    len: u64,
+}
+
+impl Sparse {
+    pub fn new(data: &[u8]) -> Self {
+        let mut sparse = Self::default();
+        sparse.write(0, data);
+        sparse
+    }
+
+    /// the extents holding any byte of `start..end`, or touching it when `adjacent` is set
+    fn overlapping(&self, start: u64, end: u64, adjacent: bool) -> Vec<u64> {
+        let reaches = |offset: u64, data: &Vec<u8>| match adjacent {
+            true => offset + data.len() as u64 >= start,
+            false => offset + data.len() as u64 > start,
+        };
+        let before = self
+            .extents
+            .range(..start)
+            .next_back()
+            .filter(|(offset, data)| reaches(**offset, data))
+            .map(|(offset, _)| *offset);
+        let within = match adjacent {
+            true => self.extents.range(start..=end),
+            false => self.extents.range(start..end),
+        };
+
+        before
+            .into_iter()
+            .chain(within.map(|(offset, _)| *offset))
+            .collect()
+    }
+}
+
+impl Storage for Sparse {
+    fn read(&self, offset: u64, len: usize) -> Cow<'_, [u8]> {
+        let start = offset.min(self.len);
+        let end = start.saturating_add(len as u64).min(self.len);
+        let overlapping = self.overlapping(start, end, false);
+
+        // borrowed when a single extent holds the whole range
+        if let &[extent] = overlapping.as_slice() {
+            let data = &self.extents[&extent];
+            if extent <= start && end <= extent + data.len() as u64 {
+                return Cow::Borrowed(&data[(start - extent) as usize..(end - extent) as usize]);
+            }
+        }
+
+        let mut out = vec![0; (end - start) as usize];
+        for extent in overlapping {
+            let data = &self.extents[&extent];
+            let from = extent.max(start);
+            let to = (extent + data.len() as u64).min(end);
+            out[(from - start) as usize..(to - start) as usize]
+                .copy_from_slice(&data[(from - extent) as usize..(to - extent) as usize]);
+        }
+
+        Cow::Owned(out)
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) -> usize {
+        if data.is_empty() {
+            return 0;
+        }
+
+        let end = offset + data.len() as u64;
+        let merged = self.overlapping(offset, end, true);
+        let start = merged.first().map_or(offset, |first| offset.min(*first));
+        let mut extent = Vec::new();
+        for old in merged {
+            let old_data = self
+                .extents
+                .remove(&old)
+                .expect("overlapping extents exist");
+            let at = (old - start) as usize;
+            if extent.len() < at + old_data.len() {
+                extent.resize(at + old_data.len(), 0);
+            }
+            extent[at..at + old_data.len()].copy_from_slice(&old_data);
+        }
+        let at = (offset - start) as usize;
+        if extent.len() < at + data.len() {
+            extent.resize(at + data.len(), 0);
+        }
+        extent[at..at + data.len()].copy_from_slice(data);
+
+        self.extents.insert(start, extent);
+        self.len = self.len.max(end);
+
+        data.len()
+    }
+
+    fn truncate(&mut self, len: u64) {
+        self.extents.retain(|offset, _| *offset < len);
+        if let Some((offset, data)) = self.extents.iter_mut().next_back() {
+            data.truncate((len - offset) as usize);
+        }
+        self.len = len;
+    }
+
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn stored_len(&self) -> u64 {
+        self.extents.values().map(|data| data.len() as u64).sum()
+    }
+
+    /// only the bytes between the extents are new
+    fn growth(&self, offset: u64, end: u64) -> u64 {
+        let covered: u64 = self
+            .overlapping(offset, end, false)
+            .into_iter()
+            .map(|extent| {
+                let len = self.extents[&extent].len() as u64;
+                (extent + len).min(end) - extent.max(offset)
+            })
+            .sum();
+
+        end.saturating_sub(offset) - covered
+    }
+
+    fn box_clone(&self) -> Box<dyn Storage> {
+        Box::new(self.clone())
+    }
+
+    fn sparse(&self) -> bool {
+        true
+    }
+
+    fn shrink(&mut self) {
+        for data in self.extents.values_mut() {
+            data.shrink_to_fit();
+        }
+    }
+}
+
+/// a read only mapping of a host file, which is kept open and exclusively locked with `flock` for
+/// as long as it's mapped
+#[derive(Debug)]
+struct Mapping {
+    ptr: *const u8,
+    len: usize,
+    _lock: fs::File,
+}
+
+// SAFETY: the pointer is only read through and only unmapped on drop, so sharing it between
+// threads is no different from sharing a `&[u8]`
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // SAFETY: `ptr` and `len` are exactly what mmap returned and nothing borrows from the
+            // mapping once the last reference to it is gone
+            unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+        }
+    }
+}
+
+/// contents read straight out of a host file mapped read only, reads borrow from the mapping
+/// without copying and copies of the storage share it.
+///
+/// a private mapping still shows changes other processes make to the file, and touching a page
+/// past the end of a file that was truncated raises `SIGBUS`. the file is locked with `flock` so
+/// cooperating writers stay away, anything on the host that ignores the lock must leave the file
+/// alone while it's mapped
+#[derive(Debug, Clone)]
+pub struct MmapStorage(Arc<Mapping>);
+
+impl MmapStorage {
+    /// maps all of `path`, `WouldBlock` if someone else holds a lock on it
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+        file.try_lock().map_err(io::Error::from)?;
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::from(io::ErrorKind::FileTooLarge))?;
+        if len == 0 {
+            return Ok(Self(Arc::new(Mapping {
+                ptr: ptr::null(),
+                len,
+                _lock: file,
+            })));
+        }
+
+        // SAFETY: a fresh read only mapping of an open file, nothing else in this process points
+        // into it. what it shows can still change if the host file does, see the type's docs
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self(Arc::new(Mapping {
+            ptr: ptr as *const u8,
+            len,
+            _lock: file,
+        })))
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match self.0.len {
+            0 => &[],
+            // SAFETY: the mapping is `len` readable bytes and lives as long as `self`. the bytes
+            // only change if the locked host file is written despite the lock
+            len => unsafe { slice::from_raw_parts(self.0.ptr, len) },
+        }
+    }
+}
+
+impl Storage for MmapStorage {
+    fn read(&self, offset: u64, len: usize) -> Cow<'_, [u8]> {
+        let data = self.bytes();
+        let start = (offset as usize).min(data.len());
+        let end = start.saturating_add(len).min(data.len());
+        Cow::Borrowed(&data[start..end])
+    }
+
+    /// never called since the storage is read only, nothing is written
+    fn write(&mut self, _offset: u64, _data: &[u8]) -> usize {
+        0
+    }
+
+    fn truncate(&mut self, _len: u64) {}
+
+    fn len(&self) -> u64 {
+        self.0.len as u64
+    }
+
+    fn box_clone(&self) -> Box<dyn Storage> {
+        Box::new(self.clone())
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+}
+
+/// a header byte `h` below 128 is followed by `h + 1` literal bytes, above 128 by one byte
+/// repeated `257 - h` times
+fn pack(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut literals: Vec<u8> = Vec::new();
+    let flush = |out: &mut Vec<u8>, literals: &mut Vec<u8>| {
+        for chunk in literals.chunks(128) {
+            out.push(chunk.len() as u8 - 1);
+            out.extend_from_slice(chunk);
+        }
+        literals.clear();
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let run = data[i..]
+            .iter()
+            .take(128)
+            .take_while(|&&b| b == data[i])
+            .count();
+        if run >= 2 {
+            flush(&mut out, &mut literals);
+            out.push((257 - run) as u8);
+            out.push(data[i]);
+        } else {
+            literals.push(data[i]);
+        }
+        i += run;
+    }
+    flush(&mut out, &mut literals);
+
+    out
+}
+
+fn unpack(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let header = data[i] as usize;
+        i += 1;
+        if header < 128 {
+            out.extend_from_slice(&data[i..i + header + 1]);
+            i += header + 1;
+        } else if header > 128 {
+            out.extend(std::iter::repeat_n(data[i], 257 - header));
+            i += 1;
+        }
+    }
+
+    out
+}
@@ -1,10 +1,59 @@
 use tracing::level_filters::LevelFilter;
+use tracing::{info, warn};
 use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::util::SubscriberInitExt;
 
+pub mod config;
+pub mod control;
 pub mod filesystem;
+pub mod shutdown;
 
-use filesystem::Daniel;
+use std::time::Duration;
+
+use config::{DEFAULT_FSNAME, MountSpec};
+use filesystem::{Daniel, SharedDaniel};
+use shutdown::{Shutdown, UnixSignals};
+
+/// how often the supervisor checks for a signal or an external unmount
+const SHUTDOWN_POLL: Duration = Duration::from_millis(100);
+
+/// keeps the mounts alive until SIGINT/SIGTERM or until they've all been unmounted externally
+fn run_until_shutdown(signals: &UnixSignals, sessions: Vec<fuser::BackgroundSession>) {
+    match shutdown::wait_for_shutdown(signals, sessions, SHUTDOWN_POLL) {
+        Shutdown::Signal => println!("unmounted cleanly, shutting down"),
+        Shutdown::Unmounted => println!("every mount was unmounted, shutting down"),
+    }
+}
+
+/// mounts every spec in the background and waits for a shutdown, exits with 1 if none of them
+/// could be mounted
+fn supervise(signals: &UnixSignals, specs: Vec<MountSpec>) {
+    let mut sessions = Vec::with_capacity(specs.len());
+    for spec in specs {
+        let options = spec.mount_options();
+        let mut fs = Daniel::new().with_read_only(spec.read_only);
+        if let Some(quota) = spec.quota {
+            fs = fs.with_max_bytes(quota);
+        }
+        match fs.spawn(&spec.mountpoint, &options) {
+            Ok(session) => {
+                info!(name = spec.name, mountpoint = %spec.mountpoint.display(), "mounted");
+                sessions.push(session);
+            }
+            Err(err) => eprintln!(
+                "failed to mount {} at {}: {err}",
+                spec.name,
+                spec.mountpoint.display()
+            ),
+        }
+    }
+    if sessions.is_empty() {
+        eprintln!("nothing was mounted");
+        std::process::exit(1);
+    }
+
+    run_until_shutdown(signals, sessions);
+}
 
 fn main() {
     let _ = tracing_subscriber::FmtSubscriber::builder()
@@ -18,15 +67,87 @@ fn main() {
         _ = std::fs::create_dir("/tmp/daniel/");
     }
 
-    let mountpoint = match std::env::args().nth(1) {
+    let signals = UnixSignals::install();
+    let mut args = std::env::args();
+    let program = args.next().unwrap();
+    let mut arg = args.next();
+    let mut control_addr = None;
+    let mut fsname = DEFAULT_FSNAME.to_string();
+    let mut subtype = DEFAULT_FSNAME.to_string();
+    let mut skeleton = None;
+    while let Some(flag @ ("--control-addr" | "--fsname" | "--subtype" | "--skeleton")) =
+        arg.as_deref()
+    {
+        let Some(value) = args.next() else {
+            eprintln!("Usage: {program} {flag} <VALUE> <MOUNTPOINT>");
+            std::process::exit(2);
+        };
+        match flag {
+            "--control-addr" => control_addr = Some(value),
+            "--fsname" => fsname = value,
+            "--skeleton" => skeleton = Some(value),
+            _ => subtype = value,
+        }
+        arg = args.next();
+    }
+
+    let mountpoint = match arg {
+        Some(flag) if flag == "--config" => {
+            if control_addr.is_some() {
+                warn!("the control socket only works with a single mountpoint, ignoring it");
+            }
+            if skeleton.is_some() {
+                warn!("the skeleton only works with a single mountpoint, ignoring it");
+            }
+            let Some(path) = args.next() else {
+                eprintln!("Usage: {program} --config <FILE>");
+                std::process::exit(2);
+            };
+            let specs = std::fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|src| config::parse_config(&src).map_err(|err| err.to_string()));
+            match specs {
+                Ok(specs) => supervise(&signals, specs),
+                Err(err) => {
+                    eprintln!("invalid config {path}: {err}");
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
         Some(path) => path,
         None => {
-            println!("Usage: {} <MOUNTPOINT>", std::env::args().next().unwrap());
+            println!(
+                "Usage: {program} [--control-addr <[HOST:]PORT>] [--fsname <NAME>] \
+                [--subtype <NAME>] [--skeleton <DIR>,...] <MOUNTPOINT>"
+            );
+            println!("       {program} --config <FILE>");
             return;
         }
     };
 
-    let fs = Daniel::new();
+    let mut fs = Daniel::new();
+    if let Some(list) = skeleton
+        && let Err(err) = config::build_skeleton(&mut fs, &list)
+    {
+        eprintln!("invalid skeleton: {err}");
+        std::process::exit(2);
+    }
 
-    fuser::mount2(fs, &mountpoint, &[]).expect("Couldn't mount filesystem");
+    let options = config::mount_options(&fsname, &subtype, false);
+    let session = match control_addr {
+        Some(addr) => {
+            let listener = control::bind(&addr).unwrap_or_else(|err| {
+                eprintln!("couldn't listen on {addr}: {err}");
+                std::process::exit(1);
+            });
+            info!(%addr, "control socket listening");
+            let fs = SharedDaniel::new(fs);
+            control::serve(listener, fs.handle());
+            fs.spawn(&mountpoint, &options)
+        }
+        None => fs.spawn(&mountpoint, &options),
+    }
+    .expect("Couldn't mount filesystem");
+    run_until_shutdown(&signals, vec![session]);
 }
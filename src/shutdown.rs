@@ -0,0 +1,177 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use tracing::error;
+
+/// something that can tell the supervisor it's time to unmount
+pub trait ShutdownSignal {
+    fn requested(&self) -> bool;
+}
+
+/// a running mount the supervisor can watch and tear down
+pub trait Mounted {
+    /// whether the session ended on its own, eg. through an external `umount`
+    fn is_finished(&self) -> bool;
+    fn unmount(self);
+}
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_signal: libc::c_int) {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// set once SIGINT or SIGTERM has been received
+#[derive(Debug)]
+pub struct UnixSignals(());
+
+impl UnixSignals {
+    pub fn install() -> Self {
+        let handler = on_signal as extern "C" fn(libc::c_int);
+        // SAFETY: the handler only stores to an atomic, which is async signal safe
+        unsafe {
+            libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+            libc::signal(libc::SIGTERM, handler as libc::sighandler_t);
+        }
+
+        Self(())
+    }
+}
+
+impl ShutdownSignal for UnixSignals {
+    fn requested(&self) -> bool {
+        REQUESTED.load(Ordering::SeqCst)
+    }
+}
+
+impl Mounted for fuser::BackgroundSession {
+    fn is_finished(&self) -> bool {
+        self.guard.is_finished()
+    }
+
+    fn unmount(self) {
+        // the rest of the session is dropped when this returns, which unmounts it
+        fn guard(session: fuser::BackgroundSession) -> JoinHandle<std::io::Result<()>> {
+            session.guard
+        }
+
+        match guard(self).join() {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => error!(%err, "session ended with an error"),
+            Err(_) => error!("session thread panicked"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shutdown {
+    Signal,
+    /// every mount ended on its own
+    Unmounted,
+}
+
+/// blocks until `signal` asks for a shutdown or every mount has ended, then unmounts all of them
+pub fn wait_for_shutdown<M: Mounted>(
+    signal: &impl ShutdownSignal,
+    mounts: Vec<M>,
+    poll: Duration,
+) -> Shutdown {
+    let reason = loop {
+        if signal.requested() {
+            break Shutdown::Signal;
+        }
+        if mounts.iter().all(Mounted::is_finished) {
+            break Shutdown::Unmounted;
+        }
+        thread::sleep(poll);
+    };
+
+    for mount in mounts {
+        mount.unmount();
+    }
+
+    reason
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::Cell, rc::Rc, time::Duration};
+
+    use super::{Mounted, Shutdown, ShutdownSignal, wait_for_shutdown};
+
+    /// asks for a shutdown after being polled `after` times
+    struct AfterPolls {
+        after: usize,
+        polls: Cell<usize>,
+    }
+
+    impl ShutdownSignal for AfterPolls {
+        fn requested(&self) -> bool {
+            self.polls.set(self.polls.get() + 1);
+            self.polls.get() > self.after
+        }
+    }
+
+    struct FakeMount {
+        finished: bool,
+        unmounted: Rc<Cell<usize>>,
+    }
+
+    impl Mounted for FakeMount {
+        fn is_finished(&self) -> bool {
+            self.finished
+        }
+
+        fn unmount(self) {
+            self.unmounted.set(self.unmounted.get() + 1);
+        }
+    }
+
+    #[test]
+    fn signal_unmounts_everything() {
+        let unmounted = Rc::new(Cell::new(0));
+        let mounts = vec![
+            FakeMount {
+                finished: false,
+                unmounted: unmounted.clone(),
+            },
+            FakeMount {
+                finished: true,
+                unmounted: unmounted.clone(),
+            },
+        ];
+        let signal = AfterPolls {
+            after: 3,
+            polls: Cell::new(0),
+        };
+
+        assert_eq!(
+            wait_for_shutdown(&signal, mounts, Duration::ZERO),
+            Shutdown::Signal
+        );
+        assert_eq!(signal.polls.get(), 4);
+        assert_eq!(unmounted.get(), 2);
+    }
+
+    #[test]
+    fn ends_once_every_mount_has() {
+        let unmounted = Rc::new(Cell::new(0));
+        let mounts = vec![FakeMount {
+            finished: true,
+            unmounted: unmounted.clone(),
+        }];
+        let signal = AfterPolls {
+            after: usize::MAX,
+            polls: Cell::new(0),
+        };
+
+        assert_eq!(
+            wait_for_shutdown(&signal, mounts, Duration::ZERO),
+            Shutdown::Unmounted
+        );
+        assert_eq!(unmounted.get(), 1);
+    }
+}